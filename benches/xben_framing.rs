@@ -0,0 +1,70 @@
+//! Compares the separator framed ben32 data inside of XBEN files with the
+//! length-prefixed (`LP32`) framing. Run with `cargo bench`.
+
+use ben::decode::{count_samples_xben, decode_xben_to_ben};
+use ben::encode::{jsonl_encode_xben, jsonl_encode_xben_length_prefixed};
+use ben::BenVariant;
+use criterion::{criterion_group, criterion_main, Criterion};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde_json::json;
+
+const N_SAMPLES: usize = 500;
+const N_NODES: usize = 2000;
+
+/// Builds a JSONL ensemble whose assignments are made of runs of random
+/// length, which is roughly what a chain on sorted blocks looks like.
+fn make_jsonl() -> String {
+    let mut rng = ChaCha8Rng::seed_from_u64(42);
+    (1..=N_SAMPLES)
+        .map(|sample| {
+            let mut assignment = Vec::with_capacity(N_NODES);
+            while assignment.len() < N_NODES {
+                let label: u16 = rng.gen_range(1..=14);
+                let run = rng.gen_range(1..=40).min(N_NODES - assignment.len());
+                assignment.extend(std::iter::repeat_n(label, run));
+            }
+            json!({"assignment": assignment, "sample": sample}).to_string() + "\n"
+        })
+        .collect()
+}
+
+fn bench_framing(c: &mut Criterion) {
+    let jsonl = make_jsonl();
+
+    let mut separated = Vec::new();
+    jsonl_encode_xben(jsonl.as_bytes(), &mut separated, BenVariant::MkvChain).unwrap();
+
+    let mut prefixed = Vec::new();
+    jsonl_encode_xben_length_prefixed(jsonl.as_bytes(), &mut prefixed, BenVariant::MkvChain)
+        .unwrap();
+
+    let mut group = c.benchmark_group("count_samples_xben");
+    group.bench_function("separator", |b| {
+        b.iter(|| count_samples_xben(separated.as_slice()).unwrap())
+    });
+    group.bench_function("lp32", |b| {
+        b.iter(|| count_samples_xben(prefixed.as_slice()).unwrap())
+    });
+    group.finish();
+
+    let mut group = c.benchmark_group("decode_xben_to_ben");
+    group.bench_function("separator", |b| {
+        b.iter(|| {
+            let mut ben = Vec::new();
+            decode_xben_to_ben(separated.as_slice(), &mut ben).unwrap();
+            ben
+        })
+    });
+    group.bench_function("lp32", |b| {
+        b.iter(|| {
+            let mut ben = Vec::new();
+            decode_xben_to_ben(prefixed.as_slice(), &mut ben).unwrap();
+            ben
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_framing);
+criterion_main!(benches);
@@ -1,9 +1,11 @@
-use ben::decode::read::extract_assignment_ben;
+use ben::decode::adapters::Selection;
+use ben::decode::index::{BenIndex, FrameIndex, FrameLayout};
 use ben::decode::*;
 use ben::encode::*;
 use ben::{logln, BenVariant};
 use clap::{Parser, ValueEnum};
 use std::{
+    collections::HashSet,
     fs::File,
     io::{self, BufReader, BufWriter, Result, Write},
     path::Path,
@@ -16,10 +18,20 @@ enum Mode {
     Decode,
     XDecode,
     Read,
+    Dump,
+    Roundtrip,
+    Filter,
     XzCompress,
     XzDecompress,
 }
 
+/// Defines the output format of the decode mode.
+#[derive(Debug, Clone, ValueEnum, PartialEq)]
+enum Format {
+    Jsonl,
+    Txt,
+}
+
 /// Defines the command line arguments accepted by the program.
 #[derive(Parser, Debug)]
 #[command(
@@ -81,6 +93,55 @@ struct Args {
     #[arg(short = 'a', long)]
     save_all: bool,
 
+    /// Output format for the decode mode when decoding a BEN file.
+    /// The txt format writes each assignment vector as a line of
+    /// space-separated integers.
+    #[arg(long, value_enum, default_value = "jsonl")]
+    format: Format,
+
+    /// Maximum number of samples to encode. Optional.
+    /// Useful when piping the output of a sampler into the encode
+    /// modes, since the file will be finalized once this many
+    /// samples have been read.
+    #[arg(long)]
+    max_samples: Option<usize>,
+
+    /// Only encode or decode every K-th sample (samples K, 2K, 3K, ...).
+    /// When encoding, the sample numbers are the line numbers of the
+    /// JSONL input. When decoding, the sample numbers written to the
+    /// JSONL output are the positions of the samples in the input file.
+    /// Optional.
+    #[arg(long, conflicts_with_all = ["indices", "range", "max_samples"])]
+    every: Option<usize>,
+
+    /// Only encode or decode the samples with the given comma-separated
+    /// sample numbers (e.g. 1,50,100). Optional.
+    #[arg(long, value_delimiter = ',', conflicts_with_all = ["range", "max_samples"])]
+    indices: Option<Vec<usize>>,
+
+    /// Only encode or decode the samples from START to END, inclusive,
+    /// given as START:END. Optional.
+    #[arg(long, value_parser = parse_range, conflicts_with = "max_samples")]
+    range: Option<(usize, usize)>,
+
+    /// The samples to keep in filter mode. One of `assigned` (no node has
+    /// label 0), `max-label=N` (no label is larger than N), or
+    /// `districts=N` (exactly N distinct labels).
+    #[arg(long, value_parser = parse_keep)]
+    keep: Option<Keep>,
+
+    /// Flush the output after every N samples when decoding to JSONL.
+    /// Optional. Useful when the output is being piped into a live
+    /// consumer, at the cost of some throughput.
+    #[arg(long)]
+    flush_every: Option<usize>,
+
+    /// Split the output of the decode mode into JSONL files with N samples
+    /// each, named `<output>.part0.jsonl`, `<output>.part1.jsonl`, and so
+    /// on. Only supported when decoding a BEN file to JSONL. Optional.
+    #[arg(long, conflicts_with_all = ["every", "indices", "range", "print"])]
+    split: Option<usize>,
+
     /// If the output file already exists, this flag
     /// will cause the program to overwrite it without
     /// asking the user for confirmation.
@@ -92,6 +153,77 @@ struct Args {
     verbose: bool,
 }
 
+fn parse_range(range: &str) -> std::result::Result<(usize, usize), String> {
+    let (start, end) = range
+        .split_once(':')
+        .ok_or_else(|| format!("Expected a range of the form START:END, found {:?}", range))?;
+    let start: usize = start
+        .parse()
+        .map_err(|e| format!("Invalid start {:?}: {}", start, e))?;
+    let end: usize = end
+        .parse()
+        .map_err(|e| format!("Invalid end {:?}: {}", end, e))?;
+    if start > end {
+        return Err(format!(
+            "The start of the range {:?} is after its end",
+            range
+        ));
+    }
+    Ok((start, end))
+}
+
+/// The built-in predicates of the filter mode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Keep {
+    Assigned,
+    MaxLabel(u16),
+    Districts(usize),
+}
+
+impl Keep {
+    fn matches(&self, assignment: &[u16]) -> bool {
+        match *self {
+            Keep::Assigned => !assignment.contains(&0),
+            Keep::MaxLabel(max_label) => assignment.iter().all(|&label| label <= max_label),
+            Keep::Districts(n_districts) => {
+                assignment.iter().collect::<HashSet<_>>().len() == n_districts
+            }
+        }
+    }
+}
+
+fn parse_keep(keep: &str) -> std::result::Result<Keep, String> {
+    let (name, value) = match keep.split_once('=') {
+        Some((name, value)) => (name, Some(value)),
+        None => (keep, None),
+    };
+    match (name, value) {
+        ("assigned", None) => Ok(Keep::Assigned),
+        ("max-label", Some(value)) => value
+            .parse()
+            .map(Keep::MaxLabel)
+            .map_err(|e| format!("Invalid label {:?}: {}", value, e)),
+        ("districts", Some(value)) => value
+            .parse()
+            .map(Keep::Districts)
+            .map_err(|e| format!("Invalid number of districts {:?}: {}", value, e)),
+        _ => Err(format!(
+            "Unknown predicate {:?}. Expected one of assigned, max-label=N, or districts=N",
+            keep
+        )),
+    }
+}
+
+/// The subsample selected by the --every, --indices, or --range flags, if any.
+fn selection(args: &Args) -> Option<Selection> {
+    args.every
+        .map(Selection::Every)
+        .or(args.indices.clone().map(Selection::Indices))
+        .or(args
+            .range
+            .map(|(start, end)| Selection::Range { start, end }))
+}
+
 fn encode_setup(
     mode: Mode,
     input_file_name: String,
@@ -109,7 +241,9 @@ fn encode_setup(
     let out_file_name = match output_file_name {
         Some(name) => name.to_owned(),
         None => {
-            if input_file_name.ends_with(".ben") && extension == ".xben" {
+            if input_file_name.ends_with(".jsonl.xz") && extension != ".xz" {
+                input_file_name.trim_end_matches(".xz").to_owned() + extension
+            } else if input_file_name.ends_with(".ben") && extension == ".xben" {
                 input_file_name.trim_end_matches(".ben").to_owned() + extension
             } else {
                 input_file_name.to_string() + extension
@@ -178,6 +312,75 @@ fn check_overwrite(file_name: &str, overwrite: bool) -> Result<()> {
     Ok(())
 }
 
+/// The name of a part of the output of the decode mode when it is split with
+/// `--split`, e.g. `out.jsonl` becomes `out.part0.jsonl`.
+fn split_part_name(out_file_name: &str, part: usize) -> String {
+    format!(
+        "{}.part{}.jsonl",
+        out_file_name.trim_end_matches(".jsonl"),
+        part
+    )
+}
+
+/// Opens a BEN file for random access. The frame index is loaded from the
+/// sidecar `<input>.benidx` file when it exists and is newer than the input;
+/// otherwise it is rebuilt and saved next to the input for next time.
+fn open_indexed(in_file_name: &str) -> Result<BenIndex<BufReader<File>>> {
+    let reader = BufReader::new(File::open(in_file_name)?);
+    let index_file_name = in_file_name.to_owned() + ".benidx";
+
+    let source_modified = std::fs::metadata(in_file_name)?.modified()?;
+    let index_is_fresh = std::fs::metadata(&index_file_name)
+        .and_then(|meta| meta.modified())
+        .is_ok_and(|index_modified| index_modified > source_modified);
+
+    if index_is_fresh {
+        let index_reader = BufReader::new(File::open(&index_file_name)?);
+        match FrameIndex::read_from(index_reader) {
+            Ok(frames) => return Ok(BenIndex::from_parts(reader, frames)),
+            Err(e) => logln!("Ignoring index file {:?}: {}", index_file_name, e),
+        }
+    }
+
+    logln!("Building index for {:?}", in_file_name);
+    let index = BenIndex::build(reader)?;
+    let saved =
+        File::create(&index_file_name).and_then(|file| index.write_to(BufWriter::new(file)));
+    if let Err(e) = saved {
+        logln!("Could not save index file {:?}: {}", index_file_name, e);
+    }
+
+    Ok(index)
+}
+
+/// Writes the header of a frame followed by its runs as `label:length`
+/// pairs, for the dump mode.
+fn write_frame_layout<W: Write>(
+    mut writer: W,
+    sample_number: usize,
+    layout: &FrameLayout,
+) -> Result<()> {
+    let n_nodes: usize = layout.runs.iter().map(|&(_, len)| len as usize).sum();
+    writeln!(
+        writer,
+        "sample {}: offset {}, max_val_bits {}, max_len_bits {}, {} payload bytes, {} runs, {} nodes",
+        sample_number,
+        layout.offset,
+        layout.max_val_bits,
+        layout.max_len_bits,
+        layout.n_bytes,
+        layout.runs.len(),
+        n_nodes
+    )?;
+    let runs = layout
+        .runs
+        .iter()
+        .map(|(val, len)| format!("{}:{}", val, len))
+        .collect::<Vec<_>>();
+    writeln!(writer, "{}", runs.join(" "))?;
+    writer.flush()
+}
+
 fn main() {
     let args = Args::parse();
 
@@ -188,6 +391,7 @@ fn main() {
     match args.mode {
         Mode::Encode => {
             logln!("Running in encode mode");
+            let selection = selection(&args);
 
             let reader: Box<dyn io::BufRead>;
             let writer: Box<dyn Write>;
@@ -237,10 +441,19 @@ fn main() {
                 }
             };
 
-            let possible_error = if args.save_all {
-                jsonl_encode_ben(reader, writer, BenVariant::Standard)
+            let variant = if args.save_all {
+                BenVariant::Standard
             } else {
-                jsonl_encode_ben(reader, writer, BenVariant::MkvChain)
+                BenVariant::MkvChain
+            };
+            let possible_error = match selection {
+                Some(selection) => jsonl_encode_ben_subsample(reader, writer, variant, selection),
+                None => jsonl_encode_ben_with_take(
+                    reader,
+                    writer,
+                    variant,
+                    args.max_samples.unwrap_or(usize::MAX),
+                ),
             };
 
             match possible_error {
@@ -252,6 +465,7 @@ fn main() {
         }
         Mode::XEncode => {
             logln!("Running in xencode mode");
+            let selection = selection(&args);
 
             let mut ben_and_xben = args.ben_and_xben;
             let mut jsonl_and_xben = args.ben_and_xben;
@@ -306,15 +520,36 @@ fn main() {
                 }
             };
 
-            if ben_and_xben {
-                if let Err(err) = ben_encode_xben(reader, writer) {
-                    eprintln!("Error: {:?}", err);
+            if ben_and_xben && selection.is_some() {
+                eprintln!(
+                    "Error: --every, --indices, and --range are only supported for JSONL input"
+                );
+            } else if ben_and_xben {
+                match ben_encode_xben_with_report(reader, writer) {
+                    Ok(report) => logln!(
+                        "BEN: {} bytes, XBEN: {} bytes ({:.2}x smaller)",
+                        report.ben_bytes,
+                        report.xben_bytes,
+                        report.ratio()
+                    ),
+                    Err(err) => eprintln!("Error: {:?}", err),
                 }
             } else if jsonl_and_xben {
-                let possible_error = if args.save_all {
-                    jsonl_encode_xben(reader, writer, BenVariant::Standard)
+                let variant = if args.save_all {
+                    BenVariant::Standard
                 } else {
-                    jsonl_encode_xben(reader, writer, BenVariant::MkvChain)
+                    BenVariant::MkvChain
+                };
+                let possible_error = match selection {
+                    Some(selection) => {
+                        jsonl_encode_xben_subsample(reader, writer, variant, selection)
+                    }
+                    None => jsonl_encode_xben_with_take(
+                        reader,
+                        writer,
+                        variant,
+                        args.max_samples.unwrap_or(usize::MAX),
+                    ),
                 };
                 if let Err(e) = possible_error {
                     eprintln!("Error: {:?}", e);
@@ -326,6 +561,33 @@ fn main() {
         Mode::Decode => {
             logln!("Running in decode mode");
 
+            if let Some(chunk_samples) = args.split {
+                let result = match &args.input_file {
+                    Some(file) if file.ends_with(".ben") && args.format == Format::Jsonl => {
+                        let out_file_name = args
+                            .output_file
+                            .clone()
+                            .unwrap_or_else(|| file.trim_end_matches(".ben").to_owned());
+                        let reader = BufReader::new(File::open(file).unwrap());
+                        jsonl_decode_ben_split(reader, chunk_samples, |part| {
+                            let part_file_name = split_part_name(&out_file_name, part);
+                            check_overwrite(&part_file_name, args.overwrite)?;
+                            File::create(&part_file_name).map(BufWriter::new)
+                        })
+                    }
+                    _ => Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "--split is only supported when decoding a BEN file to JSONL",
+                    )),
+                };
+                match result {
+                    Ok(n_parts) => logln!("Wrote {} parts", n_parts),
+                    Err(err) => eprintln!("Error: {:?}", err),
+                }
+                return;
+            }
+            let selection = selection(&args);
+
             let mut ben_and_xben = args.ben_and_xben;
             let mut jsonl_and_ben = args.jsonl_and_ben;
 
@@ -343,11 +605,21 @@ fn main() {
                     reader = Box::new(BufReader::new(File::open(&file).unwrap()))
                         as Box<dyn io::BufRead>;
 
+                    let output_file = match args.output_file {
+                        None if args.format == Format::Txt && file.ends_with(".ben") => Some(
+                            file.trim_end_matches(".ben")
+                                .trim_end_matches(".jsonl")
+                                .to_owned()
+                                + ".txt",
+                        ),
+                        output_file => output_file,
+                    };
+
                     writer = if args.print {
                         Box::new(BufWriter::new(io::stdout())) as Box<dyn Write>
                     } else {
                         let out_file_name =
-                            match decode_setup(file, args.output_file, false, args.overwrite) {
+                            match decode_setup(file, output_file, false, args.overwrite) {
                                 Ok(name) => name,
                                 Err(err) => {
                                     eprintln!("Error: {:?}", err);
@@ -380,11 +652,35 @@ fn main() {
             }
 
             if ben_and_xben {
-                if let Err(err) = decode_xben_to_ben(reader, writer) {
+                if args.format == Format::Txt {
+                    eprintln!("Error: The txt format is only supported when decoding BEN files");
+                } else if selection.is_some() {
+                    eprintln!(
+                        "Error: --every, --indices, and --range are only supported when \
+                        decoding to JSONL"
+                    );
+                } else if let Err(err) = decode_xben_to_ben(reader, writer) {
                     eprintln!("Error: {:?}", err);
                 }
             } else if jsonl_and_ben {
-                if let Err(err) = jsonl_decode_ben(reader, writer) {
+                let flush_every = args.flush_every.unwrap_or(0);
+                let possible_error = match (args.format, selection) {
+                    (Format::Jsonl, Some(selection)) => jsonl_decode_ben_subsample(
+                        reader,
+                        LineFlushWriter::new(writer, flush_every),
+                        selection,
+                        false,
+                    ),
+                    (Format::Jsonl, None) => {
+                        jsonl_decode_ben_with_flush(reader, writer, flush_every)
+                    }
+                    (Format::Txt, Some(_)) => Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "--every, --indices, and --range are not supported for the txt format",
+                    )),
+                    (Format::Txt, None) => ben_to_txt(reader, writer),
+                };
+                if let Err(err) = possible_error {
                     eprintln!("Error: {:?}", err);
                 }
             } else {
@@ -393,6 +689,7 @@ fn main() {
         }
         Mode::XDecode => {
             logln!("Running in x-decode mode");
+            let selection = selection(&args);
 
             let reader: Box<dyn io::BufRead>;
             let writer: Box<dyn Write>;
@@ -438,19 +735,24 @@ fn main() {
                 }
             }
 
-            if let Err(err) = jsonl_decode_xben(reader, writer) {
+            let flush_every = args.flush_every.unwrap_or(0);
+            let possible_error = match selection {
+                Some(selection) => subsample_xben_to_jsonl(
+                    reader,
+                    LineFlushWriter::new(writer, flush_every),
+                    selection,
+                ),
+                None => jsonl_decode_xben_with_flush(reader, writer, flush_every),
+            };
+            if let Err(err) = possible_error {
                 eprintln!("Error: {:?}", err);
             }
         }
         Mode::Read => {
             logln!("Running in read mode");
-            let file: File = File::open(
-                &args
-                    .input_file
-                    .expect("Must provide input file for read mode."),
-            )
-            .unwrap();
-            let reader: BufReader<File> = BufReader::new(file);
+            let in_file_name = args
+                .input_file
+                .expect("Must provide input file for read mode.");
 
             if args.sample_number.is_none() {
                 eprintln!("Error: Sample number is required in read mode");
@@ -469,11 +771,122 @@ fn main() {
                 }
             };
 
-            args.sample_number
-                .map(|n| match extract_assignment_ben(reader, n) {
-                    Ok(vec) => writer.write_all(format!("{:?}\n", vec).as_bytes()).unwrap(),
-                    Err(e) => eprintln!("Error: {:?}", e),
-                });
+            let result = open_indexed(&in_file_name)
+                .and_then(|mut index| index.get(args.sample_number.unwrap()));
+            match result {
+                Ok(vec) => writer.write_all(format!("{:?}\n", vec).as_bytes()).unwrap(),
+                Err(e) => eprintln!("Error: {:?}", e),
+            }
+        }
+        Mode::Dump => {
+            logln!("Running in dump mode");
+            let in_file_name = args
+                .input_file
+                .expect("Must provide input file for dump mode.");
+
+            let sample_number = match args.sample_number {
+                Some(n) => n,
+                None => {
+                    eprintln!("Error: Sample number is required in dump mode");
+                    return;
+                }
+            };
+
+            let mut writer = if args.print {
+                Box::new(BufWriter::new(io::stdout())) as Box<dyn Write>
+            } else {
+                match args.output_file {
+                    Some(name) => {
+                        let file: File = File::create(name).unwrap();
+                        Box::new(BufWriter::new(file)) as Box<dyn Write>
+                    }
+                    None => Box::new(BufWriter::new(io::stdout())) as Box<dyn Write>,
+                }
+            };
+
+            let result = open_indexed(&in_file_name)
+                .and_then(|mut index| index.frame_layout(sample_number))
+                .and_then(|layout| write_frame_layout(&mut writer, sample_number, &layout));
+            if let Err(e) = result {
+                eprintln!("Error: {:?}", e);
+            }
+        }
+        Mode::Roundtrip => {
+            logln!("Running in roundtrip mode");
+            let in_file_name = args
+                .input_file
+                .expect("Must provide input file for roundtrip mode.");
+
+            let report = File::open(&in_file_name).and_then(ben_xben_roundtrip);
+            match report {
+                Ok(report) => {
+                    let sizes = format!(
+                        "BEN: {} bytes, XBEN: {} bytes ({:.2}x smaller)",
+                        report.compression.ben_bytes,
+                        report.compression.xben_bytes,
+                        report.compression.ratio()
+                    );
+                    match report.mismatch_offset {
+                        None => println!("PASS: {}", sizes),
+                        Some(offset) => {
+                            println!(
+                                "FAIL: the decoded BEN file differs from {:?} at byte {}. {}",
+                                in_file_name, offset, sizes
+                            );
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                Err(e) => {
+                    eprintln!("Error: {:?}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Mode::Filter => {
+            logln!("Running in filter mode");
+            let in_file_name = args
+                .input_file
+                .expect("Must provide input file for filter mode.");
+
+            let keep = match args.keep {
+                Some(keep) => keep,
+                None => {
+                    eprintln!("Error: --keep is required in filter mode");
+                    return;
+                }
+            };
+
+            let writer = if args.print {
+                Box::new(BufWriter::new(io::stdout())) as Box<dyn Write>
+            } else {
+                let out_file_name = match args.output_file {
+                    Some(name) => name,
+                    None => in_file_name.trim_end_matches(".ben").to_owned() + ".filtered.ben",
+                };
+                if let Err(e) = check_overwrite(&out_file_name, args.overwrite) {
+                    eprintln!("Error: {:?}", e);
+                    return;
+                }
+                Box::new(BufWriter::new(File::create(&out_file_name).unwrap())) as Box<dyn Write>
+            };
+
+            let variant = if args.save_all {
+                BenVariant::Standard
+            } else {
+                BenVariant::MkvChain
+            };
+            let result = File::open(&in_file_name).and_then(|file| {
+                filter_ben(
+                    BufReader::new(file),
+                    writer,
+                    |assignment| keep.matches(assignment),
+                    variant,
+                )
+            });
+            if let Err(e) = result {
+                eprintln!("Error: {:?}", e);
+            }
         }
         Mode::XzCompress => {
             logln!("Running in xz compress mode");
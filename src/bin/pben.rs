@@ -167,7 +167,7 @@ fn assignment_encode_ben<R: Read + BufRead, W: Write>(reader: R, writer: W) -> i
 
 fn assignment_encode_xben<R: Read + BufRead, W: Write>(reader: R, writer: W) -> io::Result<()> {
     let encoder = XzEncoder::new(writer, 9);
-    let mut xben_writer = XBenEncoder::new(encoder, BenVariant::MkvChain);
+    let mut xben_writer = XBenEncoder::new(encoder, BenVariant::MkvChain)?;
 
     xben_writer.write_ben_file(reader)?;
     Ok(())
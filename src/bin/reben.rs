@@ -7,7 +7,7 @@ use clap::{Parser, ValueEnum};
 use serde_json::{json, Value};
 use std::{
     fs::File,
-    io::{BufReader, BufWriter, Write},
+    io::{self, BufReader, BufWriter, Write},
 };
 
 #[derive(Parser, Debug, Clone, ValueEnum, PartialEq)]
@@ -37,6 +37,12 @@ struct Args {
     #[arg(short, long)]
     output_file: Option<String>,
 
+    /// Relabel the BEN file in place rather than writing a new file. The
+    /// output is written to a temporary file which then replaces the input
+    /// once it is complete. Only supported in BEN mode.
+    #[arg(long, conflicts_with = "output_file")]
+    in_place: bool,
+
     /// Key to sort the JSON or BEN file by.
     #[arg(short, long)]
     key: Option<String>,
@@ -64,6 +70,35 @@ struct Args {
     verbose: bool,
 }
 
+/// Runs `transcode` from the input file to the output file, or back onto
+/// the input file if `--in-place` was given. An output file that is the same
+/// as the input file is refused, since it would be truncated before it is
+/// read.
+fn transcode_to<F>(args: &Args, output_file_name: &str, transcode: F) -> io::Result<()>
+where
+    F: FnOnce(BufReader<File>, &mut BufWriter<File>) -> io::Result<()>,
+{
+    if args.in_place {
+        return transcode_in_place(&args.input_file, transcode);
+    }
+
+    if is_same_file(&args.input_file, output_file_name) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "The output file {:?} is the same as the input file. Use --in-place to relabel \
+                the file in place",
+                output_file_name
+            ),
+        ));
+    }
+
+    let reader = BufReader::new(File::open(&args.input_file)?);
+    let mut writer = BufWriter::new(File::create(output_file_name)?);
+    transcode(reader, &mut writer)?;
+    writer.flush()
+}
+
 fn main() {
     let args = Args::parse();
 
@@ -73,6 +108,11 @@ fn main() {
 
     match &args.mode {
         Mode::Json => {
+            if args.in_place {
+                eprintln!("Error: --in-place is only supported in BEN mode");
+                std::process::exit(1);
+            }
+
             // TODO: Change the input file here to the shape file.
             let input_file = File::open(&args.input_file).expect("Could not open input file.");
             let reader = BufReader::new(input_file);
@@ -111,26 +151,23 @@ fn main() {
                 .expect("Could not write map file.");
         }
         Mode::Ben => {
-            let input_file = File::open(&args.input_file).expect("Could not open input file.");
-            let reader = BufReader::new(input_file);
-
             if args.map_file.is_none() && args.key.is_none() {
                 logln!("Canonicalizing assignment vectors in ben file.");
 
-                let output_file_name = match args.output_file {
-                    Some(name) => name,
+                let output_file_name = match &args.output_file {
+                    Some(name) => name.to_owned(),
                     None => {
                         args.input_file.trim_end_matches(".jsonl.ben").to_owned()
                             + "_canonicalized_assignments.jsonl.ben"
                     }
                 };
 
-                let output_file =
-                    File::create(&output_file_name).expect("Could not create output file.");
-
-                let writer = BufWriter::new(output_file);
-
-                relabel_ben_file(reader, writer).unwrap();
+                if let Err(e) = transcode_to(&args, &output_file_name, |reader, writer| {
+                    relabel_ben_file(reader, writer)
+                }) {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
                 return;
             }
 
@@ -143,8 +180,8 @@ fn main() {
             }
 
             let mut map_file_name = String::new();
-            if let Some(key) = args.key {
-                if let Some(shape) = args.shape_file {
+            if let Some(key) = &args.key {
+                if let Some(shape) = &args.shape_file {
                     logln!("Creating map file for key: {}", key);
 
                     let output_file_name = shape.trim_end_matches(".json").to_owned()
@@ -155,8 +192,8 @@ fn main() {
                     let writer = BufWriter::new(output_file);
 
                     let shape_reader =
-                        BufReader::new(File::open(&shape).expect("Could not open shape file."));
-                    let map = sort_json_file_by_key(shape_reader, writer, &key);
+                        BufReader::new(File::open(shape).expect("Could not open shape file."));
+                    let map = sort_json_file_by_key(shape_reader, writer, key);
 
                     map_file_name = shape.trim_end_matches(".json").to_owned()
                         + format!("_sorted_by_{}", key).as_str()
@@ -200,23 +237,25 @@ fn main() {
 
             let key = data["key"].as_str().unwrap();
 
-            let output_file_name = match args.output_file {
-                Some(name) => name,
+            let output_file_name = match &args.output_file {
+                Some(name) => name.to_owned(),
                 None => {
                     args.input_file.trim_end_matches(".jsonl.ben").to_owned()
                         + format!("_sorted_by_{}.jsonl.ben", key).as_str()
                 }
             };
-            let output_file =
-                File::create(&output_file_name).expect("Could not create output file.");
-            let writer = BufWriter::new(output_file);
 
             logln!(
                 "Relabeling ben file according to map file {}",
                 map_file_name,
             );
 
-            relabel_ben_file_with_map(reader, writer, new_to_old_node_map).unwrap();
+            if let Err(e) = transcode_to(&args, &output_file_name, |reader, writer| {
+                relabel_ben_file_with_map(reader, writer, new_to_old_node_map)
+            }) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
         }
     }
 }
@@ -0,0 +1,828 @@
+//! This module provides iterator adapters that can be layered on top of a
+//! `BenDecoder` (or any other iterator of `MkvRecord`s) in order to change
+//! the way that the decoded samples are handed back to the caller or which
+//! of them are kept or checked, along with a helper for running a decoder on a
+//! background thread and a decoder that walks two BEN files in lockstep.
+
+use std::io::{self, Read};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::thread;
+
+use super::{BenDecoder, MkvRecord};
+
+/// An iterator adapter that groups the samples coming out of a decoder into
+/// batches of (at most) `batch_size` expanded assignment vectors.
+///
+/// MkvChain records are expanded according to their repetition count, and
+/// runs that do not fit into the current batch are carried over into the
+/// next one, so every batch except (possibly) the last one contains exactly
+/// `batch_size` assignments.
+///
+/// # Example
+///
+/// ```
+/// use ben::decode::{adapters::BatchDecoder, BenDecoder};
+/// use ben::encode::jsonl_encode_ben;
+/// use ben::BenVariant;
+///
+/// let input = r#"{"assignment": [1,1,2,2], "sample": 1}"#.to_string()
+///     + "\n"
+///     + r#"{"assignment": [1,1,2,2], "sample": 2}"#
+///     + "\n"
+///     + r#"{"assignment": [2,2,1,1], "sample": 3}"#;
+///
+/// let mut ben = Vec::new();
+/// jsonl_encode_ben(input.as_bytes(), &mut ben, BenVariant::MkvChain).unwrap();
+///
+/// let decoder = BenDecoder::new(ben.as_slice()).unwrap();
+/// let batches = BatchDecoder::new(decoder, 2)
+///     .collect::<Result<Vec<_>, _>>()
+///     .unwrap();
+///
+/// assert_eq!(batches.len(), 2);
+/// assert_eq!(batches[0], vec![vec![1, 1, 2, 2], vec![1, 1, 2, 2]]);
+/// assert_eq!(batches[1], vec![vec![2, 2, 1, 1]]);
+/// ```
+pub struct BatchDecoder<I> {
+    inner: I,
+    batch_size: usize,
+    pending: Option<MkvRecord>,
+    error: Option<io::Error>,
+}
+
+impl<I: Iterator<Item = io::Result<MkvRecord>>> BatchDecoder<I> {
+    /// Create a new BatchDecoder wrapping the given iterator.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `batch_size` is 0.
+    pub fn new(inner: I, batch_size: usize) -> Self {
+        assert!(batch_size > 0, "Batch size must be greater than 0");
+        BatchDecoder {
+            inner,
+            batch_size,
+            pending: None,
+            error: None,
+        }
+    }
+}
+
+impl<I: Iterator<Item = io::Result<MkvRecord>>> Iterator for BatchDecoder<I> {
+    type Item = io::Result<Vec<Vec<u16>>>;
+
+    fn next(&mut self) -> Option<io::Result<Vec<Vec<u16>>>> {
+        if let Some(e) = self.error.take() {
+            return Some(Err(e));
+        }
+
+        let mut batch: Vec<Vec<u16>> = Vec::with_capacity(self.batch_size);
+
+        while batch.len() < self.batch_size {
+            let (assignment, count) = match self.pending.take() {
+                Some(record) => record,
+                None => match self.inner.next() {
+                    Some(Ok(record)) => record,
+                    Some(Err(e)) => {
+                        // Hand back whatever we have collected so far and
+                        // report the error on the following call.
+                        if batch.is_empty() {
+                            return Some(Err(e));
+                        }
+                        self.error = Some(e);
+                        break;
+                    }
+                    None => break,
+                },
+            };
+
+            let room = self.batch_size - batch.len();
+            let n_take = (count as usize).min(room);
+            for _ in 0..n_take {
+                batch.push(assignment.clone());
+            }
+
+            // Split the run across the batch boundary
+            if count as usize > n_take {
+                self.pending = Some((assignment, count - n_take as u16));
+            }
+        }
+
+        if batch.is_empty() {
+            None
+        } else {
+            Some(Ok(batch))
+        }
+    }
+}
+
+/// An iterator adapter that pairs every sample coming out of a decoder with
+/// the largest label seen in any sample up to (and including) that one.
+///
+/// This is handy for streaming consumers that want to pick the narrowest
+/// integer type that can hold the labels, and only widen it once a larger
+/// label actually shows up, without making a separate pass over the file.
+/// MkvChain records are expanded according to their repetition count, so
+/// there is exactly one item per sample.
+///
+/// # Example
+///
+/// ```
+/// use ben::decode::{adapters::MaxTrackingDecoder, BenDecoder};
+/// use ben::encode::jsonl_encode_ben;
+/// use ben::BenVariant;
+///
+/// let input = r#"{"assignment": [1,1,2,2], "sample": 1}"#.to_string()
+///     + "\n"
+///     + r#"{"assignment": [1,3,2,2], "sample": 2}"#
+///     + "\n"
+///     + r#"{"assignment": [2,2,1,1], "sample": 3}"#;
+///
+/// let mut ben = Vec::new();
+/// jsonl_encode_ben(input.as_bytes(), &mut ben, BenVariant::Standard).unwrap();
+///
+/// let decoder = BenDecoder::new(ben.as_slice()).unwrap();
+/// let maxes = MaxTrackingDecoder::new(decoder)
+///     .map(|item| item.unwrap().1)
+///     .collect::<Vec<u16>>();
+///
+/// assert_eq!(maxes, vec![2, 3, 3]);
+/// ```
+pub struct MaxTrackingDecoder<I> {
+    inner: I,
+    running_max: u16,
+    pending: Option<MkvRecord>,
+}
+
+impl<I: Iterator<Item = io::Result<MkvRecord>>> MaxTrackingDecoder<I> {
+    /// Create a new MaxTrackingDecoder wrapping the given iterator.
+    pub fn new(inner: I) -> Self {
+        MaxTrackingDecoder {
+            inner,
+            running_max: 0,
+            pending: None,
+        }
+    }
+
+    /// Returns the largest label seen so far (0 if nothing has been decoded).
+    pub fn running_max(&self) -> u16 {
+        self.running_max
+    }
+}
+
+impl<I: Iterator<Item = io::Result<MkvRecord>>> Iterator for MaxTrackingDecoder<I> {
+    type Item = io::Result<(Vec<u16>, u16)>;
+
+    fn next(&mut self) -> Option<io::Result<(Vec<u16>, u16)>> {
+        let (assignment, count) = match self.pending.take() {
+            Some(record) => record,
+            None => loop {
+                match self.inner.next()? {
+                    Ok((_, 0)) => continue,
+                    Ok((assignment, count)) => {
+                        if let Some(&max) = assignment.iter().max() {
+                            self.running_max = self.running_max.max(max);
+                        }
+                        break (assignment, count);
+                    }
+                    Err(e) => return Some(Err(e)),
+                }
+            },
+        };
+
+        if count > 1 {
+            self.pending = Some((assignment.clone(), count - 1));
+        }
+
+        Some(Ok((assignment, self.running_max)))
+    }
+}
+
+/// Expands the records of a decoder (or any other iterator of `MkvRecord`s)
+/// into one assignment vector per sample, lazily. A record with a
+/// repetition count of `n` is returned `n` times (the assignment is cloned
+/// for all but the last repetition), and errors are passed through as is.
+///
+/// # Example
+///
+/// ```
+/// use ben::decode::adapters::expand_counts;
+///
+/// let records = vec![Ok((vec![1, 2], 2)), Ok((vec![2, 1], 1))];
+/// let samples = expand_counts(records)
+///     .collect::<Result<Vec<_>, _>>()
+///     .unwrap();
+///
+/// assert_eq!(samples, vec![vec![1, 2], vec![1, 2], vec![2, 1]]);
+/// ```
+pub fn expand_counts<I>(iter: I) -> impl Iterator<Item = io::Result<Vec<u16>>>
+where
+    I: IntoIterator<Item = io::Result<MkvRecord>>,
+{
+    ExpandCounts {
+        inner: iter.into_iter(),
+        current: None,
+    }
+}
+
+struct ExpandCounts<I> {
+    inner: I,
+    // The current assignment and the number of repetitions left to return
+    current: Option<(Vec<u16>, u16)>,
+}
+
+impl<I: Iterator<Item = io::Result<MkvRecord>>> Iterator for ExpandCounts<I> {
+    type Item = io::Result<Vec<u16>>;
+
+    fn next(&mut self) -> Option<io::Result<Vec<u16>>> {
+        loop {
+            match self.current.take() {
+                Some((assignment, 1)) => return Some(Ok(assignment)),
+                Some((assignment, remaining)) if remaining > 1 => {
+                    let sample = assignment.clone();
+                    self.current = Some((assignment, remaining - 1));
+                    return Some(Ok(sample));
+                }
+                _ => match self.inner.next()? {
+                    Ok(record) => self.current = Some(record),
+                    Err(e) => return Some(Err(e)),
+                },
+            }
+        }
+    }
+}
+
+/// A selection of sample numbers (starting from 1, in the order that the
+/// samples appear in the file) used for subsampling an ensemble.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Selection {
+    /// Every `k`-th sample, i.e. samples `k, 2k, 3k, ...`.
+    Every(usize),
+    /// The samples from `start` to `end`, inclusive.
+    Range { start: usize, end: usize },
+    /// An explicit list of sample numbers. The list is expected to be
+    /// sorted; use `Selection::normalized` to sort and deduplicate it.
+    Indices(Vec<usize>),
+}
+
+impl Selection {
+    /// Sorts and deduplicates the list of an `Indices` selection. Other
+    /// selections are returned unchanged.
+    pub fn normalized(self) -> Self {
+        match self {
+            Selection::Indices(mut indices) => {
+                indices.sort_unstable();
+                indices.dedup();
+                Selection::Indices(indices)
+            }
+            other => other,
+        }
+    }
+
+    /// Whether the given sample number is part of the selection.
+    pub fn contains(&self, sample: usize) -> bool {
+        match self {
+            Selection::Every(k) => *k > 0 && sample.is_multiple_of(*k),
+            Selection::Range { start, end } => (*start..=*end).contains(&sample),
+            Selection::Indices(indices) => indices.binary_search(&sample).is_ok(),
+        }
+    }
+
+    /// The last sample number in the selection, if there is one. Nothing
+    /// past this sample needs to be read.
+    pub fn last(&self) -> Option<usize> {
+        match self {
+            Selection::Every(_) => None,
+            Selection::Range { end, .. } => Some(*end),
+            Selection::Indices(indices) => Some(indices.last().copied().unwrap_or(0)),
+        }
+    }
+
+    /// The number of samples that the selection keeps from a file with
+    /// `n_samples` samples, e.g. from the count given by
+    /// `decode::count_samples_ben`. Like `contains`, this expects the list
+    /// of an `Indices` selection to be sorted.
+    pub fn count_in(&self, n_samples: usize) -> usize {
+        match self {
+            Selection::Every(0) => 0,
+            Selection::Every(k) => n_samples / k,
+            Selection::Range { start, end } => {
+                let start = (*start).max(1);
+                let end = (*end).min(n_samples);
+                (end + 1).saturating_sub(start)
+            }
+            Selection::Indices(indices) => {
+                let mut kept = indices
+                    .iter()
+                    .filter(|&&sample| (1..=n_samples).contains(&sample))
+                    .collect::<Vec<_>>();
+                kept.dedup();
+                kept.len()
+            }
+        }
+    }
+}
+
+/// An iterator adapter that only keeps the samples in a `Selection`. The
+/// records of the inner iterator are expanded according to their
+/// repetition count, and each selected sample is returned along with its
+/// sample number in the original file. The inner iterator is not read any
+/// further once the last selected sample has been returned.
+///
+/// # Example
+///
+/// ```
+/// use ben::decode::adapters::{Selection, SubsampleDecoder};
+/// use ben::decode::BenDecoder;
+/// use ben::encode::jsonl_encode_ben;
+/// use ben::BenVariant;
+///
+/// let input = r#"{"assignment": [1,1,2,2], "sample": 1}"#.to_string()
+///     + "\n"
+///     + r#"{"assignment": [1,1,2,2], "sample": 2}"#
+///     + "\n"
+///     + r#"{"assignment": [2,2,1,1], "sample": 3}"#;
+///
+/// let mut ben = Vec::new();
+/// jsonl_encode_ben(input.as_bytes(), &mut ben, BenVariant::MkvChain).unwrap();
+///
+/// let decoder = BenDecoder::new(ben.as_slice()).unwrap();
+/// let samples = SubsampleDecoder::new(decoder, Selection::Every(2))
+///     .collect::<Result<Vec<_>, _>>()
+///     .unwrap();
+///
+/// assert_eq!(samples, vec![(2, vec![1, 1, 2, 2])]);
+/// ```
+pub struct SubsampleDecoder<I> {
+    inner: I,
+    selection: Selection,
+    last: Option<usize>,
+    // The current record and the sample number of its last repetition
+    current: Option<(Vec<u16>, usize)>,
+    next_sample: usize,
+}
+
+impl<I: Iterator<Item = io::Result<MkvRecord>>> SubsampleDecoder<I> {
+    /// Create a new SubsampleDecoder wrapping the given iterator.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the selection is `Selection::Every(0)`.
+    pub fn new(inner: I, selection: Selection) -> Self {
+        assert!(
+            selection != Selection::Every(0),
+            "Cannot select every 0th sample"
+        );
+        let selection = selection.normalized();
+        SubsampleDecoder {
+            inner,
+            last: selection.last(),
+            selection,
+            current: None,
+            next_sample: 1,
+        }
+    }
+}
+
+impl<I: Iterator<Item = io::Result<MkvRecord>>> Iterator for SubsampleDecoder<I> {
+    type Item = io::Result<(usize, Vec<u16>)>;
+
+    fn next(&mut self) -> Option<io::Result<(usize, Vec<u16>)>> {
+        loop {
+            if self.last.is_some_and(|last| self.next_sample > last) {
+                return None;
+            }
+
+            if let Some((assignment, end)) = self.current.as_ref() {
+                if self.next_sample <= *end {
+                    let sample = self.next_sample;
+                    self.next_sample += 1;
+                    if self.selection.contains(sample) {
+                        return Some(Ok((sample, assignment.clone())));
+                    }
+                    continue;
+                }
+            }
+
+            match self.inner.next()? {
+                Ok((assignment, count)) => {
+                    let end = self.next_sample + count as usize - 1;
+                    self.current = Some((assignment, end));
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+/// The comparison that a `ThresholdDecoder` uses to check a metric against
+/// its threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+}
+
+impl Comparison {
+    fn holds(self, value: f64, threshold: f64) -> bool {
+        match self {
+            Comparison::Greater => value > threshold,
+            Comparison::GreaterOrEqual => value >= threshold,
+            Comparison::Less => value < threshold,
+            Comparison::LessOrEqual => value <= threshold,
+        }
+    }
+}
+
+/// An iterator adapter that only keeps the samples for which a scalar
+/// metric (e.g. the number of cut edges) satisfies a comparison against a
+/// threshold. The metric is computed once per record, so MkvChain records
+/// are either passed through with their repetition count or skipped as a
+/// whole.
+///
+/// If `crossings_only` is set, only the records at which the metric goes
+/// from not satisfying the comparison to satisfying it are kept, which is
+/// useful for picking out the moments at which a chain first crosses the
+/// threshold.
+///
+/// # Example
+///
+/// ```
+/// use ben::decode::adapters::{Comparison, ThresholdDecoder};
+/// use ben::decode::BenDecoder;
+/// use ben::encode::jsonl_encode_ben;
+/// use ben::BenVariant;
+///
+/// let input = r#"{"assignment": [1,1,2,2], "sample": 1}"#.to_string()
+///     + "\n"
+///     + r#"{"assignment": [1,2,1,2], "sample": 2}"#;
+///
+/// let mut ben = Vec::new();
+/// jsonl_encode_ben(input.as_bytes(), &mut ben, BenVariant::Standard).unwrap();
+///
+/// // Count the number of times the label changes along the vector
+/// let changes = |a: &[u16]| a.windows(2).filter(|w| w[0] != w[1]).count() as f64;
+///
+/// let decoder = BenDecoder::new(ben.as_slice()).unwrap();
+/// let kept = ThresholdDecoder::new(decoder, changes, Comparison::Greater, 1.0)
+///     .collect::<Result<Vec<_>, _>>()
+///     .unwrap();
+///
+/// assert_eq!(kept, vec![(vec![1, 2, 1, 2], 1)]);
+/// ```
+pub struct ThresholdDecoder<I, F> {
+    inner: I,
+    metric: F,
+    comparison: Comparison,
+    threshold: f64,
+    crossings_only: bool,
+    previously_held: bool,
+}
+
+impl<I, F> ThresholdDecoder<I, F>
+where
+    I: Iterator<Item = io::Result<MkvRecord>>,
+    F: FnMut(&[u16]) -> f64,
+{
+    /// Create a new ThresholdDecoder wrapping the given iterator.
+    pub fn new(inner: I, metric: F, comparison: Comparison, threshold: f64) -> Self {
+        ThresholdDecoder {
+            inner,
+            metric,
+            comparison,
+            threshold,
+            crossings_only: false,
+            previously_held: false,
+        }
+    }
+
+    /// Only keep the records at which the comparison starts to hold.
+    pub fn crossings_only(mut self) -> Self {
+        self.crossings_only = true;
+        self
+    }
+}
+
+impl<I, F> Iterator for ThresholdDecoder<I, F>
+where
+    I: Iterator<Item = io::Result<MkvRecord>>,
+    F: FnMut(&[u16]) -> f64,
+{
+    type Item = io::Result<MkvRecord>;
+
+    fn next(&mut self) -> Option<io::Result<MkvRecord>> {
+        loop {
+            let (assignment, count) = match self.inner.next()? {
+                Ok(record) => record,
+                Err(e) => return Some(Err(e)),
+            };
+
+            let holds = self
+                .comparison
+                .holds((self.metric)(&assignment), self.threshold);
+            let previously_held = self.previously_held;
+            self.previously_held = holds;
+
+            if holds && !(self.crossings_only && previously_held) {
+                return Some(Ok((assignment, count)));
+            }
+        }
+    }
+}
+
+/// An iterator adapter that checks every record coming out of a decoder
+/// against a simple contract before handing it back: the assignment must
+/// have exactly `expected_nodes` entries and no label may be larger than
+/// `max_label`. Either check can be turned off by passing `None`.
+///
+/// Records that satisfy the contract are passed through unchanged. The
+/// first record that violates it is turned into an `InvalidData` error
+/// naming the sample number (starting from 1) at which the record starts,
+/// after which the iterator stops.
+///
+/// # Example
+///
+/// ```
+/// use ben::decode::adapters::ContractDecoder;
+/// use ben::decode::BenDecoder;
+/// use ben::encode::jsonl_encode_ben;
+/// use ben::BenVariant;
+///
+/// let input = r#"{"assignment": [1,1,2,2], "sample": 1}"#.to_string()
+///     + "\n"
+///     + r#"{"assignment": [1,1,2,5], "sample": 2}"#;
+///
+/// let mut ben = Vec::new();
+/// jsonl_encode_ben(input.as_bytes(), &mut ben, BenVariant::Standard).unwrap();
+///
+/// let decoder = BenDecoder::new(ben.as_slice()).unwrap();
+/// let mut checked = ContractDecoder::new(decoder, Some(4), Some(4));
+///
+/// assert_eq!(checked.next().unwrap().unwrap(), (vec![1, 1, 2, 2], 1));
+/// assert!(checked.next().unwrap().is_err());
+/// assert!(checked.next().is_none());
+/// ```
+pub struct ContractDecoder<I> {
+    inner: I,
+    expected_nodes: Option<usize>,
+    max_label: Option<u16>,
+    sample_number: usize,
+    failed: bool,
+}
+
+impl<I: Iterator<Item = io::Result<MkvRecord>>> ContractDecoder<I> {
+    /// Create a new ContractDecoder wrapping the given iterator.
+    pub fn new(inner: I, expected_nodes: Option<usize>, max_label: Option<u16>) -> Self {
+        ContractDecoder {
+            inner,
+            expected_nodes,
+            max_label,
+            sample_number: 0,
+            failed: false,
+        }
+    }
+
+    fn check(&self, assignment: &[u16]) -> io::Result<()> {
+        let sample = self.sample_number + 1;
+
+        if let Some(expected) = self.expected_nodes {
+            if assignment.len() != expected {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Sample {} has {} nodes, expected {}",
+                        sample,
+                        assignment.len(),
+                        expected
+                    ),
+                ));
+            }
+        }
+
+        if let Some(max_label) = self.max_label {
+            if let Some((node, &label)) = assignment
+                .iter()
+                .enumerate()
+                .find(|(_, &label)| label > max_label)
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Sample {} has label {} at node {}, which is larger than the maximum label {}",
+                        sample, label, node, max_label
+                    ),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<I: Iterator<Item = io::Result<MkvRecord>>> Iterator for ContractDecoder<I> {
+    type Item = io::Result<MkvRecord>;
+
+    fn next(&mut self) -> Option<io::Result<MkvRecord>> {
+        if self.failed {
+            return None;
+        }
+
+        let (assignment, count) = match self.inner.next()? {
+            Ok(record) => record,
+            Err(e) => {
+                self.failed = true;
+                return Some(Err(e));
+            }
+        };
+
+        if let Err(e) = self.check(&assignment) {
+            self.failed = true;
+            return Some(Err(e));
+        }
+
+        self.sample_number += count as usize;
+        Some(Ok((assignment, count)))
+    }
+}
+
+/// Decodes a BEN file on a background thread and sends the expanded
+/// assignment vectors through a bounded channel with room for `capacity`
+/// samples. Once the channel is full, the decoding thread blocks until the
+/// receiver catches up, so the memory used stays capped no matter how large
+/// the file is.
+///
+/// MkvChain frames are expanded according to their repetition count, so the
+/// receiver sees exactly one message per sample. If the file cannot be
+/// decoded, the error is sent through the channel and the thread stops. The
+/// thread also stops early if the receiver is dropped.
+///
+/// # Arguments
+///
+/// * `reader` - The reader containing the BEN file
+/// * `capacity` - The number of samples the channel can hold before the
+///   decoding thread blocks
+///
+/// # Returns
+///
+/// The receiving end of the channel
+///
+/// # Example
+///
+/// ```
+/// use ben::decode::adapters::spawn_ben_decoder;
+/// use ben::encode::jsonl_encode_ben;
+/// use ben::BenVariant;
+///
+/// let input = r#"{"assignment": [1,1,2,2], "sample": 1}"#.to_string()
+///     + "\n"
+///     + r#"{"assignment": [1,1,2,2], "sample": 2}"#;
+///
+/// let mut ben = Vec::new();
+/// jsonl_encode_ben(input.as_bytes(), &mut ben, BenVariant::MkvChain).unwrap();
+///
+/// let receiver = spawn_ben_decoder(std::io::Cursor::new(ben), 1);
+/// let samples = receiver.iter().collect::<Result<Vec<_>, _>>().unwrap();
+///
+/// assert_eq!(samples, vec![vec![1, 1, 2, 2], vec![1, 1, 2, 2]]);
+/// ```
+pub fn spawn_ben_decoder<R: Read + Send + 'static>(
+    reader: R,
+    capacity: usize,
+) -> Receiver<io::Result<Vec<u16>>> {
+    let (sender, receiver) = sync_channel(capacity);
+
+    thread::spawn(move || {
+        let decoder = match BenDecoder::new(reader) {
+            Ok(decoder) => decoder,
+            Err(e) => {
+                let _ = sender.send(Err(e.into()));
+                return;
+            }
+        };
+
+        for sample in expand_counts(decoder) {
+            let is_err = sample.is_err();
+            // The receiver hung up, so there is no point in decoding the
+            // rest of the file.
+            if sender.send(sample).is_err() || is_err {
+                return;
+            }
+        }
+    });
+
+    receiver
+}
+
+/// Decodes two BEN files in lockstep, yielding sample `i` of the first file
+/// together with sample `i` of the second one. Both files are streamed, so
+/// cross-chain statistics can be computed without loading either of them.
+/// MkvChain records are expanded, so the two files do not need to use the
+/// same variant.
+///
+/// If one file runs out of samples before the other, an `InvalidData` error
+/// naming the sample counts is returned and the iteration stops.
+///
+/// # Example
+///
+/// ```
+/// use ben::decode::adapters::ZipBenDecoder;
+/// use ben::encode::jsonl_encode_ben;
+/// use ben::BenVariant;
+///
+/// let input_a = r#"{"assignment": [1,1,2,2], "sample": 1}"#.to_string()
+///     + "\n"
+///     + r#"{"assignment": [1,1,2,2], "sample": 2}"#;
+/// let input_b = r#"{"assignment": [2,2,1,1], "sample": 1}"#.to_string()
+///     + "\n"
+///     + r#"{"assignment": [1,2,1,2], "sample": 2}"#;
+///
+/// let mut ben_a = Vec::new();
+/// jsonl_encode_ben(input_a.as_bytes(), &mut ben_a, BenVariant::MkvChain).unwrap();
+/// let mut ben_b = Vec::new();
+/// jsonl_encode_ben(input_b.as_bytes(), &mut ben_b, BenVariant::Standard).unwrap();
+///
+/// let pairs = ZipBenDecoder::new(ben_a.as_slice(), ben_b.as_slice())
+///     .unwrap()
+///     .collect::<Result<Vec<_>, _>>()
+///     .unwrap();
+///
+/// assert_eq!(pairs[1], (vec![1, 1, 2, 2], vec![1, 2, 1, 2]));
+/// ```
+pub struct ZipBenDecoder<R1: Read, R2: Read> {
+    first: ExpandCounts<BenDecoder<R1>>,
+    second: ExpandCounts<BenDecoder<R2>>,
+    n_samples: usize,
+    done: bool,
+}
+
+impl<R1: Read, R2: Read> ZipBenDecoder<R1, R2> {
+    /// Create a new ZipBenDecoder reading from the two given BEN files.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if either reader does not contain a BEN file.
+    pub fn new(first: R1, second: R2) -> io::Result<Self> {
+        Ok(Self::from_decoders(
+            BenDecoder::new(first)?,
+            BenDecoder::new(second)?,
+        ))
+    }
+
+    /// Create a new ZipBenDecoder from two decoders that have already been
+    /// set up (e.g. with `BenDecoder::builder`).
+    pub fn from_decoders(first: BenDecoder<R1>, second: BenDecoder<R2>) -> Self {
+        ZipBenDecoder {
+            first: ExpandCounts {
+                inner: first,
+                current: None,
+            },
+            second: ExpandCounts {
+                inner: second,
+                current: None,
+            },
+            n_samples: 0,
+            done: false,
+        }
+    }
+}
+
+impl<R1: Read, R2: Read> Iterator for ZipBenDecoder<R1, R2> {
+    type Item = io::Result<(Vec<u16>, Vec<u16>)>;
+
+    fn next(&mut self) -> Option<io::Result<(Vec<u16>, Vec<u16>)>> {
+        if self.done {
+            return None;
+        }
+
+        let result = match (self.first.next(), self.second.next()) {
+            (None, None) => None,
+            (Some(Ok(first)), Some(Ok(second))) => {
+                self.n_samples += 1;
+                return Some(Ok((first, second)));
+            }
+            (Some(Err(e)), _) | (_, Some(Err(e))) => Some(Err(e)),
+            (Some(Ok(_)), None) => Some(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "The first file has more samples than the second file, which has {}",
+                    self.n_samples
+                ),
+            ))),
+            (None, Some(Ok(_))) => Some(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "The second file has more samples than the first file, which has {}",
+                    self.n_samples
+                ),
+            ))),
+        };
+
+        self.done = true;
+        result
+    }
+}
+
+#[cfg(test)]
+#[path = "tests/adapters_tests.rs"]
+mod tests;
@@ -0,0 +1,936 @@
+//! This module provides functions for computing summary statistics over the
+//! samples contained in a BEN file without needing to first decode the file
+//! into JSONL.
+
+use byteorder::{BigEndian, ReadBytesExt};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Error, Read, Seek};
+
+use super::adapters::expand_counts;
+use super::index::{decode_frame_at, read_ben_variant, FrameIndex};
+use super::{decode_ben_line, BenDecoder, BenRleDecoder};
+use crate::BenVariant;
+
+/// Computes the number of positions at which two assignment vectors differ.
+fn hamming_distance(a: &[u16], b: &[u16]) -> io::Result<u32> {
+    if a.len() != b.len() {
+        return Err(Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Cannot compare assignments of different lengths ({} and {})",
+                a.len(),
+                b.len()
+            ),
+        ));
+    }
+    Ok(a.iter().zip(b.iter()).filter(|(x, y)| x != y).count() as u32)
+}
+
+/// Computes the pairwise Hamming distances between the samples of a BEN file
+/// and returns them as a condensed (upper-triangular, row-major) distance
+/// matrix. That is, for `n` samples the output has length `n * (n - 1) / 2`
+/// and the distance between the `i`-th and `j`-th samples (with `i < j`) is
+/// found at position `n * i - i * (i + 1) / 2 + (j - i - 1)`.
+///
+/// Since this is quadratic in the number of samples, the samples are not
+/// held in memory. Instead, the reader is required to implement `Seek` so
+/// that each sample can be decoded again when it is needed.
+///
+/// # Arguments
+///
+/// * `reader` - A seekable reader containing the BEN file
+/// * `indices` - An optional list of sample numbers (starting from 1) to
+///   restrict the comparison to. If `None`, every sample in the file is used.
+///
+/// # Returns
+///
+/// A vector containing the condensed distance matrix
+///
+/// # Errors
+///
+/// This function will return an error if the file is not a valid BEN file,
+/// if one of the requested sample numbers is not in the file, or if two of
+/// the samples have a different number of nodes.
+pub fn pairwise_hamming<R: Read + Seek>(
+    mut reader: R,
+    indices: Option<&[usize]>,
+) -> io::Result<Vec<u32>> {
+    let index = FrameIndex::build(&mut reader)?;
+    let offsets = index.offsets();
+
+    let selected: Vec<usize> = match indices {
+        Some(indices) => indices.to_vec(),
+        None => (1..=index.n_samples()).collect(),
+    };
+
+    let mut frames = Vec::with_capacity(selected.len());
+    for &sample in selected.iter() {
+        match index.frame_of(sample) {
+            Some(frame) => frames.push(frame),
+            None => {
+                return Err(Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "Sample number {} not found in file. Last sample is {}",
+                        sample,
+                        index.n_samples()
+                    ),
+                ))
+            }
+        }
+    }
+
+    let n = selected.len();
+    let mut distances = Vec::with_capacity(n * n.saturating_sub(1) / 2);
+
+    for i in 0..n {
+        let outer = decode_frame_at(&mut reader, offsets[frames[i]])?;
+        let mut inner: Option<(usize, Vec<u16>)> = None;
+
+        for j in (i + 1)..n {
+            if frames[j] == frames[i] {
+                distances.push(0);
+                continue;
+            }
+
+            // Consecutive selections frequently land in the same frame, so
+            // only decode when the frame changes.
+            if inner.as_ref().map(|(frame, _)| *frame) != Some(frames[j]) {
+                inner = Some((frames[j], decode_frame_at(&mut reader, offsets[frames[j]])?));
+            }
+
+            let (_, other) = inner.as_ref().unwrap();
+            distances.push(hamming_distance(&outer, other)?);
+        }
+    }
+
+    Ok(distances)
+}
+
+/// Finds the sample of a BEN file with the smallest Hamming distance to a
+/// query assignment (e.g. a proposed map), streaming through the file once.
+/// Ties go to the earliest sample, and the scan stops as soon as a sample
+/// equal to the query is found.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the BEN file
+/// * `query` - The assignment vector to compare the samples against
+///
+/// # Returns
+///
+/// The sample number (starting from 1) of the closest sample and its
+/// distance to the query
+///
+/// # Errors
+///
+/// This function will return an `InvalidInput` error if the query does not
+/// have the same number of nodes as the samples, an `InvalidData` error if
+/// the file has no samples, or an error if the BEN file cannot be decoded.
+pub fn nearest_sample<R: Read>(reader: R, query: &[u16]) -> io::Result<(usize, u32)> {
+    let mut nearest: Option<(usize, u32)> = None;
+    let mut sample = 1;
+
+    for record in BenDecoder::new(reader)? {
+        let (assignment, count) = record?;
+        if assignment.len() != query.len() {
+            return Err(Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "The query has {} nodes but sample {} has {}",
+                    query.len(),
+                    sample,
+                    assignment.len()
+                ),
+            ));
+        }
+
+        let distance = hamming_distance(&assignment, query)?;
+        if nearest.is_none_or(|(_, best)| distance < best) {
+            nearest = Some((sample, distance));
+            if distance == 0 {
+                break;
+            }
+        }
+        sample += count as usize;
+    }
+
+    nearest.ok_or_else(|| Error::new(io::ErrorKind::InvalidData, "The BEN file has no samples"))
+}
+
+/// Histograms describing how the runs of a BEN file are laid out. All of
+/// the histograms are computed over the frames as they are stored in the
+/// file, so repeated MkvChain samples are only counted once.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunStats {
+    /// The number of frames in the file.
+    pub n_frames: usize,
+    /// The number of samples in the file (including MkvChain repetitions).
+    pub n_samples: usize,
+    /// The number of runs of each length.
+    pub run_lengths: BTreeMap<u16, u64>,
+    /// The number of runs with each value.
+    pub run_values: BTreeMap<u16, u64>,
+    /// The number of frames using each bit width for the run values.
+    pub max_val_bits: BTreeMap<u8, u64>,
+    /// The number of frames using each bit width for the run lengths.
+    pub max_len_bits: BTreeMap<u8, u64>,
+}
+
+/// Computes histograms of the run lengths, run values, and frame bit widths
+/// of a BEN file. This is useful for understanding why a file compresses
+/// the way that it does and whether relabeling the file would help.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the BEN file
+///
+/// # Returns
+///
+/// The `RunStats` of the file
+///
+/// # Errors
+///
+/// This function will return an error if the file is not a valid Standard
+/// or MkvChain BEN file.
+pub fn run_stats<R: Read>(mut reader: R) -> io::Result<RunStats> {
+    let variant = read_ben_variant(&mut reader)?;
+    let mut stats = RunStats::default();
+
+    loop {
+        let mut tmp_buffer = [0u8];
+        match reader.read_exact(&mut tmp_buffer) {
+            Ok(()) => {}
+            Err(e) => {
+                if e.kind() == io::ErrorKind::UnexpectedEof {
+                    break;
+                }
+                return Err(e);
+            }
+        }
+        let max_val_bits = tmp_buffer[0];
+        let max_len_bits = reader.read_u8()?;
+        let n_bytes = reader.read_u32::<BigEndian>()?;
+
+        let rle = decode_ben_line(&mut reader, max_val_bits, max_len_bits, n_bytes)?;
+
+        let count = if variant == BenVariant::MkvChain {
+            reader.read_u16::<BigEndian>()?
+        } else {
+            1
+        };
+
+        stats.n_frames += 1;
+        stats.n_samples += count as usize;
+        *stats.max_val_bits.entry(max_val_bits).or_insert(0) += 1;
+        *stats.max_len_bits.entry(max_len_bits).or_insert(0) += 1;
+        for (value, length) in rle {
+            *stats.run_values.entry(value).or_insert(0) += 1;
+            *stats.run_lengths.entry(length).or_insert(0) += 1;
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Finds the boundary nodes of every sample in a BEN file. A node is on the
+/// boundary if at least one of its neighbors is assigned to a different
+/// district.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the BEN file
+/// * `adjacency` - The adjacency list of the dual graph, where
+///   `adjacency[i]` contains the indices of the neighbors of node `i`
+///
+/// # Returns
+///
+/// A vector containing the sorted boundary node indices of each sample.
+/// MkvChain samples are expanded, so there is one entry per sample.
+///
+/// # Errors
+///
+/// This function will return an error if the BEN file cannot be decoded,
+/// if a sample does not have one entry per node in the adjacency list, or
+/// if the adjacency list refers to a node that does not exist.
+pub fn boundary_nodes<R: Read>(reader: R, adjacency: &[Vec<u32>]) -> io::Result<Vec<Vec<usize>>> {
+    let mut indptr = Vec::with_capacity(adjacency.len() + 1);
+    let mut indices = Vec::new();
+
+    indptr.push(0);
+    for neighbors in adjacency {
+        indices.extend_from_slice(neighbors);
+        indptr.push(indices.len());
+    }
+
+    boundary_nodes_csr(reader, &indptr, &indices)
+}
+
+/// Same as `boundary_nodes`, but takes the adjacency of the dual graph as
+/// CSR-style arrays (the layout used by `scipy.sparse`), so that it can be
+/// called directly with numpy arrays. The neighbors of node `i` are
+/// `indices[indptr[i]..indptr[i + 1]]`.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the BEN file
+/// * `indptr` - The offsets of the neighbors of each node in `indices`.
+///   This should have one more entry than there are nodes.
+/// * `indices` - The concatenated neighbor lists of all of the nodes
+///
+/// # Returns
+///
+/// A vector containing the sorted boundary node indices of each sample
+///
+/// # Errors
+///
+/// This function will return an error if the BEN file cannot be decoded,
+/// if the CSR arrays are malformed, or if they do not match the number of
+/// nodes in the samples.
+pub fn boundary_nodes_csr<R: Read>(
+    reader: R,
+    indptr: &[usize],
+    indices: &[u32],
+) -> io::Result<Vec<Vec<usize>>> {
+    if indptr.is_empty()
+        || indptr[0] != 0
+        || indptr.windows(2).any(|w| w[0] > w[1])
+        || indptr[indptr.len() - 1] != indices.len()
+    {
+        return Err(Error::new(
+            io::ErrorKind::InvalidInput,
+            "Invalid CSR adjacency: indptr must start at 0, be non-decreasing, \
+            and end at the length of indices",
+        ));
+    }
+
+    let n_nodes = indptr.len() - 1;
+    if let Some(&bad) = indices.iter().find(|&&j| j as usize >= n_nodes) {
+        return Err(Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Invalid CSR adjacency: neighbor {} is out of range for {} nodes",
+                bad, n_nodes
+            ),
+        ));
+    }
+
+    let decoder = BenDecoder::new(reader)?;
+    let mut boundaries = Vec::new();
+
+    for record in decoder {
+        let (assignment, count) = record?;
+        if assignment.len() != n_nodes {
+            return Err(Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Sample {} has {} nodes, but the adjacency has {} nodes",
+                    boundaries.len() + 1,
+                    assignment.len(),
+                    n_nodes
+                ),
+            ));
+        }
+
+        let boundary = (0..n_nodes)
+            .filter(|&i| {
+                indices[indptr[i]..indptr[i + 1]]
+                    .iter()
+                    .any(|&j| assignment[j as usize] != assignment[i])
+            })
+            .collect::<Vec<usize>>();
+
+        for _ in 1..count {
+            boundaries.push(boundary.clone());
+        }
+        boundaries.push(boundary);
+    }
+
+    Ok(boundaries)
+}
+
+/// Computes the Shannon entropy (in bits) of the district label of every
+/// node across all of the samples in a BEN file. Nodes that are always
+/// assigned to the same district have an entropy of 0, while "swing" nodes
+/// that move between districts have a higher entropy. MkvChain samples are
+/// weighted by their repetition count.
+///
+/// The label frequencies are accumulated in a single pass, which uses
+/// memory proportional to `n_nodes` times the largest label seen in the
+/// file. Use `node_entropy_capped` to put a bound on this.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the BEN file
+/// * `n_nodes` - The number of nodes in each sample
+///
+/// # Returns
+///
+/// A vector containing the entropy of each node
+///
+/// # Errors
+///
+/// This function will return an error if the BEN file cannot be decoded or
+/// if one of the samples does not have `n_nodes` nodes.
+pub fn node_entropy<R: Read>(reader: R, n_nodes: usize) -> io::Result<Vec<f64>> {
+    node_entropy_capped(reader, n_nodes, None)
+}
+
+/// Same as `node_entropy`, but allows for capping the range of labels that
+/// are counted so that the memory used is at most proportional to
+/// `n_nodes * (max_label + 1)`.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the BEN file
+/// * `n_nodes` - The number of nodes in each sample
+/// * `max_label` - The largest label that is expected in the file. If
+///   `None`, the label range grows as needed.
+///
+/// # Returns
+///
+/// A vector containing the entropy of each node
+///
+/// # Errors
+///
+/// This function will return an error if the BEN file cannot be decoded, if
+/// one of the samples does not have `n_nodes` nodes, or if a sample contains
+/// a label larger than `max_label`.
+pub fn node_entropy_capped<R: Read>(
+    reader: R,
+    n_nodes: usize,
+    max_label: Option<u16>,
+) -> io::Result<Vec<f64>> {
+    let decoder = BenDecoder::new(reader)?;
+
+    // counts[label][node] is the number of samples in which the node has
+    // the given label.
+    let mut counts: Vec<Vec<u64>> = Vec::new();
+    let mut total: u64 = 0;
+
+    for record in decoder {
+        let (assignment, count) = record?;
+        if assignment.len() != n_nodes {
+            return Err(Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Expected {} nodes in sample {}, but found {}",
+                    n_nodes,
+                    total + 1,
+                    assignment.len()
+                ),
+            ));
+        }
+
+        for (node, &label) in assignment.iter().enumerate() {
+            if let Some(max_label) = max_label {
+                if label > max_label {
+                    return Err(Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "Label {} in sample {} is larger than the maximum label {}",
+                            label,
+                            total + 1,
+                            max_label
+                        ),
+                    ));
+                }
+            }
+            if label as usize >= counts.len() {
+                counts.resize(label as usize + 1, Vec::new());
+            }
+            if counts[label as usize].is_empty() {
+                counts[label as usize] = vec![0; n_nodes];
+            }
+            counts[label as usize][node] += count as u64;
+        }
+        total += count as u64;
+    }
+
+    let mut entropy = vec![0.0; n_nodes];
+    if total == 0 {
+        return Ok(entropy);
+    }
+
+    for label_counts in counts.iter().filter(|c| !c.is_empty()) {
+        for (node, &c) in label_counts.iter().enumerate() {
+            if c > 0 {
+                let p = c as f64 / total as f64;
+                entropy[node] -= p * p.log2();
+            }
+        }
+    }
+
+    Ok(entropy)
+}
+
+/// Counts the number of times that the label of each node changes between
+/// consecutive samples of a BEN file. This is a cheap diagnostic of how well
+/// a chain is mixing: nodes that never flip were never explored. MkvChain
+/// repetitions are consecutive copies of the same sample, so they do not
+/// add any flips.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the BEN file
+/// * `n_nodes` - The number of nodes in each sample
+///
+/// # Returns
+///
+/// A vector containing the number of flips of each node
+///
+/// # Errors
+///
+/// This function will return an error if the BEN file cannot be decoded or
+/// if one of the samples does not have `n_nodes` nodes.
+pub fn node_flip_counts<R: Read>(reader: R, n_nodes: usize) -> io::Result<Vec<u64>> {
+    let decoder = BenDecoder::new(reader)?;
+
+    let mut flips = vec![0u64; n_nodes];
+    let mut previous: Option<Vec<u16>> = None;
+    let mut n_samples = 0;
+
+    for record in decoder {
+        let (assignment, count) = record?;
+        if assignment.len() != n_nodes {
+            return Err(Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Expected {} nodes in sample {}, but found {}",
+                    n_nodes,
+                    n_samples + 1,
+                    assignment.len()
+                ),
+            ));
+        }
+
+        if let Some(previous) = previous.as_ref() {
+            for (node, (new, old)) in assignment.iter().zip(previous.iter()).enumerate() {
+                if new != old {
+                    flips[node] += 1;
+                }
+            }
+        }
+
+        n_samples += count as usize;
+        previous = Some(assignment);
+    }
+
+    Ok(flips)
+}
+
+/// Hashes the contents of an assignment vector.
+fn assignment_hash(assignment: &[u16]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    assignment.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Finds the full assignment that appears most often in a BEN file along
+/// with the number of times that it appears. Unlike a per-node majority
+/// vote, the result is always one of the samples in the file. MkvChain
+/// records are weighted by their repetition count.
+///
+/// The samples are tallied by a 64-bit hash of their contents, so only the
+/// current leader is kept in memory rather than every distinct assignment.
+/// If several assignments are tied, the one that reached the top count
+/// first is returned.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the BEN file
+///
+/// # Returns
+///
+/// A tuple containing the most frequent assignment and its count
+///
+/// # Errors
+///
+/// This function will return an error if the BEN file cannot be decoded or
+/// if it does not contain any samples.
+pub fn most_frequent_assignment<R: Read>(reader: R) -> io::Result<(Vec<u16>, u64)> {
+    let decoder = BenDecoder::new(reader)?;
+
+    let mut tallies: HashMap<u64, u64> = HashMap::new();
+    let mut best: Option<(Vec<u16>, u64, u64)> = None;
+
+    for record in decoder {
+        let (assignment, count) = record?;
+
+        let hash = assignment_hash(&assignment);
+
+        let tally = tallies.entry(hash).or_insert(0);
+        *tally += count as u64;
+        let tally = *tally;
+
+        match best.as_mut() {
+            Some((_, best_hash, best_count)) if *best_hash == hash => *best_count = tally,
+            Some((_, _, best_count)) if *best_count >= tally => {}
+            _ => best = Some((assignment, hash, tally)),
+        }
+    }
+
+    match best {
+        Some((assignment, _, count)) => Ok((assignment, count)),
+        None => Err(Error::new(
+            io::ErrorKind::InvalidData,
+            "Cannot find the most frequent assignment of a file with no samples",
+        )),
+    }
+}
+
+/// The error returned when a file holds more distinct samples than allowed.
+fn too_many_distinct(max_distinct: usize) -> Error {
+    Error::new(
+        io::ErrorKind::InvalidData,
+        format!("File contains more than {} distinct samples", max_distinct),
+    )
+}
+
+/// Collects every distinct assignment in a BEN file along with the number
+/// of times that it appears anywhere in the file (not just consecutively).
+/// MkvChain records are weighted by their repetition count. The assignments
+/// are returned in the order that they first appear.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the BEN file
+///
+/// # Returns
+///
+/// A vector of `(assignment, frequency)` pairs
+///
+/// # Errors
+///
+/// This function will return an error if the BEN file cannot be decoded.
+pub fn unique_samples_with_counts<R: Read>(reader: R) -> io::Result<Vec<(Vec<u16>, u64)>> {
+    unique_samples_with_counts_capped(reader, None)
+}
+
+/// Same as `unique_samples_with_counts`, but returns an error as soon as
+/// more than `max_distinct` distinct assignments have been found (if a cap
+/// is given), so that memory use stays bounded on files that turn out to be
+/// mostly unique.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the BEN file
+/// * `max_distinct` - The largest number of distinct assignments to keep
+///
+/// # Returns
+///
+/// A vector of `(assignment, frequency)` pairs
+///
+/// # Errors
+///
+/// This function will return an error if the BEN file cannot be decoded or
+/// if it contains more than `max_distinct` distinct assignments.
+pub fn unique_samples_with_counts_capped<R: Read>(
+    reader: R,
+    max_distinct: Option<usize>,
+) -> io::Result<Vec<(Vec<u16>, u64)>> {
+    let decoder = BenDecoder::new(reader)?;
+
+    let mut positions: HashMap<Vec<u16>, usize> = HashMap::new();
+    let mut unique: Vec<(Vec<u16>, u64)> = Vec::new();
+
+    for record in decoder {
+        let (assignment, count) = record?;
+        match positions.get(&assignment) {
+            Some(&position) => unique[position].1 += count as u64,
+            None => {
+                if let Some(max) = max_distinct.filter(|&max| unique.len() >= max) {
+                    return Err(too_many_distinct(max));
+                }
+                positions.insert(assignment.clone(), unique.len());
+                unique.push((assignment, count as u64));
+            }
+        }
+    }
+
+    Ok(unique)
+}
+
+/// Same as `unique_samples_with_counts`, but only a 64-bit hash of each
+/// distinct assignment is kept rather than the assignment itself, which uses
+/// far less memory on large graphs. The hashes are returned in the order
+/// that the assignments first appear.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the BEN file
+/// * `max_distinct` - An optional largest number of distinct hashes to keep
+///
+/// # Returns
+///
+/// A vector of `(hash, frequency)` pairs
+///
+/// # Errors
+///
+/// This function will return an error if the BEN file cannot be decoded or
+/// if it contains more than `max_distinct` distinct assignments.
+pub fn unique_sample_hashes_with_counts<R: Read>(
+    reader: R,
+    max_distinct: Option<usize>,
+) -> io::Result<Vec<(u64, u64)>> {
+    let decoder = BenDecoder::new(reader)?;
+
+    let mut positions: HashMap<u64, usize> = HashMap::new();
+    let mut unique: Vec<(u64, u64)> = Vec::new();
+
+    for record in decoder {
+        let (assignment, count) = record?;
+        let hash = assignment_hash(&assignment);
+        match positions.get(&hash) {
+            Some(&position) => unique[position].1 += count as u64,
+            None => {
+                if let Some(max) = max_distinct.filter(|&max| unique.len() >= max) {
+                    return Err(too_many_distinct(max));
+                }
+                positions.insert(hash, unique.len());
+                unique.push((hash, count as u64));
+            }
+        }
+    }
+
+    Ok(unique)
+}
+
+/// Collects the 64-bit content hashes of the distinct assignments in a BEN
+/// file.
+fn distinct_sample_hashes<R: Read>(reader: R) -> io::Result<HashSet<u64>> {
+    let mut hashes = HashSet::new();
+    for record in BenDecoder::new(reader)? {
+        let (assignment, _count) = record?;
+        hashes.insert(assignment_hash(&assignment));
+    }
+    Ok(hashes)
+}
+
+/// Compares the distinct assignments of two BEN files, e.g. to see how much
+/// two sampling runs overlap. Repeated samples are only counted once, and
+/// only a 64-bit hash of each distinct assignment is kept, so memory use
+/// does not depend on the size of the graph.
+///
+/// # Arguments
+///
+/// * `a` - A reader containing the first BEN file
+/// * `b` - A reader containing the second BEN file
+///
+/// # Returns
+///
+/// A tuple containing the number of distinct assignments that are only in
+/// `a`, only in `b`, and in both
+///
+/// # Errors
+///
+/// This function will return an error if either BEN file cannot be decoded.
+pub fn ensemble_set_diff<R1: Read, R2: Read>(a: R1, b: R2) -> io::Result<(u64, u64, u64)> {
+    let a_hashes = distinct_sample_hashes(a)?;
+    let b_hashes = distinct_sample_hashes(b)?;
+
+    let shared = a_hashes.intersection(&b_hashes).count() as u64;
+    Ok((
+        a_hashes.len() as u64 - shared,
+        b_hashes.len() as u64 - shared,
+        shared,
+    ))
+}
+
+/// Collects the set of labels that appear anywhere in a BEN file, e.g. to
+/// find that an ensemble uses districts 1 to 13 before allocating
+/// per-district arrays. Only the values of the runs are looked at, so the
+/// assignment vectors are never expanded.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the BEN file
+///
+/// # Returns
+///
+/// The set of distinct labels in the file
+///
+/// # Errors
+///
+/// This function will return an error if the BEN file cannot be decoded.
+pub fn distinct_labels<R: Read>(reader: R) -> io::Result<BTreeSet<u16>> {
+    let mut labels = BTreeSet::new();
+    for record in BenRleDecoder::new(reader)? {
+        let (rle, _count) = record?;
+        labels.extend(rle.iter().map(|&(val, _len)| val));
+    }
+    Ok(labels)
+}
+
+/// Counts the number of nodes assigned to each district in every sample of
+/// a BEN file. This is a lightweight balance check that works directly on
+/// the runs of each frame, so the assignment vectors are never expanded.
+///
+/// The counts of each sample are indexed by label, so the vectors have
+/// `n_districts + 1` entries in order to accommodate both 0-based and
+/// 1-based labels (with 1-based labels, the first entry is always 0).
+/// MkvChain frames are expanded, so there is one vector per sample.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the BEN file
+/// * `n_districts` - The largest label that may appear in the file
+///
+/// # Returns
+///
+/// The node counts of each district for each sample
+///
+/// # Errors
+///
+/// This function will return an `InvalidData` error if a label is larger
+/// than `n_districts`, or an error if the BEN file cannot be decoded.
+pub fn district_node_counts<R: Read>(reader: R, n_districts: u16) -> io::Result<Vec<Vec<u32>>> {
+    let mut all_counts = Vec::new();
+
+    for record in BenRleDecoder::new(reader)? {
+        let (rle, count) = record?;
+
+        let mut counts = vec![0u32; n_districts as usize + 1];
+        for (val, len) in rle {
+            match counts.get_mut(val as usize) {
+                Some(total) => *total += len as u32,
+                None => {
+                    return Err(Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "Sample {} has label {} but there are only {} districts",
+                            all_counts.len() + 1,
+                            val,
+                            n_districts
+                        ),
+                    ));
+                }
+            }
+        }
+
+        for _ in 0..count {
+            all_counts.push(counts.clone());
+        }
+    }
+
+    Ok(all_counts)
+}
+
+/// Summary statistics of a BEN file that can be used to sanity check it
+/// without decoding it into JSONL.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BenStats {
+    /// The number of samples in the file (including MkvChain repetitions).
+    pub n_samples: usize,
+    /// The number of frames stored in the file. This is smaller than the
+    /// number of samples when MkvChain repetitions are used.
+    pub n_frames: usize,
+    /// The largest assignment label seen in any sample.
+    pub max_label: u16,
+    /// The longest run of a single label seen in any sample.
+    pub max_run_length: u16,
+    /// The number of nodes in every assignment, or `None` if the file has
+    /// no samples.
+    pub n_nodes: Option<usize>,
+}
+
+/// Scans a BEN file and computes its `BenStats`. The frames are only
+/// decoded into their runs, so the full assignment vectors are never built
+/// (except when undoing the deltas of a delta file).
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the BEN file
+///
+/// # Returns
+///
+/// The `BenStats` of the file
+///
+/// # Errors
+///
+/// This function will return an error if the BEN file cannot be decoded or
+/// if its samples do not all have the same number of nodes.
+pub fn scan_ben_stats<R: Read>(reader: R) -> io::Result<BenStats> {
+    let mut stats = BenStats::default();
+
+    for record in BenRleDecoder::new(reader)? {
+        let (rle, count) = record?;
+
+        let n_nodes: usize = rle.iter().map(|&(_val, len)| len as usize).sum();
+        match stats.n_nodes {
+            Some(expected) if expected != n_nodes => {
+                return Err(Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Sample {} has {} nodes but the previous samples have {}",
+                        stats.n_samples + 1,
+                        n_nodes,
+                        expected
+                    ),
+                ));
+            }
+            _ => stats.n_nodes = Some(n_nodes),
+        }
+
+        for &(val, len) in rle.iter() {
+            stats.max_label = stats.max_label.max(val);
+            stats.max_run_length = stats.max_run_length.max(len);
+        }
+        stats.n_frames += 1;
+        stats.n_samples += count as usize;
+    }
+
+    Ok(stats)
+}
+
+/// Slides a window over the samples of a BEN file and calls `f` with the
+/// last `window` samples (oldest first) every time the window is full, i.e.
+/// once for samples `1..=window`, once for `2..=window + 1`, and so on. This
+/// is a streaming building block for lagged statistics such as the
+/// autocorrelation of a chain: only `window` samples are held in memory at
+/// any time.
+///
+/// MkvChain frames are expanded, so repeated samples fill several slots of
+/// the window.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the BEN file
+/// * `window` - The number of samples in each window
+/// * `f` - A function called on each full window
+///
+/// # Errors
+///
+/// This function will return an `InvalidInput` error if `window` is 0 and an
+/// error if the BEN file cannot be decoded.
+pub fn windowed_samples<R, F>(reader: R, window: usize, mut f: F) -> io::Result<()>
+where
+    R: Read,
+    F: FnMut(&[Vec<u16>]),
+{
+    if window == 0 {
+        return Err(Error::new(
+            io::ErrorKind::InvalidInput,
+            "The window must contain at least one sample",
+        ));
+    }
+
+    let mut samples: VecDeque<Vec<u16>> = VecDeque::with_capacity(window);
+    for assignment in expand_counts(BenDecoder::new(reader)?) {
+        if samples.len() == window {
+            samples.pop_front();
+        }
+        samples.push_back(assignment?);
+        if samples.len() == window {
+            f(samples.make_contiguous());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+#[path = "tests/analysis_tests.rs"]
+mod tests;
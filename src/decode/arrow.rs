@@ -0,0 +1,84 @@
+//! This module provides a way to decode BEN files into Arrow `RecordBatch`es
+//! so that ensembles can be handed to Arrow-based tools (DataFusion, Polars,
+//! the Arrow IPC writers, etc.) without going through JSONL first.
+//!
+//! This module is only available with the `arrow` feature.
+
+use arrow_array::{types::UInt16Type, ArrayRef, ListArray, RecordBatch, UInt64Array};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use std::io::{self, Read};
+use std::sync::Arc;
+
+use super::adapters::BatchDecoder;
+use super::BenDecoder;
+
+/// The schema of the record batches produced by `decode_ben_to_arrow`: a
+/// non-null `sample` column with the sample number (starting from 1) and an
+/// `assignment` column holding the assignment vector as a `list<uint16>`.
+pub fn arrow_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("sample", DataType::UInt64, false),
+        Field::new(
+            "assignment",
+            DataType::List(Arc::new(Field::new_list_field(DataType::UInt16, true))),
+            false,
+        ),
+    ]))
+}
+
+/// Decodes a BEN file into Arrow record batches of (at most) `batch_size`
+/// samples each. MkvChain samples are expanded, so every row of a batch is a
+/// single sample. See `arrow_schema` for the layout of the batches.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the BEN file
+/// * `batch_size` - The maximum number of samples in each record batch
+///
+/// # Returns
+///
+/// An iterator over the record batches
+///
+/// # Errors
+///
+/// This function will return an error if the banner of the BEN file is
+/// invalid. Errors that come up while decoding the frames are returned by
+/// the iterator.
+///
+/// # Panics
+///
+/// Panics if `batch_size` is 0.
+pub fn decode_ben_to_arrow<R: Read>(
+    reader: R,
+    batch_size: usize,
+) -> io::Result<impl Iterator<Item = io::Result<RecordBatch>>> {
+    let decoder = BenDecoder::new(reader)?;
+    let schema = arrow_schema();
+    let mut next_sample: u64 = 1;
+
+    Ok(BatchDecoder::new(decoder, batch_size).map(move |batch| {
+        let batch = batch?;
+
+        let samples = UInt64Array::from_iter_values(next_sample..next_sample + batch.len() as u64);
+        next_sample += batch.len() as u64;
+
+        let assignments = ListArray::from_iter_primitive::<UInt16Type, _, _>(
+            batch
+                .into_iter()
+                .map(|assignment| Some(assignment.into_iter().map(Some))),
+        );
+
+        RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(samples) as ArrayRef,
+                Arc::new(assignments) as ArrayRef,
+            ],
+        )
+        .map_err(io::Error::other)
+    }))
+}
+
+#[cfg(test)]
+#[path = "tests/arrow_tests.rs"]
+mod tests;
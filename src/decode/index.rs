@@ -0,0 +1,627 @@
+//! This module provides tools for random access into seekable BEN files.
+//!
+//! A `FrameIndex` records the byte offset of every frame in a BEN file along
+//! with the number of samples stored up to and including that frame, so
+//! that any sample can be decoded by seeking directly to the frame that
+//! contains it rather than scanning the file from the start. A `BenIndex`
+//! bundles a `FrameIndex` with the reader it was built from for convenient
+//! random access.
+//!
+//! An index can be saved to a sidecar `.benidx` file so that it does not have
+//! to be rebuilt every time the BEN file is opened. The layout of the file is
+//!
+//! ```text
+//! b"BENIDX" | version (u8) | variant (u8) | n_frames (u64)
+//!           | frame offsets (n_frames x u64)
+//!           | repetition counts (n_frames x u16, MkvChain only)
+//! ```
+//!
+//! with all integers stored big-endian.
+//!
+//! Together with a saved index, `IndexedBenReader` gives random access to BEN
+//! files that can only be read by offset (e.g. over HTTP range requests)
+//! through the `ReadAt` trait.
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{self, Error, Read, Seek, SeekFrom, Write};
+
+use super::decode_ben_line;
+use crate::utils::rle_to_vec;
+use crate::BenVariant;
+
+/// Reads the 17 byte banner at the start of a BEN file and returns the
+/// variant that it describes. Files with frame checksums are rejected with
+/// an error that names their banner, since the tools built on top of this
+/// read the frames directly and do not know about the checksums.
+pub(crate) fn read_ben_variant<R: Read>(reader: &mut R) -> io::Result<BenVariant> {
+    let mut check_buffer = [0u8; 17];
+    reader.read_exact(&mut check_buffer)?;
+
+    match &check_buffer {
+        b"STANDARD BEN FILE" => Ok(BenVariant::Standard),
+        b"MKVCHAIN BEN FILE" => Ok(BenVariant::MkvChain),
+        b"STANDARD BEN FCRC" | b"MKVCHAIN BEN FCRC" | b"MKVDELTA BEN FCRC" => Err(Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Invalid file format: files with frame checksums ({}) are not supported \
+                here, decode them with BenDecoder instead",
+                String::from_utf8_lossy(&check_buffer)
+            ),
+        )),
+        _ => Err(Error::new(
+            io::ErrorKind::InvalidData,
+            "Invalid file format",
+        )),
+    }
+}
+
+/// Seeks to the frame starting at `offset` and decodes its assignment vector.
+pub(crate) fn decode_frame_at<R: Read + Seek>(reader: &mut R, offset: u64) -> io::Result<Vec<u16>> {
+    Ok(rle_to_vec(read_frame_layout_at(reader, offset)?.runs))
+}
+
+/// Seeks to the frame starting at `offset` and decodes its header and runs.
+fn read_frame_layout_at<R: Read + Seek>(reader: &mut R, offset: u64) -> io::Result<FrameLayout> {
+    reader.seek(SeekFrom::Start(offset))?;
+    let max_val_bits = reader.read_u8()?;
+    let max_len_bits = reader.read_u8()?;
+    let n_bytes = reader.read_u32::<BigEndian>()?;
+    let runs = decode_ben_line(reader, max_val_bits, max_len_bits, n_bytes)?;
+    Ok(FrameLayout {
+        offset,
+        max_val_bits,
+        max_len_bits,
+        n_bytes,
+        runs,
+    })
+}
+
+/// The layout of a single frame of a BEN file as it is stored on disk: the
+/// bit widths chosen by the encoder, the size of the bit-packed payload, and
+/// the `(value, length)` runs that the payload holds. This is mostly useful
+/// for finding out why a sample takes up more space than expected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameLayout {
+    /// The byte offset of the frame in the file.
+    pub offset: u64,
+    /// The number of bits used for the value of each run.
+    pub max_val_bits: u8,
+    /// The number of bits used for the length of each run.
+    pub max_len_bits: u8,
+    /// The number of bytes in the payload of the frame.
+    pub n_bytes: u32,
+    /// The run-length encoded assignment vector.
+    pub runs: Vec<(u16, u16)>,
+}
+
+/// The byte offsets of the frames in a Standard or MkvChain BEN file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameIndex {
+    variant: BenVariant,
+    offsets: Vec<u64>,
+    sample_ends: Vec<usize>,
+}
+
+impl FrameIndex {
+    /// Builds the index by walking through all of the frames in a seekable
+    /// BEN file. The payloads are skipped over rather than decoded, so this
+    /// is cheap compared to decoding the file.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - A seekable reader positioned at the start of the BEN file
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file is not a valid
+    /// Standard or MkvChain BEN file, or an `UnexpectedEof` error if the
+    /// last frame is truncated.
+    pub fn build<R: Read + Seek>(reader: &mut R) -> io::Result<Self> {
+        let variant = read_ben_variant(reader)?;
+        let mut offsets = Vec::new();
+        let mut sample_ends = Vec::new();
+        let mut total = 0;
+
+        loop {
+            let offset = reader.stream_position()?;
+            let mut tmp_buffer = [0u8];
+            match reader.read_exact(&mut tmp_buffer) {
+                Ok(()) => {}
+                Err(e) => {
+                    if e.kind() == io::ErrorKind::UnexpectedEof {
+                        break;
+                    }
+                    return Err(e);
+                }
+            }
+            let _max_len_bits = reader.read_u8()?;
+            let n_bytes = reader.read_u32::<BigEndian>()?;
+            let skipped = io::copy(&mut reader.by_ref().take(n_bytes as u64), &mut io::sink())?;
+            if skipped < n_bytes as u64 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!(
+                        "Frame payload is {} bytes long but the header says {}",
+                        skipped, n_bytes
+                    ),
+                ));
+            }
+
+            let count = if variant == BenVariant::MkvChain {
+                reader.read_u16::<BigEndian>()?
+            } else {
+                1
+            };
+
+            total += count as usize;
+            offsets.push(offset);
+            sample_ends.push(total);
+        }
+
+        Ok(FrameIndex {
+            variant,
+            offsets,
+            sample_ends,
+        })
+    }
+
+    /// The variant of the indexed file.
+    pub fn variant(&self) -> BenVariant {
+        self.variant
+    }
+
+    /// The byte offset at which each frame starts.
+    pub fn offsets(&self) -> &[u64] {
+        &self.offsets
+    }
+
+    /// The number of samples stored in each frame, i.e. the repetition
+    /// counts of an MkvChain file (always 1 for a Standard file).
+    pub fn counts(&self) -> impl Iterator<Item = usize> + '_ {
+        let starts = std::iter::once(0).chain(self.sample_ends.iter().copied());
+        self.sample_ends
+            .iter()
+            .zip(starts)
+            .map(|(end, start)| end - start)
+    }
+
+    /// The number of frames in the indexed file.
+    pub fn n_frames(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// The number of samples in the indexed file (including MkvChain
+    /// repetitions).
+    pub fn n_samples(&self) -> usize {
+        self.sample_ends.last().copied().unwrap_or(0)
+    }
+
+    /// Returns the position of the frame holding the given sample number
+    /// (starting from 1), or `None` if the sample is not in the file.
+    pub fn frame_of(&self, sample: usize) -> Option<usize> {
+        if sample == 0 || sample > self.n_samples() {
+            return None;
+        }
+        Some(self.sample_ends.partition_point(|&end| end < sample))
+    }
+
+    /// Returns the byte offset of the frame holding the given sample number
+    /// (starting from 1), or an `InvalidInput` error if the sample is not in
+    /// the file.
+    pub fn offset_of(&self, sample: usize) -> io::Result<u64> {
+        match self.frame_of(sample) {
+            Some(frame) => Ok(self.offsets[frame]),
+            None => Err(Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Sample number {} not found in file. Last sample is {}",
+                    sample,
+                    self.n_samples()
+                ),
+            )),
+        }
+    }
+}
+
+/// The magic bytes at the start of a `.benidx` file.
+const INDEX_MAGIC: &[u8; 6] = b"BENIDX";
+/// The version of the `.benidx` layout written by this library.
+const INDEX_VERSION: u8 = 1;
+
+impl FrameIndex {
+    /// Writes the index in the `.benidx` layout described in the module
+    /// documentation.
+    pub fn write_to<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let variant_byte = match self.variant {
+            BenVariant::Standard => 0,
+            BenVariant::MkvChain => 1,
+            BenVariant::Delta => unreachable!("FrameIndex is never built for delta files"),
+        };
+
+        writer.write_all(INDEX_MAGIC)?;
+        writer.write_u8(INDEX_VERSION)?;
+        writer.write_u8(variant_byte)?;
+        writer.write_u64::<BigEndian>(self.offsets.len() as u64)?;
+        for &offset in self.offsets.iter() {
+            writer.write_u64::<BigEndian>(offset)?;
+        }
+        if self.variant == BenVariant::MkvChain {
+            for count in self.counts() {
+                writer.write_u16::<BigEndian>(count as u16)?;
+            }
+        }
+        writer.flush()
+    }
+
+    /// Reads an index that was written with `write_to`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidData` error if the data is not a `.benidx` file of
+    /// a supported version, if it is truncated, or if its contents are not
+    /// consistent (e.g. the offsets are not increasing).
+    pub fn read_from<R: Read>(mut reader: R) -> io::Result<Self> {
+        let invalid = |msg: String| Error::new(io::ErrorKind::InvalidData, msg);
+        let truncated = |e: Error| {
+            if e.kind() == io::ErrorKind::UnexpectedEof {
+                invalid("Index file is truncated".to_string())
+            } else {
+                e
+            }
+        };
+
+        let mut magic = [0u8; 6];
+        reader.read_exact(&mut magic).map_err(truncated)?;
+        if &magic != INDEX_MAGIC {
+            return Err(invalid("Not a BEN index file".to_string()));
+        }
+
+        let version = reader.read_u8().map_err(truncated)?;
+        if version != INDEX_VERSION {
+            return Err(invalid(format!(
+                "Unsupported BEN index version {}",
+                version
+            )));
+        }
+
+        let variant = match reader.read_u8().map_err(truncated)? {
+            0 => BenVariant::Standard,
+            1 => BenVariant::MkvChain,
+            other => return Err(invalid(format!("Invalid variant {} in index file", other))),
+        };
+
+        let n_frames = reader.read_u64::<BigEndian>().map_err(truncated)? as usize;
+        let mut offsets = Vec::new();
+        for _ in 0..n_frames {
+            let offset = reader.read_u64::<BigEndian>().map_err(truncated)?;
+            if offsets.last().is_some_and(|&last| offset <= last) || offset < 17 {
+                return Err(invalid(format!(
+                    "Invalid frame offset {} in index file",
+                    offset
+                )));
+            }
+            offsets.push(offset);
+        }
+
+        let mut sample_ends = Vec::with_capacity(n_frames);
+        let mut total = 0;
+        for _ in 0..n_frames {
+            let count = if variant == BenVariant::MkvChain {
+                reader.read_u16::<BigEndian>().map_err(truncated)?
+            } else {
+                1
+            };
+            if count == 0 {
+                return Err(invalid("Repetition count of 0 in index file".to_string()));
+            }
+            total += count as usize;
+            sample_ends.push(total);
+        }
+
+        let mut rest = [0u8];
+        if reader.read(&mut rest)? != 0 {
+            return Err(invalid(
+                "Unexpected data at the end of the index file".to_string(),
+            ));
+        }
+
+        Ok(FrameIndex {
+            variant,
+            offsets,
+            sample_ends,
+        })
+    }
+}
+
+/// A seekable BEN file together with its `FrameIndex`, giving random access
+/// to the samples of the file. Building the index takes a single pass over
+/// the frame headers, after which every lookup is one seek and one frame
+/// decode, no matter where the sample is in the file.
+///
+/// # Example
+///
+/// ```
+/// use ben::decode::index::BenIndex;
+/// use ben::encode::jsonl_encode_ben;
+/// use ben::BenVariant;
+/// use std::io::Cursor;
+///
+/// let input = r#"{"assignment": [1,1,2,2], "sample": 1}"#.to_string()
+///     + "\n"
+///     + r#"{"assignment": [1,1,2,2], "sample": 2}"#
+///     + "\n"
+///     + r#"{"assignment": [2,2,1,1], "sample": 3}"#;
+///
+/// let mut ben = Vec::new();
+/// jsonl_encode_ben(input.as_bytes(), &mut ben, BenVariant::MkvChain).unwrap();
+///
+/// let mut index = BenIndex::build(Cursor::new(ben)).unwrap();
+/// assert_eq!(index.n_samples(), 3);
+/// assert_eq!(index.get(3).unwrap(), vec![2, 2, 1, 1]);
+/// assert_eq!(index.get(2).unwrap(), vec![1, 1, 2, 2]);
+/// ```
+pub struct BenIndex<R> {
+    reader: R,
+    frames: FrameIndex,
+}
+
+impl<R: Read + Seek> BenIndex<R> {
+    /// Builds the index of the BEN file in `reader`, which should be
+    /// positioned at the start of the file.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the file is not a valid
+    /// Standard or MkvChain BEN file.
+    pub fn build(mut reader: R) -> io::Result<Self> {
+        let frames = FrameIndex::build(&mut reader)?;
+        Ok(BenIndex { reader, frames })
+    }
+
+    /// Decodes the sample with the given sample number (starting from 1).
+    ///
+    /// # Errors
+    ///
+    /// This function will return an `InvalidInput` error if the sample is
+    /// not in the file, or an error if the frame cannot be decoded.
+    pub fn get(&mut self, sample: usize) -> io::Result<Vec<u16>> {
+        let offset = self.frames.offset_of(sample)?;
+        decode_frame_at(&mut self.reader, offset)
+    }
+
+    /// Decodes the header and the runs of the frame holding the sample with
+    /// the given sample number (starting from 1) without expanding them.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an `InvalidInput` error if the sample is
+    /// not in the file, or an error if the frame cannot be decoded.
+    pub fn frame_layout(&mut self, sample: usize) -> io::Result<FrameLayout> {
+        let offset = self.frames.offset_of(sample)?;
+        read_frame_layout_at(&mut self.reader, offset)
+    }
+
+    /// Pairs a reader with an index that was previously built for it (e.g.
+    /// one loaded from a `.benidx` file with `FrameIndex::read_from`).
+    pub fn from_parts(reader: R, frames: FrameIndex) -> Self {
+        BenIndex { reader, frames }
+    }
+
+    /// Loads the index of the BEN file in `reader` from a `.benidx` file
+    /// written by `write_to` rather than scanning the BEN file.
+    ///
+    /// # Errors
+    ///
+    /// See `FrameIndex::read_from`.
+    pub fn read_from<I: Read>(reader: R, index_reader: I) -> io::Result<Self> {
+        Ok(Self::from_parts(
+            reader,
+            FrameIndex::read_from(index_reader)?,
+        ))
+    }
+
+    /// Saves the index in the `.benidx` layout so that it can be loaded
+    /// again with `read_from`.
+    pub fn write_to<W: Write>(&self, writer: W) -> io::Result<()> {
+        self.frames.write_to(writer)
+    }
+
+    /// The number of samples in the file (including MkvChain repetitions).
+    pub fn n_samples(&self) -> usize {
+        self.frames.n_samples()
+    }
+
+    /// The frame offsets that the index is built on.
+    pub fn frames(&self) -> &FrameIndex {
+        &self.frames
+    }
+
+    /// Consumes the index and returns the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+/// A source of bytes that can be read at arbitrary offsets without any
+/// shared cursor, e.g. a local file or an object store that supports HTTP
+/// range requests. Implement this for a transport to get random access to
+/// remote BEN files through `IndexedBenReader`.
+pub trait ReadAt {
+    /// Reads up to `buf.len()` bytes starting at `offset` into `buf` and
+    /// returns the number of bytes read. Fewer bytes than requested (and in
+    /// particular 0) may be returned at the end of the source.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+}
+
+impl ReadAt for [u8] {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        let start = (offset as usize).min(self.len());
+        let n = buf.len().min(self.len() - start);
+        buf[..n].copy_from_slice(&self[start..start + n]);
+        Ok(n)
+    }
+}
+
+impl ReadAt for Vec<u8> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        self.as_slice().read_at(buf, offset)
+    }
+}
+
+#[cfg(unix)]
+impl ReadAt for std::fs::File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        std::os::unix::fs::FileExt::read_at(self, buf, offset)
+    }
+}
+
+impl<T: ReadAt + ?Sized> ReadAt for &T {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        (**self).read_at(buf, offset)
+    }
+}
+
+/// Reads whole byte ranges from a `ReadAt` source. This is implemented for
+/// every `ReadAt`, so a transport only needs to provide `read_at`.
+pub trait RangeReader {
+    /// Reads exactly `len` bytes starting at `offset`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `UnexpectedEof` error if the source ends before `len`
+    /// bytes have been read.
+    fn read_range(&self, offset: u64, len: usize) -> io::Result<Vec<u8>>;
+}
+
+impl<T: ReadAt + ?Sized> RangeReader for T {
+    fn read_range(&self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0u8; len];
+        let mut filled = 0;
+        while filled < len {
+            match self.read_at(&mut buf[filled..], offset + filled as u64) {
+                Ok(0) => {
+                    return Err(Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!(
+                            "Expected {} bytes at offset {} but the source ended after {}",
+                            len, offset, filled
+                        ),
+                    ))
+                }
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(buf)
+    }
+}
+
+/// Random access into a BEN file behind a `ReadAt` source using a
+/// `FrameIndex` (e.g. one loaded from a `.benidx` file). Each sample is
+/// fetched with a single range read covering its frame, except for the last
+/// frame of the file whose length is only known once its header has been
+/// read.
+pub struct IndexedBenReader<R> {
+    source: R,
+    frames: FrameIndex,
+}
+
+impl<R: ReadAt> IndexedBenReader<R> {
+    /// Pairs a source with the index of the BEN file that it holds.
+    pub fn new(source: R, frames: FrameIndex) -> Self {
+        IndexedBenReader { source, frames }
+    }
+
+    /// Decodes the sample with the given sample number (starting from 1).
+    ///
+    /// # Errors
+    ///
+    /// This function will return an `InvalidInput` error if the sample is
+    /// not in the file, or an error if the frame cannot be read or decoded.
+    pub fn get(&self, sample: usize) -> io::Result<Vec<u16>> {
+        let offset = self.frames.offset_of(sample)?;
+        let frame = self
+            .frames
+            .frame_of(sample)
+            .expect("offset_of has already checked the sample");
+
+        let bytes = match self.frames.offsets.get(frame + 1) {
+            Some(&next) => self.source.read_range(offset, (next - offset) as usize)?,
+            None => {
+                let header = self.source.read_range(offset, 6)?;
+                let n_bytes = u32::from_be_bytes([header[2], header[3], header[4], header[5]]);
+                let payload = self.source.read_range(offset + 6, n_bytes as usize)?;
+                [header, payload].concat()
+            }
+        };
+
+        let mut frame_reader = bytes.as_slice();
+        let max_val_bits = frame_reader.read_u8()?;
+        let max_len_bits = frame_reader.read_u8()?;
+        let n_bytes = frame_reader.read_u32::<BigEndian>()?;
+        let rle = decode_ben_line(frame_reader, max_val_bits, max_len_bits, n_bytes)?;
+        Ok(rle_to_vec(rle))
+    }
+
+    /// The number of samples in the file (including MkvChain repetitions).
+    pub fn n_samples(&self) -> usize {
+        self.frames.n_samples()
+    }
+
+    /// The frame offsets that the reader is built on.
+    pub fn frames(&self) -> &FrameIndex {
+        &self.frames
+    }
+
+    /// Consumes the reader and returns the underlying source.
+    pub fn into_inner(self) -> R {
+        self.source
+    }
+}
+
+/// Decodes the requested samples of a BEN file in parallel using the rayon
+/// thread pool. Every task works on its own clone of `reader`, seeking
+/// directly to the frame that holds its sample, so the reader should be
+/// cheap to clone (e.g. a `Cursor` over a shared buffer or a memory map).
+///
+/// # Arguments
+///
+/// * `reader` - A seekable reader containing the BEN file
+/// * `index` - The `FrameIndex` of the file
+/// * `sample_numbers` - The sample numbers (starting from 1) to decode
+///
+/// # Returns
+///
+/// The assignment vectors of the requested samples in the order that they
+/// were requested.
+///
+/// # Errors
+///
+/// This function will return an error if one of the requested sample
+/// numbers is not in the file or if one of the frames cannot be decoded.
+#[cfg(feature = "rayon")]
+pub fn par_get_samples<R: Read + Seek + Clone + Send + Sync>(
+    reader: R,
+    index: &FrameIndex,
+    sample_numbers: &[usize],
+) -> io::Result<Vec<Vec<u16>>> {
+    use rayon::prelude::*;
+
+    let offsets = sample_numbers
+        .iter()
+        .map(|&sample| index.offset_of(sample))
+        .collect::<io::Result<Vec<u64>>>()?;
+
+    offsets
+        .par_iter()
+        .map_init(
+            || reader.clone(),
+            |local, &offset| decode_frame_at(local, offset),
+        )
+        .collect()
+}
+
+#[cfg(test)]
+#[path = "tests/index_tests.rs"]
+mod tests;
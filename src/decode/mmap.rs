@@ -0,0 +1,218 @@
+//! This module provides a writer that places its output in a memory-mapped
+//! file rather than going through a `write` call for every buffer. This is
+//! mostly useful when decoding very large ensembles to JSONL, where the
+//! output can easily run into the hundreds of gigabytes.
+//!
+//! The output file is preallocated to some capacity (usually computed with
+//! `estimate_jsonl_len`), grown if the estimate turns out to be too small,
+//! and truncated to the exact number of bytes written once the writer is
+//! finished.
+//!
+//! This module is only available when the `mmap` feature is enabled.
+
+use memmap2::MmapMut;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use super::jsonl_decode_xben;
+
+/// The smallest mapping that the writer will create. Mapping an empty file
+/// is an error on some platforms, so we always keep at least this much room.
+const MIN_CAPACITY: usize = 4096;
+
+/// A writer that writes into a memory-mapped output file.
+///
+/// Whenever a write does not fit into the current mapping, the file is
+/// doubled in size and remapped. Call `finish` once all of the data has been
+/// written so that the file is flushed and truncated to the number of bytes
+/// that were actually written. If the writer is dropped without calling
+/// `finish`, the file is still truncated, but any error is ignored.
+///
+/// # Example
+///
+/// ```no_run
+/// use ben::decode::mmap::MmapWriter;
+/// use std::io::Write;
+///
+/// let mut writer = MmapWriter::create("output.jsonl", 1 << 20).unwrap();
+/// writer.write_all(b"{\"assignment\":[1,2],\"sample\":1}\n").unwrap();
+/// writer.finish().unwrap();
+/// ```
+pub struct MmapWriter {
+    file: File,
+    mmap: Option<MmapMut>,
+    position: usize,
+}
+
+impl MmapWriter {
+    /// Creates (or truncates) the file at `path` and preallocates it to
+    /// `capacity` bytes.
+    ///
+    /// The file must not be modified by anything else while the writer is
+    /// alive since its contents are accessed through a shared mapping.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the output file
+    /// * `capacity` - The number of bytes to preallocate
+    ///
+    /// # Returns
+    ///
+    /// An io::Result containing the new writer
+    pub fn create<P: AsRef<Path>>(path: P, capacity: usize) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+
+        let capacity = capacity.max(MIN_CAPACITY);
+        file.set_len(capacity as u64)?;
+        let mmap = map_file(&file)?;
+
+        Ok(MmapWriter {
+            file,
+            mmap: Some(mmap),
+            position: 0,
+        })
+    }
+
+    /// Returns the number of bytes written so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Returns the current size of the mapping.
+    pub fn capacity(&self) -> usize {
+        self.mmap.as_ref().map_or(0, |m| m.len())
+    }
+
+    /// Flushes the mapping to disk and truncates the output file to the
+    /// number of bytes that were written.
+    ///
+    /// # Returns
+    ///
+    /// An io::Result containing the result of the operation
+    pub fn finish(mut self) -> io::Result<()> {
+        self.release()
+    }
+
+    fn release(&mut self) -> io::Result<()> {
+        if let Some(mmap) = self.mmap.take() {
+            mmap.flush()?;
+            // The mapping has to be gone before the file can be shrunk
+            drop(mmap);
+            self.file.set_len(self.position as u64)?;
+        }
+        Ok(())
+    }
+
+    fn grow(&mut self, needed: usize) -> io::Result<()> {
+        let mut capacity = self.capacity().max(MIN_CAPACITY);
+        while capacity < needed {
+            capacity = capacity.checked_mul(2).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::OutOfMemory,
+                    format!("Cannot grow memory-mapped output to {} bytes", needed),
+                )
+            })?;
+        }
+
+        if let Some(mmap) = self.mmap.take() {
+            mmap.flush()?;
+        }
+        self.file.set_len(capacity as u64)?;
+        self.mmap = Some(map_file(&self.file)?);
+        Ok(())
+    }
+}
+
+impl Write for MmapWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.mmap.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "Cannot write to a memory-mapped output that has been finished",
+            ));
+        }
+
+        let end = self.position + buf.len();
+        if end > self.capacity() {
+            self.grow(end)?;
+        }
+
+        let mmap = self.mmap.as_mut().unwrap();
+        mmap[self.position..end].copy_from_slice(buf);
+        self.position = end;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &self.mmap {
+            Some(mmap) => mmap.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for MmapWriter {
+    fn drop(&mut self) {
+        let _ = self.release();
+    }
+}
+
+fn map_file(file: &File) -> io::Result<MmapMut> {
+    // SAFETY: the file was opened by the writer and, as documented on
+    // `MmapWriter::create`, is not modified by anyone else while it is mapped.
+    unsafe { MmapMut::map_mut(file) }
+}
+
+/// Gives an upper bound on the size of the JSONL file produced by decoding
+/// an ensemble with the given shape, suitable as the capacity passed to
+/// `MmapWriter::create`.
+///
+/// # Arguments
+///
+/// * `n_samples` - The number of samples in the ensemble
+/// * `n_nodes` - The length of each assignment vector
+/// * `max_label` - The largest district label used in the ensemble
+///
+/// # Returns
+///
+/// The estimated number of bytes in the JSONL output
+pub fn estimate_jsonl_len(n_samples: usize, n_nodes: usize, max_label: u16) -> usize {
+    let label_width = max_label.to_string().len() + 1;
+    let sample_width = n_samples.to_string().len();
+    // {"assignment":[ ... ],"sample": ... }\n
+    let line_len = 15 + n_nodes * label_width + 11 + sample_width + 2;
+    n_samples.saturating_mul(line_len)
+}
+
+/// Decodes an XBEN file into a JSONL file that is written through a memory
+/// map preallocated to `capacity` bytes. The file is grown if the capacity is
+/// too small, and is truncated to the exact output length at the end.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the xben encoded assignment vectors
+/// * `path` - The path of the JSONL file to create
+/// * `capacity` - The number of bytes to preallocate for the output
+///
+/// # Returns
+///
+/// An io::Result containing the result of the operation
+pub fn jsonl_decode_xben_to_mmap<R: BufRead, P: AsRef<Path>>(
+    reader: R,
+    path: P,
+    capacity: usize,
+) -> io::Result<()> {
+    let mut writer = MmapWriter::create(path, capacity)?;
+    jsonl_decode_xben(reader, &mut writer)?;
+    writer.finish()
+}
+
+#[cfg(test)]
+#[path = "tests/mmap_tests.rs"]
+mod tests;
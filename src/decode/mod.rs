@@ -12,13 +12,24 @@
 //! BEN file format works well with the `read` submodule of this module
 //! which is designed to extract a single assignment vector from a BEN file.
 
+pub mod adapters;
+pub mod analysis;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod index;
+#[cfg(feature = "mmap")]
+pub mod mmap;
 pub mod read;
+pub mod validate;
+pub mod wide;
 
 use byteorder::{BigEndian, ReadBytesExt};
 use serde_json::json;
-use std::io::{self, BufRead, Error, Read, Write};
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Error, Read, Write};
 
-use crate::utils::rle_to_vec;
+use self::adapters::{Selection, SubsampleDecoder};
+use crate::utils::{assign_to_rle, rle_to_vec};
 
 use super::encode::translate::*;
 use super::{log, logln, BenVariant};
@@ -29,6 +40,32 @@ pub enum DecoderInitError {
     Io(io::Error),
 }
 
+impl DecoderInitError {
+    /// Builds an `Io` error for a failure `offset` bytes into the stream.
+    fn io_at(error: io::Error, offset: u64) -> Self {
+        DecoderInitError::Io(io::Error::new(
+            error.kind(),
+            StreamOffsetError {
+                offset,
+                source: error,
+            },
+        ))
+    }
+
+    /// The byte offset in the stream at which the error occurred. A bad
+    /// banner is always reported at byte 0, as is an IO error whose position
+    /// is not known.
+    pub fn offset(&self) -> u64 {
+        match self {
+            DecoderInitError::InvalidFileFormat(_) => 0,
+            DecoderInitError::Io(error) => error
+                .get_ref()
+                .and_then(|e| e.downcast_ref::<StreamOffsetError>())
+                .map_or(0, |e| e.offset),
+        }
+    }
+}
+
 impl std::fmt::Display for DecoderInitError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -59,76 +96,441 @@ impl From<DecoderInitError> for io::Error {
     fn from(error: DecoderInitError) -> Self {
         match error {
             DecoderInitError::Io(e) => e,
-            DecoderInitError::InvalidFileFormat(msg) => {
-                io::Error::new(io::ErrorKind::InvalidData, msg)
+            e @ DecoderInitError::InvalidFileFormat(_) => {
+                io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+            }
+        }
+    }
+}
+
+/// The IO error wrapped by `DecoderInitError::Io` when the position of the
+/// failure in the stream is known.
+#[derive(Debug)]
+struct StreamOffsetError {
+    offset: u64,
+    source: io::Error,
+}
+
+impl std::fmt::Display for StreamOffsetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at byte {}", self.source, self.offset)
+    }
+}
+
+impl std::error::Error for StreamOffsetError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// The error attached to every error yielded by a `BenDecoder`. It records
+/// where in the stream the failure happened so that corrupt files can be
+/// inspected and the valid prefix recovered.
+///
+/// The error can be recovered from the `io::Error` returned by the decoder
+/// using
+///
+/// ```ignore
+/// let frame_error = err.get_ref().and_then(|e| e.downcast_ref::<FrameError>());
+/// ```
+#[derive(Debug)]
+pub struct FrameError {
+    /// The number of the first sample in the frame that failed to decode.
+    pub sample: usize,
+    /// The byte offset at which the failing frame starts. Everything before
+    /// this offset decoded successfully.
+    pub frame_offset: u64,
+    /// The byte offset in the stream at which the error occurred.
+    pub offset: u64,
+    /// The underlying error.
+    pub source: io::Error,
+}
+
+impl std::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Error decoding sample {} at byte {} (frame starts at byte {}): {}",
+            self.sample, self.offset, self.frame_offset, self.source
+        )
+    }
+}
+
+impl std::error::Error for FrameError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// A reader adapter that keeps track of the number of bytes that have been
+/// read from the underlying reader.
+pub struct CountingReader<R: Read> {
+    inner: R,
+    position: u64,
+}
+
+impl<R: Read> CountingReader<R> {
+    pub fn new(inner: R) -> Self {
+        CountingReader { inner, position: 0 }
+    }
+
+    /// The number of bytes read from the underlying reader so far.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Consumes the adapter and returns the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.position += amt as u64;
+    }
+}
+
+/// A writer adapter that flushes the underlying writer after every
+/// `flush_every` lines (newline characters) written through it. This trades
+/// throughput for latency when the decoded JSONL is piped into a live
+/// consumer, since buffered output otherwise only appears in large bursts.
+/// A `flush_every` of 0 never flushes on its own.
+pub struct LineFlushWriter<W: Write> {
+    inner: W,
+    flush_every: usize,
+    lines_since_flush: usize,
+}
+
+impl<W: Write> LineFlushWriter<W> {
+    pub fn new(inner: W, flush_every: usize) -> Self {
+        LineFlushWriter {
+            inner,
+            flush_every,
+            lines_since_flush: 0,
+        }
+    }
+
+    /// Consumes the adapter and returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for LineFlushWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if self.flush_every > 0 {
+            self.lines_since_flush += buf[..n].iter().filter(|&&b| b == b'\n').count();
+            if self.lines_since_flush >= self.flush_every {
+                self.inner.flush()?;
+                self.lines_since_flush = 0;
             }
         }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.lines_since_flush = 0;
+        self.inner.flush()
     }
 }
 
+/// A single decoded frame of a BEN file: the assignment vector along with
+/// the number of times it is repeated. For the Standard variant the count
+/// is always 1.
+pub type MkvRecord = (Vec<u16>, u16);
+
+/// A single frame of a BEN file in run-length encoded form: a list of
+/// `(value, length)` runs along with the number of times it is repeated.
+pub type RleRecord = (Vec<(u16, u16)>, u16);
+
 // Note: This will make Read easier to use since
 // I can now implement the read chunk with a Cursor
 // object.
 pub struct BenDecoder<R: Read> {
-    reader: R,
+    reader: CountingReader<R>,
     sample_count: usize,
+    sample_base: usize,
     variant: BenVariant,
+    samples_decoded: usize,
+    previous_assignment: Vec<u16>,
+    frame_crc: bool,
+    max_bytes: Option<u32>,
+    strict: bool,
+    strict_eof: bool,
+    expected_nodes: Option<usize>,
+    fixed_widths: Option<(u8, u8)>,
+    payload_buffer: Vec<u8>,
+    progress: Option<Box<dyn FnMut(usize) + Send>>,
 }
 
 impl<R: Read> BenDecoder<R> {
-    pub fn new(mut reader: R) -> Result<Self, DecoderInitError> {
+    pub fn new(reader: R) -> Result<Self, DecoderInitError> {
+        let mut reader = CountingReader::new(reader);
         let mut check_buffer = [0u8; 17];
 
-        if let Err(e) = reader.read_exact(&mut check_buffer) {
-            return Err(DecoderInitError::Io(e));
-        }
-
-        match &check_buffer {
-            b"STANDARD BEN FILE" => Ok(BenDecoder {
-                reader,
-                sample_count: 0,
-                variant: BenVariant::Standard,
-            }),
-            b"MKVCHAIN BEN FILE" => Ok(BenDecoder {
-                reader,
-                sample_count: 0,
-                variant: BenVariant::MkvChain,
-            }),
-            _ => Err(DecoderInitError::InvalidFileFormat(format!(
-                "Invalid file format. Found header bytes {:?}",
-                check_buffer
-            ))),
+        if let Err(error) = reader.read_exact(&mut check_buffer) {
+            return Err(DecoderInitError::io_at(error, reader.position()));
         }
+
+        let (variant, frame_crc) = match &check_buffer {
+            b"STANDARD BEN FILE" => (BenVariant::Standard, false),
+            b"MKVCHAIN BEN FILE" => (BenVariant::MkvChain, false),
+            b"MKVDELTA BEN FILE" => (BenVariant::Delta, false),
+            b"STANDARD BEN FCRC" => (BenVariant::Standard, true),
+            b"MKVCHAIN BEN FCRC" => (BenVariant::MkvChain, true),
+            b"MKVDELTA BEN FCRC" => (BenVariant::Delta, true),
+            _ => {
+                return Err(DecoderInitError::InvalidFileFormat(format!(
+                    "{:?}",
+                    check_buffer
+                )))
+            }
+        };
+
+        Ok(BenDecoder {
+            reader,
+            sample_count: 0,
+            sample_base: 1,
+            variant,
+            samples_decoded: 0,
+            previous_assignment: Vec::new(),
+            frame_crc,
+            max_bytes: None,
+            strict: false,
+            strict_eof: false,
+            expected_nodes: None,
+            fixed_widths: None,
+            payload_buffer: Vec::new(),
+            progress: None,
+        })
     }
 
-    fn write_all_jsonl(&mut self, mut writer: impl Write) -> io::Result<()> {
-        while let Some(result_tuple) = self.next() {
-            match result_tuple {
-                Ok((assignment, count)) => {
-                    for _ in 0..count {
-                        self.sample_count += 1;
-                        let line = json!({
-                            "assignment": assignment,
-                            "sample": self.sample_count,
-                        })
-                        .to_string()
-                            + "\n";
-                        writer.write_all(line.as_bytes()).unwrap();
-                    }
-                }
-                Err(e) => {
-                    return Err(e);
+    /// Create a new BenDecoder that checks that every assignment has `n`
+    /// nodes, which catches poorly-formed ensembles before they reach code
+    /// that expects a fixed number of nodes. A sample of a different length
+    /// is returned as an `InvalidData` error naming the sample and both
+    /// lengths. This is the same check as `BenDecoderBuilder::expected_nodes`
+    /// without the buffering of the builder.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidFileFormat` error if the stream is not a BEN file of
+    /// the given variant.
+    pub fn with_expected_len(
+        reader: R,
+        variant: BenVariant,
+        n: usize,
+    ) -> Result<Self, DecoderInitError> {
+        let mut decoder = BenDecoder::new(reader)?;
+        if decoder.variant != variant {
+            // Report the banner that was actually found at the start of the file
+            let banner = match (decoder.variant, decoder.frame_crc) {
+                (BenVariant::Standard, false) => "STANDARD BEN FILE",
+                (BenVariant::MkvChain, false) => "MKVCHAIN BEN FILE",
+                (BenVariant::Delta, false) => "MKVDELTA BEN FILE",
+                (BenVariant::Standard, true) => "STANDARD BEN FCRC",
+                (BenVariant::MkvChain, true) => "MKVCHAIN BEN FCRC",
+                (BenVariant::Delta, true) => "MKVDELTA BEN FCRC",
+            };
+            return Err(DecoderInitError::InvalidFileFormat(banner.to_string()));
+        }
+        decoder.expected_nodes = Some(n);
+        Ok(decoder)
+    }
+
+    /// Tell the decoder that every frame of the file uses the same bit
+    /// widths, which is common when the assignments are similar. The header
+    /// of each frame is checked against the widths (a frame that does not
+    /// match is returned as an `InvalidData` error), and the payload is then
+    /// unpacked with a tighter loop that does not need to handle arbitrary
+    /// widths, out of a buffer that is reused from one frame to the next.
+    /// The change frames of a Delta file are not affected.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either width is outside of the range 1..=16.
+    pub fn with_fixed_widths(mut self, max_val_bits: u8, max_len_bits: u8) -> Self {
+        assert!(
+            (1..=16).contains(&max_val_bits) && (1..=16).contains(&max_len_bits),
+            "Bit widths must be between 1 and 16"
+        );
+        self.fixed_widths = Some((max_val_bits, max_len_bits));
+        self
+    }
+
+    /// Returns the variant of the BEN file being decoded.
+    pub fn variant(&self) -> BenVariant {
+        self.variant
+    }
+
+    /// Returns true if the frames of the file carry CRC32 checksums.
+    pub fn has_frame_crc(&self) -> bool {
+        self.frame_crc
+    }
+
+    /// Returns the number of bytes of the stream that have been consumed
+    /// so far, including the banner.
+    pub fn position(&self) -> u64 {
+        self.reader.position()
+    }
+
+    /// Reads the checksum and payload of a frame and verifies that they
+    /// match.
+    fn read_checked_payload(&mut self, payload_len: u64) -> io::Result<Vec<u8>> {
+        let expected = self.reader.read_u32::<BigEndian>()?;
+
+        let mut payload = Vec::new();
+        (&mut self.reader)
+            .take(payload_len)
+            .read_to_end(&mut payload)?;
+        if (payload.len() as u64) < payload_len {
+            return Err(Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Frame for sample {} is truncated", self.samples_decoded + 1),
+            ));
+        }
+
+        let actual = crc32fast::hash(&payload);
+        if actual != expected {
+            return Err(Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Checksum mismatch in frame for sample {}: expected {:#010x} but found {:#010x}",
+                    self.samples_decoded + 1,
+                    expected,
+                    actual
+                ),
+            ));
+        }
+        Ok(payload)
+    }
+
+    /// Reads the payload of a frame into the buffer that is reused from one
+    /// frame to the next. The buffer grows as the data comes in rather than
+    /// being sized from the header up front.
+    fn read_payload(&mut self, payload_len: u64) -> io::Result<()> {
+        self.payload_buffer.clear();
+        let n_read = (&mut self.reader)
+            .take(payload_len)
+            .read_to_end(&mut self.payload_buffer)?;
+        if (n_read as u64) < payload_len {
+            return Err(Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Frame for sample {} is truncated", self.samples_decoded + 1),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Reads the repetition count that follows the payload of a frame (always
+    /// 1 for the variants that do not store one).
+    fn read_count(&mut self) -> io::Result<u16> {
+        if self.variant == BenVariant::MkvChain {
+            self.reader.read_u16::<BigEndian>()
+        } else {
+            Ok(1)
+        }
+    }
+
+    /// Skips the rest of a frame whose header failed one of the checks, so
+    /// that the next call to `next` starts at the following frame rather
+    /// than in the middle of this one, and returns `error`. If the frame
+    /// cannot be skipped (e.g. it is truncated), the next call simply finds
+    /// the end of the stream.
+    fn reject_frame(&mut self, payload_len: u64, error: Error) -> Error {
+        let _ = self.skip_frame_rest(payload_len);
+        error
+    }
+
+    /// Reads past the checksum, payload, and count of the current frame.
+    fn skip_frame_rest(&mut self, payload_len: u64) -> io::Result<()> {
+        if self.frame_crc {
+            self.reader.read_u32::<BigEndian>()?;
+        }
+        io::copy(&mut (&mut self.reader).take(payload_len), &mut io::sink())?;
+        let count = self.read_count()?;
+        self.samples_decoded += count as usize;
+        Ok(())
+    }
+
+    /// Applies the changes from a delta frame to the previously decoded
+    /// assignment.
+    fn apply_delta(&self, changes: Vec<(u32, u16)>) -> io::Result<Vec<u16>> {
+        let mut assignment = self.previous_assignment.clone();
+        for (idx, val) in changes {
+            match assignment.get_mut(idx as usize) {
+                Some(node) => *node = val,
+                None => {
+                    return Err(Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "Delta frame for sample {} changes node {} but the assignment only has {} nodes",
+                            self.samples_decoded + 1,
+                            idx,
+                            assignment.len()
+                        ),
+                    ));
                 }
             }
         }
-        Ok(())
+        Ok(assignment)
     }
-}
 
-impl<R: Read> Iterator for BenDecoder<R> {
-    type Item = io::Result<(Vec<u16>, u16)>;
+    /// Builds the error returned in `strict_eof` mode when the bytes
+    /// starting at `offset` do not form a frame. The rest of the stream is
+    /// consumed so that the number of trailing bytes can be reported.
+    fn trailing_bytes_error(&mut self, offset: u64) -> Error {
+        let n_trailing = match io::copy(&mut self.reader, &mut io::sink()) {
+            Ok(_) => self.reader.position() - offset,
+            Err(e) => return e,
+        };
+        Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Found {} trailing bytes after the last frame at offset {}",
+                n_trailing, offset
+            ),
+        )
+    }
+
+    /// Reads the next frame and attaches the position of any failure to
+    /// the returned error.
+    fn next_rle(&mut self) -> Option<io::Result<RleRecord>> {
+        let frame_offset = self.reader.position();
+        let sample = self.samples_decoded + 1;
+
+        match self.read_frame()? {
+            Ok(record) => Some(Ok(record)),
+            Err(e) => Some(Err(Error::new(
+                e.kind(),
+                FrameError {
+                    sample,
+                    frame_offset,
+                    offset: self.reader.position(),
+                    source: e,
+                },
+            ))),
+        }
+    }
 
-    fn next(&mut self) -> Option<io::Result<(Vec<u16>, u16)>> {
+    /// Reads the next frame from the stream and returns its run-length
+    /// encoded assignment along with the repetition count.
+    fn read_frame(&mut self) -> Option<io::Result<RleRecord>> {
         let mut tmp_buffer = [0u8];
         let max_val_bits: u8 = match self.reader.read_exact(&mut tmp_buffer) {
             Ok(()) => tmp_buffer[0],
@@ -142,106 +544,901 @@ impl<R: Read> Iterator for BenDecoder<R> {
             }
         };
 
-        let max_len_bits = self
-            .reader
-            .read_u8()
-            .expect(format!("Error when reading sample {}.", self.sample_count).as_str());
-        let n_bytes = self
-            .reader
-            .read_u32::<BigEndian>()
-            .expect(format!("Error when reading sample {}.", self.sample_count).as_str());
-
-        let assignment =
-            match decode_ben_line(&mut self.reader, max_val_bits, max_len_bits, n_bytes) {
-                Ok(output_rle) => rle_to_vec(output_rle),
-                Err(e) => return Some(Err(e)),
-            };
+        let is_delta_frame = self.variant == BenVariant::Delta && self.samples_decoded > 0;
 
-        let count = if self.variant == BenVariant::MkvChain {
-            self.reader
-                .read_u16::<BigEndian>()
-                .expect(format!("Error when reading sample {}.", self.sample_count).as_str())
+        // A frame always starts with a bit width, so anything else is data
+        // that does not belong to the file (e.g. the banner of a second file
+        // that was concatenated onto this one).
+        let max_first_bits = if is_delta_frame { 32 } else { 16 };
+        if self.strict_eof && !(1..=max_first_bits).contains(&max_val_bits) {
+            let offset = self.reader.position() - 1;
+            return Some(Err(self.trailing_bytes_error(offset)));
+        }
+
+        let max_len_bits = match self.reader.read_u8() {
+            Ok(max_len_bits) => max_len_bits,
+            Err(e) => return Some(Err(e)),
+        };
+        let n_bytes = match self.reader.read_u32::<BigEndian>() {
+            Ok(n_bytes) => n_bytes,
+            Err(e) => return Some(Err(e)),
+        };
+
+        let sample_index = self.samples_decoded + 1;
+
+        // The header of a delta frame holds the number of changed nodes
+        // rather than the length of the payload.
+        let payload_len = if is_delta_frame {
+            (n_bytes as u64 * (max_val_bits as u64 + max_len_bits as u64)).div_ceil(8)
         } else {
-            1
+            n_bytes as u64
         };
 
-        log!("Decoding sample: {}\r", self.sample_count + count as usize);
-        Some(Ok((assignment, count)))
-    }
-}
+        if let Some(max_bytes) = self.max_bytes {
+            if payload_len > max_bytes as u64 {
+                return Some(Err(self.reject_frame(
+                    payload_len,
+                    Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "Frame for sample {} is {} bytes long which exceeds the maximum of {} bytes",
+                            sample_index, payload_len, max_bytes
+                        ),
+                    ),
+                )));
+            }
+        }
 
-/// This function takes a reader containing a single ben32 encoded assignment
-/// vector and decodes it into a full assignment vector of u16s.
-///
-/// # Arguments
-///
-/// * `reader` - A reader containing the ben32 encoded assignment vector
-///
-/// # Returns
-///
-/// A vector of u16s containing the decoded assignment vector
-///
-/// # Errors
-///
-/// This function will return an error if the input reader is not a multiple of 4
-/// bytes long since each assignment vector is an run-length encoded as a 32 bit
-/// integer (2 bytes for the value and 2 bytes for the count).
-///
-fn decode_ben32_line<R: BufRead>(
-    mut reader: R,
-    variant: BenVariant,
-) -> io::Result<(Vec<u16>, u16)> {
-    let mut buffer = [0u8; 4];
-    let mut output_vec: Vec<u16> = Vec::new();
+        if !is_delta_frame {
+            // Widths of 0 or more than 32 bits cannot be decoded at all, so
+            // they are rejected even outside of strict mode.
+            let max_bits = if self.strict { 16 } else { 32 };
+            if !(1..=max_bits).contains(&max_val_bits) || !(1..=max_bits).contains(&max_len_bits) {
+                return Some(Err(self.reject_frame(
+                    n_bytes as u64,
+                    Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "Invalid frame header for sample {}: max_val_bits = {}, max_len_bits = {}",
+                            sample_index, max_val_bits, max_len_bits
+                        ),
+                    ),
+                )));
+            }
 
-    loop {
-        match reader.read_exact(&mut buffer) {
-            Ok(()) => {
-                println!("found {:?}", buffer);
-                let encoded = u32::from_be_bytes(buffer);
-                if encoded == 0 {
-                    // Check for separator (all 0s)
-                    break; // Exit loop to process next sample
+            if let Some((fixed_val_bits, fixed_len_bits)) = self.fixed_widths {
+                if (max_val_bits, max_len_bits) != (fixed_val_bits, fixed_len_bits) {
+                    return Some(Err(self.reject_frame(
+                        n_bytes as u64,
+                        Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "Frame for sample {} has max_val_bits = {}, max_len_bits = {} but {}, {} were expected",
+                                sample_index, max_val_bits, max_len_bits, fixed_val_bits, fixed_len_bits
+                            ),
+                        ),
+                    )));
                 }
+            }
+        }
 
-                let value = (encoded >> 16) as u16; // High 16 bits
-                let count = (encoded & 0xFFFF) as u16; // Low 16 bits
+        // When the file stores frame checksums, the payload is read and
+        // verified before any of it is decoded.
+        let checked_payload = if self.frame_crc {
+            match self.read_checked_payload(payload_len) {
+                Ok(payload) => Some(payload),
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                    // The payload did not match its checksum, but it was read
+                    // in full, so only the count is left to skip.
+                    if let Ok(count) = self.read_count() {
+                        self.samples_decoded += count as usize;
+                    }
+                    return Some(Err(e));
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        } else {
+            None
+        };
 
-                // Reconstruct the original data
-                for _ in 0..count {
-                    output_vec.push(value);
+        let rle = if is_delta_frame {
+            // Every frame after the first one in a delta file holds the index
+            // width, the label width, and the number of changed nodes.
+            let changes = match &checked_payload {
+                Some(payload) => {
+                    decode_delta_frame(payload.as_slice(), max_val_bits, max_len_bits, n_bytes)
                 }
+                None => decode_delta_frame(&mut self.reader, max_val_bits, max_len_bits, n_bytes),
+            };
+            match changes.and_then(|changes| self.apply_delta(changes)) {
+                Ok(assignment) => {
+                    let rle = assign_to_rle(assignment.clone());
+                    self.previous_assignment = assignment;
+                    rle
+                }
+                Err(e) => return Some(Err(e)),
             }
-            Err(e) => {
-                return Err(e); // Propagate other errors
+        } else {
+            let output_rle = match (&checked_payload, self.fixed_widths) {
+                (Some(payload), Some(_)) => {
+                    Ok(unpack_fixed_width_runs(payload, max_val_bits, max_len_bits))
+                }
+                (None, Some(_)) => self.read_payload(n_bytes as u64).map(|_| {
+                    unpack_fixed_width_runs(&self.payload_buffer, max_val_bits, max_len_bits)
+                }),
+                (Some(payload), None) => {
+                    decode_ben_line(payload.as_slice(), max_val_bits, max_len_bits, n_bytes)
+                }
+                (None, None) => {
+                    decode_ben_line(&mut self.reader, max_val_bits, max_len_bits, n_bytes)
+                }
+            };
+            match output_rle {
+                Ok(output_rle) => {
+                    if self.variant == BenVariant::Delta {
+                        self.previous_assignment = rle_to_vec(output_rle.clone());
+                    }
+                    output_rle
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        };
+
+        let count = match self.read_count() {
+            Ok(count) => count,
+            Err(e) => return Some(Err(e)),
+        };
+        self.samples_decoded += count as usize;
+
+        // The whole frame has been read at this point, so the next call
+        // starts at the following frame even if this one is rejected.
+        if let Some(expected_nodes) = self.expected_nodes {
+            let n_nodes: usize = rle.iter().map(|&(_, len)| len as usize).sum();
+            if n_nodes != expected_nodes {
+                return Some(Err(Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Sample {} has {} nodes but {} were expected",
+                        sample_index, n_nodes, expected_nodes
+                    ),
+                )));
             }
         }
+
+        if let Some(progress) = self.progress.as_mut() {
+            progress(self.samples_decoded);
+        }
+
+        log!("Decoding sample: {}\r", self.sample_count + count as usize);
+        Some(Ok((rle, count)))
     }
 
-    let count = if variant == BenVariant::MkvChain {
-        reader
-            .read_u16::<BigEndian>()
-            .expect("Error when reading sample.")
-    } else {
-        1
-    };
+    fn write_all_jsonl(&mut self, mut writer: impl Write) -> io::Result<()> {
+        while let Some(result_tuple) = self.next() {
+            match result_tuple {
+                Ok((assignment, count)) => {
+                    for _ in 0..count {
+                        let line = json!({
+                            "assignment": assignment,
+                            "sample": self.sample_base + self.sample_count,
+                        })
+                        .to_string()
+                            + "\n";
+                        writer.write_all(line.as_bytes())?;
+                        self.sample_count += 1;
+                    }
+                }
+                Err(e) => {
+                    return Err(e);
+                }
+            }
+        }
+        Ok(())
+    }
+}
 
-    Ok((output_vec, count))
+impl<R: Read> Iterator for BenDecoder<R> {
+    type Item = io::Result<MkvRecord>;
+
+    fn next(&mut self) -> Option<io::Result<MkvRecord>> {
+        self.next_rle()
+            .map(|record| record.map(|(rle, count)| (rle_to_vec(rle), count)))
+    }
 }
 
-/// This function takes a reader containing a file encoded with the
-/// "ben32" format and decodes it into a JSONL file.
+/// An iterator over the frames of a BEN file that yields the run-length
+/// encoded assignment vectors rather than expanding them. This avoids
+/// materializing every node of every sample when only the runs are needed.
+pub struct BenRleDecoder<R: Read> {
+    decoder: BenDecoder<R>,
+}
+
+impl<R: Read> BenRleDecoder<R> {
+    pub fn new(reader: R) -> Result<Self, DecoderInitError> {
+        Ok(BenRleDecoder {
+            decoder: BenDecoder::new(reader)?,
+        })
+    }
+
+    /// Returns the variant of the BEN file being decoded.
+    pub fn variant(&self) -> BenVariant {
+        self.decoder.variant()
+    }
+}
+
+impl<R: Read> From<BenDecoder<R>> for BenRleDecoder<R> {
+    fn from(decoder: BenDecoder<R>) -> Self {
+        BenRleDecoder { decoder }
+    }
+}
+
+impl<R: Read> Iterator for BenRleDecoder<R> {
+    type Item = io::Result<RleRecord>;
+
+    fn next(&mut self) -> Option<io::Result<RleRecord>> {
+        self.decoder.next_rle()
+    }
+}
+
+/// A single frame of a BEN file as it is stored on disk, borrowed from the
+/// `RawFrameDecoder` that read it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawFrame<'a> {
+    /// The number of bits used for the value of each run.
+    pub max_val_bits: u8,
+    /// The number of bits used for the length of each run.
+    pub max_len_bits: u8,
+    /// The bit-packed `(value, length)` runs, which can be unpacked with
+    /// `decode_ben_line`.
+    pub payload: &'a [u8],
+    /// The number of times the frame repeats (always 1 for a Standard file).
+    pub count: u16,
+}
+
+/// The lowest level reader of the frames of a BEN file. The frame headers
+/// and repetition counts are read, but the payloads are handed back as they
+/// are without being unpacked, so that they can be forwarded elsewhere or
+/// unpacked by a custom bit reader. The payload is read into a buffer that
+/// is reused from one frame to the next, so no memory is allocated per frame
+/// once the buffer has grown to the size of the largest payload.
 ///
-/// The output JSONL file will have the formatting
+/// Since each frame borrows the decoder, this is not an `Iterator`; call
+/// `next_frame` in a loop instead. Only Standard and MkvChain files are
+/// supported, since the frames of a Delta file cannot be read on their own.
+///
+/// # Example
 ///
-/// ```json
-/// {"assignment": [...], "sample": #}
 /// ```
+/// use ben::decode::{decode_ben_line, RawFrameDecoder};
+/// use ben::encode::BenEncoder;
+/// use ben::BenVariant;
 ///
-/// # Arguments
+/// let mut ben = Vec::new();
+/// let mut encoder = BenEncoder::new(&mut ben, BenVariant::MkvChain);
+/// encoder.write_assignment(vec![1, 1, 2, 2]).unwrap();
+/// encoder.write_assignment(vec![1, 1, 2, 2]).unwrap();
+/// drop(encoder);
+///
+/// let mut decoder = RawFrameDecoder::new(ben.as_slice()).unwrap();
+/// while let Some(frame) = decoder.next_frame() {
+///     let frame = frame.unwrap();
+///     let rle = decode_ben_line(
+///         frame.payload,
+///         frame.max_val_bits,
+///         frame.max_len_bits,
+///         frame.payload.len() as u32,
+///     )
+///     .unwrap();
+///     assert_eq!(rle, vec![(1, 2), (2, 2)]);
+///     assert_eq!(frame.count, 2);
+/// }
+/// ```
+pub struct RawFrameDecoder<R: Read> {
+    reader: R,
+    variant: BenVariant,
+    payload: Vec<u8>,
+}
+
+impl<R: Read> RawFrameDecoder<R> {
+    /// Create a new RawFrameDecoder, reading the banner of the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidFileFormat` error if the stream is not a Standard
+    /// or MkvChain BEN file.
+    pub fn new(mut reader: R) -> Result<Self, DecoderInitError> {
+        let mut check_buffer = [0u8; 17];
+        if let Err(error) = reader.read_exact(&mut check_buffer) {
+            return Err(DecoderInitError::Io(error));
+        }
+
+        let variant = match &check_buffer {
+            b"STANDARD BEN FILE" => BenVariant::Standard,
+            b"MKVCHAIN BEN FILE" => BenVariant::MkvChain,
+            _ => {
+                return Err(DecoderInitError::InvalidFileFormat(format!(
+                    "{:?}",
+                    check_buffer
+                )))
+            }
+        };
+
+        Ok(RawFrameDecoder {
+            reader,
+            variant,
+            payload: Vec::new(),
+        })
+    }
+
+    /// Returns the variant of the BEN file being read.
+    pub fn variant(&self) -> BenVariant {
+        self.variant
+    }
+
+    /// Reads the next frame, or returns `None` at the end of the file.
+    pub fn next_frame(&mut self) -> Option<io::Result<RawFrame<'_>>> {
+        let max_val_bits = match self.reader.read_u8() {
+            Ok(max_val_bits) => max_val_bits,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return None,
+            Err(e) => return Some(Err(e)),
+        };
+
+        Some(
+            self.read_frame_rest()
+                .map(|(max_len_bits, count)| RawFrame {
+                    max_val_bits,
+                    max_len_bits,
+                    payload: &self.payload,
+                    count,
+                }),
+        )
+    }
+
+    /// Reads the rest of a frame after its first byte into the payload
+    /// buffer, returning the length bit width and the repetition count.
+    fn read_frame_rest(&mut self) -> io::Result<(u8, u16)> {
+        let max_len_bits = self.reader.read_u8()?;
+        let n_bytes = self.reader.read_u32::<BigEndian>()?;
+
+        // The length comes straight from the file, so the payload is read
+        // through `take` rather than allocated up front.
+        self.payload.clear();
+        (&mut self.reader)
+            .take(n_bytes as u64)
+            .read_to_end(&mut self.payload)?;
+        if (self.payload.len() as u64) < n_bytes as u64 {
+            return Err(Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "Frame payload is {} bytes long but the header says {}",
+                    self.payload.len(),
+                    n_bytes
+                ),
+            ));
+        }
+
+        let count = if self.variant == BenVariant::MkvChain {
+            self.reader.read_u16::<BigEndian>()?
+        } else {
+            1
+        };
+        Ok((max_len_bits, count))
+    }
+}
+
+/// An iterator over the samples of an XBEN file that yields each
+/// assignment vector along with its repetition count, in the same way that
+/// `BenDecoder` does for BEN files. The xz stream is decompressed as the
+/// iterator advances, so the whole file is never held in memory.
+pub struct XBenDecoder<R: BufRead> {
+    reader: BufReader<xz2::bufread::XzDecoder<R>>,
+    variant: BenVariant,
+    length_prefixed: bool,
+}
+
+impl<R: BufRead> XBenDecoder<R> {
+    /// Creates a new decoder after reading and checking the banner of the
+    /// decompressed stream.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidData` error if the stream is not an XBEN file.
+    pub fn new(reader: R) -> io::Result<Self> {
+        let mut reader = BufReader::new(xz2::bufread::XzDecoder::new(reader));
+
+        let mut check_buffer = [0u8; 17];
+        reader.read_exact(&mut check_buffer)?;
+
+        let (variant, length_prefixed) = match &check_buffer {
+            b"STANDARD BEN FILE" => (BenVariant::Standard, false),
+            b"MKVCHAIN BEN FILE" => (BenVariant::MkvChain, false),
+            b"STANDARD BEN LP32" => (BenVariant::Standard, true),
+            b"MKVCHAIN BEN LP32" => (BenVariant::MkvChain, true),
+            _ => {
+                return Err(Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Invalid file format",
+                ))
+            }
+        };
+
+        Ok(XBenDecoder {
+            reader,
+            variant,
+            length_prefixed,
+        })
+    }
+
+    /// Returns the variant of the XBEN file being decoded.
+    pub fn variant(&self) -> BenVariant {
+        self.variant
+    }
+}
+
+impl<R: BufRead> Iterator for XBenDecoder<R> {
+    type Item = io::Result<MkvRecord>;
+
+    fn next(&mut self) -> Option<io::Result<MkvRecord>> {
+        let rle = if self.length_prefixed {
+            read_lp32_line(&mut self.reader)
+        } else {
+            read_ben32_frame(&mut self.reader)
+        };
+
+        let rle = match rle {
+            Ok(Some(rle)) => rle,
+            Ok(None) => return None,
+            Err(e) => return Some(Err(e)),
+        };
+
+        let count = if self.variant == BenVariant::MkvChain {
+            match self.reader.read_u16::<BigEndian>() {
+                Ok(count) => count,
+                Err(e) => return Some(Err(e)),
+            }
+        } else {
+            1
+        };
+
+        Some(Ok((rle_to_vec(rle), count)))
+    }
+}
+
+/// A decoder for BEN files that are still being written by another process.
+///
+/// Bytes are pulled from the source as they become available and held
+/// until a complete frame has arrived, so running into the end of the
+/// source in the middle of a frame (or of the banner) is not an error.
+/// Instead, `next_record` returns an error of kind
+/// `io::ErrorKind::WouldBlock` and can simply be called again once more of
+/// the file has been written, giving a `tail -f`-like view of the ensemble.
+///
+/// # Example
+///
+/// ```
+/// use ben::decode::TailingBenDecoder;
+/// use ben::encode::BenEncoder;
+/// use ben::BenVariant;
+/// use std::collections::VecDeque;
+/// use std::io::ErrorKind;
+///
+/// let mut ben = Vec::new();
+/// let mut encoder = BenEncoder::new(&mut ben, BenVariant::Standard);
+/// encoder.write_assignment(vec![1, 1, 2, 2]).unwrap();
+/// drop(encoder);
+///
+/// // Only part of the file has been written so far
+/// let (first, rest) = ben.split_at(20);
+/// let mut decoder = TailingBenDecoder::new(first.iter().copied().collect::<VecDeque<u8>>());
+/// assert_eq!(decoder.next_record().unwrap_err().kind(), ErrorKind::WouldBlock);
+/// assert!(!decoder.at_frame_boundary());
+///
+/// // The rest of the file arrives
+/// decoder.get_mut().extend(rest);
+/// assert_eq!(decoder.next_record().unwrap(), (vec![1, 1, 2, 2], 1));
+/// assert!(decoder.at_frame_boundary());
+/// ```
+pub struct TailingBenDecoder<R: Read> {
+    source: R,
+    decoder: Option<BenDecoder<VecDeque<u8>>>,
+    pending: VecDeque<u8>,
+}
+
+impl<R: Read> TailingBenDecoder<R> {
+    pub fn new(source: R) -> Self {
+        TailingBenDecoder {
+            source,
+            decoder: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Returns a mutable reference to the source.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.source
+    }
+
+    /// Returns true if every byte that has been read from the source so far
+    /// belongs to a frame that has already been returned, i.e. the source is
+    /// not currently stopped in the middle of a frame.
+    pub fn at_frame_boundary(&self) -> bool {
+        match &self.decoder {
+            Some(decoder) => decoder.reader.inner.is_empty(),
+            None => self.pending.is_empty(),
+        }
+    }
+
+    /// Returns the number of bytes that have been read from the source but
+    /// do not yet form a complete frame.
+    pub fn buffered_bytes(&self) -> usize {
+        match &self.decoder {
+            Some(decoder) => decoder.reader.inner.len(),
+            None => self.pending.len(),
+        }
+    }
+
+    /// The length of the next frame in bytes, if enough of it has been
+    /// buffered to tell.
+    fn next_frame_len(decoder: &BenDecoder<VecDeque<u8>>) -> Option<usize> {
+        let buffer = &decoder.reader.inner;
+        if buffer.len() < 6 {
+            return None;
+        }
+
+        let max_val_bits = buffer[0] as u64;
+        let max_len_bits = buffer[1] as u64;
+        let n_bytes = u32::from_be_bytes([buffer[2], buffer[3], buffer[4], buffer[5]]) as u64;
+
+        let payload_len = if decoder.variant == BenVariant::Delta && decoder.samples_decoded > 0 {
+            (n_bytes * (max_val_bits + max_len_bits)).div_ceil(8)
+        } else {
+            n_bytes
+        };
+        let crc_len = if decoder.frame_crc { 4 } else { 0 };
+        let count_len = if decoder.variant == BenVariant::MkvChain {
+            2
+        } else {
+            0
+        };
+
+        Some((6 + crc_len + payload_len + count_len) as usize)
+    }
+
+    /// Decodes the next frame of the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind `io::ErrorKind::WouldBlock` if the source
+    /// does not (yet) hold a complete frame. Any other error means that the
+    /// file is invalid or that the source could not be read.
+    pub fn next_record(&mut self) -> io::Result<MkvRecord> {
+        let mut buffer = [0u8; 65536];
+        loop {
+            if self.decoder.is_none() && self.pending.len() >= 17 {
+                let decoder = BenDecoder::new(std::mem::take(&mut self.pending))?;
+                self.decoder = Some(decoder);
+            }
+
+            if let Some(decoder) = self.decoder.as_mut() {
+                if let Some(frame_len) = Self::next_frame_len(decoder) {
+                    if decoder.reader.inner.len() >= frame_len {
+                        return decoder
+                            .next()
+                            .expect("A complete frame is always buffered here");
+                    }
+                }
+            }
+
+            let n = match self.source.read(&mut buffer) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => 0,
+                Err(e) => return Err(e),
+            };
+            if n == 0 {
+                return Err(Error::new(
+                    io::ErrorKind::WouldBlock,
+                    "The next frame has not been completely written yet",
+                ));
+            }
+
+            match self.decoder.as_mut() {
+                Some(decoder) => decoder.reader.inner.extend(&buffer[..n]),
+                None => self.pending.extend(&buffer[..n]),
+            }
+        }
+    }
+}
+
+/// A decoder for files written with `encode::encode_template_ben`. The
+/// template assignment is read when the decoder is created, and every
+/// sample is rebuilt by applying its frame of changes to the template.
+///
+/// # Example
+///
+/// ```
+/// use ben::decode::TemplateBenDecoder;
+/// use ben::encode::encode_template_ben;
+///
+/// let template = vec![1, 1, 2, 2, 3, 3];
+/// let samples = vec![vec![1, 2, 2, 2, 3, 3], vec![1, 1, 2, 2, 3, 1]];
+///
+/// let mut buffer = Vec::new();
+/// encode_template_ben(&template, samples.clone(), &mut buffer).unwrap();
+///
+/// let decoder = TemplateBenDecoder::new(buffer.as_slice()).unwrap();
+/// assert_eq!(decoder.template(), template.as_slice());
+/// assert_eq!(decoder.collect::<Result<Vec<_>, _>>().unwrap(), samples);
+/// ```
+pub struct TemplateBenDecoder<R: Read> {
+    reader: R,
+    template: Vec<u16>,
+    samples_decoded: usize,
+}
+
+impl<R: Read> TemplateBenDecoder<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut check_buffer = [0u8; 17];
+        reader.read_exact(&mut check_buffer)?;
+        if &check_buffer != b"TEMPLATE BEN FILE" {
+            return Err(Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid file format",
+            ));
+        }
+
+        let max_val_bits = reader.read_u8()?;
+        let max_len_bits = reader.read_u8()?;
+        let n_bytes = reader.read_u32::<BigEndian>()?;
+        let template = rle_to_vec(decode_ben_line(
+            &mut reader,
+            max_val_bits,
+            max_len_bits,
+            n_bytes,
+        )?);
+
+        Ok(TemplateBenDecoder {
+            reader,
+            template,
+            samples_decoded: 0,
+        })
+    }
+
+    /// Returns the template assignment of the file.
+    pub fn template(&self) -> &[u16] {
+        &self.template
+    }
+
+    fn read_sample(&mut self, idx_bits: u8) -> io::Result<Vec<u16>> {
+        let val_bits = self.reader.read_u8()?;
+        let n_changes = self.reader.read_u32::<BigEndian>()?;
+        let changes = decode_delta_frame(&mut self.reader, idx_bits, val_bits, n_changes)?;
+
+        let mut assignment = self.template.clone();
+        for (idx, val) in changes {
+            match assignment.get_mut(idx as usize) {
+                Some(node) => *node = val,
+                None => {
+                    return Err(Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "Frame for sample {} changes node {} but the template only has {} nodes",
+                            self.samples_decoded + 1,
+                            idx,
+                            self.template.len()
+                        ),
+                    ));
+                }
+            }
+        }
+        Ok(assignment)
+    }
+}
+
+impl<R: Read> Iterator for TemplateBenDecoder<R> {
+    type Item = io::Result<Vec<u16>>;
+
+    fn next(&mut self) -> Option<io::Result<Vec<u16>>> {
+        let mut tmp_buffer = [0u8];
+        let idx_bits = match self.reader.read_exact(&mut tmp_buffer) {
+            Ok(()) => tmp_buffer[0],
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return None,
+            Err(e) => return Some(Err(e)),
+        };
+
+        let result = self.read_sample(idx_bits);
+        self.samples_decoded += 1;
+        Some(result)
+    }
+}
+
+/// A builder that allows for all of the optional behaviour of a
+/// `BenDecoder` to be configured at once.
+///
+/// A frame that fails one of the checks (`max_bytes`, `strict`,
+/// `expected_nodes`, or `fixed_widths`) is still read past before its error
+/// is returned, so iteration can carry on with the next frame.
+///
+/// # Example
+///
+/// ```
+/// use ben::decode::BenDecoderBuilder;
+/// use ben::encode::jsonl_encode_ben;
+/// use ben::BenVariant;
+///
+/// let input = r#"{"assignment": [1,1,2,2], "sample": 1}"#;
+///
+/// let mut ben = Vec::new();
+/// jsonl_encode_ben(input.as_bytes(), &mut ben, BenVariant::Standard).unwrap();
+///
+/// let decoder = BenDecoderBuilder::new()
+///     .strict(true)
+///     .expected_nodes(4)
+///     .build(ben.as_slice())
+///     .unwrap();
+///
+/// for record in decoder {
+///     let (assignment, _count) = record.unwrap();
+///     assert_eq!(assignment, vec![1, 1, 2, 2]);
+/// }
+/// ```
+#[derive(Default)]
+pub struct BenDecoderBuilder {
+    max_bytes: Option<u32>,
+    buffer_size: Option<usize>,
+    strict: bool,
+    strict_eof: bool,
+    expected_nodes: Option<usize>,
+    fixed_widths: Option<(u8, u8)>,
+    progress: Option<Box<dyn FnMut(usize) + Send>>,
+}
+
+impl BenDecoderBuilder {
+    /// Create a new builder with all of the options turned off.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reject any frame whose payload is larger than `max_bytes` bytes
+    /// rather than trying to allocate a buffer for it. For the delta frames
+    /// of a delta file, the payload length is worked out from the number of
+    /// changed nodes and the bit widths in the header.
+    pub fn max_bytes(mut self, max_bytes: u32) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Set the capacity of the buffer placed in front of the reader.
+    pub fn buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = Some(buffer_size);
+        self
+    }
+
+    /// In strict mode, frames whose headers claim bit widths outside of the
+    /// range 1..=16 are rejected with an error. Outside of strict mode, only
+    /// widths that cannot be decoded at all (0 or more than 32) are rejected.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// In strict EOF mode, bytes after the last frame of the file that do
+    /// not start a valid frame are reported as an error giving their count
+    /// and offset, rather than being decoded as if they were another frame.
+    /// This catches files that were accidentally concatenated byte-wise.
+    pub fn strict_eof(mut self, strict_eof: bool) -> Self {
+        self.strict_eof = strict_eof;
+        self
+    }
+
+    /// Reject any assignment vector that does not have exactly
+    /// `expected_nodes` entries.
+    pub fn expected_nodes(mut self, expected_nodes: usize) -> Self {
+        self.expected_nodes = Some(expected_nodes);
+        self
+    }
+
+    /// Expect every frame to use the given bit widths. See
+    /// `BenDecoder::with_fixed_widths`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either width is outside of the range 1..=16.
+    pub fn fixed_widths(mut self, max_val_bits: u8, max_len_bits: u8) -> Self {
+        assert!(
+            (1..=16).contains(&max_val_bits) && (1..=16).contains(&max_len_bits),
+            "Bit widths must be between 1 and 16"
+        );
+        self.fixed_widths = Some((max_val_bits, max_len_bits));
+        self
+    }
+
+    /// Set a callback that is invoked with the total number of samples
+    /// decoded so far after every frame.
+    pub fn progress(mut self, progress: impl FnMut(usize) + Send + 'static) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Read the BEN header from the reader and return the configured
+    /// `BenDecoder`.
+    ///
+    /// # Errors
+    ///
+    /// Returns a `DecoderInitError` if the header cannot be read or is not
+    /// a valid BEN header.
+    pub fn build<R: Read>(self, reader: R) -> Result<BenDecoder<BufReader<R>>, DecoderInitError> {
+        let reader = match self.buffer_size {
+            Some(size) => BufReader::with_capacity(size, reader),
+            None => BufReader::new(reader),
+        };
+
+        let mut decoder = BenDecoder::new(reader)?;
+        decoder.max_bytes = self.max_bytes;
+        decoder.strict = self.strict;
+        decoder.strict_eof = self.strict_eof;
+        decoder.expected_nodes = self.expected_nodes;
+        decoder.fixed_widths = self.fixed_widths;
+        decoder.progress = self.progress;
+        Ok(decoder)
+    }
+}
+
+/// This function takes a reader containing a single ben32 encoded assignment
+/// vector and decodes it into a full assignment vector of u16s.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the ben32 encoded assignment vector
+///
+/// # Returns
+///
+/// A vector of u16s containing the decoded assignment vector
+///
+/// # Errors
+///
+/// This function will return an error if the input reader is not a multiple of 4
+/// bytes long since each assignment vector is an run-length encoded as a 32 bit
+/// integer (2 bytes for the value and 2 bytes for the count).
+///
+fn decode_ben32_line<R: BufRead>(
+    mut reader: R,
+    variant: BenVariant,
+) -> io::Result<(Vec<u16>, u16)> {
+    let output_vec = match read_ben32_frame(&mut reader)? {
+        Some(rle) => rle_to_vec(rle),
+        None => return Err(Error::from(io::ErrorKind::UnexpectedEof)),
+    };
+
+    let count = if variant == BenVariant::MkvChain {
+        reader.read_u16::<BigEndian>().map_err(|e| {
+            Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Error when reading sample: {}", e),
+            )
+        })?
+    } else {
+        1
+    };
+
+    Ok((output_vec, count))
+}
+
+/// This function takes a reader containing a file encoded with the
+/// "ben32" format and decodes it into a JSONL file.
+///
+/// The output JSONL file will have the formatting
+///
+/// ```json
+/// {"assignment": [...], "sample": #}
+/// ```
+///
+/// # Arguments
 ///
 /// * `reader` - A reader containing the ben32 encoded assignment vectors
 /// * `writer` - A writer that will contain the JSONL formatted assignment vectors
+/// * `first_sample` - The sample number given to the first assignment vector
+/// * `variant` - The variant of the BEN file that the ben32 data came from
 ///
 /// # Returns
 ///
@@ -255,13 +1452,12 @@ fn decode_ben32_line<R: BufRead>(
 fn jsonl_decode_ben32<R: BufRead, W: Write>(
     mut reader: R,
     mut writer: W,
-    starting_sample: usize,
+    first_sample: usize,
     variant: BenVariant,
 ) -> io::Result<()> {
-    let mut sample_number = 1;
+    let mut sample_number = 0;
     loop {
         let result = decode_ben32_line(&mut reader, variant);
-        println!("In jsonl_decode_ben32 result {:?}", result);
         if let Err(e) = result {
             if e.kind() == io::ErrorKind::UnexpectedEof {
                 return Ok(());
@@ -275,15 +1471,62 @@ fn jsonl_decode_ben32<R: BufRead, W: Write>(
             // Write the reconstructed vector as JSON to the output file
             let line = json!({
                 "assignment": output_vec,
-                "sample": sample_number + starting_sample,
+                "sample": first_sample + sample_number,
+            })
+            .to_string()
+                + "\n";
+
+            writer.write_all(line.as_bytes())?;
+            sample_number += 1;
+        }
+    }
+}
+
+/// This function takes a reader containing length-prefixed ben32 data and
+/// decodes it into a JSONL file. Since every assignment vector carries its
+/// own length, there is no need to scan for separators here.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the length-prefixed ben32 encoded assignment vectors
+/// * `writer` - A writer that will contain the JSONL formatted assignment vectors
+/// * `first_sample` - The sample number given to the first assignment vector
+/// * `variant` - The variant of the BEN file that the data came from
+///
+/// # Returns
+///
+/// An io::Result containing the result of the operation
+fn jsonl_decode_lp32<R: Read, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    first_sample: usize,
+    variant: BenVariant,
+) -> io::Result<()> {
+    let mut sample_number = first_sample;
+    while let Some(rle) = read_lp32_line(&mut reader)? {
+        let count = if variant == BenVariant::MkvChain {
+            reader.read_u16::<BigEndian>()?
+        } else {
+            1
+        };
+
+        let assignment = rle_to_vec(rle);
+        for _ in 0..count {
+            let line = json!({
+                "assignment": assignment,
+                "sample": sample_number,
             })
             .to_string()
                 + "\n";
 
             writer.write_all(line.as_bytes())?;
             sample_number += 1;
+            log!("Decoding sample: {}\r", sample_number - first_sample);
         }
     }
+    logln!();
+    logln!("Done!");
+    Ok(())
 }
 
 /// This function takes a reader containing a file encoded in the XBEN format
@@ -321,6 +1564,14 @@ pub fn decode_xben_to_ben<R: BufRead, W: Write>(reader: R, mut writer: W) -> io:
             writer.write_all(b"MKVCHAIN BEN FILE")?;
             BenVariant::MkvChain
         }
+        b"STANDARD BEN LP32" => {
+            writer.write_all(b"STANDARD BEN FILE")?;
+            return lp32_to_ben_lines(decoder, writer, BenVariant::Standard);
+        }
+        b"MKVCHAIN BEN LP32" => {
+            writer.write_all(b"MKVCHAIN BEN FILE")?;
+            return lp32_to_ben_lines(decoder, writer, BenVariant::MkvChain);
+        }
         _ => {
             return Err(Error::new(
                 io::ErrorKind::InvalidData,
@@ -340,35 +1591,7 @@ pub fn decode_xben_to_ben<R: BufRead, W: Write>(reader: R, mut writer: W) -> io:
 
         overflow.extend(&buffer[..count]);
 
-        let mut last_valid_assignment = 0;
-
-        // It is technically faster to read backwards from the last
-        // multiple of 4 smaller than the length of the overflow buffer
-        // but this provides only a minute speedup in almost all cases (maybe a
-        // few seconds). Reading from the front is both safer from a
-        // maintenance perspective and allows for a better progress indicator
-        match variant {
-            BenVariant::Standard => {
-                for i in (3..overflow.len()).step_by(4) {
-                    if overflow[i - 3..=i] == [0, 0, 0, 0] {
-                        last_valid_assignment = i + 1;
-                        line_count += 1;
-                        log!("Decoding sample: {}\r", line_count);
-                    }
-                }
-            }
-            BenVariant::MkvChain => {
-                for i in (3..overflow.len() - 2).step_by(2) {
-                    if overflow[i - 3..=i] == [0, 0, 0, 0] {
-                        last_valid_assignment = i + 3;
-                        let lines = &overflow[i + 1..i + 3];
-                        let n_lines = u16::from_be_bytes([lines[0], lines[1]]);
-                        line_count += n_lines as usize;
-                        log!("Decoding sample: {}\r", line_count);
-                    }
-                }
-            }
-        }
+        let last_valid_assignment = scan_ben32_frames(&overflow, variant, &mut line_count);
 
         if last_valid_assignment == 0 {
             continue;
@@ -382,6 +1605,58 @@ pub fn decode_xben_to_ben<R: BufRead, W: Write>(reader: R, mut writer: W) -> io:
     Ok(())
 }
 
+/// Scans a buffer of (separator framed) ben32 data for the ends of the
+/// frames that it holds, adding the number of samples in every complete
+/// frame to `line_count`.
+///
+/// # Arguments
+///
+/// * `buffer` - The ben32 data, starting at the beginning of a frame
+/// * `variant` - The variant of the BEN file that the ben32 data came from
+/// * `line_count` - The running number of samples seen so far
+///
+/// # Returns
+///
+/// The number of bytes in the buffer up to the end of the last complete
+/// frame (0 if there are no complete frames)
+fn scan_ben32_frames(buffer: &[u8], variant: BenVariant, line_count: &mut usize) -> usize {
+    let mut last_valid_assignment = 0;
+
+    // It is technically faster to read backwards from the last
+    // multiple of 4 smaller than the length of the overflow buffer
+    // but this provides only a minute speedup in almost all cases (maybe a
+    // few seconds). Reading from the front is both safer from a
+    // maintenance perspective and allows for a better progress indicator
+    match variant {
+        BenVariant::Standard => {
+            for i in (3..buffer.len()).step_by(4) {
+                if buffer[i - 3..=i] == [0, 0, 0, 0] {
+                    last_valid_assignment = i + 1;
+                    *line_count += 1;
+                    log!("Decoding sample: {}\r", line_count);
+                }
+            }
+        }
+        BenVariant::MkvChain => {
+            // Need a different step size here because each assignment
+            // vector is no longer guaranteed to be a multiple of 4 bytes
+            // due to the 2-byte repetition count appended at the end
+            for i in (3..buffer.len().saturating_sub(2)).step_by(2) {
+                if buffer[i - 3..=i] == [0, 0, 0, 0] {
+                    last_valid_assignment = i + 3;
+                    let lines = &buffer[i + 1..i + 3];
+                    let n_lines = u16::from_be_bytes([lines[0], lines[1]]);
+                    *line_count += n_lines as usize;
+                    log!("Decoding sample: {}\r", line_count);
+                }
+            }
+        }
+        BenVariant::Delta => unreachable!("XBEN files never contain delta frames"),
+    }
+
+    last_valid_assignment
+}
+
 /// This is a convenience function that decodes a general level 9 LZMA2 compressed file.
 ///
 /// # Arguments
@@ -439,12 +1714,27 @@ pub fn xz_decompress<R: BufRead, W: Write>(reader: R, mut writer: W) -> io::Resu
 /// # Returns
 ///
 /// A vector of tuples containing the run-length encoded assignment vector
+///
+/// # Errors
+///
+/// Returns an `InvalidData` error if either bit width is 0 or larger than 32,
+/// since no payload can be decoded with those widths.
 pub fn decode_ben_line<R: Read>(
     mut reader: R,
     max_val_bits: u8,
     max_len_bits: u8,
     n_bytes: u32,
 ) -> io::Result<Vec<(u16, u16)>> {
+    if !(1..=32).contains(&max_val_bits) || !(1..=32).contains(&max_len_bits) {
+        return Err(Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Invalid frame header: max_val_bits = {}, max_len_bits = {}",
+                max_val_bits, max_len_bits
+            ),
+        ));
+    }
+
     let mut assign_bits: Vec<u8> = vec![0; n_bytes as usize];
     reader.read_exact(&mut assign_bits)?;
 
@@ -468,71 +1758,603 @@ pub fn decode_ben_line<R: Read>(
         if n_bits_in_buff >= max_val_bits as u16 && !val_set {
             val = (buffer >> (32 - max_val_bits)) as u16;
 
-            buffer = (buffer << max_val_bits) as u32;
-            n_bits_in_buff -= max_val_bits as u16;
-            val_set = true;
+            buffer = (buffer << max_val_bits) as u32;
+            n_bits_in_buff -= max_val_bits as u16;
+            val_set = true;
+        }
+
+        if n_bits_in_buff >= max_len_bits as u16 && val_set && !len_set {
+            len = (buffer >> (32 - max_len_bits)) as u16;
+            buffer = buffer << max_len_bits;
+            n_bits_in_buff -= max_len_bits as u16;
+            len_set = true;
+        }
+
+        if val_set && len_set {
+            // If max_val_bits and max_len_bits are <= 4
+            // then the rle can bet (0,0) pairs pushed to it
+            if len > 0 {
+                output_rle.push((val, len));
+            }
+            val_set = false;
+            len_set = false;
+        }
+
+        while n_bits_in_buff >= max_val_bits as u16 + max_len_bits as u16 {
+            if n_bits_in_buff >= max_val_bits as u16 && !val_set {
+                val = (buffer >> (32 - max_val_bits)) as u16;
+                buffer = (buffer << max_val_bits) as u32;
+                n_bits_in_buff -= max_val_bits as u16;
+                val_set = true;
+            }
+
+            if n_bits_in_buff >= max_len_bits as u16 && val_set && !len_set {
+                len = (buffer >> (32 - max_len_bits)) as u16;
+                buffer = buffer << max_len_bits;
+                n_bits_in_buff -= max_len_bits as u16;
+                len_set = true;
+            }
+
+            if val_set && len_set {
+                // If the max_val_bits and max_len_bits are <= 4
+                // then the rle can bet (0,0) pairs pushed to it
+                if len > 0 {
+                    output_rle.push((val, len));
+                }
+                val_set = false;
+                len_set = false;
+            }
+        }
+    }
+
+    Ok(output_rle)
+}
+
+/// Unpacks the `(value, length)` runs of a frame payload whose bit widths
+/// are known up front (see `BenDecoder::with_fixed_widths`). Since the
+/// widths are between 1 and 16 bits, a whole run always fits into the
+/// buffer, so each run is pulled out with a single shift and mask rather
+/// than a value and a length at a time. Runs of length 0 (the padding at the
+/// end of the payload) are dropped, the same as in `decode_ben_line`.
+fn unpack_fixed_width_runs(payload: &[u8], max_val_bits: u8, max_len_bits: u8) -> Vec<(u16, u16)> {
+    let run_bits = (max_val_bits + max_len_bits) as u32;
+    let len_mask = (1u64 << max_len_bits) - 1;
+    let n_runs = payload.len() * 8 / run_bits as usize;
+
+    let mut runs = Vec::with_capacity(n_runs);
+    let mut bytes = payload.iter();
+    let mut buffer: u64 = 0;
+    let mut n_bits_in_buff: u32 = 0;
+
+    for _ in 0..n_runs {
+        while n_bits_in_buff < run_bits {
+            buffer = (buffer << 8) | bytes.next().copied().unwrap_or(0) as u64;
+            n_bits_in_buff += 8;
+        }
+        n_bits_in_buff -= run_bits;
+        let run = buffer >> n_bits_in_buff;
+        buffer &= (1u64 << n_bits_in_buff) - 1;
+
+        let len = (run & len_mask) as u16;
+        if len > 0 {
+            runs.push(((run >> max_len_bits) as u16, len));
+        }
+    }
+
+    runs
+}
+
+/// This is a helper function that reads the payload of a single delta frame
+/// and returns the list of `(node_index, new_label)` changes that it encodes.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the delta frame payload
+/// * `idx_bits` - The number of bits used to encode each node index
+/// * `val_bits` - The number of bits used to encode each label
+/// * `n_changes` - The number of changes stored in the frame
+///
+/// # Returns
+///
+/// A vector of tuples containing the index of each changed node and its new label
+fn decode_delta_frame<R: Read>(
+    reader: R,
+    idx_bits: u8,
+    val_bits: u8,
+    n_changes: u32,
+) -> io::Result<Vec<(u32, u16)>> {
+    if !(1..=32).contains(&idx_bits) || !(1..=16).contains(&val_bits) {
+        return Err(Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Invalid delta frame header: idx_bits = {}, val_bits = {}",
+                idx_bits, val_bits
+            ),
+        ));
+    }
+
+    // The number of changes comes straight from the file, so the payload is
+    // read through `take` rather than allocated up front.
+    let payload_len = (n_changes as u64 * (idx_bits as u64 + val_bits as u64)).div_ceil(8);
+    let mut payload = Vec::new();
+    reader.take(payload_len).read_to_end(&mut payload)?;
+    if (payload.len() as u64) < payload_len {
+        return Err(Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!(
+                "Delta frame with {} changes is truncated after {} of {} bytes",
+                n_changes,
+                payload.len(),
+                payload_len
+            ),
+        ));
+    }
+
+    let mut bytes = payload.into_iter();
+    let mut buffer: u64 = 0;
+    let mut n_bits_in_buff: u8 = 0;
+
+    let mut read_field = |n_bits: u8| -> u64 {
+        while n_bits_in_buff < n_bits {
+            buffer = (buffer << 8) | bytes.next().unwrap_or(0) as u64;
+            n_bits_in_buff += 8;
+        }
+        n_bits_in_buff -= n_bits;
+        let field = buffer >> n_bits_in_buff;
+        buffer &= (1u64 << n_bits_in_buff) - 1;
+        field
+    };
+
+    let mut changes = Vec::with_capacity((n_changes as usize).min(MAX_PREALLOCATED_RUNS));
+    for _ in 0..n_changes {
+        let idx = read_field(idx_bits) as u32;
+        let val = read_field(val_bits) as u16;
+        changes.push((idx, val));
+    }
+
+    Ok(changes)
+}
+
+/// This function takes a reader containing a file encoded in the BEN format
+/// and decodes it into a JSONL file.
+///
+/// The output JSONL file will have the formatting
+///
+/// ```json
+/// {"assignment": [...], "sample": #}
+/// ```
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the ben encoded assignment vectors
+/// * `writer` - A writer that will contain the JSONL formatted assignment vectors
+///
+/// # Returns
+///
+/// An io::Result containing the result of the operation
+///
+/// # Errors
+///
+/// This function will return an error if the input reader contains invalid ben
+/// data or if the the decode method encounters while trying to extract a single
+/// assignment vector, that error is then propagated.
+pub fn jsonl_decode_ben<R: Read, W: Write>(reader: R, writer: W) -> io::Result<()> {
+    jsonl_decode_ben_with_base(reader, writer, 1)
+}
+
+/// Same as `jsonl_decode_ben`, but the writer is flushed after every
+/// `flush_every` samples so that the output shows up promptly when it is
+/// being consumed while the decode is still in progress. A `flush_every` of
+/// 0 never flushes early. The writer is always flushed at the end.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the ben encoded assignment vectors
+/// * `writer` - A writer that will contain the JSONL formatted assignment vectors
+/// * `flush_every` - The number of samples to write between flushes
+///
+/// # Returns
+///
+/// An io::Result containing the result of the operation
+pub fn jsonl_decode_ben_with_flush<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    flush_every: usize,
+) -> io::Result<()> {
+    let mut writer = LineFlushWriter::new(writer, flush_every);
+    jsonl_decode_ben(reader, &mut writer)?;
+    writer.flush()
+}
+
+/// Decodes a gzip-compressed BEN file (as written by
+/// `encode::jsonl_encode_ben_gz`) into a JSONL file.
+///
+/// This function is only available with the `gzip` feature.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the gzipped BEN file
+/// * `writer` - A writer that will contain the JSONL formatted assignment vectors
+///
+/// # Returns
+///
+/// An io::Result containing the result of the operation
+#[cfg(feature = "gzip")]
+pub fn decode_ben_gz_to_jsonl<R: Read, W: Write>(reader: R, writer: W) -> io::Result<()> {
+    jsonl_decode_ben(flate2::read::MultiGzDecoder::new(reader), writer)
+}
+
+/// Opens a BEN stream that may or may not be gzip-compressed. The first two
+/// bytes of the reader are read and put back in front of it and, if they are
+/// the gzip magic number `1F 8B`, the reader is wrapped in a gzip decoder.
+/// Otherwise the reader is returned as is, so the result can be passed to
+/// any of the decode functions in either case.
+///
+/// This function is only available with the `gzip` feature.
+///
+/// # Arguments
+///
+/// * `reader` - A buffered reader containing a BEN file or a gzipped BEN file
+///
+/// # Returns
+///
+/// A reader over the uncompressed BEN file
+#[cfg(feature = "gzip")]
+pub fn open_ben_auto<R: BufRead + 'static>(mut reader: R) -> io::Result<Box<dyn Read>> {
+    const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+    // A single `fill_buf` can come back with only one byte (e.g. from a
+    // pipe), so read until both bytes are in or the input runs out.
+    let mut head = Vec::with_capacity(GZIP_MAGIC.len());
+    (&mut reader)
+        .take(GZIP_MAGIC.len() as u64)
+        .read_to_end(&mut head)?;
+    let is_gzip = head == GZIP_MAGIC;
+    let reader = io::Cursor::new(head).chain(reader);
+    if is_gzip {
+        Ok(Box::new(flate2::bufread::MultiGzDecoder::new(reader)))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Same as `jsonl_decode_ben`, but the sample numbers written to the JSONL
+/// file start at `sample_base` rather than 1. Passing 0 gives zero-based
+/// sample numbers.
+///
+/// Wide BEN files (with 32-bit labels, see the `wide` module) are detected
+/// from their banner and decoded as well.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the ben encoded assignment vectors
+/// * `writer` - A writer that will contain the JSONL formatted assignment vectors
+/// * `sample_base` - The sample number given to the first sample in the file
+///
+/// # Returns
+///
+/// An io::Result containing the result of the operation
+pub fn jsonl_decode_ben_with_base<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    sample_base: usize,
+) -> io::Result<()> {
+    let mut reader = reader;
+    let mut banner = Vec::with_capacity(17);
+    (&mut reader).take(17).read_to_end(&mut banner)?;
+    let is_wide = wide::is_wide_banner(&banner);
+    let reader = io::Cursor::new(banner).chain(reader);
+
+    if is_wide {
+        return wide::jsonl_decode_ben_wide(reader, writer, sample_base);
+    }
+
+    let mut ben_decoder = BenDecoder::new(reader)?;
+    ben_decoder.sample_base = sample_base;
+    ben_decoder.write_all_jsonl(writer)
+}
+
+/// Same as `jsonl_decode_ben`, but the repeated samples of a MkvChain file
+/// are not expanded. Each frame is written as a single line with a
+/// `"weight"` field holding its repetition count:
+///
+/// ```json
+/// {"assignment": [...], "sample": #, "weight": #}
+/// ```
+///
+/// The `"sample"` field is the number of the first sample in the run, so
+/// the sample numbers match those of `jsonl_decode_ben` and the weights add
+/// up to the total number of samples. Frames of the other variants always
+/// have a weight of 1.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the ben encoded assignment vectors
+/// * `writer` - A writer that will contain the JSONL formatted assignment vectors
+///
+/// # Returns
+///
+/// An io::Result containing the result of the operation
+///
+/// # Errors
+///
+/// This function will return an error if the input reader contains invalid ben
+/// data.
+pub fn jsonl_decode_ben_weighted<R: Read, W: Write>(reader: R, mut writer: W) -> io::Result<()> {
+    let mut sample = 1;
+    for record in BenDecoder::new(reader)? {
+        let (assignment, count) = record?;
+        let line = json!({
+            "assignment": assignment,
+            "sample": sample,
+            "weight": count,
+        })
+        .to_string()
+            + "\n";
+        writer.write_all(line.as_bytes())?;
+        sample += count as usize;
+    }
+    Ok(())
+}
+
+/// Same as `jsonl_decode_ben`, but the output is split across several JSONL
+/// files with `chunk_samples` samples each (the last one may have fewer).
+/// The files are opened by calling `make_writer` with the index of the part
+/// (starting at 0) whenever a new one is needed, so the caller decides how
+/// they are named. The sample numbers keep counting up across the parts.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the ben encoded assignment vectors
+/// * `chunk_samples` - The number of samples to write to each part
+/// * `make_writer` - Opens the writer for the part with the given index
+///
+/// # Returns
+///
+/// The number of parts that were written (0 if the file has no samples)
+///
+/// # Errors
+///
+/// This function will return an error if `chunk_samples` is 0, if the input
+/// reader contains invalid ben data, or if `make_writer` returns an error.
+pub fn jsonl_decode_ben_split<R, W, F>(
+    reader: R,
+    chunk_samples: usize,
+    mut make_writer: F,
+) -> io::Result<usize>
+where
+    R: Read,
+    W: Write,
+    F: FnMut(usize) -> io::Result<W>,
+{
+    if chunk_samples == 0 {
+        return Err(Error::new(
+            io::ErrorKind::InvalidInput,
+            "Cannot split the output into parts of 0 samples",
+        ));
+    }
+
+    let mut writer: Option<W> = None;
+    let mut n_parts = 0;
+    let mut sample = 0;
+    for record in BenDecoder::new(reader)? {
+        let (assignment, count) = record?;
+        for _ in 0..count {
+            if sample % chunk_samples == 0 {
+                if let Some(mut previous) = writer.take() {
+                    previous.flush()?;
+                }
+                writer = Some(make_writer(n_parts)?);
+                n_parts += 1;
+            }
+            sample += 1;
+
+            let line = json!({
+                "assignment": assignment,
+                "sample": sample,
+            })
+            .to_string()
+                + "\n";
+            if let Some(writer) = writer.as_mut() {
+                writer.write_all(line.as_bytes())?;
+            }
+        }
+    }
+
+    if let Some(mut writer) = writer {
+        writer.flush()?;
+    }
+    Ok(n_parts)
+}
+
+/// Writes numbered samples (such as those coming out of a
+/// `SubsampleDecoder`) to a JSONL file. If `renumber` is set, the samples
+/// are numbered 1..K in the order that they are written. Otherwise, each
+/// sample keeps the number that it was handed over with.
+fn write_numbered_jsonl<I, W>(samples: I, mut writer: W, renumber: bool) -> io::Result<()>
+where
+    I: Iterator<Item = io::Result<(usize, Vec<u16>)>>,
+    W: Write,
+{
+    for (i, result) in samples.enumerate() {
+        let (sample, assignment) = result?;
+        let sample = if renumber { i + 1 } else { sample };
+        let line = json!({
+            "assignment": assignment,
+            "sample": sample,
+        })
+        .to_string()
+            + "\n";
+        writer.write_all(line.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Decodes only the samples of a BEN file that are in the selection into a
+/// JSONL file. MkvChain frames are expanded according to their repetition
+/// count before the selection is applied.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the ben encoded assignment vectors
+/// * `writer` - A writer that will contain the JSONL formatted assignment vectors
+/// * `selection` - The samples to keep
+/// * `renumber` - If true, the `"sample"` field of the output is numbered
+///   1..K in output order. If false, it is the position of the sample in
+///   the original file.
+///
+/// # Returns
+///
+/// An io::Result containing the result of the operation
+///
+/// # Errors
+///
+/// This function will return an error if the selection is
+/// `Selection::Every(0)` or if the input reader contains invalid ben data.
+pub fn jsonl_decode_ben_subsample<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    selection: Selection,
+    renumber: bool,
+) -> io::Result<()> {
+    if selection == Selection::Every(0) {
+        return Err(Error::new(
+            io::ErrorKind::InvalidInput,
+            "Cannot select every 0th sample",
+        ));
+    }
+
+    let decoder = BenDecoder::new(reader)?;
+    write_numbered_jsonl(SubsampleDecoder::new(decoder, selection), writer, renumber)
+}
+
+/// Decodes only the samples of an XBEN file that are in the selection into a
+/// JSONL file. This is the XBEN counterpart of `jsonl_decode_ben_subsample`:
+/// MkvChain frames are expanded according to their repetition count before
+/// the selection is applied, and the `"sample"` field of the output is the
+/// position of the sample in the original file.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the xben encoded assignment vectors
+/// * `writer` - A writer that will contain the JSONL formatted assignment vectors
+/// * `selection` - The samples to keep
+///
+/// # Returns
+///
+/// An io::Result containing the result of the operation
+///
+/// # Errors
+///
+/// This function will return an error if the selection is
+/// `Selection::Every(0)` or if the input reader contains invalid xben data.
+pub fn subsample_xben_to_jsonl<R: BufRead, W: Write>(
+    reader: R,
+    writer: W,
+    selection: Selection,
+) -> io::Result<()> {
+    if selection == Selection::Every(0) {
+        return Err(Error::new(
+            io::ErrorKind::InvalidInput,
+            "Cannot select every 0th sample",
+        ));
+    }
+
+    let decoder = XBenDecoder::new(reader)?;
+    write_numbered_jsonl(SubsampleDecoder::new(decoder, selection), writer, false)
+}
+
+/// This function decodes as many samples as it can from a BEN file and
+/// stops at the first error. This is useful for salvaging the valid prefix
+/// of a file that has been truncated or corrupted.
+///
+/// MkvChain frames are expanded, so the returned vector contains one
+/// assignment vector per sample.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the ben encoded assignment vectors
+///
+/// # Returns
+///
+/// A tuple containing all of the samples that were decoded cleanly along
+/// with the error that stopped the decoding, or `None` if the end of the
+/// file was reached without any errors.
+pub fn decode_ben_best_effort<R: Read>(reader: R) -> (Vec<Vec<u16>>, Option<io::Error>) {
+    let mut samples = Vec::new();
+
+    let ben_decoder = match BenDecoder::new(reader) {
+        Ok(decoder) => decoder,
+        Err(e) => return (samples, Some(e.into())),
+    };
+
+    for sample in adapters::expand_counts(ben_decoder) {
+        match sample {
+            Ok(assignment) => samples.push(assignment),
+            Err(e) => return (samples, Some(e)),
         }
+    }
 
-        if n_bits_in_buff >= max_len_bits as u16 && val_set && !len_set {
-            len = (buffer >> (32 - max_len_bits)) as u16;
-            buffer = buffer << max_len_bits;
-            n_bits_in_buff -= max_len_bits as u16;
-            len_set = true;
-        }
+    (samples, None)
+}
 
-        if val_set && len_set {
-            // If max_val_bits and max_len_bits are <= 4
-            // then the rle can bet (0,0) pairs pushed to it
-            if len > 0 {
-                output_rle.push((val, len));
-            }
-            val_set = false;
-            len_set = false;
-        }
+/// Decodes every sample of a BEN file into a single flat, row-major buffer
+/// (e.g. to back a 2D numpy array) with one row of `n_nodes` labels per
+/// sample. This is the inverse of `encode::encode_matrix_ben`. MkvChain
+/// records are expanded according to their repetition count.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the ben encoded assignment vectors
+///
+/// # Returns
+///
+/// A tuple containing the buffer of `n_samples * n_nodes` labels and
+/// `n_nodes` (both empty if the file has no samples)
+///
+/// # Errors
+///
+/// This function will return an error if the input reader contains invalid ben
+/// data or if the samples do not all have the same number of nodes.
+pub fn decode_ben_to_matrix<R: Read>(reader: R) -> io::Result<(Vec<u16>, usize)> {
+    let mut flat = Vec::new();
+    let mut n_nodes = None;
+    let mut n_samples = 0;
 
-        while n_bits_in_buff >= max_val_bits as u16 + max_len_bits as u16 {
-            if n_bits_in_buff >= max_val_bits as u16 && !val_set {
-                val = (buffer >> (32 - max_val_bits)) as u16;
-                buffer = (buffer << max_val_bits) as u32;
-                n_bits_in_buff -= max_val_bits as u16;
-                val_set = true;
-            }
+    for record in BenDecoder::new(reader)? {
+        let (assignment, count) = record?;
 
-            if n_bits_in_buff >= max_len_bits as u16 && val_set && !len_set {
-                len = (buffer >> (32 - max_len_bits)) as u16;
-                buffer = buffer << max_len_bits;
-                n_bits_in_buff -= max_len_bits as u16;
-                len_set = true;
-            }
+        let n_nodes = *n_nodes.get_or_insert(assignment.len());
+        if assignment.len() != n_nodes {
+            return Err(Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Sample {} has {} nodes but the first sample has {}",
+                    n_samples + 1,
+                    assignment.len(),
+                    n_nodes
+                ),
+            ));
+        }
 
-            if val_set && len_set {
-                // If the max_val_bits and max_len_bits are <= 4
-                // then the rle can bet (0,0) pairs pushed to it
-                if len > 0 {
-                    output_rle.push((val, len));
-                }
-                val_set = false;
-                len_set = false;
-            }
+        for _ in 0..count {
+            flat.extend_from_slice(&assignment);
         }
+        n_samples += count as usize;
     }
 
-    Ok(output_rle)
+    Ok((flat, n_nodes.unwrap_or(0)))
 }
 
 /// This function takes a reader containing a file encoded in the BEN format
-/// and decodes it into a JSONL file.
-///
-/// The output JSONL file will have the formatting
-///
-/// ```json
-/// {"assignment": [...], "sample": #}
-/// ```
+/// and decodes it into a plain text file in which every line contains the
+/// assignment vector of a single sample as space-separated integers. This
+/// is convenient for piping into tools like `awk` or `numpy.loadtxt`.
 ///
 /// # Arguments
 ///
 /// * `reader` - A reader containing the ben encoded assignment vectors
-/// * `writer` - A writer that will contain the JSONL formatted assignment vectors
+/// * `writer` - A writer that will contain the space-separated assignment vectors
 ///
 /// # Returns
 ///
@@ -541,11 +2363,24 @@ pub fn decode_ben_line<R: Read>(
 /// # Errors
 ///
 /// This function will return an error if the input reader contains invalid ben
-/// data or if the the decode method encounters while trying to extract a single
-/// assignment vector, that error is then propagated.
-pub fn jsonl_decode_ben<R: Read, W: Write>(reader: R, writer: W) -> io::Result<()> {
-    let mut ben_decoder = BenDecoder::new(reader)?;
-    ben_decoder.write_all_jsonl(writer)
+/// data or if there is an issue writing to the writer.
+pub fn ben_to_txt<R: Read, W: Write>(reader: R, mut writer: W) -> io::Result<()> {
+    let ben_decoder = BenDecoder::new(reader)?;
+
+    for record in ben_decoder {
+        let (assignment, count) = record?;
+        let line = assignment
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>()
+            .join(" ")
+            + "\n";
+
+        for _ in 0..count {
+            writer.write_all(line.as_bytes())?;
+        }
+    }
+    Ok(())
 }
 
 /// This function takes a reader containing a file encoded in the XBEN format
@@ -571,7 +2406,50 @@ pub fn jsonl_decode_ben<R: Read, W: Write>(reader: R, writer: W) -> io::Result<(
 /// This function will return an error if the input reader contains invalid xben
 /// data or if the the decode method encounters while trying to extract a single
 /// assignment vector, that error is then propagated.
-pub fn jsonl_decode_xben<R: BufRead, W: Write>(reader: R, mut writer: W) -> io::Result<()> {
+pub fn jsonl_decode_xben<R: BufRead, W: Write>(reader: R, writer: W) -> io::Result<()> {
+    jsonl_decode_xben_with_base(reader, writer, 1)
+}
+
+/// Same as `jsonl_decode_xben`, but the writer is flushed after every
+/// `flush_every` samples (see `jsonl_decode_ben_with_flush`).
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the xben encoded assignment vectors
+/// * `writer` - A writer that will contain the JSONL formatted assignment vectors
+/// * `flush_every` - The number of samples to write between flushes
+///
+/// # Returns
+///
+/// An io::Result containing the result of the operation
+pub fn jsonl_decode_xben_with_flush<R: BufRead, W: Write>(
+    reader: R,
+    writer: W,
+    flush_every: usize,
+) -> io::Result<()> {
+    let mut writer = LineFlushWriter::new(writer, flush_every);
+    jsonl_decode_xben(reader, &mut writer)?;
+    writer.flush()
+}
+
+/// Same as `jsonl_decode_xben`, but the sample numbers written to the JSONL
+/// file start at `sample_base` rather than 1. Passing 0 gives zero-based
+/// sample numbers.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the xben encoded assignment vectors
+/// * `writer` - A writer that will contain the JSONL formatted assignment vectors
+/// * `sample_base` - The sample number given to the first sample in the file
+///
+/// # Returns
+///
+/// An io::Result containing the result of the operation
+pub fn jsonl_decode_xben_with_base<R: BufRead, W: Write>(
+    reader: R,
+    mut writer: W,
+    sample_base: usize,
+) -> io::Result<()> {
     let mut decoder = xz2::read::XzDecoder::new(reader);
 
     let mut first_buffer = [0u8; 17];
@@ -583,6 +2461,12 @@ pub fn jsonl_decode_xben<R: BufRead, W: Write>(reader: R, mut writer: W) -> io::
     let variant = match &first_buffer {
         b"STANDARD BEN FILE" => BenVariant::Standard,
         b"MKVCHAIN BEN FILE" => BenVariant::MkvChain,
+        b"STANDARD BEN LP32" => {
+            return jsonl_decode_lp32(decoder, writer, sample_base, BenVariant::Standard);
+        }
+        b"MKVCHAIN BEN LP32" => {
+            return jsonl_decode_lp32(decoder, writer, sample_base, BenVariant::MkvChain);
+        }
         _ => {
             return Err(Error::new(
                 io::ErrorKind::InvalidData,
@@ -595,7 +2479,7 @@ pub fn jsonl_decode_xben<R: BufRead, W: Write>(reader: R, mut writer: W) -> io::
     let mut overflow: Vec<u8> = Vec::new();
 
     let mut line_count: usize = 0;
-    let mut starting_sample: usize = 0;
+    let mut starting_sample: usize = sample_base;
     while let Ok(count) = decoder.read(&mut buffer) {
         if count == 0 {
             break;
@@ -603,38 +2487,7 @@ pub fn jsonl_decode_xben<R: BufRead, W: Write>(reader: R, mut writer: W) -> io::
 
         overflow.extend(&buffer[..count]);
 
-        let mut last_valid_assignment = 0;
-
-        // It is technically faster to read backwards from the last
-        // multiple of 4 smaller than the length of the overflow buffer
-        // but this provides only a minute speedup in almost all cases (maybe a
-        // few seconds). Reading from the front is both safer from a
-        // maintenance perspective and allows for a better progress indicator
-        match variant {
-            BenVariant::Standard => {
-                for i in (3..overflow.len()).step_by(4) {
-                    if overflow[i - 3..=i] == [0, 0, 0, 0] {
-                        last_valid_assignment = i + 1;
-                        line_count += 1;
-                        log!("Decoding sample: {}\r", line_count);
-                    }
-                }
-            }
-            BenVariant::MkvChain => {
-                // Need a different step size here because each assignment
-                // vector is no longer guaranteed to be a multiple of 4 bytes
-                // due to the 2-byte repetition count appended at the end
-                for i in (last_valid_assignment + 3..overflow.len() - 2).step_by(2) {
-                    if overflow[i - 3..=i] == [0, 0, 0, 0] {
-                        last_valid_assignment = i + 3;
-                        let lines = &overflow[i + 1..i + 3];
-                        let n_lines = u16::from_be_bytes([lines[0], lines[1]]);
-                        line_count += n_lines as usize;
-                        log!("Decoding sample: {}\r", line_count);
-                    }
-                }
-            }
-        }
+        let last_valid_assignment = scan_ben32_frames(&overflow, variant, &mut line_count);
 
         if last_valid_assignment == 0 {
             continue;
@@ -647,13 +2500,328 @@ pub fn jsonl_decode_xben<R: BufRead, W: Write>(reader: R, mut writer: W) -> io::
             variant,
         )?;
         overflow = overflow[last_valid_assignment..].to_vec();
-        starting_sample = line_count;
+        starting_sample = sample_base + line_count;
     }
     logln!();
     logln!("Done!");
     Ok(())
 }
 
+/// Counts the samples in a BEN file without decoding any of the assignment
+/// vectors. Only the frame headers (and the repetition counts of MkvChain
+/// files) are read; the payloads are skipped over. This works for all of the
+/// variants, including files with frame checksums, which are not checked.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the BEN file
+///
+/// # Returns
+///
+/// The number of samples in the file, including MkvChain repetitions
+///
+/// # Errors
+///
+/// This function will return an error if the file does not start with a BEN
+/// banner or if a frame is cut short.
+pub fn count_samples_ben<R: Read>(mut reader: R) -> io::Result<usize> {
+    let mut banner = [0u8; 17];
+    reader.read_exact(&mut banner)?;
+
+    let (variant, frame_crc) = match &banner {
+        b"STANDARD BEN FILE" => (BenVariant::Standard, false),
+        b"MKVCHAIN BEN FILE" => (BenVariant::MkvChain, false),
+        b"MKVDELTA BEN FILE" => (BenVariant::Delta, false),
+        b"STANDARD BEN FCRC" => (BenVariant::Standard, true),
+        b"MKVCHAIN BEN FCRC" => (BenVariant::MkvChain, true),
+        b"MKVDELTA BEN FCRC" => (BenVariant::Delta, true),
+        _ => {
+            return Err(Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid file format",
+            ));
+        }
+    };
+
+    let mut n_samples = 0;
+    loop {
+        let max_val_bits = match reader.read_u8() {
+            Ok(max_val_bits) => max_val_bits,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        };
+        let max_len_bits = reader.read_u8()?;
+        let n_bytes = reader.read_u32::<BigEndian>()?;
+
+        // Every frame after the first one in a delta file stores the number
+        // of changed nodes in place of the number of bytes.
+        let payload_len = if variant == BenVariant::Delta && n_samples > 0 {
+            (n_bytes as u64 * (max_val_bits as u64 + max_len_bits as u64)).div_ceil(8)
+        } else {
+            n_bytes as u64
+        };
+        if frame_crc {
+            reader.read_u32::<BigEndian>()?;
+        }
+        if io::copy(&mut (&mut reader).take(payload_len), &mut io::sink())? < payload_len {
+            return Err(Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Frame for sample {} is truncated", n_samples + 1),
+            ));
+        }
+
+        n_samples += if variant == BenVariant::MkvChain {
+            reader.read_u16::<BigEndian>()? as usize
+        } else {
+            1
+        };
+    }
+
+    Ok(n_samples)
+}
+
+/// Counts the samples in an XBEN file without decoding any of the
+/// assignment vectors. The file still has to be decompressed, but the
+/// ben32 data is only scanned for the ends of the frames (or walked by
+/// length prefix for `LP32` files).
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the XBEN file
+///
+/// # Returns
+///
+/// The number of samples in the file, including MkvChain repetitions
+///
+/// # Errors
+///
+/// This function will return an error if the file cannot be decompressed or
+/// does not start with a BEN banner.
+pub fn count_samples_xben<R: Read>(reader: R) -> io::Result<usize> {
+    let mut decoder = xz2::read::XzDecoder::new(reader);
+
+    let mut first_buffer = [0u8; 17];
+    decoder.read_exact(&mut first_buffer)?;
+
+    let (variant, length_prefixed) = match &first_buffer {
+        b"STANDARD BEN FILE" => (BenVariant::Standard, false),
+        b"MKVCHAIN BEN FILE" => (BenVariant::MkvChain, false),
+        b"STANDARD BEN LP32" => (BenVariant::Standard, true),
+        b"MKVCHAIN BEN LP32" => (BenVariant::MkvChain, true),
+        _ => {
+            return Err(Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid file format",
+            ));
+        }
+    };
+
+    let mut line_count: usize = 0;
+    if length_prefixed {
+        while read_lp32_line(&mut decoder)?.is_some() {
+            line_count += if variant == BenVariant::MkvChain {
+                decoder.read_u16::<BigEndian>()? as usize
+            } else {
+                1
+            };
+        }
+        return Ok(line_count);
+    }
+
+    let mut buffer = vec![0u8; 1048576]; // 1MB buffer
+    let mut overflow: Vec<u8> = Vec::new();
+    loop {
+        let count = decoder.read(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+
+        overflow.extend(&buffer[..count]);
+        let last_valid_assignment = scan_ben32_frames(&overflow, variant, &mut line_count);
+        overflow.drain(..last_valid_assignment);
+    }
+    logln!();
+
+    Ok(line_count)
+}
+
+/// The result of checking the two layers of an XBEN file with
+/// `verify_xben`. The XZ layer is the LZMA2 compressed stream and the ben32
+/// layer is the data that comes out of it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VerifyReport {
+    /// The variant given by the banner, if the banner could be read.
+    pub variant: Option<BenVariant>,
+    /// The number of bytes that the XZ stream decompressed to.
+    pub decompressed_bytes: u64,
+    /// The number of complete ben32 frames that were found.
+    pub n_frames: usize,
+    /// The number of samples in the complete frames (including MkvChain
+    /// repetitions).
+    pub n_samples: usize,
+    /// The reason the XZ stream could not be decompressed, if any.
+    pub xz_error: Option<String>,
+    /// The reason the ben32 data is malformed, if any. When the XZ stream is
+    /// broken, this only covers the part of the stream that could be
+    /// decompressed.
+    pub ben32_error: Option<String>,
+}
+
+impl VerifyReport {
+    /// Whether both layers of the file are valid.
+    pub fn is_ok(&self) -> bool {
+        self.xz_error.is_none() && self.ben32_error.is_none()
+    }
+}
+
+/// A reader that stops at the first error coming out of the inner reader
+/// and keeps it around so that the reader of the decompressed data sees a
+/// plain end of stream instead.
+struct ErrorCapture<R: Read> {
+    inner: R,
+    position: u64,
+    error: Option<io::Error>,
+}
+
+impl<R: Read> Read for ErrorCapture<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.error.is_some() {
+            return Ok(0);
+        }
+        match self.inner.read(buf) {
+            Ok(n) => {
+                self.position += n as u64;
+                Ok(n)
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => Err(e),
+            Err(e) => {
+                self.error = Some(e);
+                Ok(0)
+            }
+        }
+    }
+}
+
+/// Walks the ben32 frames of a decompressed XBEN stream, counting the frames
+/// and samples in the report as it goes.
+fn check_ben32_frames<R: Read>(mut reader: R, report: &mut VerifyReport) -> io::Result<()> {
+    let mut banner = [0u8; 17];
+    reader.read_exact(&mut banner)?;
+
+    let (variant, length_prefixed) = match &banner {
+        b"STANDARD BEN FILE" => (BenVariant::Standard, false),
+        b"MKVCHAIN BEN FILE" => (BenVariant::MkvChain, false),
+        b"STANDARD BEN LP32" => (BenVariant::Standard, true),
+        b"MKVCHAIN BEN LP32" => (BenVariant::MkvChain, true),
+        _ => {
+            return Err(Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid file format",
+            ));
+        }
+    };
+    report.variant = Some(variant);
+
+    loop {
+        let sample = report.n_samples + 1;
+        let rle = if length_prefixed {
+            match read_lp32_line(&mut reader)? {
+                Some(rle) => rle,
+                None => break,
+            }
+        } else {
+            match read_ben32_frame(&mut reader) {
+                Ok(Some(rle)) => rle,
+                Ok(None) => break,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Err(Error::new(
+                        e.kind(),
+                        format!("Missing end of line separator in sample {}", sample),
+                    ));
+                }
+                Err(e) => return Err(e),
+            }
+        };
+
+        if rle.iter().any(|&(_, len)| len == 0) {
+            return Err(Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Run of length 0 in sample {}", sample),
+            ));
+        }
+
+        let count = if variant == BenVariant::MkvChain {
+            let count = reader.read_u16::<BigEndian>().map_err(|e| {
+                Error::new(
+                    e.kind(),
+                    format!("Missing repetition count in sample {}", sample),
+                )
+            })?;
+            if count == 0 {
+                return Err(Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Repetition count of 0 in sample {}", sample),
+                ));
+            }
+            count
+        } else {
+            1
+        };
+
+        report.n_frames += 1;
+        report.n_samples += count as usize;
+    }
+
+    Ok(())
+}
+
+/// Checks the integrity of an XBEN file one layer at a time: first that the
+/// XZ stream decompresses cleanly, and then that the ben32 data inside of it
+/// is well formed (every frame ends with a `00 00 00 00` separator, or has a
+/// length prefix for `LP32` files, and MkvChain frames have a non-zero
+/// repetition count). The problems that were found are recorded in the
+/// returned report rather than returned as errors so that it is clear which
+/// of the two layers is at fault.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the XBEN file
+///
+/// # Returns
+///
+/// A `VerifyReport` describing the file
+pub fn verify_xben<R: BufRead>(reader: R) -> io::Result<VerifyReport> {
+    let mut report = VerifyReport::default();
+    let mut capture = ErrorCapture {
+        inner: xz2::bufread::XzDecoder::new(reader),
+        position: 0,
+        error: None,
+    };
+
+    let ben32_result = check_ben32_frames(&mut capture, &mut report);
+
+    // Make sure the whole XZ stream is read even if the ben32 data broke off
+    // early.
+    io::copy(&mut capture, &mut io::sink())?;
+
+    report.decompressed_bytes = capture.position;
+    report.xz_error = capture.error.map(|e| e.to_string());
+
+    if let Err(e) = ben32_result {
+        // If the XZ stream is broken, the ben32 data being cut short is
+        // expected and not worth reporting.
+        if report.xz_error.is_none() || e.kind() != io::ErrorKind::UnexpectedEof {
+            report.ben32_error = Some(e.to_string());
+        }
+    }
+
+    Ok(report)
+}
+
 #[cfg(test)]
 #[path = "tests/decode_tests.rs"]
 mod tests;
+
+#[cfg(test)]
+#[path = "tests/common.rs"]
+pub(crate) mod test_common;
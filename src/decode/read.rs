@@ -4,7 +4,9 @@
 //! vectors from a BEN file.
 use serde_json::{Error as SerdeError, Value};
 use std::fmt::{self};
+use std::io::{Seek, SeekFrom};
 
+use super::index::{decode_frame_at, FrameIndex};
 use super::*;
 
 /// Types of errors that can occur during the extraction of assignments.
@@ -214,6 +216,80 @@ pub fn extract_assignment_ben<R: Read>(
     Ok(assignment)
 }
 
+/// Extracts the last assignment vector of a seekable BEN file without
+/// decoding the rest of the file. BEN frames do not mark where they start,
+/// and the payload of a frame can contain bytes that look like a frame
+/// header, so the file cannot be scanned backward from the end. Instead, the
+/// reader hops from one frame header to the next (see `FrameIndex::build`),
+/// reading only the 6 byte header of each frame and seeking past its
+/// payload. Only the last frame is decoded.
+///
+/// The reader must implement `Seek`; use `extract_last_assignment_stream`
+/// for readers that cannot seek. Delta files and files with frame checksums
+/// cannot be indexed this way, so they are decoded forward from the start
+/// instead.
+///
+/// # Arguments
+///
+/// * `reader` - A seekable reader containing the BEN file
+///
+/// # Returns
+///
+/// The last assignment vector in the file
+///
+/// # Errors
+///
+/// This function will return an error if the file is not a BEN file, if it
+/// does not contain any samples, or if it cannot be decoded.
+pub fn extract_last_assignment<R: Read + Seek>(mut reader: R) -> io::Result<Vec<u16>> {
+    reader.seek(SeekFrom::Start(0))?;
+    let mut banner = [0u8; 17];
+    reader.read_exact(&mut banner)?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    if !matches!(&banner, b"STANDARD BEN FILE" | b"MKVCHAIN BEN FILE") {
+        return extract_last_assignment_stream(reader);
+    }
+
+    let frames = FrameIndex::build(&mut reader)?;
+    match frames.offsets().last() {
+        Some(&offset) => decode_frame_at(&mut reader, offset),
+        None => Err(no_samples_error()),
+    }
+}
+
+/// Same as `extract_last_assignment`, but for readers that cannot seek. The
+/// whole file is decoded and only the last assignment vector is kept.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the BEN file
+///
+/// # Returns
+///
+/// The last assignment vector in the file
+///
+/// # Errors
+///
+/// This function will return an error if the file is not a BEN file, if it
+/// does not contain any samples, or if it cannot be decoded.
+pub fn extract_last_assignment_stream<R: Read>(reader: R) -> io::Result<Vec<u16>> {
+    let mut last = None;
+    for record in BenRleDecoder::new(reader)? {
+        let (rle, _count) = record?;
+        last = Some(rle);
+    }
+    last.map(rle_to_vec).ok_or_else(no_samples_error)
+}
+
+/// The error returned when asking for the last sample of an empty file.
+fn no_samples_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Cannot extract the last assignment of a file with no samples",
+    )
+}
+
 // #[cfg(test)]
 // mod tests {
 //     include!("tests/read_tests.rs");
@@ -0,0 +1,315 @@
+use super::*;
+use crate::decode::test_common::encode_samples;
+use crate::decode::BenDecoder;
+use crate::encode::jsonl_encode_ben;
+use crate::BenVariant;
+
+fn mkv_records(records: &[(Vec<u16>, u16)]) -> impl Iterator<Item = io::Result<MkvRecord>> + '_ {
+    records.iter().cloned().map(Ok)
+}
+
+#[test]
+fn test_batch_decoder_splits_runs_across_batches() {
+    let records = vec![(vec![1, 1, 2], 3), (vec![2, 2, 1], 1), (vec![1, 2, 1], 4)];
+
+    let batches = BatchDecoder::new(mkv_records(&records), 3)
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap();
+
+    assert_eq!(
+        batches,
+        vec![
+            vec![vec![1, 1, 2], vec![1, 1, 2], vec![1, 1, 2]],
+            vec![vec![2, 2, 1], vec![1, 2, 1], vec![1, 2, 1]],
+            vec![vec![1, 2, 1], vec![1, 2, 1]],
+        ]
+    );
+}
+
+#[test]
+fn test_batch_decoder_from_ben_file() {
+    let input = format!(
+        "{}\n{}\n{}\n{}\n{}\n",
+        "{\"assignment\":[1,1,2,2,3],\"sample\":1}",
+        "{\"assignment\":[1,1,2,2,3],\"sample\":2}",
+        "{\"assignment\":[1,1,2,2,3],\"sample\":3}",
+        "{\"assignment\":[3,1,2,2,1],\"sample\":4}",
+        "{\"assignment\":[3,1,2,2,2],\"sample\":5}"
+    );
+
+    let mut ben = Vec::new();
+    jsonl_encode_ben(input.as_bytes(), &mut ben, BenVariant::MkvChain).unwrap();
+
+    let decoder = BenDecoder::new(ben.as_slice()).unwrap();
+    let batch_lens = BatchDecoder::new(decoder, 2)
+        .map(|batch| batch.unwrap().len())
+        .collect::<Vec<usize>>();
+
+    assert_eq!(batch_lens, vec![2, 2, 1]);
+}
+
+#[test]
+fn test_batch_decoder_returns_partial_batch_before_error() {
+    let records = vec![
+        Ok((vec![1, 2], 1)),
+        Err(io::Error::new(io::ErrorKind::InvalidData, "bad frame")),
+    ];
+
+    let mut batches = BatchDecoder::new(records.into_iter(), 4);
+
+    assert_eq!(batches.next().unwrap().unwrap(), vec![vec![1, 2]]);
+    assert!(batches.next().unwrap().is_err());
+    assert!(batches.next().is_none());
+}
+
+#[test]
+fn test_max_tracking_decoder_reports_running_max() {
+    let records = vec![(vec![1, 2, 1], 2), (vec![3, 2, 1], 1), (vec![1, 2, 2], 1)];
+
+    let items = MaxTrackingDecoder::new(mkv_records(&records))
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap();
+
+    assert_eq!(
+        items,
+        vec![
+            (vec![1, 2, 1], 2),
+            (vec![1, 2, 1], 2),
+            (vec![3, 2, 1], 3),
+            (vec![1, 2, 2], 3),
+        ]
+    );
+}
+
+#[test]
+fn test_max_tracking_decoder_passes_errors_through() {
+    let records = vec![
+        Ok((vec![1, 300], 1)),
+        Err(io::Error::new(io::ErrorKind::InvalidData, "bad frame")),
+    ];
+
+    let mut decoder = MaxTrackingDecoder::new(records.into_iter());
+
+    assert_eq!(decoder.next().unwrap().unwrap(), (vec![1, 300], 300));
+    assert!(decoder.next().unwrap().is_err());
+    assert!(decoder.next().is_none());
+    assert_eq!(decoder.running_max(), 300);
+}
+
+#[test]
+fn test_spawn_ben_decoder_expands_mkv_runs() {
+    let input = format!(
+        "{}\n{}\n{}\n",
+        "{\"assignment\":[1,1,2,2,3],\"sample\":1}",
+        "{\"assignment\":[1,1,2,2,3],\"sample\":2}",
+        "{\"assignment\":[3,1,2,2,1],\"sample\":3}"
+    );
+
+    let mut ben = Vec::new();
+    jsonl_encode_ben(input.as_bytes(), &mut ben, BenVariant::MkvChain).unwrap();
+
+    let receiver = spawn_ben_decoder(io::Cursor::new(ben), 1);
+    let samples = receiver.iter().collect::<io::Result<Vec<_>>>().unwrap();
+
+    assert_eq!(
+        samples,
+        vec![
+            vec![1, 1, 2, 2, 3],
+            vec![1, 1, 2, 2, 3],
+            vec![3, 1, 2, 2, 1]
+        ]
+    );
+}
+
+#[test]
+fn test_spawn_ben_decoder_sends_init_error() {
+    let receiver = spawn_ben_decoder(io::Cursor::new(b"NOT A BEN FILE!!!".to_vec()), 4);
+
+    assert!(receiver.recv().unwrap().is_err());
+    assert!(receiver.recv().is_err());
+}
+
+#[test]
+fn test_threshold_decoder() {
+    let records = vec![
+        (vec![1, 1, 1], 2),
+        (vec![1, 2, 2], 3),
+        (vec![2, 2, 2], 1),
+        (vec![1, 2, 1], 1),
+        (vec![2, 1, 2], 4),
+    ];
+    let n_ones = |a: &[u16]| a.iter().filter(|&&x| x == 1).count() as f64;
+
+    let kept = ThresholdDecoder::new(mkv_records(&records), n_ones, Comparison::LessOrEqual, 1.0)
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(
+        kept,
+        vec![(vec![1, 2, 2], 3), (vec![2, 2, 2], 1), (vec![2, 1, 2], 4)]
+    );
+
+    let crossings =
+        ThresholdDecoder::new(mkv_records(&records), n_ones, Comparison::LessOrEqual, 1.0)
+            .crossings_only()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+    assert_eq!(crossings, vec![(vec![1, 2, 2], 3), (vec![2, 1, 2], 4)]);
+}
+
+#[test]
+fn test_contract_decoder() {
+    let records = vec![(vec![1, 1, 2], 3), (vec![2, 2, 1], 1), (vec![1, 3, 1], 2)];
+
+    let passed = ContractDecoder::new(mkv_records(&records), Some(3), Some(3))
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(passed, records);
+
+    let mut labels = ContractDecoder::new(mkv_records(&records), None, Some(2));
+    assert!(labels.next().unwrap().is_ok());
+    assert!(labels.next().unwrap().is_ok());
+    let err = labels.next().unwrap().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("Sample 5"));
+    assert!(labels.next().is_none());
+
+    let records = vec![(vec![1, 1, 2], 2), (vec![2, 2], 1)];
+    let err = ContractDecoder::new(mkv_records(&records), Some(3), None)
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap_err();
+    assert!(err.to_string().contains("Sample 3 has 2 nodes"));
+}
+
+#[test]
+fn test_selection() {
+    assert!(Selection::Every(3).contains(6));
+    assert!(!Selection::Every(3).contains(7));
+    assert_eq!(Selection::Every(3).last(), None);
+
+    let range = Selection::Range { start: 2, end: 4 };
+    assert!(!range.contains(1));
+    assert!(range.contains(2));
+    assert!(range.contains(4));
+    assert_eq!(range.last(), Some(4));
+
+    let indices = Selection::Indices(vec![5, 1, 5, 3]).normalized();
+    assert_eq!(indices, Selection::Indices(vec![1, 3, 5]));
+    assert!(indices.contains(3));
+    assert!(!indices.contains(4));
+    assert_eq!(indices.last(), Some(5));
+}
+
+#[test]
+fn test_selection_count_in() {
+    assert_eq!(Selection::Every(3).count_in(10), 3);
+    assert_eq!(Selection::Every(3).count_in(2), 0);
+    assert_eq!(Selection::Every(0).count_in(10), 0);
+
+    assert_eq!(Selection::Range { start: 2, end: 4 }.count_in(10), 3);
+    assert_eq!(Selection::Range { start: 2, end: 40 }.count_in(10), 9);
+    assert_eq!(Selection::Range { start: 0, end: 3 }.count_in(10), 3);
+    assert_eq!(Selection::Range { start: 12, end: 40 }.count_in(10), 0);
+
+    let indices = Selection::Indices(vec![1, 3, 3, 5, 12]);
+    assert_eq!(indices.count_in(10), 3);
+    assert_eq!(indices.count_in(4), 2);
+
+    // The counts match what the SubsampleDecoder actually returns
+    let records = vec![(vec![1, 2], 4), (vec![2, 1], 3), (vec![3, 3], 5)];
+    for selection in [
+        Selection::Every(5),
+        Selection::Range { start: 3, end: 9 },
+        Selection::Indices(vec![2, 4, 8, 12, 13]),
+    ] {
+        let n_selected = SubsampleDecoder::new(mkv_records(&records), selection.clone()).count();
+        assert_eq!(selection.count_in(12), n_selected);
+    }
+}
+
+#[test]
+fn test_subsample_decoder_expands_runs() {
+    let records = vec![(vec![1, 1, 2], 3), (vec![2, 2, 1], 1), (vec![1, 2, 1], 4)];
+
+    let every = SubsampleDecoder::new(mkv_records(&records), Selection::Every(3))
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(every, vec![(3, vec![1, 1, 2]), (6, vec![1, 2, 1])]);
+
+    let indices = SubsampleDecoder::new(mkv_records(&records), Selection::Indices(vec![4, 2, 8]))
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(
+        indices,
+        vec![(2, vec![1, 1, 2]), (4, vec![2, 2, 1]), (8, vec![1, 2, 1])]
+    );
+
+    // Nothing past the end of the range is read
+    let mut inner = mkv_records(&records).chain(std::iter::once(Err(io::Error::other("unread"))));
+    let range = SubsampleDecoder::new(&mut inner, Selection::Range { start: 3, end: 4 })
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(range, vec![(3, vec![1, 1, 2]), (4, vec![2, 2, 1])]);
+}
+
+#[test]
+fn test_expand_counts() {
+    let records = vec![(vec![1, 1, 2], 3), (vec![2, 2, 1], 1)];
+
+    let samples = expand_counts(mkv_records(&records))
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(
+        samples,
+        vec![vec![1, 1, 2], vec![1, 1, 2], vec![1, 1, 2], vec![2, 2, 1]]
+    );
+
+    let with_error = vec![
+        Ok((vec![1, 2], 2)),
+        Err(io::Error::other("broken")),
+        Ok((vec![2, 1], 1)),
+    ];
+    let results = expand_counts(with_error).collect::<Vec<_>>();
+    assert_eq!(results.len(), 4);
+    assert!(results[2].is_err());
+    assert_eq!(results[3].as_ref().unwrap(), &vec![2, 1]);
+}
+
+#[test]
+fn test_zip_ben_decoder_aligns_samples() {
+    let first: Vec<Vec<u16>> = (0..6u16).map(|i| vec![1, 1, 2, i / 3 + 1]).collect();
+    let second: Vec<Vec<u16>> = (0..6u16).map(|i| vec![2, i % 2 + 1, 1, 1]).collect();
+
+    let ben_first = encode_samples(&first, BenVariant::MkvChain);
+    let ben_second = encode_samples(&second, BenVariant::Standard);
+
+    let pairs = ZipBenDecoder::new(ben_first.as_slice(), ben_second.as_slice())
+        .unwrap()
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap();
+
+    let expected: Vec<(Vec<u16>, Vec<u16>)> = first.into_iter().zip(second).collect();
+    assert_eq!(pairs, expected);
+}
+
+#[test]
+fn test_zip_ben_decoder_errors_on_different_sample_counts() {
+    let samples: Vec<Vec<u16>> = (0..5u16).map(|i| vec![1, 2, i]).collect();
+    let ben_long = encode_samples(&samples, BenVariant::MkvChain);
+    let ben_short = encode_samples(&samples[..3], BenVariant::MkvChain);
+
+    for (first, second, message) in [
+        (&ben_long, &ben_short, "The first file"),
+        (&ben_short, &ben_long, "The second file"),
+    ] {
+        let mut decoder = ZipBenDecoder::new(first.as_slice(), second.as_slice()).unwrap();
+        for _ in 0..3 {
+            decoder.next().unwrap().unwrap();
+        }
+
+        let err = decoder.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with(message));
+        assert!(err.to_string().ends_with("has 3"));
+        assert!(decoder.next().is_none());
+    }
+}
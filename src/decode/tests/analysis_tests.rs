@@ -0,0 +1,394 @@
+use super::*;
+use crate::decode::test_common::encode_samples;
+use std::io::Cursor;
+
+#[test]
+fn test_pairwise_hamming_all_samples() {
+    let samples = vec![
+        vec![1, 1, 2, 2],
+        vec![1, 2, 2, 2],
+        vec![1, 2, 2, 2],
+        vec![2, 2, 1, 1],
+    ];
+
+    for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+        let ben = encode_samples(&samples, variant);
+        let distances = pairwise_hamming(Cursor::new(ben), None).unwrap();
+
+        assert_eq!(distances, vec![1, 1, 4, 0, 3, 3]);
+    }
+}
+
+#[test]
+fn test_pairwise_hamming_subset() {
+    let samples = vec![
+        vec![1, 1, 2, 2],
+        vec![1, 2, 2, 2],
+        vec![1, 2, 2, 2],
+        vec![2, 2, 1, 1],
+    ];
+
+    let ben = encode_samples(&samples, BenVariant::MkvChain);
+    let distances = pairwise_hamming(Cursor::new(ben), Some(&[4, 1, 3])).unwrap();
+
+    assert_eq!(distances, vec![4, 3, 1]);
+}
+
+#[test]
+fn test_pairwise_hamming_sample_out_of_range() {
+    let ben = encode_samples(&[vec![1, 1], vec![1, 2]], BenVariant::Standard);
+
+    let result = pairwise_hamming(Cursor::new(ben), Some(&[1, 3]));
+
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_run_stats() {
+    let samples = vec![vec![1, 1, 2, 2], vec![1, 1, 2, 2], vec![3, 1, 1, 1]];
+
+    let ben = encode_samples(&samples, BenVariant::MkvChain);
+    let stats = run_stats(ben.as_slice()).unwrap();
+
+    assert_eq!(stats.n_frames, 2);
+    assert_eq!(stats.n_samples, 3);
+    assert_eq!(stats.run_lengths, BTreeMap::from([(1, 1), (2, 2), (3, 1)]));
+    assert_eq!(stats.run_values, BTreeMap::from([(1, 2), (2, 1), (3, 1)]));
+    assert_eq!(stats.max_val_bits, BTreeMap::from([(2, 2)]));
+    assert_eq!(stats.max_len_bits, BTreeMap::from([(2, 2)]));
+}
+
+#[test]
+fn test_boundary_nodes() {
+    // A 2x3 grid:
+    // 0 1 2
+    // 3 4 5
+    let adjacency = vec![
+        vec![1, 3],
+        vec![0, 2, 4],
+        vec![1, 5],
+        vec![0, 4],
+        vec![1, 3, 5],
+        vec![2, 4],
+    ];
+    let samples = vec![
+        vec![1, 1, 2, 1, 1, 2],
+        vec![1, 1, 2, 1, 1, 2],
+        vec![1, 1, 1, 2, 2, 2],
+        vec![1, 1, 1, 1, 1, 1],
+    ];
+
+    let ben = encode_samples(&samples, BenVariant::MkvChain);
+    let boundaries = boundary_nodes(ben.as_slice(), &adjacency).unwrap();
+
+    assert_eq!(
+        boundaries,
+        vec![
+            vec![1, 2, 4, 5],
+            vec![1, 2, 4, 5],
+            vec![0, 1, 2, 3, 4, 5],
+            vec![],
+        ]
+    );
+
+    let indptr = vec![0, 2, 5, 7, 9, 12, 14];
+    let indices = vec![1, 3, 0, 2, 4, 1, 5, 0, 4, 1, 3, 5, 2, 4];
+    let csr_boundaries = boundary_nodes_csr(ben.as_slice(), &indptr, &indices).unwrap();
+    assert_eq!(csr_boundaries, boundaries);
+}
+
+#[test]
+fn test_boundary_nodes_invalid_adjacency() {
+    let ben = encode_samples(&[vec![1, 1, 2]], BenVariant::Standard);
+
+    let err = boundary_nodes(ben.as_slice(), &[vec![1], vec![0, 2], vec![3]]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    let err = boundary_nodes(ben.as_slice(), &[vec![1], vec![0]]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    let err = boundary_nodes_csr(ben.as_slice(), &[0, 1, 3], &[1, 0]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_node_entropy() {
+    let samples = vec![
+        vec![1, 1, 2, 3],
+        vec![1, 1, 2, 3],
+        vec![1, 2, 2, 4],
+        vec![1, 2, 2, 5],
+    ];
+
+    let ben = encode_samples(&samples, BenVariant::MkvChain);
+    let entropy = node_entropy(ben.as_slice(), 4).unwrap();
+
+    assert_eq!(entropy[0], 0.0);
+    assert!((entropy[1] - 1.0).abs() < 1e-12);
+    assert_eq!(entropy[2], 0.0);
+    assert!((entropy[3] - 1.5).abs() < 1e-12);
+
+    let capped = node_entropy_capped(ben.as_slice(), 4, Some(5)).unwrap();
+    assert_eq!(capped, entropy);
+
+    let err = node_entropy_capped(ben.as_slice(), 4, Some(4)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    let err = node_entropy(ben.as_slice(), 3).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_node_flip_counts() {
+    let samples = vec![
+        vec![1, 1, 2, 2],
+        vec![1, 1, 2, 2],
+        vec![1, 2, 2, 2],
+        vec![1, 1, 2, 3],
+        vec![1, 1, 2, 3],
+    ];
+
+    for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+        let ben = encode_samples(&samples, variant);
+        let flips = node_flip_counts(ben.as_slice(), 4).unwrap();
+        assert_eq!(flips, vec![0, 2, 0, 1]);
+    }
+
+    let ben = encode_samples(&samples, BenVariant::Standard);
+    let err = node_flip_counts(ben.as_slice(), 5).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_most_frequent_assignment() {
+    let samples = vec![
+        vec![1, 1, 2, 2],
+        vec![2, 2, 1, 1],
+        vec![2, 2, 1, 1],
+        vec![1, 1, 2, 2],
+        vec![1, 2, 1, 2],
+        vec![2, 2, 1, 1],
+        vec![1, 1, 2, 2],
+        vec![1, 1, 2, 2],
+    ];
+
+    for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+        let ben = encode_samples(&samples, variant);
+        let (assignment, count) = most_frequent_assignment(ben.as_slice()).unwrap();
+        assert_eq!(assignment, vec![1, 1, 2, 2]);
+        assert_eq!(count, 4);
+    }
+
+    let ben = encode_samples(&[], BenVariant::MkvChain);
+    let err = most_frequent_assignment(ben.as_slice()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_unique_samples_with_counts() {
+    let samples = vec![
+        vec![1, 1, 2, 2],
+        vec![1, 1, 2, 2],
+        vec![2, 2, 1, 1],
+        vec![1, 1, 2, 2],
+        vec![1, 2, 1, 2],
+        vec![2, 2, 1, 1],
+    ];
+
+    for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+        let ben = encode_samples(&samples, variant);
+
+        let unique = unique_samples_with_counts(ben.as_slice()).unwrap();
+        assert_eq!(
+            unique,
+            vec![
+                (vec![1, 1, 2, 2], 3),
+                (vec![2, 2, 1, 1], 2),
+                (vec![1, 2, 1, 2], 1)
+            ]
+        );
+
+        let hashes = unique_sample_hashes_with_counts(ben.as_slice(), None).unwrap();
+        assert_eq!(
+            hashes.iter().map(|(_, count)| *count).collect::<Vec<_>>(),
+            vec![3, 2, 1]
+        );
+        assert_eq!(hashes[0].0, assignment_hash(&[1, 1, 2, 2]));
+
+        let err = unique_samples_with_counts_capped(ben.as_slice(), Some(2)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = unique_sample_hashes_with_counts(ben.as_slice(), Some(2)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(unique_samples_with_counts_capped(ben.as_slice(), Some(3)).is_ok());
+    }
+}
+
+#[test]
+fn test_ensemble_set_diff() {
+    let a = vec![
+        vec![1, 1, 2, 2],
+        vec![1, 1, 2, 2],
+        vec![2, 1, 1, 2],
+        vec![1, 2, 2, 1],
+    ];
+    let b = vec![vec![2, 1, 1, 2], vec![2, 2, 1, 1], vec![1, 1, 2, 2]];
+
+    for variant in [
+        BenVariant::Standard,
+        BenVariant::MkvChain,
+        BenVariant::Delta,
+    ] {
+        let a_ben = encode_samples(&a, variant);
+        let b_ben = encode_samples(&b, variant);
+
+        assert_eq!(
+            ensemble_set_diff(a_ben.as_slice(), b_ben.as_slice()).unwrap(),
+            (1, 1, 2)
+        );
+        assert_eq!(
+            ensemble_set_diff(b_ben.as_slice(), a_ben.as_slice()).unwrap(),
+            (1, 1, 2)
+        );
+        assert_eq!(
+            ensemble_set_diff(a_ben.as_slice(), a_ben.as_slice()).unwrap(),
+            (0, 0, 3)
+        );
+    }
+}
+
+#[test]
+fn test_distinct_labels() {
+    let samples = vec![
+        vec![1, 1, 2, 2],
+        vec![1, 1, 2, 2],
+        vec![3, 1, 1, 7],
+        vec![2, 2, 3, 1],
+    ];
+
+    for variant in [
+        BenVariant::Standard,
+        BenVariant::MkvChain,
+        BenVariant::Delta,
+    ] {
+        let ben = encode_samples(&samples, variant);
+        let labels = distinct_labels(ben.as_slice()).unwrap();
+        assert_eq!(labels.into_iter().collect::<Vec<_>>(), vec![1, 2, 3, 7]);
+    }
+}
+
+#[test]
+fn test_scan_ben_stats() {
+    let samples = vec![
+        vec![1, 1, 1, 2, 2, 3],
+        vec![1, 1, 1, 2, 2, 3],
+        vec![1, 1, 1, 2, 2, 3],
+        vec![4, 4, 4, 4, 2, 9],
+        vec![1, 2, 1, 2, 1, 2],
+    ];
+
+    let ben = encode_samples(&samples, BenVariant::MkvChain);
+    assert_eq!(
+        scan_ben_stats(ben.as_slice()).unwrap(),
+        BenStats {
+            n_samples: 5,
+            n_frames: 3,
+            max_label: 9,
+            max_run_length: 4,
+            n_nodes: Some(6),
+        }
+    );
+
+    let ben = encode_samples(&samples, BenVariant::Standard);
+    let stats = scan_ben_stats(ben.as_slice()).unwrap();
+    assert_eq!((stats.n_samples, stats.n_frames), (5, 5));
+
+    let ben = encode_samples(&[vec![1, 1, 2], vec![1, 2]], BenVariant::Standard);
+    let err = scan_ben_stats(ben.as_slice()).unwrap_err();
+    assert!(err.to_string().contains("Sample 2 has 2 nodes"));
+}
+
+#[test]
+fn test_windowed_samples() {
+    let samples = vec![vec![1, 1], vec![1, 2], vec![1, 2], vec![2, 2]];
+    let ben = encode_samples(&samples, BenVariant::MkvChain);
+
+    let mut windows = Vec::new();
+    windowed_samples(ben.as_slice(), 2, |window| windows.push(window.to_vec())).unwrap();
+    assert_eq!(
+        windows,
+        vec![
+            vec![vec![1, 1], vec![1, 2]],
+            vec![vec![1, 2], vec![1, 2]],
+            vec![vec![1, 2], vec![2, 2]],
+        ]
+    );
+
+    let mut n_calls = 0;
+    windowed_samples(ben.as_slice(), 5, |_| n_calls += 1).unwrap();
+    assert_eq!(n_calls, 0);
+
+    let err = windowed_samples(ben.as_slice(), 0, |_| {}).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_district_node_counts() {
+    let samples = vec![
+        vec![1, 1, 2, 2, 2, 3],
+        vec![1, 1, 2, 2, 2, 3],
+        vec![3, 1, 1, 1, 3, 2],
+    ];
+
+    for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+        let ben = encode_samples(&samples, variant);
+        let counts = district_node_counts(ben.as_slice(), 3).unwrap();
+        assert_eq!(
+            counts,
+            vec![vec![0, 2, 3, 1], vec![0, 2, 3, 1], vec![0, 3, 1, 2]]
+        );
+
+        let err = district_node_counts(ben.as_slice(), 2).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("Sample 1 has label 3"));
+    }
+}
+
+#[test]
+fn test_nearest_sample() {
+    let samples = vec![
+        vec![1, 1, 2, 2, 3, 3],
+        vec![1, 1, 2, 2, 3, 3],
+        vec![1, 2, 2, 2, 3, 1],
+        vec![2, 2, 1, 1, 3, 3],
+        vec![1, 2, 2, 3, 3, 3],
+        vec![1, 2, 2, 3, 3, 3],
+    ];
+
+    for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+        let ben = encode_samples(&samples, variant);
+
+        // Samples 3 and 5 are both at distance 1, so the earlier one wins
+        let query = [1, 2, 2, 3, 3, 1];
+        assert_eq!(nearest_sample(ben.as_slice(), &query).unwrap(), (3, 1));
+
+        assert_eq!(nearest_sample(ben.as_slice(), &samples[4]).unwrap(), (5, 0));
+        assert_eq!(nearest_sample(ben.as_slice(), &samples[1]).unwrap(), (1, 0));
+
+        let err = nearest_sample(ben.as_slice(), &[1, 2, 3]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    let empty = encode_samples(&[], BenVariant::Standard);
+    let err = nearest_sample(empty.as_slice(), &[1, 2]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_nearest_sample_stops_at_exact_match() {
+    let samples = vec![vec![1, 1, 2], vec![1, 2, 2], vec![2, 2, 2]];
+    let mut ben = encode_samples(&samples, BenVariant::Standard);
+
+    // Garbage after the exact match is never read
+    ben.extend([0xff; 8]);
+    assert_eq!(nearest_sample(ben.as_slice(), &[1, 2, 2]).unwrap(), (2, 0));
+}
@@ -0,0 +1,46 @@
+use super::*;
+use crate::encode::jsonl_encode_ben;
+use crate::BenVariant;
+use arrow_array::{Array, UInt16Array};
+
+#[test]
+fn test_decode_ben_to_arrow() {
+    let input = r#"{"assignment": [1,1,2,2], "sample": 1}
+{"assignment": [1,1,2,2], "sample": 2}
+{"assignment": [2,2,1,1], "sample": 3}
+"#;
+
+    let mut ben = Vec::new();
+    jsonl_encode_ben(input.as_bytes(), &mut ben, BenVariant::MkvChain).unwrap();
+
+    let batches = decode_ben_to_arrow(ben.as_slice(), 2)
+        .unwrap()
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap();
+
+    assert_eq!(batches.len(), 2);
+    assert_eq!(batches[0].schema(), arrow_schema());
+    assert_eq!(batches[0].num_rows(), 2);
+    assert_eq!(batches[1].num_rows(), 1);
+
+    let samples = batches[1]
+        .column(0)
+        .as_any()
+        .downcast_ref::<UInt64Array>()
+        .unwrap();
+    assert_eq!(samples.value(0), 3);
+
+    let assignments = batches[1]
+        .column(1)
+        .as_any()
+        .downcast_ref::<ListArray>()
+        .unwrap();
+    let last = assignments.value(0);
+    let last = last.as_any().downcast_ref::<UInt16Array>().unwrap();
+    assert_eq!(last.values().to_vec(), vec![2, 2, 1, 1]);
+}
+
+#[test]
+fn test_decode_ben_to_arrow_invalid_banner() {
+    assert!(decode_ben_to_arrow(&b"NOT A BEN FILE AT ALL"[..], 2).is_err());
+}
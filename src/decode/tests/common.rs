@@ -0,0 +1,13 @@
+//! Fixtures shared by the test modules of `decode`.
+
+use crate::encode::BenEncoder;
+use crate::BenVariant;
+
+/// Encodes the samples, in order, into an in-memory BEN file.
+pub(crate) fn encode_samples(samples: &[Vec<u16>], variant: BenVariant) -> Vec<u8> {
+    let mut encoder = BenEncoder::new(Vec::new(), variant);
+    for sample in samples {
+        encoder.write_assignment_slice(sample).unwrap();
+    }
+    encoder.finish().unwrap()
+}
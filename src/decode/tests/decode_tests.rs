@@ -407,7 +407,7 @@ fn test_jsonl_decode_ben32_simple() {
     let mut output: Vec<u8> = Vec::new();
     let writer = &mut output;
 
-    let result = jsonl_decode_ben32(&mut reader, writer, 0, BenVariant::Standard);
+    let result = jsonl_decode_ben32(&mut reader, writer, 1, BenVariant::Standard);
 
     if let Err(e) = result {
         panic!("Error: {}", e);
@@ -431,7 +431,7 @@ fn test_jsonl_decode_ben32_16_bit_val() {
     let mut output: Vec<u8> = Vec::new();
     let writer = &mut output;
 
-    let result = jsonl_decode_ben32(&mut reader, writer, 0, BenVariant::Standard);
+    let result = jsonl_decode_ben32(&mut reader, writer, 1, BenVariant::Standard);
     if let Err(e) = result {
         panic!("Error: {}", e);
     }
@@ -454,7 +454,7 @@ fn test_jsonl_decode_ben32_16_bit_len() {
     let mut output: Vec<u8> = Vec::new();
     let writer = &mut output;
 
-    let result = jsonl_decode_ben32(&mut reader, writer, 0, BenVariant::Standard);
+    let result = jsonl_decode_ben32(&mut reader, writer, 1, BenVariant::Standard);
     if let Err(e) = result {
         panic!("Error: {}", e);
     }
@@ -477,7 +477,7 @@ fn test_jsonl_decode_ben32_max_val_65535() {
     let mut output: Vec<u8> = Vec::new();
     let writer = &mut output;
 
-    let result = jsonl_decode_ben32(&mut reader, writer, 0, BenVariant::Standard);
+    let result = jsonl_decode_ben32(&mut reader, writer, 1, BenVariant::Standard);
     if let Err(e) = result {
         panic!("Error: {}", e);
     }
@@ -500,7 +500,7 @@ fn test_jsonl_decode_ben32_max_len_65535() {
     let mut output: Vec<u8> = Vec::new();
     let writer = &mut output;
 
-    let result = jsonl_decode_ben32(&mut reader, writer, 0, BenVariant::Standard);
+    let result = jsonl_decode_ben32(&mut reader, writer, 1, BenVariant::Standard);
     if let Err(e) = result {
         panic!("Error: {}", e);
     }
@@ -523,7 +523,7 @@ fn test_decode_ben32_single_element() {
     let mut output: Vec<u8> = Vec::new();
     let writer = &mut output;
 
-    let result = jsonl_decode_ben32(&mut reader, writer, 0, BenVariant::Standard);
+    let result = jsonl_decode_ben32(&mut reader, writer, 1, BenVariant::Standard);
     println!("result {:?}", result);
     if let Err(e) = result {
         panic!("Error: {}", e);
@@ -549,7 +549,7 @@ fn test_decode_ben32_multiple_simple_lines() {
     let mut output: Vec<u8> = Vec::new();
     let writer = &mut output;
 
-    let result = jsonl_decode_ben32(&mut reader, writer, 0, BenVariant::Standard);
+    let result = jsonl_decode_ben32(&mut reader, writer, 1, BenVariant::Standard);
     if let Err(e) = result {
         panic!("Error: {}", e);
     }
@@ -589,3 +589,1204 @@ fn test_decode_ben32_multiple_simple_lines() {
 
     assert_eq!(output, expected_output.concat().as_bytes());
 }
+
+#[test]
+fn test_ben_decoder_builder_options() {
+    let mut input: Vec<u8> = b"STANDARD BEN FILE".to_vec();
+    input.extend(vec![2, 3, 0, 0, 0, 2, 0b01100_100, 0b01_11011_0]);
+
+    let progress = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let progress_clone = progress.clone();
+
+    let decoder = BenDecoderBuilder::new()
+        .max_bytes(2)
+        .buffer_size(16)
+        .strict(true)
+        .expected_nodes(8)
+        .progress(move |n| progress_clone.lock().unwrap().push(n))
+        .build(input.as_slice())
+        .unwrap();
+
+    let records = decoder.collect::<io::Result<Vec<_>>>().unwrap();
+
+    assert_eq!(records, vec![(rle_to_vec(vec![(1, 4), (2, 1), (3, 3)]), 1)]);
+    assert_eq!(*progress.lock().unwrap(), vec![1]);
+}
+
+#[test]
+fn test_ben_decoder_with_expected_len() {
+    let samples: Vec<Vec<u16>> = vec![
+        vec![1, 1, 2, 2],
+        vec![1, 2, 2, 2],
+        vec![1, 2, 2],
+        vec![2, 2, 1, 1],
+    ];
+
+    for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+        let mut encoder = crate::encode::BenEncoder::new(Vec::new(), variant);
+        for sample in samples.iter() {
+            encoder.write_assignment_slice(sample).unwrap();
+        }
+        let ben = encoder.finish().unwrap();
+
+        let mut decoder = BenDecoder::with_expected_len(ben.as_slice(), variant, 4).unwrap();
+        assert_eq!(decoder.next().unwrap().unwrap().0, samples[0]);
+        assert_eq!(decoder.next().unwrap().unwrap().0, samples[1]);
+
+        let err = decoder.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err
+            .to_string()
+            .contains("Sample 3 has 3 nodes but 4 were expected"));
+
+        // The short sample has been read past, so decoding picks up again
+        // with the sample after it.
+        assert_eq!(decoder.next().unwrap().unwrap().0, samples[3]);
+        assert!(decoder.next().is_none());
+    }
+
+    let mut ben = Vec::new();
+    crate::encode::BenEncoder::new_with_frame_crc(&mut ben, BenVariant::MkvChain)
+        .write_assignment(vec![1, 2])
+        .unwrap();
+    let err = BenDecoder::with_expected_len(ben.as_slice(), BenVariant::Standard, 2)
+        .err()
+        .unwrap();
+    assert!(matches!(err, DecoderInitError::InvalidFileFormat(_)));
+    assert!(err.to_string().contains("MKVCHAIN BEN FCRC"));
+}
+
+#[test]
+fn test_ben_decoder_with_fixed_widths() {
+    // Every frame has labels up to 3 and runs up to 3 long, so 2 bits each
+    let samples: Vec<Vec<u16>> = vec![
+        vec![1, 1, 2, 2, 3, 3, 3],
+        vec![1, 2, 3, 3, 2, 3, 1],
+        vec![1, 2, 3, 3, 2, 3, 1],
+        vec![3, 3, 3, 2, 2, 1, 2],
+        vec![2, 1, 1, 1, 3, 2, 1],
+    ];
+
+    for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+        let mut encoder = crate::encode::BenEncoder::new(Vec::new(), variant);
+        for sample in samples.iter() {
+            encoder.write_assignment_slice(sample).unwrap();
+        }
+        let ben = encoder.finish().unwrap();
+
+        let expected = BenDecoder::new(ben.as_slice())
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        let fixed = BenDecoder::new(ben.as_slice())
+            .unwrap()
+            .with_fixed_widths(2, 2)
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(fixed, expected);
+
+        let built = BenDecoderBuilder::new()
+            .fixed_widths(2, 2)
+            .build(ben.as_slice())
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(built, expected);
+
+        let mut decoder = BenDecoder::new(ben.as_slice())
+            .unwrap()
+            .with_fixed_widths(2, 3);
+        let err = decoder.next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err
+            .to_string()
+            .contains("has max_val_bits = 2, max_len_bits = 2 but 2, 3 were expected"));
+
+        // Each rejected frame is read past, so every frame gets its own error
+        let rest = decoder.collect::<Vec<_>>();
+        assert_eq!(rest.len(), expected.len() - 1);
+        assert!(rest.iter().all(|record| record.is_err()));
+    }
+
+    // The widest runs that a frame can hold
+    let mut wide = vec![40000u16; 40000];
+    wide.extend([1, 2, 65535]);
+    let mut ben = Vec::new();
+    crate::encode::BenEncoder::new(&mut ben, BenVariant::Standard)
+        .write_assignment(wide.clone())
+        .unwrap();
+    let mut decoder = BenDecoder::new(ben.as_slice())
+        .unwrap()
+        .with_fixed_widths(16, 16);
+    assert_eq!(decoder.next().unwrap().unwrap().0, wide);
+}
+
+#[test]
+fn test_ben_decoder_builder_rejects_invalid_frames() {
+    let mut input: Vec<u8> = b"STANDARD BEN FILE".to_vec();
+    input.extend(vec![2, 3, 0, 0, 0, 2, 0b01100_100, 0b01_11011_0]);
+
+    let mut decoder = BenDecoderBuilder::new()
+        .max_bytes(1)
+        .build(input.as_slice())
+        .unwrap();
+    assert!(decoder.next().unwrap().is_err());
+
+    let mut decoder = BenDecoderBuilder::new()
+        .expected_nodes(7)
+        .build(input.as_slice())
+        .unwrap();
+    assert!(decoder.next().unwrap().is_err());
+
+    let mut bad_widths: Vec<u8> = b"STANDARD BEN FILE".to_vec();
+    bad_widths.extend(vec![17, 3, 0, 0, 0, 0]);
+    let mut decoder = BenDecoderBuilder::new()
+        .strict(true)
+        .build(bad_widths.as_slice())
+        .unwrap();
+    assert!(decoder.next().unwrap().is_err());
+}
+
+#[test]
+fn test_ben_decoder_skips_rejected_frames() {
+    let mut ben = Vec::new();
+    let mut encoder = crate::encode::BenEncoder::new(&mut ben, BenVariant::MkvChain);
+    for assignment in [
+        vec![1, 1, 2, 2],
+        vec![1, 1, 2, 2],
+        vec![1, 2, 3, 1, 2, 3, 1, 2],
+        vec![2, 2, 1, 1],
+    ] {
+        encoder.write_assignment(assignment).unwrap();
+    }
+    encoder.finish().unwrap();
+
+    // The long sample is rejected by both checks, but the decoder should
+    // still be lined up with the frame that follows it.
+    for builder in [
+        BenDecoderBuilder::new().max_bytes(2),
+        BenDecoderBuilder::new().expected_nodes(4),
+    ] {
+        let results = builder.build(ben.as_slice()).unwrap().collect::<Vec<_>>();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap(), &(vec![1, 1, 2, 2], 2));
+        assert!(results[1]
+            .as_ref()
+            .unwrap_err()
+            .to_string()
+            .contains("sample 3"));
+        assert_eq!(results[2].as_ref().unwrap(), &(vec![2, 2, 1, 1], 1));
+    }
+}
+
+#[test]
+fn test_ben_decoder_strict_eof_rejects_concatenated_files() {
+    let mut single: Vec<u8> = b"STANDARD BEN FILE".to_vec();
+    single.extend(crate::encode::encode_ben_vec_from_assign(vec![
+        1, 1, 1, 1, 2, 3, 3, 3,
+    ]));
+    let mut concatenated = single.clone();
+    concatenated.extend(&single);
+
+    let decoder = BenDecoderBuilder::new()
+        .strict_eof(true)
+        .build(single.as_slice())
+        .unwrap();
+    assert_eq!(decoder.collect::<io::Result<Vec<_>>>().unwrap().len(), 1);
+
+    let mut decoder = BenDecoderBuilder::new()
+        .strict_eof(true)
+        .build(concatenated.as_slice())
+        .unwrap();
+    assert!(decoder.next().unwrap().is_ok());
+    let err = decoder.next().unwrap().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(
+        err.to_string()
+            .contains("Found 25 trailing bytes after the last frame at offset 25"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_delta_ben_roundtrip() {
+    let mut big = (0..70_000)
+        .map(|i| (i / 1000 % 7) as u16 + 1)
+        .collect::<Vec<u16>>();
+    let mut samples = vec![big.clone()];
+    big[0] = 1;
+    big[69_999] = 2;
+    samples.push(big.clone());
+    samples.push(big.clone());
+    big[40_000] = 500;
+    samples.push(big);
+
+    let input = samples
+        .iter()
+        .enumerate()
+        .map(|(i, s)| json!({"assignment": s, "sample": i + 1}).to_string() + "\n")
+        .collect::<String>();
+
+    let mut ben = Vec::new();
+    crate::encode::jsonl_encode_delta_ben(input.as_bytes(), &mut ben).unwrap();
+
+    let decoder = BenDecoder::new(ben.as_slice()).unwrap();
+    assert_eq!(decoder.variant(), BenVariant::Delta);
+    let records = decoder.collect::<io::Result<Vec<_>>>().unwrap();
+    assert_eq!(
+        records,
+        samples.into_iter().map(|s| (s, 1)).collect::<Vec<_>>()
+    );
+
+    let mut output = Vec::new();
+    jsonl_decode_ben(ben.as_slice(), &mut output).unwrap();
+    assert_eq!(output, input.as_bytes());
+}
+
+#[test]
+fn test_delta_ben_index_out_of_range() {
+    let mut input: Vec<u8> = b"MKVDELTA BEN FILE".to_vec();
+    input.extend(crate::encode::encode_ben_vec_from_assign(vec![1, 1]));
+    // Change node 2 (out of range) to 1
+    input.extend(vec![2, 1, 0, 0, 0, 1, 0b101_00000]);
+
+    let mut decoder = BenDecoder::new(input.as_slice()).unwrap();
+    assert!(decoder.next().unwrap().is_ok());
+    assert_eq!(
+        decoder.next().unwrap().unwrap_err().kind(),
+        io::ErrorKind::InvalidData
+    );
+}
+
+#[test]
+fn test_garbage_input_is_an_error_not_a_panic() {
+    let garbage = [0xde, 0xad, 0xbe, 0xef, 0x00];
+
+    let err = BenDecoder::new(garbage.as_slice()).err().unwrap();
+    assert!(matches!(err, DecoderInitError::Io(_)));
+
+    let mut output = Vec::new();
+    let err = jsonl_decode_ben(garbage.as_slice(), &mut output).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    assert!(output.is_empty());
+}
+
+#[test]
+fn test_truncated_frames_are_errors_not_panics() {
+    let input = r#"{"assignment":[1,1,2,2,3],"sample":1}
+{"assignment":[1,1,2,2,3],"sample":2}
+{"assignment":[3,3,2,2,1],"sample":3}
+"#;
+
+    for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+        let mut ben = Vec::new();
+        crate::encode::jsonl_encode_ben(input.as_bytes(), &mut ben, variant).unwrap();
+
+        // Every cut after the banner that does not fall on a frame boundary
+        let mut frame_ends = vec![17];
+        let mut decoder = BenDecoder::new(ben.as_slice()).unwrap();
+        while decoder.next().is_some() {
+            frame_ends.push(decoder.position() as usize);
+        }
+
+        for cut in 18..ben.len() {
+            if frame_ends.contains(&cut) {
+                continue;
+            }
+            let decoder = BenDecoder::new(&ben[..cut]).unwrap();
+            let err = decoder
+                .collect::<io::Result<Vec<_>>>()
+                .expect_err(&format!("cut at byte {} should fail", cut));
+            assert_eq!(
+                err.kind(),
+                io::ErrorKind::UnexpectedEof,
+                "cut at byte {}",
+                cut
+            );
+        }
+    }
+
+    // A frame header with widths of zero bits
+    let mut ben = b"STANDARD BEN FILE".to_vec();
+    ben.extend([0, 0, 0, 0, 0, 5, 1, 2, 3, 4, 5]);
+    let mut decoder = BenDecoder::new(ben.as_slice()).unwrap();
+    let err = decoder.next().unwrap().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(decoder.next().is_none());
+    let err = decode_ben_line([1, 2, 3, 4, 5].as_slice(), 0, 0, 5).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_delta_frame_with_huge_change_count_is_an_error() {
+    // A one node first frame, then a delta frame claiming 2^32 - 1 changes
+    // of 48 bits each but holding only 3 bytes
+    let mut ben = b"MKVDELTA BEN FILE".to_vec();
+    ben.extend([1, 1, 0, 0, 0, 1, 0xC0]);
+    ben.extend([32, 16, 0xFF, 0xFF, 0xFF, 0xFF, 1, 2, 3]);
+
+    let mut decoder = BenDecoder::new(ben.as_slice()).unwrap();
+    assert_eq!(decoder.next().unwrap().unwrap(), (vec![1], 1));
+    let err = decoder.next().unwrap().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+    let mut decoder = BenDecoderBuilder::new()
+        .max_bytes(1024)
+        .build(ben.as_slice())
+        .unwrap();
+    assert_eq!(decoder.next().unwrap().unwrap(), (vec![1], 1));
+    let err = decoder.next().unwrap().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err
+        .to_string()
+        .contains("exceeds the maximum of 1024 bytes"));
+}
+
+#[test]
+fn test_ben_decoder_init_error_offsets() {
+    let err = BenDecoder::new(b"STANDARD BEN".as_slice()).err().unwrap();
+    assert!(matches!(err, DecoderInitError::Io(_)));
+    assert_eq!(err.offset(), 12);
+
+    let err = BenDecoder::new(b"NOT A BEN FILE!!!".as_slice())
+        .err()
+        .unwrap();
+    assert!(matches!(err, DecoderInitError::InvalidFileFormat(_)));
+    assert_eq!(err.offset(), 0);
+}
+
+#[test]
+fn test_ben_decoder_frame_error_offset() {
+    let mut input: Vec<u8> = b"STANDARD BEN FILE".to_vec();
+    input.extend(vec![2, 3, 0, 0, 0, 2, 0b01100_100, 0b01_11011_0]);
+    // The second frame claims 4 bytes of payload but only has 1
+    input.extend(vec![2, 3, 0, 0, 0, 4, 0b01100_100]);
+
+    let mut decoder = BenDecoder::new(input.as_slice()).unwrap();
+    assert!(decoder.next().unwrap().is_ok());
+    assert_eq!(decoder.position(), 25);
+
+    let err = decoder.next().unwrap().unwrap_err();
+    let frame_error = err
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<FrameError>())
+        .unwrap();
+
+    assert_eq!(frame_error.sample, 2);
+    assert_eq!(frame_error.frame_offset, 25);
+    assert_eq!(frame_error.offset, 32);
+}
+
+#[test]
+fn test_ben_rle_decoder() {
+    let input = r#"{"assignment":[1,1,2,2,2],"sample":1}
+{"assignment":[1,1,2,2,2],"sample":2}
+{"assignment":[3,1,1,1,1],"sample":3}
+"#;
+
+    for variant in [BenVariant::MkvChain, BenVariant::Delta] {
+        let mut ben = Vec::new();
+        crate::encode::jsonl_encode_ben(input.as_bytes(), &mut ben, variant).unwrap();
+
+        let records = BenRleDecoder::new(ben.as_slice())
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap()
+            .into_iter()
+            .flat_map(|(rle, count)| std::iter::repeat_n(rle, count as usize))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            records,
+            vec![
+                vec![(1, 2), (2, 3)],
+                vec![(1, 2), (2, 3)],
+                vec![(3, 1), (1, 4)],
+            ]
+        );
+    }
+}
+
+#[test]
+fn test_jsonl_decode_with_sample_base() {
+    let input = r#"{"assignment":[1,1,2,2],"sample":1}
+{"assignment":[1,1,2,2],"sample":2}
+{"assignment":[2,1,2,2],"sample":3}
+"#;
+    let expected = r#"{"assignment":[1,1,2,2],"sample":0}
+{"assignment":[1,1,2,2],"sample":1}
+{"assignment":[2,1,2,2],"sample":2}
+"#;
+
+    let mut ben = Vec::new();
+    crate::encode::jsonl_encode_ben(input.as_bytes(), &mut ben, BenVariant::MkvChain).unwrap();
+    let mut output = Vec::new();
+    jsonl_decode_ben_with_base(ben.as_slice(), &mut output, 0).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), expected);
+
+    let mut xben = Vec::new();
+    crate::encode::jsonl_encode_xben(input.as_bytes(), &mut xben, BenVariant::MkvChain).unwrap();
+    let mut output = Vec::new();
+    jsonl_decode_xben_with_base(xben.as_slice(), &mut output, 0).unwrap();
+    assert_eq!(
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .collect::<Vec<_>>(),
+        expected.lines().collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn test_ben_to_txt() {
+    let input = r#"{"assignment":[1,1,2,2],"sample":1}
+{"assignment":[1,1,2,2],"sample":2}
+{"assignment":[12,1,2,2],"sample":3}
+"#;
+
+    let mut ben = Vec::new();
+    crate::encode::jsonl_encode_ben(input.as_bytes(), &mut ben, BenVariant::MkvChain).unwrap();
+
+    let mut output = Vec::new();
+    ben_to_txt(ben.as_slice(), &mut output).unwrap();
+
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "1 1 2 2\n1 1 2 2\n12 1 2 2\n"
+    );
+}
+
+#[test]
+fn test_ben_decoder_frame_crc_roundtrip() {
+    let samples = vec![
+        vec![1, 1, 2, 2],
+        vec![1, 1, 2, 2],
+        vec![1, 2, 2, 2],
+        vec![3, 2, 2, 1],
+    ];
+
+    for variant in [
+        BenVariant::Standard,
+        BenVariant::MkvChain,
+        BenVariant::Delta,
+    ] {
+        let mut ben = Vec::new();
+        let mut encoder = crate::encode::BenEncoder::new_with_frame_crc(&mut ben, variant);
+        for sample in samples.iter() {
+            encoder.write_assignment(sample.clone()).unwrap();
+        }
+        drop(encoder);
+
+        let decoder = BenDecoder::new(ben.as_slice()).unwrap();
+        assert!(decoder.has_frame_crc());
+        let decoded = decoder
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap()
+            .into_iter()
+            .flat_map(|(assignment, count)| std::iter::repeat_n(assignment, count as usize))
+            .collect::<Vec<_>>();
+
+        assert_eq!(decoded, samples);
+    }
+}
+
+#[test]
+fn test_ben_decoder_frame_crc_mismatch() {
+    let mut ben = Vec::new();
+    let mut encoder = crate::encode::BenEncoder::new_with_frame_crc(&mut ben, BenVariant::Standard);
+    encoder.write_assignment(vec![1, 1, 2, 2]).unwrap();
+    encoder.write_assignment(vec![1, 2, 2, 2]).unwrap();
+    drop(encoder);
+
+    // Flip a bit in the payload of the second frame
+    let last = ben.len() - 1;
+    ben[last] ^= 0b1000_0000;
+
+    let mut decoder = BenDecoder::new(ben.as_slice()).unwrap();
+    assert!(decoder.next().unwrap().is_ok());
+
+    let err = decoder.next().unwrap().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let frame_error = err
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<FrameError>())
+        .unwrap();
+    assert_eq!(frame_error.sample, 2);
+}
+
+#[test]
+fn test_decode_ben_best_effort() {
+    let input = r#"{"assignment":[1,1,2,2],"sample":1}
+{"assignment":[1,1,2,2],"sample":2}
+{"assignment":[2,1,2,2],"sample":3}
+"#;
+
+    let mut ben = Vec::new();
+    crate::encode::jsonl_encode_ben(input.as_bytes(), &mut ben, BenVariant::MkvChain).unwrap();
+
+    let (samples, err) = decode_ben_best_effort(ben.as_slice());
+    assert_eq!(
+        samples,
+        vec![vec![1, 1, 2, 2], vec![1, 1, 2, 2], vec![2, 1, 2, 2]]
+    );
+    assert!(err.is_none());
+
+    // Cut the file in the middle of the payload of the last frame
+    let truncated = &ben[..ben.len() - 3];
+    let (samples, err) = decode_ben_best_effort(truncated);
+    assert_eq!(samples, vec![vec![1, 1, 2, 2], vec![1, 1, 2, 2]]);
+    assert_eq!(err.unwrap().kind(), io::ErrorKind::UnexpectedEof);
+
+    let (samples, err) = decode_ben_best_effort(b"NOT A BEN FILE!!!".as_slice());
+    assert!(samples.is_empty());
+    assert!(err.is_some());
+}
+
+#[test]
+fn test_length_prefixed_xben_roundtrip() {
+    let input = r#"{"assignment":[1,1,2,2],"sample":1}
+{"assignment":[1,1,2,2],"sample":2}
+{"assignment":[2,1,2,300],"sample":3}
+{"assignment":[3,3,3,3],"sample":4}
+"#;
+
+    for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+        let mut xben = Vec::new();
+        crate::encode::jsonl_encode_xben_length_prefixed(input.as_bytes(), &mut xben, variant)
+            .unwrap();
+
+        let mut banner = [0u8; 17];
+        xz2::read::XzDecoder::new(xben.as_slice())
+            .read_exact(&mut banner)
+            .unwrap();
+        assert_eq!(&banner[13..], b"LP32");
+
+        let mut output = Vec::new();
+        jsonl_decode_xben(xben.as_slice(), &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), input);
+
+        let mut ben = Vec::new();
+        decode_xben_to_ben(xben.as_slice(), &mut ben).unwrap();
+        let mut expected = Vec::new();
+        crate::encode::jsonl_encode_ben(input.as_bytes(), &mut expected, variant).unwrap();
+        assert_eq!(ben, expected);
+
+        // xz compressed input with a trailing blank line gives the same file
+        let mut compressed = Vec::new();
+        crate::encode::xz_compress((input.to_string() + "\n").as_bytes(), &mut compressed).unwrap();
+        let mut from_xz = Vec::new();
+        crate::encode::jsonl_encode_xben_length_prefixed(
+            compressed.as_slice(),
+            &mut from_xz,
+            variant,
+        )
+        .unwrap();
+        assert_eq!(from_xz, xben);
+    }
+}
+
+#[test]
+fn test_length_prefixed_xben_truncated() {
+    let input = r#"{"assignment":[1,1,2,2],"sample":1}"#;
+
+    let mut xben = Vec::new();
+    crate::encode::jsonl_encode_xben_length_prefixed(
+        input.as_bytes(),
+        &mut xben,
+        BenVariant::Standard,
+    )
+    .unwrap();
+
+    let mut raw = Vec::new();
+    xz2::read::XzDecoder::new(xben.as_slice())
+        .read_to_end(&mut raw)
+        .unwrap();
+    raw.pop();
+
+    let mut truncated = Vec::new();
+    crate::encode::xz_compress(raw.as_slice(), &mut truncated).unwrap();
+
+    let err = jsonl_decode_xben(truncated.as_slice(), io::sink()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn test_verify_xben() {
+    let input = r#"{"assignment":[1,1,2,2],"sample":1}
+{"assignment":[1,1,2,2],"sample":2}
+{"assignment":[2,1,2,2],"sample":3}
+"#;
+
+    let mut xben = Vec::new();
+    crate::encode::jsonl_encode_xben(input.as_bytes(), &mut xben, BenVariant::MkvChain).unwrap();
+
+    let report = verify_xben(xben.as_slice()).unwrap();
+    assert!(report.is_ok());
+    assert_eq!(report.variant, Some(BenVariant::MkvChain));
+    assert_eq!(report.n_frames, 2);
+    assert_eq!(report.n_samples, 3);
+
+    // Corrupting the XZ stream is caught by the XZ layer
+    let mut broken_xz = xben.clone();
+    broken_xz.truncate(xben.len() - 10);
+    let report = verify_xben(broken_xz.as_slice()).unwrap();
+    assert!(report.xz_error.is_some());
+    assert!(report.ben32_error.is_none());
+
+    // A valid XZ stream with a truncated frame is caught by the ben32 layer
+    let mut raw = Vec::new();
+    xz2::read::XzDecoder::new(xben.as_slice())
+        .read_to_end(&mut raw)
+        .unwrap();
+    raw.pop();
+    let mut broken_ben32 = Vec::new();
+    crate::encode::xz_compress(raw.as_slice(), &mut broken_ben32).unwrap();
+
+    let report = verify_xben(broken_ben32.as_slice()).unwrap();
+    assert!(report.xz_error.is_none());
+    assert!(report
+        .ben32_error
+        .unwrap()
+        .contains("Missing repetition count in sample 3"));
+    assert_eq!(report.n_samples, 2);
+}
+
+#[test]
+fn test_jsonl_decode_ben_writer_error_is_returned() {
+    let input = r#"{"assignment":[1,1,2,2],"sample":1}
+{"assignment":[1,1,2,2],"sample":2}
+"#;
+
+    let mut ben = Vec::new();
+    crate::encode::jsonl_encode_ben(input.as_bytes(), &mut ben, BenVariant::MkvChain).unwrap();
+
+    let mut small_buffer = [0u8; 10];
+    let err = jsonl_decode_ben(ben.as_slice(), &mut small_buffer[..]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+}
+
+#[test]
+fn test_jsonl_decode_ben_with_flush() {
+    // Records the number of complete lines that had been written at each flush
+    struct FlushRecorder {
+        data: Vec<u8>,
+        flushed_at: Vec<usize>,
+    }
+
+    impl Write for FlushRecorder {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            let lines = self.data.iter().filter(|&&b| b == b'\n').count();
+            self.flushed_at.push(lines);
+            Ok(())
+        }
+    }
+
+    let input = (1..=7)
+        .map(|i| json!({"assignment": [1, 1, 2, 2], "sample": i}).to_string() + "\n")
+        .collect::<String>();
+    let mut ben = Vec::new();
+    crate::encode::jsonl_encode_ben(input.as_bytes(), &mut ben, BenVariant::MkvChain).unwrap();
+
+    let mut recorder = FlushRecorder {
+        data: Vec::new(),
+        flushed_at: Vec::new(),
+    };
+    jsonl_decode_ben_with_flush(ben.as_slice(), &mut recorder, 3).unwrap();
+    assert_eq!(recorder.flushed_at, vec![3, 6, 7]);
+    assert_eq!(
+        String::from_utf8(recorder.data).unwrap(),
+        input.replace(' ', "")
+    );
+
+    let mut recorder = FlushRecorder {
+        data: Vec::new(),
+        flushed_at: Vec::new(),
+    };
+    jsonl_decode_ben_with_flush(ben.as_slice(), &mut recorder, 0).unwrap();
+    assert_eq!(recorder.flushed_at, vec![7]);
+}
+
+#[test]
+fn test_jsonl_decode_ben_subsample_renumber() {
+    let input = (1..=6)
+        .map(|i| json!({"assignment": [1, 1, 2, (i + 1) / 2], "sample": i}).to_string() + "\n")
+        .collect::<String>();
+
+    let mut ben = Vec::new();
+    crate::encode::jsonl_encode_ben(input.as_bytes(), &mut ben, BenVariant::MkvChain).unwrap();
+
+    let sample_numbers = |renumber: bool| {
+        let mut output = Vec::new();
+        jsonl_decode_ben_subsample(ben.as_slice(), &mut output, Selection::Every(2), renumber)
+            .unwrap();
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let value: Value = serde_json::from_str(line).unwrap();
+                (
+                    value["sample"].as_u64().unwrap(),
+                    value["assignment"][3].as_u64().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(sample_numbers(false), vec![(2, 1), (4, 2), (6, 3)]);
+    assert_eq!(sample_numbers(true), vec![(1, 1), (2, 2), (3, 3)]);
+
+    let err = jsonl_decode_ben_subsample(ben.as_slice(), Vec::new(), Selection::Every(0), true)
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_tailing_ben_decoder_byte_by_byte() {
+    for variant in [
+        BenVariant::Standard,
+        BenVariant::MkvChain,
+        BenVariant::Delta,
+    ] {
+        let samples = [
+            vec![1, 1, 2, 2, 3],
+            vec![1, 1, 2, 2, 3],
+            vec![1, 2, 2, 2, 3],
+            vec![3, 2, 2, 2, 1],
+        ];
+
+        let mut ben = Vec::new();
+        let mut encoder = crate::encode::BenEncoder::new_with_frame_crc(&mut ben, variant);
+        for sample in samples.iter() {
+            encoder.write_assignment(sample.clone()).unwrap();
+        }
+        drop(encoder);
+
+        let mut expected = Vec::new();
+        for record in BenDecoder::new(ben.as_slice()).unwrap() {
+            expected.push(record.unwrap());
+        }
+
+        // Feed the file in one byte at a time, as if it were being written
+        let mut decoder = TailingBenDecoder::new(std::collections::VecDeque::new());
+        let mut records = Vec::new();
+        for (i, &byte) in ben.iter().enumerate() {
+            decoder.get_mut().push_back(byte);
+            match decoder.next_record() {
+                Ok(record) => {
+                    assert!(decoder.at_frame_boundary());
+                    records.push(record);
+                }
+                Err(e) => {
+                    assert_eq!(e.kind(), io::ErrorKind::WouldBlock);
+                    // Right after the banner is the only other boundary
+                    assert_eq!(decoder.at_frame_boundary(), i + 1 == 17);
+                }
+            }
+        }
+
+        assert_eq!(records, expected);
+        assert_eq!(
+            decoder.next_record().unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+    }
+}
+
+#[test]
+fn test_tailing_ben_decoder_invalid_banner() {
+    let mut decoder = TailingBenDecoder::new(&b"NOT A BEN FILE AT ALL"[..]);
+    assert_eq!(
+        decoder.next_record().unwrap_err().kind(),
+        io::ErrorKind::InvalidData
+    );
+}
+
+#[test]
+fn test_template_ben_roundtrip() {
+    let template: Vec<u16> = (0..1000).map(|i| (i / 100) as u16 + 1).collect();
+    let samples: Vec<Vec<u16>> = (0..20)
+        .map(|i| {
+            let mut sample = template.clone();
+            sample[i * 37] = 300;
+            sample[999 - i] = 1;
+            sample
+        })
+        .chain(std::iter::once(template.clone()))
+        .collect();
+
+    let mut buffer = Vec::new();
+    crate::encode::encode_template_ben(&template, samples.clone(), &mut buffer).unwrap();
+
+    let decoder = TemplateBenDecoder::new(buffer.as_slice()).unwrap();
+    assert_eq!(decoder.template(), template.as_slice());
+    assert_eq!(decoder.collect::<io::Result<Vec<_>>>().unwrap(), samples);
+
+    assert!(BenDecoder::new(buffer.as_slice()).is_err());
+}
+
+#[test]
+fn test_template_ben_errors() {
+    let err =
+        crate::encode::encode_template_ben(&[1, 2, 3], vec![vec![1, 2]], Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    let mut ben = Vec::new();
+    crate::encode::BenEncoder::new(&mut ben, BenVariant::Standard)
+        .write_assignment(vec![1, 2])
+        .unwrap();
+    let err = TemplateBenDecoder::new(ben.as_slice()).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn test_ben_gz_roundtrip() {
+    let input = r#"{"assignment":[1,1,2,2],"sample":1}
+{"assignment":[1,1,2,2],"sample":2}
+{"assignment":[2,1,2,2],"sample":3}
+"#;
+
+    let mut gz = Vec::new();
+    crate::encode::jsonl_encode_ben_gz(input.as_bytes(), &mut gz, BenVariant::MkvChain).unwrap();
+    assert_eq!(&gz[..2], &[0x1f, 0x8b]);
+
+    let mut ben = Vec::new();
+    flate2::read::GzDecoder::new(gz.as_slice())
+        .read_to_end(&mut ben)
+        .unwrap();
+    let mut expected = Vec::new();
+    crate::encode::jsonl_encode_ben(input.as_bytes(), &mut expected, BenVariant::MkvChain).unwrap();
+    assert_eq!(ben, expected);
+
+    let mut output = Vec::new();
+    decode_ben_gz_to_jsonl(gz.as_slice(), &mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), input);
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn test_open_ben_auto_sniffs_gzip() {
+    use std::io::Write;
+
+    let input = r#"{"assignment":[1,1,2,2],"sample":1}
+{"assignment":[1,1,2,2],"sample":2}
+{"assignment":[2,1,2,2],"sample":3}
+"#;
+
+    let mut ben = Vec::new();
+    crate::encode::jsonl_encode_ben(input.as_bytes(), &mut ben, BenVariant::MkvChain).unwrap();
+
+    let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gz.write_all(&ben).unwrap();
+    let gz = gz.finish().unwrap();
+
+    for data in [gz, ben] {
+        let reader = open_ben_auto(io::Cursor::new(data.clone())).unwrap();
+        let mut output = Vec::new();
+        jsonl_decode_ben(reader, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), input);
+
+        // Only one byte at a time is available, like a slow pipe
+        let reader = open_ben_auto(io::BufReader::with_capacity(1, io::Cursor::new(data))).unwrap();
+        let mut output = Vec::new();
+        jsonl_decode_ben(reader, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), input);
+    }
+}
+
+#[test]
+fn test_subsample_xben_to_jsonl() {
+    // Pairs of repeated samples so that the MkvChain frames have counts of 2
+    let input = (1..=100)
+        .map(|i| json!({"assignment": [1, 2, (i + 1) / 2], "sample": i}).to_string() + "\n")
+        .collect::<String>();
+
+    for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+        let mut plain = Vec::new();
+        crate::encode::jsonl_encode_xben(input.as_bytes(), &mut plain, variant).unwrap();
+        let mut prefixed = Vec::new();
+        crate::encode::jsonl_encode_xben_length_prefixed(input.as_bytes(), &mut prefixed, variant)
+            .unwrap();
+
+        for xben in [plain, prefixed] {
+            let mut output = Vec::new();
+            subsample_xben_to_jsonl(
+                xben.as_slice(),
+                &mut output,
+                Selection::Indices(vec![1, 50, 100]),
+            )
+            .unwrap();
+
+            let samples = String::from_utf8(output)
+                .unwrap()
+                .lines()
+                .map(|line| {
+                    let value: Value = serde_json::from_str(line).unwrap();
+                    (
+                        value["sample"].as_u64().unwrap(),
+                        value["assignment"][2].as_u64().unwrap(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(samples, vec![(1, 1), (50, 25), (100, 50)]);
+        }
+    }
+}
+
+#[test]
+fn test_decoders_are_send() {
+    // The Python bindings need to move a decoder off the GIL-holding thread
+    // while it reads, so these must stay `Send` for `Send` readers.
+    fn assert_send<T: Send>() {}
+    assert_send::<BenDecoder<std::fs::File>>();
+    assert_send::<BenRleDecoder<std::fs::File>>();
+    assert_send::<XBenDecoder<std::io::BufReader<std::fs::File>>>();
+}
+
+#[test]
+fn test_jsonl_decode_ben_weighted_collapses_runs() {
+    let input = [
+        vec![1, 1, 2, 2],
+        vec![1, 1, 2, 2],
+        vec![1, 1, 2, 2],
+        vec![2, 1, 1, 2],
+        vec![1, 2, 2, 1],
+        vec![1, 2, 2, 1],
+    ]
+    .iter()
+    .enumerate()
+    .map(|(i, s)| json!({"assignment": s, "sample": i + 1}).to_string() + "\n")
+    .collect::<String>();
+
+    let mut ben = Vec::new();
+    crate::encode::jsonl_encode_ben(input.as_bytes(), &mut ben, BenVariant::MkvChain).unwrap();
+
+    let mut expanded = Vec::new();
+    jsonl_decode_ben(ben.as_slice(), &mut expanded).unwrap();
+    let expanded = String::from_utf8(expanded).unwrap();
+    assert_eq!(expanded, input);
+
+    let mut collapsed = Vec::new();
+    jsonl_decode_ben_weighted(ben.as_slice(), &mut collapsed).unwrap();
+    let lines = String::from_utf8(collapsed)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .collect::<Vec<_>>();
+
+    assert!(lines.len() < expanded.lines().count());
+    assert_eq!(
+        lines
+            .iter()
+            .map(|line| line["weight"].as_u64().unwrap())
+            .sum::<u64>(),
+        expanded.lines().count() as u64
+    );
+    assert_eq!(
+        lines,
+        vec![
+            json!({"assignment": [1, 1, 2, 2], "sample": 1, "weight": 3}),
+            json!({"assignment": [2, 1, 1, 2], "sample": 4, "weight": 1}),
+            json!({"assignment": [1, 2, 2, 1], "sample": 5, "weight": 2}),
+        ]
+    );
+}
+
+#[test]
+fn test_count_samples_matches_fixture() {
+    let input = std::fs::read_to_string(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/example/small_example.jsonl"
+    ))
+    .unwrap();
+    let repeated = input.clone() + input.lines().last().unwrap() + "\n";
+
+    for (input, n_samples) in [(&input, 6), (&repeated, 7)] {
+        for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+            let mut ben = Vec::new();
+            crate::encode::jsonl_encode_ben(input.as_bytes(), &mut ben, variant).unwrap();
+            assert_eq!(count_samples_ben(ben.as_slice()).unwrap(), n_samples);
+
+            let mut xben = Vec::new();
+            crate::encode::jsonl_encode_xben(input.as_bytes(), &mut xben, variant).unwrap();
+            assert_eq!(count_samples_xben(xben.as_slice()).unwrap(), n_samples);
+
+            let mut lp32 = Vec::new();
+            crate::encode::jsonl_encode_xben_length_prefixed(input.as_bytes(), &mut lp32, variant)
+                .unwrap();
+            assert_eq!(count_samples_xben(lp32.as_slice()).unwrap(), n_samples);
+        }
+    }
+}
+
+#[test]
+fn test_count_samples_ben_delta_and_truncated() {
+    let input = (0..20)
+        .map(|i| {
+            json!({"assignment": [1, 1, 2, 2, (i % 3) as u16], "sample": i + 1}).to_string() + "\n"
+        })
+        .collect::<String>();
+
+    let mut ben = Vec::new();
+    crate::encode::jsonl_encode_ben(input.as_bytes(), &mut ben, BenVariant::Delta).unwrap();
+    assert_eq!(count_samples_ben(ben.as_slice()).unwrap(), 20);
+
+    let err = count_samples_ben(&ben[..ben.len() - 1]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+    let err = count_samples_ben(&b"NOT A BEN FILE!!!"[..]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_jsonl_decode_ben_split() {
+    let input = (1..=7)
+        .map(|i| json!({"assignment": [1, 2, (i / 3) as u16], "sample": i}).to_string() + "\n")
+        .collect::<String>();
+
+    let mut ben = Vec::new();
+    crate::encode::jsonl_encode_ben(input.as_bytes(), &mut ben, BenVariant::MkvChain).unwrap();
+
+    let dir = std::env::temp_dir().join(format!("ben_split_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let part_path = |part: usize| dir.join(format!("out.part{}.jsonl", part));
+
+    let n_parts = jsonl_decode_ben_split(ben.as_slice(), 3, |part| {
+        std::fs::File::create(part_path(part))
+    })
+    .unwrap();
+    assert_eq!(n_parts, 3);
+
+    let parts = (0..n_parts)
+        .map(|part| std::fs::read_to_string(part_path(part)).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        parts
+            .iter()
+            .map(|part| part.lines().count())
+            .collect::<Vec<_>>(),
+        vec![3, 3, 1]
+    );
+    assert_eq!(parts.concat(), input);
+    assert!(!part_path(3).exists());
+
+    let err = jsonl_decode_ben_split(ben.as_slice(), 0, |part| {
+        std::fs::File::create(part_path(part))
+    })
+    .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_decode_ben_to_matrix() {
+    let flat: Vec<u16> = vec![1, 1, 2, 2, 1, 1, 2, 2, 2, 1, 1, 2, 3, 3, 1, 2];
+
+    for variant in [
+        BenVariant::Standard,
+        BenVariant::MkvChain,
+        BenVariant::Delta,
+    ] {
+        let mut ben = Vec::new();
+        crate::encode::encode_matrix_ben(&flat, 4, &mut ben, variant).unwrap();
+
+        let (decoded, n_nodes) = decode_ben_to_matrix(ben.as_slice()).unwrap();
+        assert_eq!(n_nodes, 4);
+        assert_eq!(decoded, flat);
+
+        let rows = BenDecoder::new(ben.as_slice())
+            .unwrap()
+            .flat_map(|record| {
+                let (assignment, count) = record.unwrap();
+                vec![assignment; count as usize]
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(decoded.chunks(n_nodes).collect::<Vec<_>>(), rows);
+    }
+
+    let empty = b"STANDARD BEN FILE".to_vec();
+    assert_eq!(decode_ben_to_matrix(empty.as_slice()).unwrap(), (vec![], 0));
+
+    let mut ben = Vec::new();
+    let mut encoder = crate::encode::BenEncoder::new(&mut ben, BenVariant::MkvChain);
+    encoder.write_assignment(vec![1, 2]).unwrap();
+    encoder.write_assignment(vec![1, 2]).unwrap();
+    encoder.write_assignment(vec![1, 2, 2]).unwrap();
+    drop(encoder);
+    let err = decode_ben_to_matrix(ben.as_slice()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err
+        .to_string()
+        .contains("Sample 3 has 3 nodes but the first sample has 2"));
+}
+
+#[test]
+fn test_raw_frame_decoder_matches_rle_decoder() {
+    let samples: Vec<Vec<u16>> = vec![
+        vec![1, 1, 2, 2, 3],
+        vec![1, 1, 2, 2, 3],
+        vec![7, 7, 7, 7, 7, 7, 7, 1],
+        vec![2, 300, 2, 1, 1],
+    ];
+
+    for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+        let mut encoder = crate::encode::BenEncoder::new(Vec::new(), variant);
+        for sample in samples.iter() {
+            encoder.write_assignment_slice(sample).unwrap();
+        }
+        let ben = encoder.finish().unwrap();
+
+        let expected = BenRleDecoder::new(ben.as_slice())
+            .unwrap()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+
+        let mut decoder = RawFrameDecoder::new(ben.as_slice()).unwrap();
+        assert_eq!(decoder.variant(), variant);
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.next_frame() {
+            let frame = frame.unwrap();
+            let rle = decode_ben_line(
+                frame.payload,
+                frame.max_val_bits,
+                frame.max_len_bits,
+                frame.payload.len() as u32,
+            )
+            .unwrap();
+            frames.push((rle, frame.count));
+        }
+        assert_eq!(frames, expected);
+
+        let mut truncated = RawFrameDecoder::new(&ben[..ben.len() - 3]).unwrap();
+        let results = std::iter::from_fn(|| truncated.next_frame().map(|frame| frame.is_ok()))
+            .collect::<Vec<_>>();
+        assert_eq!(results.last(), Some(&false));
+    }
+
+    let mut ben = Vec::new();
+    crate::encode::BenEncoder::new(&mut ben, BenVariant::Delta)
+        .write_assignment(vec![1, 2])
+        .unwrap();
+    assert!(matches!(
+        RawFrameDecoder::new(ben.as_slice()),
+        Err(DecoderInitError::InvalidFileFormat(_))
+    ));
+}
+
+#[test]
+fn test_raw_frame_decoder_truncated_huge_frame() {
+    // A frame header claiming almost 4GB of payload followed by two bytes
+    let mut ben = b"STANDARD BEN FILE".to_vec();
+    ben.extend([2, 2, 0xFF, 0xFF, 0xFF, 0xF0, 0x5A, 0x5A]);
+
+    let mut decoder = RawFrameDecoder::new(ben.as_slice()).unwrap();
+    let err = decoder.next_frame().unwrap().err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+}
@@ -0,0 +1,266 @@
+use super::*;
+use crate::decode::test_common::encode_samples;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::io::Cursor;
+
+#[test]
+fn test_frame_index_mkvchain() {
+    let samples = vec![
+        vec![1, 1, 2, 2],
+        vec![1, 1, 2, 2],
+        vec![1, 1, 2, 2],
+        vec![2, 2, 1, 1],
+        vec![3, 3, 1, 1],
+    ];
+    let ben = encode_samples(&samples, BenVariant::MkvChain);
+    let mut reader = Cursor::new(ben);
+
+    let index = FrameIndex::build(&mut reader).unwrap();
+
+    assert_eq!(index.variant(), BenVariant::MkvChain);
+    assert_eq!(index.n_frames(), 3);
+    assert_eq!(index.n_samples(), 5);
+    assert_eq!(index.offsets()[0], 17);
+    assert_eq!(index.frame_of(0), None);
+    assert_eq!(index.frame_of(3), Some(0));
+    assert_eq!(index.frame_of(4), Some(1));
+    assert_eq!(index.frame_of(5), Some(2));
+    assert_eq!(index.frame_of(6), None);
+
+    for (i, sample) in samples.iter().enumerate() {
+        let offset = index.offset_of(i + 1).unwrap();
+        assert_eq!(&decode_frame_at(&mut reader, offset).unwrap(), sample);
+    }
+    assert_eq!(
+        index.offset_of(6).unwrap_err().kind(),
+        io::ErrorKind::InvalidInput
+    );
+}
+
+#[test]
+fn test_frame_index_truncated_final_frame() {
+    let samples = vec![vec![1, 1, 2, 2], vec![3, 3, 1, 1]];
+    let mut ben = encode_samples(&samples, BenVariant::Standard);
+    ben.pop();
+
+    let err = FrameIndex::build(&mut Cursor::new(ben.clone())).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+    let decoded = crate::decode::BenDecoder::new(ben.as_slice())
+        .unwrap()
+        .collect::<io::Result<Vec<_>>>();
+    assert_eq!(decoded.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn test_ben_index_random_get_matches_linear_decode() {
+    let mut rng = ChaCha8Rng::seed_from_u64(7);
+    let mut samples: Vec<Vec<u16>> = Vec::new();
+    for _ in 0..200 {
+        // Repeat samples now and then so that MkvChain frames hold runs
+        if !samples.is_empty() && rng.gen_bool(0.4) {
+            samples.push(samples.last().unwrap().clone());
+        } else {
+            samples.push((0..30).map(|_| rng.gen_range(1..=5)).collect());
+        }
+    }
+
+    for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+        let ben = encode_samples(&samples, variant);
+
+        let linear = crate::decode::adapters::expand_counts(
+            crate::decode::BenDecoder::new(ben.as_slice()).unwrap(),
+        )
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap();
+        assert_eq!(linear, samples);
+
+        let mut index = BenIndex::build(Cursor::new(ben)).unwrap();
+        assert_eq!(index.n_samples(), samples.len());
+        assert_eq!(index.frames().counts().sum::<usize>(), samples.len());
+        if variant == BenVariant::MkvChain {
+            assert!(index.frames().n_frames() < samples.len());
+        }
+
+        for _ in 0..100 {
+            let sample = rng.gen_range(1..=samples.len());
+            assert_eq!(index.get(sample).unwrap(), linear[sample - 1]);
+        }
+        assert_eq!(
+            index.get(0).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            index.get(samples.len() + 1).unwrap_err().kind(),
+            io::ErrorKind::InvalidInput
+        );
+    }
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_par_get_samples_matches_linear_decode() {
+    let samples: Vec<Vec<u16>> = (0..50u16)
+        .map(|i| vec![i % 3, i % 3, i % 5 + 1, i % 7 + 2, 4])
+        .collect();
+
+    for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+        let ben = encode_samples(&samples, variant);
+        let mut reader = Cursor::new(ben.as_slice());
+        let index = FrameIndex::build(&mut reader).unwrap();
+
+        let requested = [50, 1, 17, 17, 33, 2, 49];
+        let decoded = par_get_samples(reader, &index, &requested).unwrap();
+
+        let expected: Vec<Vec<u16>> = requested.iter().map(|&s| samples[s - 1].clone()).collect();
+        assert_eq!(decoded, expected);
+
+        let err = par_get_samples(Cursor::new(ben.as_slice()), &index, &[1, 51]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
+
+#[test]
+fn test_index_file_round_trip() {
+    let samples = vec![
+        vec![1, 1, 2, 2],
+        vec![1, 1, 2, 2],
+        vec![2, 2, 1, 1],
+        vec![3, 3, 1, 1],
+        vec![3, 3, 1, 1],
+    ];
+
+    for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+        let ben = encode_samples(&samples, variant);
+        let index = BenIndex::build(Cursor::new(ben.as_slice())).unwrap();
+
+        let mut saved = Vec::new();
+        index.write_to(&mut saved).unwrap();
+        assert_eq!(&saved[..6], b"BENIDX");
+
+        let mut loaded =
+            BenIndex::read_from(Cursor::new(ben.as_slice()), saved.as_slice()).unwrap();
+        assert_eq!(loaded.frames(), index.frames());
+        for (i, sample) in samples.iter().enumerate() {
+            assert_eq!(&loaded.get(i + 1).unwrap(), sample);
+        }
+    }
+}
+
+#[test]
+fn test_index_file_rejects_corrupt_data() {
+    let ben = encode_samples(&[vec![1, 2], vec![1, 2], vec![2, 1]], BenVariant::MkvChain);
+    let index = FrameIndex::build(&mut Cursor::new(ben)).unwrap();
+    let mut saved = Vec::new();
+    index.write_to(&mut saved).unwrap();
+
+    for len in 0..saved.len() {
+        let err = FrameIndex::read_from(&saved[..len]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("truncated"), "{}", err);
+    }
+
+    let mut bad_magic = saved.clone();
+    bad_magic[0] = b'X';
+    let err = FrameIndex::read_from(bad_magic.as_slice()).unwrap_err();
+    assert!(err.to_string().contains("Not a BEN index file"));
+
+    let mut bad_version = saved.clone();
+    bad_version[6] = 99;
+    let err = FrameIndex::read_from(bad_version.as_slice()).unwrap_err();
+    assert!(err.to_string().contains("version 99"));
+
+    let mut trailing = saved.clone();
+    trailing.push(0);
+    let err = FrameIndex::read_from(trailing.as_slice()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+/// A `ReadAt` source that records the ranges that were requested from it.
+struct RecordingSource {
+    data: Vec<u8>,
+    requests: std::cell::RefCell<Vec<(u64, usize)>>,
+}
+
+impl ReadAt for RecordingSource {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        self.requests.borrow_mut().push((offset, buf.len()));
+        self.data.read_at(buf, offset)
+    }
+}
+
+#[test]
+fn test_indexed_ben_reader() {
+    let samples = vec![
+        vec![1, 1, 2, 2],
+        vec![1, 1, 2, 2],
+        vec![2, 2, 1, 1],
+        vec![3, 3, 3, 1],
+    ];
+
+    for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+        let ben = encode_samples(&samples, variant);
+        let frames = FrameIndex::build(&mut Cursor::new(ben.as_slice())).unwrap();
+        let reader = IndexedBenReader::new(
+            RecordingSource {
+                data: ben,
+                requests: Default::default(),
+            },
+            frames,
+        );
+
+        assert_eq!(reader.n_samples(), 4);
+        for (i, sample) in samples.iter().enumerate() {
+            assert_eq!(&reader.get(i + 1).unwrap(), sample);
+        }
+
+        // Only the last frame needs a separate read for its header
+        let requests = reader.into_inner().requests.into_inner();
+        assert_eq!(requests.len(), samples.len() + 1);
+    }
+}
+
+#[test]
+fn test_read_range_past_end() {
+    let data = vec![1u8, 2, 3, 4];
+    assert_eq!(data.read_range(1, 2).unwrap(), vec![2, 3]);
+    let err = data.read_range(2, 5).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn test_frame_layout() {
+    let samples = vec![
+        vec![1, 1, 2, 2],
+        vec![1, 1, 1, 2, 2, 5],
+        vec![1, 1, 1, 2, 2, 5],
+    ];
+
+    for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+        let ben = encode_samples(&samples, variant);
+        let mut index = BenIndex::build(Cursor::new(ben)).unwrap();
+
+        let layout = index.frame_layout(3).unwrap();
+        assert_eq!(layout.runs, index.frame_layout(2).unwrap().runs);
+        assert_eq!(layout.offset, index.frames().offset_of(3).unwrap());
+        assert_eq!(layout.runs, vec![(1, 3), (2, 2), (5, 1)]);
+        assert_eq!((layout.max_val_bits, layout.max_len_bits), (3, 2));
+        assert_eq!(layout.n_bytes, 2);
+
+        let err = index.frame_layout(4).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
+
+#[test]
+fn test_read_ben_variant_names_frame_crc_banner() {
+    let mut ben = Vec::new();
+    let mut encoder = crate::encode::BenEncoder::new_with_frame_crc(&mut ben, BenVariant::MkvChain);
+    encoder.write_assignment(vec![1, 1, 2]).unwrap();
+    drop(encoder);
+
+    let err = read_ben_variant(&mut ben.as_slice()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("MKVCHAIN BEN FCRC"));
+}
@@ -0,0 +1,65 @@
+use super::*;
+use crate::encode::jsonl_encode_xben;
+use crate::BenVariant;
+use std::fs;
+
+fn scratch_path(name: &str) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("ben_mmap_{}_{}", std::process::id(), name))
+}
+
+fn sample_jsonl() -> String {
+    (1..=50)
+        .map(|i| {
+            let assignment = (0..20).map(|j| (i + j) % 7 + 1).collect::<Vec<_>>();
+            format!(r#"{{"assignment":{:?},"sample":{}}}"#, assignment, i).replace(' ', "") + "\n"
+        })
+        .collect()
+}
+
+#[test]
+fn test_mmap_writer_grows_and_truncates() {
+    let path = scratch_path("grow.txt");
+    let data = (0..10_000u32)
+        .map(|i| format!("{}\n", i))
+        .collect::<String>();
+
+    let mut writer = MmapWriter::create(&path, 16).unwrap();
+    writer.write_all(data.as_bytes()).unwrap();
+    assert_eq!(writer.position(), data.len());
+    assert!(writer.capacity() >= data.len());
+    writer.finish().unwrap();
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), data);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_mmap_writer_truncates_on_drop() {
+    let path = scratch_path("drop.txt");
+    {
+        let mut writer = MmapWriter::create(&path, 1 << 16).unwrap();
+        writer.write_all(b"hello").unwrap();
+    }
+
+    assert_eq!(fs::read(&path).unwrap(), b"hello");
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_jsonl_decode_xben_to_mmap() {
+    let input = sample_jsonl();
+    let mut xben = Vec::new();
+    jsonl_encode_xben(input.as_bytes(), &mut xben, BenVariant::MkvChain).unwrap();
+
+    let estimate = estimate_jsonl_len(50, 20, 7);
+    assert!(estimate >= input.len());
+
+    // Once with room to spare and once with a capacity that forces the
+    // output to be remapped several times.
+    for capacity in [estimate, 1] {
+        let path = scratch_path(&format!("decode_{}.jsonl", capacity));
+        jsonl_decode_xben_to_mmap(xben.as_slice(), &path, capacity).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), input);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -105,3 +105,83 @@ fn test_extract_assignment_sample_too_large() {
         ),
     }
 }
+
+#[test]
+fn test_extract_last_assignment() {
+    let input = std::fs::read_to_string(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/example/small_example.jsonl"
+    ))
+    .unwrap();
+    let last_line = input.lines().last().unwrap();
+    let last: Vec<u16> = serde_json::from_value(
+        serde_json::from_str::<Value>(last_line).unwrap()["assignment"].clone(),
+    )
+    .unwrap();
+    let repeated = input.clone() + last_line + "\n";
+
+    for input in [&input, &repeated] {
+        for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+            let mut ben = Vec::new();
+            crate::encode::jsonl_encode_ben(input.as_bytes(), &mut ben, variant).unwrap();
+
+            assert_eq!(
+                extract_last_assignment(io::Cursor::new(&ben)).unwrap(),
+                last
+            );
+            assert_eq!(
+                extract_last_assignment_stream(ben.as_slice()).unwrap(),
+                last
+            );
+        }
+    }
+}
+
+#[test]
+fn test_extract_last_assignment_small_frames() {
+    let input = (1..=20)
+        .map(|i| {
+            serde_json::json!({"assignment": [1, 1, 2, (i % 7) as u16, 3], "sample": i}).to_string()
+                + "\n"
+        })
+        .collect::<String>();
+
+    for variant in [
+        BenVariant::Standard,
+        BenVariant::MkvChain,
+        BenVariant::Delta,
+    ] {
+        let mut ben = Vec::new();
+        crate::encode::jsonl_encode_ben(input.as_bytes(), &mut ben, variant).unwrap();
+        assert_eq!(
+            extract_last_assignment(io::Cursor::new(&ben)).unwrap(),
+            vec![1, 1, 2, 6, 3]
+        );
+    }
+
+    let empty = b"STANDARD BEN FILE".to_vec();
+    let err = extract_last_assignment(io::Cursor::new(&empty)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let err = extract_last_assignment_stream(empty.as_slice()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_extract_last_assignment_fake_header_in_payload() {
+    // The payload of this frame contains bytes that look like the header of
+    // a 206 node frame reaching to the end of the file.
+    let rle = vec![(8, 2048), (0, 6), (200, 51205), (5, 257)];
+    let assignment = rle_to_vec(rle.clone());
+    assert_eq!(assignment.len(), 53_516);
+
+    for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+        let mut encoder = crate::encode::BenEncoder::new(Vec::new(), variant);
+        encoder.write_rle(rle.clone()).unwrap();
+        let ben = encoder.finish().unwrap();
+
+        assert_eq!(
+            extract_last_assignment(io::Cursor::new(&ben)).unwrap(),
+            assignment
+        );
+    }
+}
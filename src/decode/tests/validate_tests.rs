@@ -0,0 +1,75 @@
+use super::*;
+use crate::decode::test_common::encode_samples;
+
+#[test]
+fn test_validate_good_file() {
+    let samples = vec![
+        vec![1, 1, 2, 2, 3],
+        vec![1, 1, 2, 2, 3],
+        vec![1, 2, 1, 2, 1],
+        vec![300, 300, 300, 1, 1],
+    ];
+
+    for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+        let ben = encode_samples(&samples, variant);
+        assert_eq!(validate_ben(ben.as_slice()).unwrap(), 4);
+    }
+}
+
+#[test]
+fn test_validate_inconsistent_n_bytes() {
+    let samples = vec![vec![1, 1, 2, 2, 3], vec![3, 3, 3, 2, 1]];
+    let mut ben = encode_samples(&samples, BenVariant::Standard);
+
+    // Claim an extra byte in the first frame and supply a zero byte for it,
+    // so the frame can still be read but holds too much padding.
+    ben[17 + 5] += 1;
+    let first_frame_end = 17 + 6 + ben[17 + 5] as usize - 1;
+    ben.insert(first_frame_end, 0);
+
+    match validate_ben(ben.as_slice()) {
+        Err(ValidationError::InconsistentLength {
+            sample: 1,
+            n_bytes,
+            expected,
+        }) => assert_eq!(n_bytes, expected + 1),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn test_validate_other_problems() {
+    let samples = vec![vec![1, 1, 2, 2], vec![1, 2]];
+
+    let ben = encode_samples(&samples, BenVariant::MkvChain);
+    assert!(matches!(
+        validate_ben(ben.as_slice()),
+        Err(ValidationError::NodeCountMismatch {
+            sample: 2,
+            n_nodes: 2,
+            expected: 4
+        })
+    ));
+    assert_eq!(
+        validate_ben_with_node_check(ben.as_slice(), false).unwrap(),
+        2
+    );
+
+    let truncated = &ben[..ben.len() - 1];
+    assert!(matches!(
+        validate_ben_with_node_check(truncated, false),
+        Err(ValidationError::MissingCount { sample: 2 })
+    ));
+
+    let mut bad_widths = ben.clone();
+    bad_widths[17] = 17;
+    assert!(matches!(
+        validate_ben(bad_widths.as_slice()),
+        Err(ValidationError::InvalidBitWidths { sample: 1, .. })
+    ));
+
+    assert!(matches!(
+        validate_ben(b"NOT A BEN FILE!!!".as_slice()),
+        Err(ValidationError::InvalidBanner)
+    ));
+}
@@ -0,0 +1,74 @@
+use super::*;
+use crate::encode::wide::{encode_ben_vec_from_rle_u32, encode_wide_ben, jsonl_encode_ben_wide};
+use serde_json::Value;
+
+#[test]
+fn test_wide_frame_matches_regular_frame_for_small_labels() {
+    let rle = vec![(1, 3), (2, 2), (3, 4)];
+    let wide = encode_ben_vec_from_rle_u32(&rle);
+    let regular = crate::encode::encode_ben_vec_from_assign(vec![1, 1, 1, 2, 2, 3, 3, 3, 3]);
+    assert_eq!(wide, regular);
+}
+
+#[test]
+fn test_wide_frame_with_zero_bit_widths_is_an_error() {
+    for (val_bits, len_bits) in [(0, 8), (8, 0), (0, 0)] {
+        let err = decode_ben_line_u32([0xFFu8; 4].as_slice(), val_bits, len_bits, 4).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn test_wide_ben_round_trip_large_labels() {
+    let samples: Vec<Vec<u32>> = vec![
+        vec![70000, 70000, 1, 1, 4_000_000_000],
+        vec![70000, 70000, 1, 1, 4_000_000_000],
+        vec![0, 65536, 65536, 2, 2],
+    ];
+
+    for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+        let mut ben = Vec::new();
+        encode_wide_ben(samples.iter().cloned().map(Ok), &mut ben, variant).unwrap();
+
+        let decoder = WideBenDecoder::new(ben.as_slice()).unwrap();
+        assert_eq!(decoder.variant(), variant);
+        let decoded = decoder
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap()
+            .into_iter()
+            .flat_map(|(assignment, count)| std::iter::repeat_n(assignment, count as usize))
+            .collect::<Vec<_>>();
+        assert_eq!(decoded, samples);
+    }
+}
+
+#[test]
+fn test_jsonl_decode_ben_routes_wide_files() {
+    let input = r#"{"assignment": [70000, 70000, 3, 3], "sample": 1}
+{"assignment": [70000, 70000, 3, 3], "sample": 2}
+{"assignment": [1, 70001, 70001, 3], "sample": 3}
+"#;
+
+    let mut ben = Vec::new();
+    jsonl_encode_ben_wide(input.as_bytes(), &mut ben, BenVariant::MkvChain).unwrap();
+    assert_eq!(&ben[..17], b"MKVCHAIN BEN WIDE");
+
+    let mut output = Vec::new();
+    crate::decode::jsonl_decode_ben(ben.as_slice(), &mut output).unwrap();
+
+    let expected: Vec<Value> = input
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    let decoded: Vec<Value> = String::from_utf8(output)
+        .unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert_eq!(decoded, expected);
+
+    let too_large = r#"{"assignment": [4294967296], "sample": 1}"#;
+    let err =
+        jsonl_encode_ben_wide(too_large.as_bytes(), Vec::new(), BenVariant::Standard).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,288 @@
+//! This module contains a structural validator for BEN files. Rather than
+//! decoding the samples, `validate_ben` checks that every frame is laid out
+//! the way the encoder would have written it, so that a file can be checked
+//! before it is archived.
+
+use byteorder::{BigEndian, ReadBytesExt};
+use std::fmt;
+use std::io::{self, Read};
+
+use super::index::read_ben_variant;
+use super::CountingReader;
+use crate::BenVariant;
+
+/// The problems that `validate_ben` can find in a BEN file. Every variant
+/// that concerns a frame names the sample number (starting from 1) of the
+/// first sample stored in that frame.
+#[derive(Debug)]
+pub enum ValidationError {
+    /// The file does not start with a Standard or MkvChain BEN banner.
+    InvalidBanner,
+    /// The file ends in the middle of the frame.
+    Truncated { sample: usize, offset: u64 },
+    /// The bit widths in the frame header are outside of the range 1..=16.
+    InvalidBitWidths {
+        sample: usize,
+        max_val_bits: u8,
+        max_len_bits: u8,
+    },
+    /// The payload length in the frame header does not match the number of
+    /// runs that the payload holds.
+    InconsistentLength {
+        sample: usize,
+        n_bytes: u32,
+        expected: u32,
+    },
+    /// A run of length 0 appears before the padding at the end of the frame.
+    ZeroLengthRun { sample: usize, run: usize },
+    /// The assignment does not have the same number of nodes as the first
+    /// sample of the file.
+    NodeCountMismatch {
+        sample: usize,
+        n_nodes: usize,
+        expected: usize,
+    },
+    /// The repetition count after an MkvChain frame is missing.
+    MissingCount { sample: usize },
+    /// The repetition count after an MkvChain frame is 0.
+    ZeroCount { sample: usize },
+    /// The file could not be read.
+    Io(io::Error),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::InvalidBanner => {
+                write!(f, "The file is not a Standard or MkvChain BEN file")
+            }
+            ValidationError::Truncated { sample, offset } => write!(
+                f,
+                "The frame for sample {} is truncated at byte {}",
+                sample, offset
+            ),
+            ValidationError::InvalidBitWidths {
+                sample,
+                max_val_bits,
+                max_len_bits,
+            } => write!(
+                f,
+                "Invalid frame header for sample {}: max_val_bits = {}, max_len_bits = {}",
+                sample, max_val_bits, max_len_bits
+            ),
+            ValidationError::InconsistentLength {
+                sample,
+                n_bytes,
+                expected,
+            } => write!(
+                f,
+                "The frame for sample {} claims {} bytes but its runs fill {} bytes",
+                sample, n_bytes, expected
+            ),
+            ValidationError::ZeroLengthRun { sample, run } => write!(
+                f,
+                "Run {} of the frame for sample {} has length 0",
+                run, sample
+            ),
+            ValidationError::NodeCountMismatch {
+                sample,
+                n_nodes,
+                expected,
+            } => write!(
+                f,
+                "Sample {} has {} nodes but the first sample has {}",
+                sample, n_nodes, expected
+            ),
+            ValidationError::MissingCount { sample } => write!(
+                f,
+                "The repetition count after the frame for sample {} is missing",
+                sample
+            ),
+            ValidationError::ZeroCount { sample } => write!(
+                f,
+                "The repetition count after the frame for sample {} is 0",
+                sample
+            ),
+            ValidationError::Io(e) => write!(f, "IO error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ValidationError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ValidationError {
+    fn from(error: io::Error) -> Self {
+        ValidationError::Io(error)
+    }
+}
+
+/// Checks the payload of a frame and returns the number of nodes in it.
+///
+/// The payload holds `(value, length)` pairs packed back to back, followed by
+/// enough zero bits to fill the last byte. When the pairs are narrower than
+/// a byte, the padding can itself look like `(0, 0)` pairs, so a run of
+/// length 0 is only an error if a non-zero bit follows it.
+fn check_payload(
+    payload: &[u8],
+    max_val_bits: u8,
+    max_len_bits: u8,
+    sample: usize,
+) -> Result<usize, ValidationError> {
+    let pair_bits = (max_val_bits + max_len_bits) as usize;
+    let total_bits = payload.len() * 8;
+    let bit = |i: usize| (payload[i / 8] >> (7 - i % 8)) & 1;
+    let read_bits = |start: usize, n: u8| {
+        (start..start + n as usize).fold(0u32, |acc, i| (acc << 1) | bit(i) as u32)
+    };
+
+    let mut n_runs = 0;
+    let mut n_nodes = 0;
+    let mut start = 0;
+    while start + pair_bits <= total_bits {
+        let len = read_bits(start + max_val_bits as usize, max_len_bits);
+        if len == 0 {
+            if (start..total_bits).all(|i| bit(i) == 0) {
+                break;
+            }
+            return Err(ValidationError::ZeroLengthRun {
+                sample,
+                run: n_runs + 1,
+            });
+        }
+        n_runs += 1;
+        n_nodes += len as usize;
+        start += pair_bits;
+    }
+
+    let expected = (n_runs * pair_bits).div_ceil(8) as u32;
+    if n_runs == 0 || expected != payload.len() as u32 {
+        return Err(ValidationError::InconsistentLength {
+            sample,
+            n_bytes: payload.len() as u32,
+            expected,
+        });
+    }
+
+    Ok(n_nodes)
+}
+
+/// Walks every frame of a Standard or MkvChain BEN file and checks that it
+/// is structurally sound: the bit widths are valid, `n_bytes` matches the
+/// number of runs in the payload, no run has length 0, every sample has the
+/// same number of nodes as the first one, and MkvChain frames are followed
+/// by a non-zero repetition count.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the BEN file
+///
+/// # Returns
+///
+/// The number of samples in the file (including MkvChain repetitions)
+///
+/// # Errors
+///
+/// Returns the first `ValidationError` found in the file.
+pub fn validate_ben<R: Read>(reader: R) -> Result<usize, ValidationError> {
+    validate_ben_with_node_check(reader, true)
+}
+
+/// Same as `validate_ben`, but checking that every sample has the same number
+/// of nodes as the first one can be turned off with `check_node_counts`
+/// (e.g. for files that deliberately mix graphs).
+pub fn validate_ben_with_node_check<R: Read>(
+    reader: R,
+    check_node_counts: bool,
+) -> Result<usize, ValidationError> {
+    let mut reader = CountingReader::new(reader);
+    let variant = read_ben_variant(&mut reader).map_err(|e| match e.kind() {
+        io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => ValidationError::InvalidBanner,
+        _ => ValidationError::Io(e),
+    })?;
+
+    let mut n_samples = 0;
+    let mut first_n_nodes: Option<usize> = None;
+    loop {
+        let sample = n_samples + 1;
+        let truncated = |reader: &CountingReader<R>, e: io::Error| {
+            if e.kind() == io::ErrorKind::UnexpectedEof {
+                ValidationError::Truncated {
+                    sample,
+                    offset: reader.position(),
+                }
+            } else {
+                ValidationError::Io(e)
+            }
+        };
+
+        let mut tmp_buffer = [0u8];
+        match reader.read_exact(&mut tmp_buffer) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let max_val_bits = tmp_buffer[0];
+        let max_len_bits = reader.read_u8().map_err(|e| truncated(&reader, e))?;
+        if !(1..=16).contains(&max_val_bits) || !(1..=16).contains(&max_len_bits) {
+            return Err(ValidationError::InvalidBitWidths {
+                sample,
+                max_val_bits,
+                max_len_bits,
+            });
+        }
+
+        let n_bytes = reader
+            .read_u32::<BigEndian>()
+            .map_err(|e| truncated(&reader, e))?;
+        let mut payload = Vec::new();
+        (&mut reader)
+            .take(n_bytes as u64)
+            .read_to_end(&mut payload)?;
+        if payload.len() < n_bytes as usize {
+            return Err(ValidationError::Truncated {
+                sample,
+                offset: reader.position(),
+            });
+        }
+
+        let n_nodes = check_payload(&payload, max_val_bits, max_len_bits, sample)?;
+        match first_n_nodes {
+            Some(expected) if check_node_counts && expected != n_nodes => {
+                return Err(ValidationError::NodeCountMismatch {
+                    sample,
+                    n_nodes,
+                    expected,
+                });
+            }
+            Some(_) => {}
+            None => first_n_nodes = Some(n_nodes),
+        }
+
+        let count = if variant == BenVariant::MkvChain {
+            match reader.read_u16::<BigEndian>() {
+                Ok(0) => return Err(ValidationError::ZeroCount { sample }),
+                Ok(count) => count,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Err(ValidationError::MissingCount { sample });
+                }
+                Err(e) => return Err(e.into()),
+            }
+        } else {
+            1
+        };
+        n_samples += count as usize;
+    }
+
+    Ok(n_samples)
+}
+
+#[cfg(test)]
+#[path = "tests/validate_tests.rs"]
+mod tests;
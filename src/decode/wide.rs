@@ -0,0 +1,187 @@
+//! This module contains the functions for reading "wide" BEN files, whose
+//! assignment labels are 32 bits wide rather than 16 (see the
+//! `encode::wide` module). `jsonl_decode_ben` detects wide files from their
+//! banner and routes them here automatically.
+
+use byteorder::{BigEndian, ReadBytesExt};
+use serde_json::json;
+use std::io::{self, Error, Read, Write};
+
+use crate::BenVariant;
+
+/// Whether a 17 byte banner is the banner of a wide BEN file.
+pub(crate) fn is_wide_banner(banner: &[u8]) -> bool {
+    banner == b"STANDARD BEN WIDE" || banner == b"MKVCHAIN BEN WIDE"
+}
+
+/// Decodes the payload of a single frame of a wide BEN file into a run-length
+/// encoded assignment vector. This is the counterpart of `decode_ben_line`
+/// for values that are up to 32 bits wide.
+///
+/// # Arguments
+///
+/// * `reader` - A reader positioned at the start of the frame payload
+/// * `max_val_bits` - The number of bits used for each value
+/// * `max_len_bits` - The number of bits used for each run length
+/// * `n_bytes` - The number of bytes in the payload
+///
+/// # Returns
+///
+/// A vector of `(value, length)` runs
+///
+/// # Errors
+///
+/// Returns an error if the bit widths are zero or too large, or if the payload cannot
+/// be read.
+pub fn decode_ben_line_u32<R: Read>(
+    mut reader: R,
+    max_val_bits: u8,
+    max_len_bits: u8,
+    n_bytes: u32,
+) -> io::Result<Vec<(u32, u16)>> {
+    if max_val_bits == 0 || max_len_bits == 0 || max_val_bits > 32 || max_len_bits > 16 {
+        return Err(Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Invalid bit widths in frame header ({} value bits, {} length bits)",
+                max_val_bits, max_len_bits
+            ),
+        ));
+    }
+
+    let mut assign_bits: Vec<u8> = vec![0; n_bytes as usize];
+    reader.read_exact(&mut assign_bits)?;
+
+    let pair_bits = max_val_bits + max_len_bits;
+    let mut output_rle: Vec<(u32, u16)> = Vec::new();
+    let mut buffer: u64 = 0;
+    let mut n_bits_in_buff: u8 = 0;
+
+    for byte in assign_bits {
+        buffer = (buffer << 8) | byte as u64;
+        n_bits_in_buff += 8;
+
+        while pair_bits > 0 && n_bits_in_buff >= pair_bits {
+            n_bits_in_buff -= pair_bits;
+            let pair = buffer >> n_bits_in_buff;
+            buffer &= (1u64 << n_bits_in_buff) - 1;
+
+            let val = (pair >> max_len_bits) as u32;
+            let len = (pair & ((1u64 << max_len_bits) - 1)) as u16;
+            // Padding at the end of the frame can look like (0, 0) runs
+            if len > 0 {
+                output_rle.push((val, len));
+            }
+        }
+    }
+
+    Ok(output_rle)
+}
+
+/// An iterator over the frames of a wide BEN file that yields each
+/// assignment vector (with u32 labels) along with its repetition count.
+pub struct WideBenDecoder<R: Read> {
+    reader: R,
+    variant: BenVariant,
+}
+
+impl<R: Read> WideBenDecoder<R> {
+    /// Creates a new decoder after reading and checking the banner.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidData` error if the file is not a wide BEN file.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut check_buffer = [0u8; 17];
+        reader.read_exact(&mut check_buffer)?;
+
+        let variant = match &check_buffer {
+            b"STANDARD BEN WIDE" => BenVariant::Standard,
+            b"MKVCHAIN BEN WIDE" => BenVariant::MkvChain,
+            _ => {
+                return Err(Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Invalid file format",
+                ))
+            }
+        };
+
+        Ok(WideBenDecoder { reader, variant })
+    }
+
+    /// The variant of the file being decoded.
+    pub fn variant(&self) -> BenVariant {
+        self.variant
+    }
+}
+
+impl<R: Read> Iterator for WideBenDecoder<R> {
+    type Item = io::Result<(Vec<u32>, u16)>;
+
+    fn next(&mut self) -> Option<io::Result<(Vec<u32>, u16)>> {
+        let mut tmp_buffer = [0u8];
+        match self.reader.read_exact(&mut tmp_buffer) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return None,
+            Err(e) => return Some(Err(e)),
+        }
+
+        let frame = (|| {
+            let max_len_bits = self.reader.read_u8()?;
+            let n_bytes = self.reader.read_u32::<BigEndian>()?;
+            let rle = decode_ben_line_u32(&mut self.reader, tmp_buffer[0], max_len_bits, n_bytes)?;
+            let count = if self.variant == BenVariant::MkvChain {
+                self.reader.read_u16::<BigEndian>()?
+            } else {
+                1
+            };
+
+            let assignment = rle
+                .into_iter()
+                .flat_map(|(val, len)| std::iter::repeat_n(val, len as usize))
+                .collect();
+            Ok((assignment, count))
+        })();
+
+        Some(frame)
+    }
+}
+
+/// Decodes a wide BEN file into a JSONL file, numbering the samples
+/// starting from `sample_base`.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the wide BEN file
+/// * `writer` - A writer that will contain the JSONL formatted assignment vectors
+/// * `sample_base` - The sample number given to the first sample in the file
+///
+/// # Errors
+///
+/// Returns an error if the file is not a valid wide BEN file or if writing
+/// to the writer fails.
+pub fn jsonl_decode_ben_wide<R: Read, W: Write>(
+    reader: R,
+    mut writer: W,
+    sample_base: usize,
+) -> io::Result<()> {
+    let mut sample = sample_base;
+    for record in WideBenDecoder::new(reader)? {
+        let (assignment, count) = record?;
+        for _ in 0..count {
+            let line = json!({
+                "assignment": assignment,
+                "sample": sample,
+            })
+            .to_string()
+                + "\n";
+            writer.write_all(line.as_bytes())?;
+            sample += 1;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+#[path = "tests/wide_tests.rs"]
+mod tests;
@@ -22,13 +22,23 @@
 
 pub mod relabel;
 pub mod translate;
+pub mod wide;
 
+use crate::decode::adapters::{expand_counts, Selection};
+use crate::decode::index::{decode_frame_at, read_ben_variant};
+use crate::decode::{decode_ben_line, decode_xben_to_ben, BenDecoder, CountingReader};
 use crate::utils::*;
+use byteorder::{BigEndian, ReadBytesExt};
 use serde_json::Value;
-use std::io::{self, BufRead, Cursor, Read, Result, Write};
+use std::collections::hash_map::{Entry, HashMap};
+use std::hash::Hash;
+use std::io::{self, BufRead, BufReader, Cursor, Read, Result, Seek, SeekFrom, Write};
+use std::sync::mpsc::Receiver;
+use xz2::bufread::XzDecoder;
+use xz2::stream::{Check, Filters, LzmaOptions, Stream};
 use xz2::write::XzEncoder;
 
-use self::translate::ben_to_ben32_lines;
+use self::translate::{ben32_to_lp32_line, ben_to_ben32_lines};
 use super::{log, logln, BenVariant};
 
 /// A struct to make the writing of BEN files easier
@@ -45,89 +55,371 @@ use super::{log, logln, BenVariant};
 /// ben_encoder.write_assignment(vec![1, 1, 1, 2, 2, 2]);
 /// ```
 pub struct BenEncoder<W: Write> {
-    writer: W,
+    // Only `None` once `finish` has handed the writer back
+    writer: Option<W>,
     previous_sample: Vec<u8>,
+    previous_assignment: Vec<u16>,
     count: u16,
     variant: BenVariant,
+    frame_crc: bool,
+    simplify_min_run: u16,
+    rle_buffer: Vec<(u16, u16)>,
+    n_samples: usize,
 }
 
 impl<W: Write> BenEncoder<W> {
     /// Create a new BenEncoder instance and handles
     /// the BEN file header.
-    pub fn new(mut writer: W, variant: BenVariant) -> Self {
-        match variant {
-            BenVariant::Standard => {
-                writer.write_all(b"STANDARD BEN FILE").unwrap();
-            }
-            BenVariant::MkvChain => {
-                writer.write_all(b"MKVCHAIN BEN FILE").unwrap();
-            }
-        }
+    pub fn new(writer: W, variant: BenVariant) -> Self {
+        Self::start(writer, variant, false)
+    }
+
+    /// Same as `new`, but a CRC32 checksum of the payload is stored in the
+    /// header of every frame so that the decoder can pinpoint exactly which
+    /// samples are corrupt. Files written this way use an extended banner
+    /// (e.g. `STANDARD BEN FCRC`) and can only be read by decoders that
+    /// understand frame checksums.
+    pub fn new_with_frame_crc(writer: W, variant: BenVariant) -> Self {
+        Self::start(writer, variant, true)
+    }
+
+    fn start(mut writer: W, variant: BenVariant, frame_crc: bool) -> Self {
+        let banner: &[u8; 17] = match (variant, frame_crc) {
+            (BenVariant::Standard, false) => b"STANDARD BEN FILE",
+            (BenVariant::MkvChain, false) => b"MKVCHAIN BEN FILE",
+            (BenVariant::Delta, false) => b"MKVDELTA BEN FILE",
+            (BenVariant::Standard, true) => b"STANDARD BEN FCRC",
+            (BenVariant::MkvChain, true) => b"MKVCHAIN BEN FCRC",
+            (BenVariant::Delta, true) => b"MKVDELTA BEN FCRC",
+        };
+        writer.write_all(banner).unwrap();
+
         BenEncoder {
-            writer,
+            writer: Some(writer),
             previous_sample: Vec::new(),
+            previous_assignment: Vec::new(),
             count: 0,
             variant,
+            frame_crc,
+            simplify_min_run: 0,
+            rle_buffer: Vec::new(),
+            n_samples: 0,
+        }
+    }
+
+    /// **Lossy.** Absorb every run shorter than `min_run` into the run that
+    /// precedes it before the assignment is packed. This changes the labels
+    /// of the nodes in the absorbed runs, so the file will NOT decode back to
+    /// the assignments that were written. It is only meant for things like
+    /// visualization layers where small slivers can be ignored in exchange
+    /// for a much smaller file. See `utils::simplify_rle` for the details.
+    ///
+    /// Off by default. A value of 0 or 1 disables the simplification.
+    pub fn with_simplify_min_run(mut self, min_run: u16) -> Self {
+        self.simplify_min_run = min_run;
+        self
+    }
+
+    /// Writes a single frame, inserting the checksum of the payload after
+    /// the 6 byte frame header if frame checksums are enabled.
+    fn write_frame(writer: &mut W, frame: &[u8], frame_crc: bool) -> Result<()> {
+        if frame_crc {
+            let (header, payload) = frame.split_at(6);
+            writer.write_all(header)?;
+            writer.write_all(&crc32fast::hash(payload).to_be_bytes())?;
+            writer.write_all(payload)
+        } else {
+            writer.write_all(frame)
         }
     }
 
     /// Write a run-length encoded assignment vector to the
     /// BEN file.
     pub fn write_rle(&mut self, rle_vec: Vec<(u16, u16)>) -> Result<()> {
+        self.write_rle_slice(&rle_vec)
+    }
+
+    /// Same as `write_rle`, but borrows the run-length encoded assignment
+    /// vector so that callers can reuse a single buffer across samples.
+    pub fn write_rle_slice(&mut self, rle: &[(u16, u16)]) -> Result<()> {
+        self.n_samples += 1;
+
+        let simplified;
+        let rle_vec = if self.simplify_min_run > 1 {
+            simplified = simplify_rle(rle.to_vec(), self.simplify_min_run);
+            &simplified[..]
+        } else {
+            rle
+        };
+
         match self.variant {
             BenVariant::Standard => {
-                let encoded = encode_ben_vec_from_rle(rle_vec);
-                self.writer.write_all(&encoded)?;
+                let encoded = encode_ben_vec_from_rle_slice(rle_vec);
+                Self::write_frame(self.writer.as_mut().unwrap(), &encoded, self.frame_crc)?;
                 Ok(())
             }
             BenVariant::MkvChain => {
-                let encoded = encode_ben_vec_from_rle(rle_vec);
+                let encoded = encode_ben_vec_from_rle_slice(rle_vec);
                 if encoded == self.previous_sample {
                     self.count += 1;
                 } else {
                     if self.count > 0 {
-                        self.writer.write_all(&self.previous_sample)?;
-                        self.writer.write_all(&self.count.to_be_bytes())?;
+                        Self::write_frame(
+                            self.writer.as_mut().unwrap(),
+                            &self.previous_sample,
+                            self.frame_crc,
+                        )?;
+                        self.writer
+                            .as_mut()
+                            .unwrap()
+                            .write_all(&self.count.to_be_bytes())?;
                     }
                     self.previous_sample = encoded;
                     self.count = 1;
                 }
                 Ok(())
             }
+            BenVariant::Delta => {
+                let assign_vec: Vec<u16> = rle_vec
+                    .iter()
+                    .flat_map(|&(val, len)| std::iter::repeat_n(val, len as usize))
+                    .collect();
+
+                if self.count == 0 {
+                    let encoded = encode_ben_vec_from_assign(assign_vec.clone());
+                    Self::write_frame(self.writer.as_mut().unwrap(), &encoded, self.frame_crc)?;
+                    self.previous_assignment = assign_vec;
+                    self.count = 1;
+                    return Ok(());
+                }
+
+                if assign_vec.len() != self.previous_assignment.len() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "Delta encoding requires all assignments to have the same length. \
+                            Expected {} nodes but found {}",
+                            self.previous_assignment.len(),
+                            assign_vec.len()
+                        ),
+                    ));
+                }
+
+                let changes: Vec<(u32, u16)> = assign_vec
+                    .iter()
+                    .zip(self.previous_assignment.iter())
+                    .enumerate()
+                    .filter(|(_, (new, old))| new != old)
+                    .map(|(i, (new, _))| (i as u32, *new))
+                    .collect();
+
+                Self::write_frame(
+                    self.writer.as_mut().unwrap(),
+                    &encode_delta_frame(&changes),
+                    self.frame_crc,
+                )?;
+                self.previous_assignment = assign_vec;
+                Ok(())
+            }
+        }
+    }
+
+    /// Writes the pending MkvChain run, if there is one.
+    fn write_trailer(&mut self) -> Result<()> {
+        if self.variant == BenVariant::MkvChain && self.count > 0 {
+            let writer = self.writer.as_mut().unwrap();
+            Self::write_frame(writer, &self.previous_sample, self.frame_crc)?;
+            writer.write_all(&self.count.to_be_bytes())?;
+            self.count = 0;
         }
+        Ok(())
+    }
+
+    /// Finishes the BEN file and hands back the underlying writer, e.g. so
+    /// that a footer can be written after the last frame. The pending
+    /// MkvChain run is written out and the writer is flushed.
+    ///
+    /// Dropping a `BenEncoder` does the same thing, but panics if the writes
+    /// fail. This method returns the error instead.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ben::encode::BenEncoder;
+    /// use ben::BenVariant;
+    ///
+    /// let mut encoder = BenEncoder::new(Vec::new(), BenVariant::MkvChain);
+    /// encoder.write_assignment(vec![1, 1, 2]).unwrap();
+    /// encoder.write_assignment(vec![1, 1, 2]).unwrap();
+    ///
+    /// let buffer = encoder.finish().unwrap();
+    /// assert_eq!(&buffer[buffer.len() - 2..], &[0, 2]);
+    /// ```
+    pub fn finish(mut self) -> Result<W> {
+        let result = self.write_trailer();
+        let mut writer = self.writer.take().unwrap();
+        result?;
+        writer.flush()?;
+        Ok(writer)
     }
 
     /// Write an assignment vector to the BEN file.
     pub fn write_assignment(&mut self, assign_vec: Vec<u16>) -> Result<()> {
-        let rle_vec = assign_to_rle(assign_vec);
-        self.write_rle(rle_vec)?;
+        self.write_assignment_slice(&assign_vec)
+    }
+
+    /// Same as `write_assignment`, but borrows the assignment vector. The
+    /// runs are collected into a buffer owned by the encoder, so writing a
+    /// sample does not allocate a new run-length encoded vector.
+    pub fn write_assignment_slice(&mut self, assignment: &[u16]) -> Result<()> {
+        let mut rle_vec = std::mem::take(&mut self.rle_buffer);
+        rle_vec.clear();
+        for &assign in assignment {
+            match rle_vec.last_mut() {
+                Some((val, len)) if *val == assign => *len += 1,
+                _ => rle_vec.push((assign, 1)),
+            }
+        }
+
+        let result = self.write_rle_slice(&rle_vec);
+        self.rle_buffer = rle_vec;
+        result
+    }
+
+    /// Write a batch of assignment vectors stored as a flat, row-major
+    /// buffer (e.g. the data of a 2D numpy array), treating every consecutive
+    /// chunk of `n_nodes` labels as a single sample. This can be called
+    /// repeatedly on the same encoder as batches become available; for the
+    /// MkvChain variant, a sample that repeats across the boundary between
+    /// two batches still only bumps the repetition count.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidInput` error (before writing anything) if `n_nodes`
+    /// is 0 or if the length of the buffer is not a multiple of `n_nodes`.
+    pub fn write_matrix(&mut self, flat: &[u16], n_nodes: usize) -> Result<()> {
+        check_matrix_shape(flat.len(), n_nodes)?;
+
+        for row in flat.chunks_exact(n_nodes) {
+            log!("Encoding sample: {}\r", self.n_samples + 1);
+            self.write_assignment_slice(row)?;
+        }
         Ok(())
     }
 
     /// Write a JSON value containing an assignment vector to the BEN file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidData` error if the value has no `"assignment"` key
+    /// or if the assignment is invalid. The error names the number of the
+    /// sample among those written by this encoder.
     pub fn write_json_value(&mut self, data: Value) -> Result<()> {
-        let assign_vec = data["assignment"].as_array().unwrap();
-        let rle_vec = assign_to_rle(
-            assign_vec
-                .into_iter()
-                .map(|x| x.as_u64().unwrap() as u16)
-                .collect(),
-        );
-        self.write_rle(rle_vec)?;
-        Ok(())
+        let assign_vec = json_sample_assignment(&data, self.n_samples + 1)?;
+        self.write_assignment_slice(&assign_vec)
+    }
+}
+
+impl<W: Read + Write + Seek> BenEncoder<W> {
+    /// Create a BenEncoder that adds samples to the end of an existing BEN
+    /// file instead of starting a new one. The banner of the existing file is
+    /// checked against `variant`, and frame checksums are used if the
+    /// existing file has them. An empty writer is treated like a new file.
+    ///
+    /// For the MkvChain variant, the appended samples always start a new
+    /// frame, so a sample that is equal to the last sample of the existing
+    /// file is stored in a frame of its own rather than bumping the count
+    /// of the last frame. The file still decodes to the same samples.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the existing file does not start with a BEN
+    /// banner, if the banner does not match `variant`, or if `variant` is
+    /// `BenVariant::Delta` (delta frames depend on the previous sample, which
+    /// the encoder does not know about).
+    pub fn append(mut writer: W, variant: BenVariant) -> Result<Self> {
+        if variant == BenVariant::Delta {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Appending to delta BEN files is not supported",
+            ));
+        }
+
+        let len = writer.seek(SeekFrom::End(0))?;
+        if len == 0 {
+            return Ok(BenEncoder::new(writer, variant));
+        }
+
+        let mut banner = [0u8; 17];
+        writer.seek(SeekFrom::Start(0))?;
+        writer.read_exact(&mut banner)?;
+
+        let (found_variant, frame_crc) = match &banner {
+            b"STANDARD BEN FILE" => (BenVariant::Standard, false),
+            b"MKVCHAIN BEN FILE" => (BenVariant::MkvChain, false),
+            b"MKVDELTA BEN FILE" => (BenVariant::Delta, false),
+            b"STANDARD BEN FCRC" => (BenVariant::Standard, true),
+            b"MKVCHAIN BEN FCRC" => (BenVariant::MkvChain, true),
+            b"MKVDELTA BEN FCRC" => (BenVariant::Delta, true),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Cannot append to a file with banner {:?}",
+                        String::from_utf8_lossy(&banner)
+                    ),
+                ))
+            }
+        };
+
+        if found_variant != variant {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Cannot append {:?} samples to a {:?} BEN file",
+                    variant, found_variant
+                ),
+            ));
+        }
+
+        writer.seek(SeekFrom::End(0))?;
+
+        Ok(BenEncoder {
+            writer: Some(writer),
+            previous_sample: Vec::new(),
+            previous_assignment: Vec::new(),
+            count: 0,
+            variant,
+            frame_crc,
+            simplify_min_run: 0,
+            rle_buffer: Vec::new(),
+            n_samples: 0,
+        })
     }
 }
 
+/// Checks that a flat buffer of `len` labels splits evenly into assignment
+/// vectors of `n_nodes` nodes.
+fn check_matrix_shape(len: usize, n_nodes: usize) -> Result<()> {
+    if n_nodes == 0 || !len.is_multiple_of(n_nodes) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Buffer of length {} cannot be split into assignments of {} nodes",
+                len, n_nodes
+            ),
+        ));
+    }
+    Ok(())
+}
+
 impl<W: Write> Drop for BenEncoder<W> {
     fn drop(&mut self) {
-        if self.variant == BenVariant::MkvChain && self.count > 0 {
-            self.writer
-                .write_all(&self.previous_sample)
-                .expect("Error writing last line to file");
-            self.writer
-                .write_all(&self.count.to_be_bytes())
-                .expect("Error writing last line count to file");
+        if self.writer.is_none() {
+            return;
         }
+        self.write_trailer()
+            .expect("Error writing the end of the file");
     }
 }
 
@@ -138,36 +430,124 @@ pub struct XBenEncoder<W: Write> {
     previous_sample: Vec<u8>,
     count: u16,
     variant: BenVariant,
+    length_prefixed: bool,
+    n_samples: usize,
 }
 
 impl<W: Write> XBenEncoder<W> {
-    pub fn new(mut encoder: XzEncoder<W>, variant: BenVariant) -> Self {
-        match variant {
-            BenVariant::Standard => {
-                encoder.write_all(b"STANDARD BEN FILE").unwrap();
-                XBenEncoder {
-                    encoder,
-                    previous_sample: Vec::new(),
-                    count: 0,
-                    variant: BenVariant::Standard,
-                }
-            }
-            BenVariant::MkvChain => {
-                encoder.write_all(b"MKVCHAIN BEN FILE").unwrap();
-                XBenEncoder {
-                    encoder,
-                    previous_sample: Vec::new(),
-                    count: 0,
-                    variant: BenVariant::MkvChain,
-                }
-            }
+    /// Create a new XBenEncoder instance and handles
+    /// the BEN file header.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidInput` error if the variant is `BenVariant::Delta`
+    /// since delta frames cannot be represented in the ben32 format, or an
+    /// error if the header cannot be written.
+    pub fn new(mut encoder: XzEncoder<W>, variant: BenVariant) -> Result<Self> {
+        let banner: &[u8; 17] = match variant {
+            BenVariant::Standard => b"STANDARD BEN FILE",
+            BenVariant::MkvChain => b"MKVCHAIN BEN FILE",
+            BenVariant::Delta => return Err(xben_delta_error()),
+        };
+        encoder.write_all(banner)?;
+        Ok(XBenEncoder {
+            encoder,
+            previous_sample: Vec::new(),
+            count: 0,
+            variant,
+            length_prefixed: false,
+            n_samples: 0,
+        })
+    }
+
+    /// Create a new XBenEncoder instance that writes the length-prefixed
+    /// flavor of ben32 (see the `translate` module). Every assignment vector
+    /// is preceded by its length in bytes rather than followed by a
+    /// separator, so readers can skip over assignment vectors without
+    /// scanning them. Files written this way use a `LP32` banner and cannot
+    /// be read by older versions of this library.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidInput` error if the variant is `BenVariant::Delta`
+    /// since delta frames cannot be represented in the ben32 format, or an
+    /// error if the header cannot be written.
+    pub fn new_length_prefixed(mut encoder: XzEncoder<W>, variant: BenVariant) -> Result<Self> {
+        let banner: &[u8; 17] = match variant {
+            BenVariant::Standard => b"STANDARD BEN LP32",
+            BenVariant::MkvChain => b"MKVCHAIN BEN LP32",
+            BenVariant::Delta => return Err(xben_delta_error()),
+        };
+        encoder.write_all(banner)?;
+        Ok(XBenEncoder {
+            encoder,
+            previous_sample: Vec::new(),
+            count: 0,
+            variant,
+            length_prefixed: true,
+            n_samples: 0,
+        })
+    }
+
+    /// Create a new XBenEncoder instance that compresses with the given
+    /// LZMA2 options rather than one of the 0-9 presets. This allows the
+    /// dictionary size (and the other LZMA2 parameters) to be tuned
+    /// independently of the preset, e.g. to use a small dictionary for
+    /// small ensembles or a very large one for huge, highly repetitive ones.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ben::encode::XBenEncoder;
+    /// use ben::BenVariant;
+    /// use xz2::stream::LzmaOptions;
+    ///
+    /// let mut options = LzmaOptions::new_preset(6).unwrap();
+    /// options.dict_size(1 << 20);
+    ///
+    /// let mut xben = Vec::new();
+    /// let mut encoder =
+    ///     XBenEncoder::with_lzma_options(&mut xben, &options, BenVariant::MkvChain).unwrap();
+    /// encoder
+    ///     .write_json_value(serde_json::json!({"assignment": [1, 1, 2], "sample": 1}))
+    ///     .unwrap();
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the variant is `BenVariant::Delta` or if the
+    /// LZMA2 encoder cannot be built from the options.
+    pub fn with_lzma_options(
+        writer: W,
+        options: &LzmaOptions,
+        variant: BenVariant,
+    ) -> Result<Self> {
+        if variant == BenVariant::Delta {
+            return Err(xben_delta_error());
         }
+
+        let mut filters = Filters::new();
+        filters.lzma2(options);
+        let stream = Stream::new_stream_encoder(&filters, Check::Crc64)?;
+        Self::new(XzEncoder::new_stream(writer, stream), variant)
     }
 
     /// Write a an assigment vector encoded as a JSON value
     /// to the XBEN file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an `InvalidData` error if the value has no `"assignment"` key
+    /// or if the assignment is invalid. The error names the number of the
+    /// sample among those written by this encoder.
     pub fn write_json_value(&mut self, data: Value) -> Result<()> {
-        let encoded = encode_ben32_line(data);
+        let encoded = encode_ben32_line(data, self.n_samples + 1)?;
+        self.n_samples += 1;
+        let encoded = if self.length_prefixed {
+            ben32_to_lp32_line(&encoded)
+        } else {
+            encoded
+        };
         match self.variant {
             BenVariant::Standard => {
                 self.encoder.write_all(&encoded)?;
@@ -184,6 +564,7 @@ impl<W: Write> XBenEncoder<W> {
                     self.count = 1;
                 }
             }
+            BenVariant::Delta => unreachable!("XBenEncoder cannot be built for delta files"),
         }
         Ok(())
     }
@@ -192,6 +573,13 @@ impl<W: Write> XBenEncoder<W> {
     /// This function will check to see if the header is there and then
     /// handle it accordingly.
     pub fn write_ben_file(&mut self, mut reader: impl BufRead) -> Result<()> {
+        if self.length_prefixed {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Converting BEN files to length-prefixed XBEN files is not supported",
+            ));
+        }
+
         let mut buff = [0u8; 17];
         reader.read_exact(&mut buff)?;
 
@@ -209,6 +597,14 @@ impl<W: Write> XBenEncoder<W> {
     }
 }
 
+/// The error returned when an XBEN encoder is asked to write delta frames.
+fn xben_delta_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "The XBEN format does not support the delta variant of BEN files",
+    )
+}
+
 impl<W: Write> Drop for XBenEncoder<W> {
     fn drop(&mut self) {
         if self.variant == BenVariant::MkvChain && self.count > 0 {
@@ -231,20 +627,55 @@ impl<W: Write> Drop for XBenEncoder<W> {
 /// # Arguments
 ///
 /// * `data` - A JSON object containing an assignment vector and a sample number
+/// * `sample` - The number of the sample, used in errors
+///
+/// # Returns
+///
+/// A vector of bytes containing the ben32 encoded assignment vector
+///
+/// # Errors
+///
+/// Returns an error naming `sample` if the line has no `"assignment"` key or
+/// if one of the labels in the assignment vector is not a non-negative
+/// integer.
+fn encode_ben32_line(data: Value, sample: usize) -> Result<Vec<u8>> {
+    let assign_vec = json_sample_assignment(&data, sample)?;
+
+    Ok(assignment_to_ben32(&assign_vec))
+}
+
+/// This function takes a single assignment vector and encodes it into
+/// the ben32 format. Every run in the assignment is stored as a 32 bit
+/// integer (the high 16 bits hold the value and the low 16 bits hold the
+/// length of the run) and the line is terminated by 4 bytes of 0.
+///
+/// This is the ben32 counterpart to `encode_ben_vec_from_assign`. No
+/// repetition count is added for the MkvChain variant.
+///
+/// # Arguments
+///
+/// * `assign` - The assignment vector to encode
 ///
 /// # Returns
 ///
 /// A vector of bytes containing the ben32 encoded assignment vector
-fn encode_ben32_line(data: Value) -> Vec<u8> {
-    let assign_vec = data["assignment"].as_array().unwrap();
+///
+/// # Example
+///
+/// ```
+/// use ben::encode::assignment_to_ben32;
+///
+/// let ben32 = assignment_to_ben32(&[1, 1, 2]);
+/// assert_eq!(ben32, vec![0, 1, 0, 2, 0, 2, 0, 1, 0, 0, 0, 0]);
+/// ```
+pub fn assignment_to_ben32(assign: &[u16]) -> Vec<u8> {
     let mut prev_assign: u16 = 0;
     let mut count: u16 = 0;
     let mut first = true;
 
     let mut ret = Vec::new();
 
-    for assignment in assign_vec {
-        let assign = assignment.as_u64().unwrap() as u16;
+    for &assign in assign {
         if first {
             prev_assign = assign;
             count = 1;
@@ -272,6 +703,14 @@ fn encode_ben32_line(data: Value) -> Vec<u8> {
     ret
 }
 
+/// The lines of a JSONL file with the blank ones (e.g. trailing newlines)
+/// filtered out, so that every line that is left is a sample.
+pub(crate) fn non_blank_lines<R: BufRead>(reader: R) -> impl Iterator<Item = Result<String>> {
+    reader
+        .lines()
+        .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+}
+
 /// This function takes a JSONL file and compresses it to the
 /// XBEN format.
 ///
@@ -292,16 +731,110 @@ pub fn jsonl_encode_xben<R: BufRead, W: Write>(
     writer: W,
     variant: BenVariant,
 ) -> Result<()> {
-    let encoder = XzEncoder::new(writer, 9);
-    let mut ben_encoder = XBenEncoder::new(encoder, variant);
+    jsonl_encode_xben_with_level(reader, writer, variant, 9)
+}
+
+/// Same as `jsonl_encode_xben`, but at most `take` samples are read from the
+/// reader before the file is finalized. Blank lines are skipped and do not
+/// count towards `take`.
+///
+/// # Arguments
+///
+/// * `reader` - A buffered reader for the input file
+/// * `writer` - A writer for the output file
+/// * `variant` - The BEN variant to encode the file with
+/// * `take` - The maximum number of samples to read from the reader
+pub fn jsonl_encode_xben_with_take<R: BufRead, W: Write>(
+    reader: R,
+    writer: W,
+    variant: BenVariant,
+    take: usize,
+) -> Result<()> {
+    encode_jsonl_xben(reader, writer, variant, 9, take)
+}
+
+/// Checks that an XZ compression level is one of the 0-9 presets.
+fn check_xz_level(level: u32) -> Result<()> {
+    if level > 9 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "XZ compression level must be between 0 and 9, got {}",
+                level
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Same as `jsonl_encode_xben`, but the XZ compression level can be chosen.
+/// Lower levels are much faster and are useful for quick experiments where
+/// the smallest possible file is not needed. `jsonl_encode_xben` uses
+/// level 9.
+///
+/// # Arguments
+///
+/// * `reader` - A buffered reader for the input file
+/// * `writer` - A writer for the output file
+/// * `variant` - The BEN variant to encode the file with
+/// * `level` - The XZ preset to compress with, from 0 to 9
+///
+/// # Errors
+///
+/// Returns an `InvalidInput` error if the level is larger than 9.
+pub fn jsonl_encode_xben_with_level<R: BufRead, W: Write>(
+    reader: R,
+    writer: W,
+    variant: BenVariant,
+    level: u32,
+) -> Result<()> {
+    encode_jsonl_xben(reader, writer, variant, level, usize::MAX)
+}
+
+/// Shared body of the `jsonl_encode_xben*` functions. Reads at most `take`
+/// samples, skipping blank lines.
+fn encode_jsonl_xben<R: BufRead, W: Write>(
+    reader: R,
+    writer: W,
+    variant: BenVariant,
+    level: u32,
+    take: usize,
+) -> Result<()> {
+    check_xz_level(level)?;
+    let reader = decompress_if_xz(reader)?;
+    let encoder = XzEncoder::new(writer, level);
+    let mut ben_encoder = XBenEncoder::new(encoder, variant)?;
+
+    for (i, line_result) in non_blank_lines(reader).take(take).enumerate() {
+        log!("Encoding line: {}\r", i + 1);
+        let line = line_result?;
+        let data: Value = serde_json::from_str(&line)?;
 
-    let mut line_num = 1;
+        ben_encoder.write_json_value(data)?;
+    }
+
+    logln!();
+    logln!("Done!");
+
+    Ok(())
+}
 
-    for line_result in reader.lines() {
-        log!("Encoding line: {}\r", line_num);
-        line_num += 1;
+/// Same as `jsonl_encode_xben`, but the ben32 data inside of the XBEN file
+/// uses the length-prefixed framing (see `XBenEncoder::new_length_prefixed`).
+/// The default separator framing is still what `jsonl_encode_xben` writes.
+pub fn jsonl_encode_xben_length_prefixed<R: BufRead, W: Write>(
+    reader: R,
+    writer: W,
+    variant: BenVariant,
+) -> Result<()> {
+    let reader = decompress_if_xz(reader)?;
+    let encoder = XzEncoder::new(writer, 9);
+    let mut ben_encoder = XBenEncoder::new_length_prefixed(encoder, variant)?;
+
+    for (i, line_result) in non_blank_lines(reader).enumerate() {
+        log!("Encoding line: {}\r", i + 1);
         let line = line_result?;
-        let data: Value = serde_json::from_str(&line).expect("Error parsing JSON from line");
+        let data: Value = serde_json::from_str(&line)?;
 
         ben_encoder.write_json_value(data)?;
     }
@@ -368,6 +901,50 @@ pub fn encode_ben_vec_from_assign(assign_vec: Vec<u16>) -> Vec<u8> {
     encode_ben_vec_from_rle(rle_vec)
 }
 
+/// This function computes the number of bytes that `assign` would occupy
+/// as a BEN frame (the 6 byte header plus the bit-packed payload) without
+/// actually encoding it. The length is exactly that of the vector returned
+/// by `encode_ben_vec_from_assign`.
+///
+/// The trailing repetition count written after each frame of an MkvChain
+/// file is not included; see `ben_frame_trailer_len`.
+///
+/// # Arguments
+///
+/// * `assign` - The assignment vector
+///
+/// # Returns
+///
+/// The length of the encoded frame in bytes
+pub fn ben_frame_len(assign: &[u16]) -> usize {
+    let mut n_runs: usize = 0;
+    let mut max_val: u16 = 0;
+    let mut max_len: usize = 0;
+
+    let mut i = 0;
+    while i < assign.len() {
+        let run_len = assign[i..].iter().take_while(|&&x| x == assign[i]).count();
+        n_runs += 1;
+        max_val = max_val.max(assign[i]);
+        max_len = max_len.max(run_len);
+        i += run_len;
+    }
+
+    let max_val_bits = (16 - max_val.leading_zeros() as usize).max(1);
+    let max_len_bits = usize::BITS as usize - max_len.leading_zeros() as usize;
+    6 + ((max_val_bits + max_len_bits) * n_runs).div_ceil(8)
+}
+
+/// The number of bytes written after each frame for the given variant, i.e.
+/// 2 for the repetition count of an MkvChain frame and 0 otherwise. Together
+/// with `ben_frame_len` this gives the size of each sample in a BEN file.
+pub fn ben_frame_trailer_len(variant: BenVariant) -> usize {
+    match variant {
+        BenVariant::MkvChain => 2,
+        _ => 0,
+    }
+}
+
 /// This function takes a run-length encoded assignment vector and
 /// encodes into a bit-packed ben version
 ///
@@ -379,6 +956,12 @@ pub fn encode_ben_vec_from_assign(assign_vec: Vec<u16>) -> Vec<u8> {
 ///
 /// A vector of bytes containing the bit-packed ben encoded assignment vector
 fn encode_ben_vec_from_rle(rle_vec: Vec<(u16, u16)>) -> Vec<u8> {
+    encode_ben_vec_from_rle_slice(&rle_vec)
+}
+
+/// Same as `encode_ben_vec_from_rle`, but borrows the run-length encoded
+/// assignment vector.
+fn encode_ben_vec_from_rle_slice(rle_vec: &[(u16, u16)]) -> Vec<u8> {
     let mut output_vec: Vec<u8> = Vec::new();
 
     let max_val: u16 = rle_vec.iter().max_by_key(|x| x.0).unwrap().0;
@@ -399,7 +982,7 @@ fn encode_ben_vec_from_rle(rle_vec: Vec<(u16, u16)>) -> Vec<u8> {
     let mut remainder: u32 = 0;
     let mut remainder_bits: u8 = 0;
 
-    for (val, len) in rle_vec {
+    for &(val, len) in rle_vec {
         let mut new_val: u32 = (remainder << max_val_bits) | (val as u32);
 
         let mut buff: u8;
@@ -435,6 +1018,53 @@ fn encode_ben_vec_from_rle(rle_vec: Vec<(u16, u16)>) -> Vec<u8> {
     output_vec
 }
 
+/// This function takes a list of `(node_index, new_label)` changes and
+/// bit-packs them into a delta frame.
+///
+/// A delta frame consists of a byte giving the number of bits used for each
+/// node index, a byte giving the number of bits used for each label, a
+/// big-endian u32 containing the number of changes, and then the bit-packed
+/// `(node_index, new_label)` pairs padded out to a whole number of bytes.
+///
+/// # Arguments
+///
+/// * `changes` - A slice of tuples containing the index of each node that changed
+///   along with its new label
+///
+/// # Returns
+///
+/// A vector of bytes containing the delta frame
+fn encode_delta_frame(changes: &[(u32, u16)]) -> Vec<u8> {
+    let max_idx = changes.iter().map(|x| x.0).max().unwrap_or(0);
+    let max_val = changes.iter().map(|x| x.1).max().unwrap_or(0);
+    let idx_bits: u8 = (32 - max_idx.leading_zeros() as u8).max(1);
+    let val_bits: u8 = (16 - max_val.leading_zeros() as u8).max(1);
+
+    let mut output_vec: Vec<u8> = vec![idx_bits, val_bits];
+    output_vec.extend((changes.len() as u32).to_be_bytes());
+
+    let mut buffer: u64 = 0;
+    let mut n_bits_in_buff: u8 = 0;
+
+    for &(idx, val) in changes {
+        for (field, n_bits) in [(idx as u64, idx_bits), (val as u64, val_bits)] {
+            buffer = (buffer << n_bits) | field;
+            n_bits_in_buff += n_bits;
+            while n_bits_in_buff >= 8 {
+                n_bits_in_buff -= 8;
+                output_vec.push((buffer >> n_bits_in_buff) as u8);
+            }
+            buffer &= (1u64 << n_bits_in_buff) - 1;
+        }
+    }
+
+    if n_bits_in_buff > 0 {
+        output_vec.push((buffer << (8 - n_bits_in_buff)) as u8);
+    }
+
+    output_vec
+}
+
 /// This function takes a JSONL file and compresses it into
 /// the BEN format.
 ///
@@ -450,6 +1080,7 @@ fn encode_ben_vec_from_rle(rle_vec: Vec<(u16, u16)>) -> Vec<u8> {
 ///
 /// * `reader` - A buffered reader for the input file
 /// * `writer` - A writer for the output file
+/// * `variant` - The variant of the BEN format to write
 ///
 /// # Returns
 ///
@@ -485,51 +1116,1368 @@ pub fn jsonl_encode_ben<R: BufRead, W: Write>(
     writer: W,
     variant: BenVariant,
 ) -> Result<()> {
-    let mut line_num = 1;
+    jsonl_encode_ben_with_take(reader, writer, variant, usize::MAX)
+}
+
+/// Same as `jsonl_encode_ben`, but at most `take` samples are read from the
+/// reader. This is useful for infinite sources like a sampler piping into
+/// stdin. Blank lines are skipped and do not count towards `take`. Once the
+/// limit is reached, any pending MkvChain run is flushed and the file is
+/// finalized.
+///
+/// # Arguments
+///
+/// * `reader` - A buffered reader for the input file
+/// * `writer` - A writer for the output file
+/// * `variant` - The variant of the BEN format to write
+/// * `take` - The maximum number of samples to read from the reader
+///
+/// # Returns
+///
+/// A Result type that contains the result of the operation
+pub fn jsonl_encode_ben_with_take<R: BufRead, W: Write>(
+    reader: R,
+    writer: W,
+    variant: BenVariant,
+    take: usize,
+) -> Result<()> {
+    let reader = decompress_if_xz(reader)?;
     let mut ben_encoder = BenEncoder::new(writer, variant);
-    for line_result in reader.lines() {
-        log!("Encoding line: {}\r", line_num);
-        line_num += 1;
+    for (i, line_result) in non_blank_lines(reader).take(take).enumerate() {
+        log!("Encoding line: {}\r", i + 1);
         let line = line_result?; // Handle potential I/O errors for each line
-        let data: Value = serde_json::from_str(&line).expect("Error parsing JSON from line");
+        let data: Value = serde_json::from_str(&line)?;
 
         ben_encoder.write_json_value(data)?;
     }
+    ben_encoder.finish()?;
     logln!();
     logln!("Done!"); // Print newline after progress bar
     Ok(())
 }
 
-/// This function takes a BEN file and encodes it into an XBEN
-/// file using bit-to-byte decompression followed by LZMA2 compression.
+/// An iterator over the assignments of a JSONL file, for reading an ensemble,
+/// changing the assignments, and re-encoding them without repeating the
+/// parsing logic of `jsonl_encode_ben`.
 ///
-/// # Arguments
+/// Each item is the assignment vector along with its sample number. The
+/// sample number is taken from the `"sample"` field of the line when there
+/// is one, and is otherwise the position of the line in the file (starting
+/// from 1). Blank lines (e.g. trailing newlines) are skipped.
 ///
-/// * `reader` - A buffered reader for the input file
-/// * `writer` - A writer for the output file
+/// # Example
 ///
-/// # Returns
+/// ```
+/// use ben::encode::JsonlAssignments;
 ///
-/// A Result type that contains the result of the operation
-pub fn ben_encode_xben<R: BufRead, W: Write>(mut reader: R, writer: W) -> Result<()> {
-    let mut check_buffer = [0u8; 17];
-    reader.read_exact(&mut check_buffer)?;
-
-    let encoder = XzEncoder::new(writer, 9);
+/// let input = r#"{"assignment": [1,1,2], "sample": 1}"#.to_string()
+///     + "\n"
+///     + r#"{"assignment": [2,1,1], "sample": 2}"#
+///     + "\n\n";
+///
+/// let samples = JsonlAssignments::new(input.as_bytes())
+///     .collect::<Result<Vec<_>, _>>()
+///     .unwrap();
+///
+/// assert_eq!(samples, vec![(vec![1, 1, 2], 1), (vec![2, 1, 1], 2)]);
+/// ```
+pub struct JsonlAssignments<R: BufRead> {
+    lines: io::Lines<R>,
+    n_samples: usize,
+}
 
-    let mut ben_encoder = match &check_buffer {
-        b"STANDARD BEN FILE" => XBenEncoder::new(encoder, BenVariant::Standard),
-        b"MKVCHAIN BEN FILE" => XBenEncoder::new(encoder, BenVariant::MkvChain),
-        _ => {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Invalid file format",
-            ));
+impl<R: BufRead> JsonlAssignments<R> {
+    /// Create a new JsonlAssignments iterator over the lines of `reader`.
+    pub fn new(reader: R) -> Self {
+        JsonlAssignments {
+            lines: reader.lines(),
+            n_samples: 0,
         }
-    };
+    }
+}
 
-    ben_encoder.write_ben_file(reader)?;
+impl<R: BufRead> Iterator for JsonlAssignments<R> {
+    type Item = Result<(Vec<u16>, usize)>;
+
+    fn next(&mut self) -> Option<Result<(Vec<u16>, usize)>> {
+        let line = loop {
+            match self.lines.next()? {
+                Ok(line) if line.trim().is_empty() => continue,
+                Ok(line) => break line,
+                Err(e) => return Some(Err(e)),
+            }
+        };
+        self.n_samples += 1;
+
+        let data: Value = match serde_json::from_str(&line) {
+            Ok(data) => data,
+            Err(e) => {
+                return Some(Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Sample {}: {}", self.n_samples, e),
+                )))
+            }
+        };
+        let sample = data["sample"]
+            .as_u64()
+            .map_or(self.n_samples, |sample| sample as usize);
 
+        Some(json_sample_assignment(&data, sample).map(|assignment| (assignment, sample)))
+    }
+}
+
+/// The number of samples that `jsonl_encode_auto` looks at before picking
+/// a variant.
+const AUTO_VARIANT_WINDOW: usize = 1000;
+
+/// Encodes a JSONL file into a BEN file, picking whichever of the Standard
+/// and MkvChain variants gives the smaller file.
+///
+/// The variant has to be known before the banner is written, so the first
+/// 1000 samples are buffered in memory and the size of the file is computed
+/// for both variants over that window (MkvChain saves a whole frame for
+/// every repeated sample, but costs 2 bytes for every frame). The banner of
+/// the winning variant is then written, followed by the buffered samples
+/// and the rest of the input. Because of this buffering window the writer
+/// does not need to be seekable. Inputs with fewer samples than the window
+/// are decided on all of their samples.
+///
+/// # Arguments
+///
+/// * `reader` - A buffered reader for the input JSONL file
+/// * `writer` - A writer for the output BEN file
+///
+/// # Returns
+///
+/// The variant that was written
+///
+/// # Errors
+///
+/// This function will return an error if a line of the input is not valid
+/// JSON, if an assignment is invalid, or if writing to the writer fails.
+pub fn jsonl_encode_auto<R: BufRead, W: Write>(reader: R, writer: W) -> Result<BenVariant> {
+    let reader = decompress_if_xz(reader)?;
+    let mut assignments = non_blank_lines(reader).enumerate().map(|(i, line)| {
+        let data: Value = serde_json::from_str(&line?)?;
+        json_sample_assignment(&data, i + 1)
+    });
+
+    let window = assignments
+        .by_ref()
+        .take(AUTO_VARIANT_WINDOW)
+        .collect::<Result<Vec<Vec<u16>>>>()?;
+
+    let mut standard_len = 0;
+    let mut mkvchain_len = 0;
+    for (i, assignment) in window.iter().enumerate() {
+        let frame_len = ben_frame_len(assignment);
+        standard_len += frame_len;
+        if i == 0 || window[i - 1] != *assignment {
+            mkvchain_len += frame_len + ben_frame_trailer_len(BenVariant::MkvChain);
+        }
+    }
+
+    let variant = if mkvchain_len < standard_len {
+        BenVariant::MkvChain
+    } else {
+        BenVariant::Standard
+    };
+    logln!(
+        "Picked the {:?} variant from the first {} samples",
+        variant,
+        window.len()
+    );
+
+    let mut ben_encoder = BenEncoder::new(writer, variant);
+    for (i, assignment) in window.into_iter().map(Ok).chain(assignments).enumerate() {
+        log!("Encoding line: {}\r", i + 1);
+        ben_encoder.write_assignment(assignment?)?;
+    }
+    ben_encoder.finish()?;
+
+    logln!();
+    logln!("Done!");
+    Ok(variant)
+}
+
+/// The magic bytes at the start of every xz stream.
+const XZ_MAGIC: [u8; 6] = [0xFD, b'7', b'z', b'X', b'Z', 0x00];
+
+/// Peeks at the start of a reader and, if it holds an xz-compressed stream
+/// (e.g. a `.jsonl.xz` file), wraps it in a decoder so that the caller reads
+/// the decompressed bytes. Any other input is handed back as is.
+///
+/// This is used by the JSONL encoding functions so that compressed JSONL
+/// files can be encoded without first decompressing them to disk.
+///
+/// # Arguments
+///
+/// * `reader` - A buffered reader for the input file
+///
+/// # Returns
+///
+/// A buffered reader over the (decompressed) contents of the input
+pub fn decompress_if_xz<'a, R: BufRead + 'a>(mut reader: R) -> Result<Box<dyn BufRead + 'a>> {
+    // A single `fill_buf` can come back with fewer bytes than the magic
+    // (e.g. from a pipe), so read until there are enough of them or the
+    // input runs out, and then put them back in front of the reader.
+    let mut head = Vec::with_capacity(XZ_MAGIC.len());
+    (&mut reader)
+        .take(XZ_MAGIC.len() as u64)
+        .read_to_end(&mut head)?;
+    let is_xz = head == XZ_MAGIC;
+    let reader = Cursor::new(head).chain(reader);
+    if is_xz {
+        Ok(Box::new(BufReader::new(XzDecoder::new_multi_decoder(
+            reader,
+        ))))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Returns the lines of a JSONL file whose sample numbers (the 1-based line
+/// numbers) are in the selection. No more lines are read once the last
+/// selected sample has been reached.
+fn selected_lines<'a, R: BufRead + 'a>(
+    reader: R,
+    selection: Selection,
+) -> Result<impl Iterator<Item = Result<String>> + 'a> {
+    if selection == Selection::Every(0) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Cannot select every 0th sample",
+        ));
+    }
+
+    let reader = decompress_if_xz(reader)?;
+    let selection = selection.normalized();
+    let last = selection.last();
+    Ok(reader
+        .lines()
+        .enumerate()
+        .take_while(move |(i, _)| last.is_none_or(|last| *i < last))
+        .filter(move |(i, _)| selection.contains(i + 1))
+        .map(|(_, line)| line))
+}
+
+/// Same as `jsonl_encode_ben`, but only the samples in the selection are
+/// encoded. The sample numbers that the selection refers to are the line
+/// numbers of the JSONL file (starting from 1), so a large JSONL file can
+/// be thinned out while it is being encoded.
+///
+/// # Arguments
+///
+/// * `reader` - A buffered reader for the input file
+/// * `writer` - A writer for the output file
+/// * `variant` - The BEN variant to encode the file with
+/// * `selection` - The samples to keep
+///
+/// # Returns
+///
+/// A Result type that contains the result of the operation
+pub fn jsonl_encode_ben_subsample<R: BufRead, W: Write>(
+    reader: R,
+    writer: W,
+    variant: BenVariant,
+    selection: Selection,
+) -> Result<()> {
+    let mut ben_encoder = BenEncoder::new(writer, variant);
+    for (i, line_result) in selected_lines(reader, selection)?.enumerate() {
+        log!("Encoding line: {}\r", i + 1);
+        let line = line_result?;
+        let data: Value = serde_json::from_str(&line)?;
+
+        ben_encoder.write_json_value(data)?;
+    }
+    ben_encoder.finish()?;
+    logln!();
+    logln!("Done!");
+    Ok(())
+}
+
+/// Same as `jsonl_encode_xben`, but only the samples in the selection are
+/// encoded. See `jsonl_encode_ben_subsample`.
+pub fn jsonl_encode_xben_subsample<R: BufRead, W: Write>(
+    reader: R,
+    writer: W,
+    variant: BenVariant,
+    selection: Selection,
+) -> Result<()> {
+    let lines = selected_lines(reader, selection)?;
+
+    let encoder = XzEncoder::new(writer, 9);
+    let mut ben_encoder = XBenEncoder::new(encoder, variant)?;
+    for (i, line_result) in lines.enumerate() {
+        log!("Encoding line: {}\r", i + 1);
+        let line = line_result?;
+        let data: Value = serde_json::from_str(&line)?;
+
+        ben_encoder.write_json_value(data)?;
+    }
+    logln!();
+    logln!("Done!");
+    Ok(())
+}
+
+/// Same as `jsonl_encode_ben`, but the BEN stream is wrapped in gzip
+/// compression (a `.ben.gz` file) in a single pass. Unlike XBEN, the
+/// assignments are not transformed into ben32 first, so this is simply a
+/// gzipped BEN file for use with gzip-centric tooling.
+///
+/// This function is only available with the `gzip` feature.
+///
+/// # Arguments
+///
+/// * `reader` - A buffered reader for the input file
+/// * `writer` - A writer for the output file
+/// * `variant` - The BEN variant to encode the file with
+///
+/// # Returns
+///
+/// A Result type that contains the result of the operation
+#[cfg(feature = "gzip")]
+pub fn jsonl_encode_ben_gz<R: BufRead, W: Write>(
+    reader: R,
+    writer: W,
+    variant: BenVariant,
+) -> Result<()> {
+    let mut encoder = flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+    jsonl_encode_ben(reader, &mut encoder, variant)?;
+    encoder.finish()?;
+    Ok(())
+}
+
+/// Resumes an interrupted call to `jsonl_encode_ben` by skipping the first
+/// `resume_from` samples of the input and appending the rest of them to the
+/// existing BEN file in `writer` (see `BenEncoder::append`). Blank lines are
+/// not samples, so they are skipped without being counted.
+///
+/// It is up to the caller to make sure that the existing file holds exactly
+/// the first `resume_from` samples of the input, e.g. by recording how many
+/// samples have been flushed to disk as the original encode runs.
+///
+/// # Arguments
+///
+/// * `reader` - A buffered reader for the input file
+/// * `writer` - The existing BEN file, opened for both reading and writing
+/// * `variant` - The BEN variant of the existing file
+/// * `resume_from` - The number of input samples that are already in the file
+///
+/// # Returns
+///
+/// A Result type that contains the result of the operation
+pub fn jsonl_encode_ben_resume<R: BufRead, W: Read + Write + Seek>(
+    reader: R,
+    writer: W,
+    variant: BenVariant,
+    resume_from: usize,
+) -> Result<()> {
+    let mut ben_encoder = BenEncoder::append(writer, variant)?;
+    for (i, line_result) in non_blank_lines(reader).enumerate().skip(resume_from) {
+        log!("Encoding line: {}\r", i + 1);
+        let line = line_result?;
+        let data: Value = serde_json::from_str(&line)?;
+
+        ben_encoder.write_json_value(data)?;
+    }
+    ben_encoder.finish()?;
+    logln!();
+    logln!("Done!");
+    Ok(())
+}
+
+/// Same as `jsonl_encode_ben`, but reports the progress of the encoding as
+/// the fraction of the input that has been consumed so far. This is useful
+/// for driving a progress bar when the size of the input is known ahead of
+/// time (e.g. from the file metadata).
+///
+/// # Arguments
+///
+/// * `reader` - A buffered reader for the input file
+/// * `writer` - A writer for the output file
+/// * `variant` - The BEN variant to encode the file with
+/// * `total_bytes` - The total number of bytes in the input
+/// * `progress` - A callback that is called after every sample with a
+///   fraction in `0.0..=1.0`. It is always called with `1.0` once the
+///   encoding is done.
+///
+/// # Returns
+///
+/// A Result type that contains the result of the operation
+pub fn jsonl_encode_ben_with_progress<R: BufRead, W: Write, F: FnMut(f64)>(
+    reader: R,
+    writer: W,
+    variant: BenVariant,
+    total_bytes: u64,
+    mut progress: F,
+) -> Result<()> {
+    let mut reader = CountingReader::new(reader);
+    let mut ben_encoder = BenEncoder::new(writer, variant);
+    let mut line = String::new();
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let data: Value = serde_json::from_str(&line)?;
+        ben_encoder.write_json_value(data)?;
+
+        if total_bytes > 0 {
+            progress((reader.position() as f64 / total_bytes as f64).min(1.0));
+        }
+    }
+
+    ben_encoder.finish()?;
+    progress(1.0);
+    Ok(())
+}
+
+/// Encodes a set of samples relative to a single template assignment. The
+/// template is stored once at the start of the file (as a regular BEN frame)
+/// and every sample after it is stored as a delta frame listing the nodes
+/// whose labels differ from the template. Unlike the delta variant, each
+/// frame depends only on the template and not on the previous sample, which
+/// suits ensembles made up of small perturbations of a base plan.
+///
+/// Template files use their own banner (`TEMPLATE BEN FILE`) and are read
+/// with `decode::TemplateBenDecoder`.
+///
+/// # Arguments
+///
+/// * `template` - The base assignment that every sample is compared against
+/// * `samples` - The samples to encode
+/// * `writer` - A writer for the output file
+///
+/// # Returns
+///
+/// A Result type that contains the result of the operation
+///
+/// # Errors
+///
+/// Returns an error if one of the samples does not have the same number of
+/// nodes as the template.
+pub fn encode_template_ben<I, W>(template: &[u16], samples: I, mut writer: W) -> Result<()>
+where
+    I: IntoIterator<Item = Vec<u16>>,
+    W: Write,
+{
+    writer.write_all(b"TEMPLATE BEN FILE")?;
+    writer.write_all(&encode_ben_vec_from_assign(template.to_vec()))?;
+
+    for (i, sample) in samples.into_iter().enumerate() {
+        if sample.len() != template.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Sample {} has {} nodes but the template has {}",
+                    i + 1,
+                    sample.len(),
+                    template.len()
+                ),
+            ));
+        }
+
+        let changes: Vec<(u32, u16)> = sample
+            .iter()
+            .zip(template.iter())
+            .enumerate()
+            .filter(|(_, (new, base))| new != base)
+            .map(|(i, (new, _))| (i as u32, *new))
+            .collect();
+
+        writer.write_all(&encode_delta_frame(&changes))?;
+    }
+
+    Ok(())
+}
+
+/// This function takes a JSONL file and compresses it into the delta
+/// variant of the BEN format. The first sample is stored in full and each
+/// subsequent sample only stores the nodes whose labels changed relative
+/// to the previous sample. This works best for ensembles coming from
+/// Markov chains in which consecutive samples differ in only a few nodes.
+///
+/// All of the assignment vectors in the file are required to have the
+/// same length.
+///
+/// # Arguments
+///
+/// * `reader` - A buffered reader for the input file
+/// * `writer` - A writer for the output file
+///
+/// # Returns
+///
+/// A Result type that contains the result of the operation
+pub fn jsonl_encode_delta_ben<R: BufRead, W: Write>(reader: R, writer: W) -> Result<()> {
+    jsonl_encode_ben(reader, writer, BenVariant::Delta)
+}
+
+/// This function pulls assignment vectors out of a channel and encodes them
+/// into a BEN file until every sender has been dropped. This allows the
+/// samples to be produced on worker threads while a single thread owns the
+/// writer.
+///
+/// For the MkvChain variant, repeated assignment vectors are merged across
+/// channel items, and the final run is flushed once the channel closes.
+///
+/// # Arguments
+///
+/// * `rx` - The receiving end of a channel of assignment vectors
+/// * `writer` - A writer for the output file
+/// * `variant` - The variant of the BEN format to write
+///
+/// # Returns
+///
+/// A Result type that contains the result of the operation
+///
+/// # Example
+///
+/// ```
+/// use std::sync::mpsc::channel;
+/// use std::thread;
+/// use ben::{encode::encode_ben_from_channel, BenVariant};
+///
+/// let (tx, rx) = channel();
+/// let producer = thread::spawn(move || {
+///     for _ in 0..3 {
+///         tx.send(vec![1, 1, 2, 2]).unwrap();
+///     }
+/// });
+///
+/// let mut output = Vec::new();
+/// encode_ben_from_channel(rx, &mut output, BenVariant::MkvChain).unwrap();
+/// producer.join().unwrap();
+/// ```
+pub fn encode_ben_from_channel<W: Write>(
+    rx: Receiver<Vec<u16>>,
+    writer: W,
+    variant: BenVariant,
+) -> Result<()> {
+    let mut ben_encoder = BenEncoder::new(writer, variant);
+
+    for (sample_number, assignment) in rx.into_iter().enumerate() {
+        log!("Encoding sample: {}\r", sample_number + 1);
+        ben_encoder.write_assignment(assignment)?;
+    }
+    ben_encoder.finish()?;
+
+    logln!();
+    logln!("Done!");
+    Ok(())
+}
+
+/// This function encodes a collection of assignment vectors stored as a
+/// flat, row-major buffer (e.g. the data of a 2D numpy array) into a BEN
+/// file. Every consecutive chunk of `n_nodes` labels is treated as a single
+/// sample.
+///
+/// # Arguments
+///
+/// * `flat` - The row-major buffer of `n_samples * n_nodes` labels
+/// * `n_nodes` - The number of nodes in each assignment vector
+/// * `writer` - A writer for the output file
+/// * `variant` - The variant of the BEN format to write
+///
+/// # Returns
+///
+/// A Result type that contains the result of the operation
+///
+/// # Errors
+///
+/// This function will return an error if `n_nodes` is 0 or if the length of
+/// the buffer is not a multiple of `n_nodes`.
+pub fn encode_matrix_ben<W: Write>(
+    flat: &[u16],
+    n_nodes: usize,
+    writer: W,
+    variant: BenVariant,
+) -> Result<()> {
+    // Checked up front so that nothing is written for a bad buffer
+    check_matrix_shape(flat.len(), n_nodes)?;
+
+    let mut ben_encoder = BenEncoder::new(writer, variant);
+    ben_encoder.write_matrix(flat, n_nodes)?;
+    ben_encoder.finish()?;
+
+    logln!();
+    logln!("Done!");
+    Ok(())
+}
+
+/// This function takes a BEN file and encodes it into an XBEN
+/// file using bit-to-byte decompression followed by LZMA2 compression.
+///
+/// # Arguments
+///
+/// * `reader` - A buffered reader for the input file
+/// * `writer` - A writer for the output file
+///
+/// # Returns
+///
+/// A Result type that contains the result of the operation
+pub fn ben_encode_xben<R: BufRead, W: Write>(reader: R, writer: W) -> Result<()> {
+    ben_encode_xben_with_report(reader, writer)?;
+    Ok(())
+}
+
+/// Same as `ben_encode_xben`, but the XZ compression level can be chosen.
+/// `ben_encode_xben` uses level 9.
+///
+/// # Arguments
+///
+/// * `reader` - A buffered reader for the input file
+/// * `writer` - A writer for the output file
+/// * `level` - The XZ preset to compress with, from 0 to 9
+///
+/// # Errors
+///
+/// Returns an `InvalidInput` error if the level is larger than 9.
+pub fn ben_encode_xben_with_level<R: BufRead, W: Write>(
+    reader: R,
+    writer: W,
+    level: u32,
+) -> Result<()> {
+    check_xz_level(level)?;
+    ben_encode_xben_impl(reader, writer, level)?;
+    Ok(())
+}
+
+/// The sizes of the input and output of a BEN to XBEN conversion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionReport {
+    /// The number of bytes in the BEN file (including the banner).
+    pub ben_bytes: u64,
+    /// The number of bytes in the XBEN file.
+    pub xben_bytes: u64,
+}
+
+impl CompressionReport {
+    /// The size of the BEN file divided by the size of the XBEN file.
+    pub fn ratio(&self) -> f64 {
+        self.ben_bytes as f64 / self.xben_bytes as f64
+    }
+}
+
+/// A writer adapter that keeps track of the number of bytes that have been
+/// written to the underlying writer.
+struct CountingWriter<W: Write> {
+    inner: W,
+    position: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Same as `ben_encode_xben`, but also reports the number of bytes that were
+/// read from the BEN file and written to the XBEN file once the XBEN file
+/// has been finalized.
+///
+/// # Arguments
+///
+/// * `reader` - A buffered reader for the input file
+/// * `writer` - A writer for the output file
+///
+/// # Returns
+///
+/// A `CompressionReport` with the sizes of the two files
+pub fn ben_encode_xben_with_report<R: BufRead, W: Write>(
+    reader: R,
+    writer: W,
+) -> Result<CompressionReport> {
+    ben_encode_xben_impl(reader, writer, 9)
+}
+
+fn ben_encode_xben_impl<R: BufRead, W: Write>(
+    reader: R,
+    writer: W,
+    level: u32,
+) -> Result<CompressionReport> {
+    let mut reader = CountingReader::new(reader);
+    let mut writer = CountingWriter {
+        inner: writer,
+        position: 0,
+    };
+
+    let mut check_buffer = [0u8; 17];
+    reader.read_exact(&mut check_buffer)?;
+
+    let encoder = XzEncoder::new(&mut writer, level);
+
+    let mut ben_encoder = match &check_buffer {
+        b"STANDARD BEN FILE" => XBenEncoder::new(encoder, BenVariant::Standard)?,
+        b"MKVCHAIN BEN FILE" => XBenEncoder::new(encoder, BenVariant::MkvChain)?,
+        b"MKVDELTA BEN FILE" => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "The XBEN format does not support the delta variant of BEN files",
+            ));
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid file format",
+            ));
+        }
+    };
+
+    ben_encoder.write_ben_file(&mut reader)?;
+
+    // The XBEN file is only finalized once the encoder is dropped
+    drop(ben_encoder);
+
+    Ok(CompressionReport {
+        ben_bytes: reader.position(),
+        xben_bytes: writer.position,
+    })
+}
+
+/// The result of `ben_xben_roundtrip`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoundtripReport {
+    /// The sizes of the BEN file and of the XBEN file made from it.
+    pub compression: CompressionReport,
+    /// The offset of the first byte at which the decoded BEN file differs
+    /// from the original one, or `None` if the two are identical. If one
+    /// file is a prefix of the other, this is the length of the shorter one.
+    pub mismatch_offset: Option<u64>,
+}
+
+impl RoundtripReport {
+    /// Whether the BEN file survived the round trip byte for byte.
+    pub fn passed(&self) -> bool {
+        self.mismatch_offset.is_none()
+    }
+}
+
+/// Checks that a BEN file survives a round trip through the XBEN format.
+/// The BEN file is encoded into an in-memory XBEN file, which is decoded
+/// back into a BEN file and compared against the original byte for byte.
+/// Nothing is written to disk, so this can be used to build trust in the
+/// format on a new dataset before deleting the BEN file.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the BEN file
+///
+/// # Returns
+///
+/// A `RoundtripReport` with the compression ratio and the outcome of the
+/// comparison
+///
+/// # Errors
+///
+/// This function will return an error if the BEN file cannot be read or
+/// cannot be converted to XBEN (e.g. delta BEN files).
+pub fn ben_xben_roundtrip<R: Read>(mut reader: R) -> Result<RoundtripReport> {
+    let mut ben = Vec::new();
+    reader.read_to_end(&mut ben)?;
+
+    let mut xben = Vec::new();
+    let compression = ben_encode_xben_with_report(ben.as_slice(), &mut xben)?;
+
+    let mut decoded = Vec::new();
+    decode_xben_to_ben(xben.as_slice(), &mut decoded)?;
+
+    let mismatch_offset = match ben.iter().zip(decoded.iter()).position(|(a, b)| a != b) {
+        Some(offset) => Some(offset as u64),
+        None if ben.len() != decoded.len() => Some(ben.len().min(decoded.len()) as u64),
+        None => None,
+    };
+
+    Ok(RoundtripReport {
+        compression,
+        mismatch_offset,
+    })
+}
+
+/// This function takes a MkvChain BEN file and rewrites it as a Standard
+/// BEN file by repeating each frame according to its repetition count. This
+/// is the inverse of encoding with the MkvChain variant and allows the file
+/// to be handed to tools that do not understand the trailing counts.
+///
+/// The frames are copied as-is, so no re-encoding of the assignment vectors
+/// takes place. Standard BEN files are copied through unchanged.
+///
+/// # Arguments
+///
+/// * `reader` - A reader for the input MkvChain BEN file
+/// * `writer` - A writer for the output Standard BEN file
+///
+/// # Returns
+///
+/// A Result type that contains the result of the operation
+///
+/// # Errors
+///
+/// This function will return an error if the input is not a Standard or
+/// MkvChain BEN file, or if a frame is truncated.
+pub fn flatten_mkvchain<R: Read, W: Write>(mut reader: R, mut writer: W) -> Result<()> {
+    let mut check_buffer = [0u8; 17];
+    reader.read_exact(&mut check_buffer)?;
+
+    match &check_buffer {
+        b"STANDARD BEN FILE" => {
+            writer.write_all(&check_buffer)?;
+            io::copy(&mut reader, &mut writer)?;
+            return Ok(());
+        }
+        b"MKVCHAIN BEN FILE" => {}
+        b"MKVDELTA BEN FILE" => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Flattening is not supported for the delta variant of BEN files",
+            ));
+        }
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid file format",
+            ));
+        }
+    }
+
+    writer.write_all(b"STANDARD BEN FILE")?;
+
+    let mut sample_number = 1;
+    loop {
+        let mut header = [0u8; 6];
+        match reader.read_exact(&mut header[..1]) {
+            Ok(()) => {}
+            Err(e) => {
+                if e.kind() == io::ErrorKind::UnexpectedEof {
+                    break;
+                }
+                return Err(e);
+            }
+        }
+        reader.read_exact(&mut header[1..])?;
+
+        let n_bytes = u32::from_be_bytes([header[2], header[3], header[4], header[5]]);
+        let mut frame = header.to_vec();
+        frame.resize(6 + n_bytes as usize, 0);
+        reader.read_exact(&mut frame[6..])?;
+
+        let mut count_buffer = [0u8; 2];
+        reader.read_exact(&mut count_buffer)?;
+        let count = u16::from_be_bytes(count_buffer);
+
+        log!("Flattening sample: {}\r", sample_number);
+        sample_number += count as usize;
+
+        for _ in 0..count {
+            writer.write_all(&frame)?;
+        }
+    }
+
+    logln!();
+    logln!("Done!");
+    Ok(())
+}
+
+/// This function takes two BEN files describing ensembles on the same graph
+/// and interleaves their samples into a single BEN file. One sample is taken
+/// from each file in turn until both are exhausted, so if one file has more
+/// samples than the other, the remaining samples of the longer file are
+/// written at the end.
+///
+/// MkvChain frames are expanded into their individual samples before
+/// interleaving, so the inputs may be of any variant.
+///
+/// # Arguments
+///
+/// * `a` - A reader for the first BEN file
+/// * `b` - A reader for the second BEN file
+/// * `writer` - A writer for the output BEN file
+/// * `variant` - The variant of the BEN format to write
+///
+/// # Returns
+///
+/// A Result type that contains the result of the operation
+///
+/// # Errors
+///
+/// This function will return an error if either input is not a valid BEN
+/// file or if the samples do not all have the same number of nodes.
+pub fn interleave_ben<R1: Read, R2: Read, W: Write>(
+    a: R1,
+    b: R2,
+    writer: W,
+    variant: BenVariant,
+) -> Result<()> {
+    let mut samples_a = expand_counts(BenDecoder::new(a)?);
+    let mut samples_b = expand_counts(BenDecoder::new(b)?);
+
+    let mut ben_encoder = BenEncoder::new(writer, variant);
+    let mut n_nodes: Option<usize> = None;
+    let mut sample_number = 0;
+
+    let mut write_sample = |assignment: Vec<u16>, source: &str| -> Result<()> {
+        match n_nodes {
+            None => n_nodes = Some(assignment.len()),
+            Some(n) if n != assignment.len() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Sample from the {} file has {} nodes but {} were expected",
+                        source,
+                        assignment.len(),
+                        n
+                    ),
+                ));
+            }
+            Some(_) => {}
+        }
+
+        sample_number += 1;
+        log!("Interleaving sample: {}\r", sample_number);
+        ben_encoder.write_assignment(assignment)
+    };
+
+    let mut a_done = false;
+    let mut b_done = false;
+    while !a_done || !b_done {
+        if !a_done {
+            match samples_a.next() {
+                Some(assignment) => write_sample(assignment?, "first")?,
+                None => a_done = true,
+            }
+        }
+        if !b_done {
+            match samples_b.next() {
+                Some(assignment) => write_sample(assignment?, "second")?,
+                None => b_done = true,
+            }
+        }
+    }
+
+    logln!();
+    logln!("Done!");
+    Ok(())
+}
+
+/// This function re-emits the samples of a BEN file in ascending order of a
+/// user-provided key, e.g. the number of cut edges of each plan. Samples
+/// with equal keys keep their original relative order.
+///
+/// The keys are computed in a single pass over the file, remembering the
+/// byte offset of each frame along with its key. The frames are then sorted
+/// and decoded a second time by seeking back to their offsets, so the input
+/// must implement `Seek`. Only one key and one offset are held in memory per
+/// frame (i.e. per sample, or per run of repeated samples for the MkvChain
+/// variant) rather than the assignment vectors themselves.
+///
+/// # Arguments
+///
+/// * `reader` - A seekable reader for the input BEN file (Standard or MkvChain)
+/// * `writer` - A writer for the output BEN file
+/// * `key_fn` - A function computing the sort key of an assignment vector
+/// * `variant` - The variant of the BEN format to write
+///
+/// # Returns
+///
+/// A Result type that contains the result of the operation
+///
+/// # Errors
+///
+/// This function will return an error if the input is not a valid Standard
+/// or MkvChain BEN file, or if writing to the writer fails.
+pub fn sort_ben_by<R, W, F>(
+    mut reader: R,
+    writer: W,
+    mut key_fn: F,
+    variant: BenVariant,
+) -> Result<()>
+where
+    R: Read + Seek,
+    W: Write,
+    F: FnMut(&[u16]) -> f64,
+{
+    let input_variant = read_ben_variant(&mut reader)?;
+
+    let mut frames: Vec<(f64, u64, u16)> = Vec::new();
+    loop {
+        let offset = reader.stream_position()?;
+        let mut tmp_buffer = [0u8];
+        match reader.read_exact(&mut tmp_buffer) {
+            Ok(()) => {}
+            Err(e) => {
+                if e.kind() == io::ErrorKind::UnexpectedEof {
+                    break;
+                }
+                return Err(e);
+            }
+        }
+        let max_len_bits = reader.read_u8()?;
+        let n_bytes = reader.read_u32::<BigEndian>()?;
+        let assignment = rle_to_vec(decode_ben_line(
+            &mut reader,
+            tmp_buffer[0],
+            max_len_bits,
+            n_bytes,
+        )?);
+
+        let count = if input_variant == BenVariant::MkvChain {
+            reader.read_u16::<BigEndian>()?
+        } else {
+            1
+        };
+
+        log!("Computing key for frame: {}\r", frames.len() + 1);
+        frames.push((key_fn(&assignment), offset, count));
+    }
+    logln!();
+
+    frames.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut ben_encoder = BenEncoder::new(writer, variant);
+    for (i, &(_, offset, count)) in frames.iter().enumerate() {
+        log!("Writing frame: {}\r", i + 1);
+        let assignment = decode_frame_at(&mut reader, offset)?;
+        for _ in 0..count {
+            ben_encoder.write_assignment(assignment.clone())?;
+        }
+    }
+    ben_encoder.finish()?;
+
+    logln!();
+    logln!("Done!");
+    Ok(())
+}
+
+/// This function splits the samples of a BEN file into several BEN files
+/// according to a categorical key computed from each sample, e.g. the
+/// number of majority-minority districts in each plan. This is essentially
+/// a group-by over the ensemble.
+///
+/// The writer for a key is opened with `open_writer` the first time a
+/// sample with that key is seen, and the banner is written to it along with
+/// that first sample. For the MkvChain variant, consecutive samples routed
+/// to the same output are collapsed as usual, even if they were separated
+/// by samples with other keys in the input.
+///
+/// # Arguments
+///
+/// * `reader` - A reader for the input BEN file
+/// * `key_fn` - A function computing the key of an assignment vector
+/// * `open_writer` - A function opening the output for a new key
+/// * `variant` - The variant of the BEN format to write to every output
+///
+/// # Returns
+///
+/// The writers for each key that was seen, after all samples have been
+/// written and flushed
+///
+/// # Errors
+///
+/// This function will return an error if the input is not a valid BEN file,
+/// or if writing to one of the outputs fails.
+pub fn partition_ben_by<R, W, K, F, G>(
+    reader: R,
+    mut key_fn: F,
+    mut open_writer: G,
+    variant: BenVariant,
+) -> Result<HashMap<K, W>>
+where
+    R: Read,
+    W: Write,
+    K: Eq + Hash,
+    F: FnMut(&[u16]) -> K,
+    G: FnMut(&K) -> W,
+{
+    let mut encoders: HashMap<K, BenEncoder<W>> = HashMap::new();
+
+    for (i, assignment) in expand_counts(BenDecoder::new(reader)?).enumerate() {
+        log!("Partitioning sample: {}\r", i + 1);
+        let assignment = assignment?;
+        let key = key_fn(&assignment);
+        let encoder = match encoders.entry(key) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let writer = open_writer(entry.key());
+                entry.insert(BenEncoder::new(writer, variant))
+            }
+        };
+        encoder.write_assignment(assignment)?;
+    }
+
+    logln!();
+    logln!("Done!");
+    encoders
+        .into_iter()
+        .map(|(key, encoder)| Ok((key, encoder.finish()?)))
+        .collect()
+}
+
+/// This function re-encodes a BEN file, keeping only the samples for which
+/// `keep` returns true (e.g. dropping plans that leave some node unassigned).
+///
+/// The predicate is called once per frame, so a run of repeated MkvChain
+/// samples is either kept with its full repetition count or dropped
+/// entirely. When the output is MkvChain, kept samples that end up next to
+/// each other are collapsed as usual.
+///
+/// # Arguments
+///
+/// * `reader` - A reader for the input BEN file
+/// * `writer` - A writer for the output BEN file
+/// * `keep` - A function deciding whether an assignment vector is kept
+/// * `variant` - The variant of the BEN format to write
+///
+/// # Returns
+///
+/// The number of samples that were kept
+///
+/// # Errors
+///
+/// This function will return an error if the input is not a valid BEN file,
+/// or if writing to the writer fails.
+pub fn filter_ben<R, W, F>(reader: R, writer: W, mut keep: F, variant: BenVariant) -> Result<usize>
+where
+    R: Read,
+    W: Write,
+    F: FnMut(&[u16]) -> bool,
+{
+    let mut ben_encoder = BenEncoder::new(writer, variant);
+    let mut n_read = 0;
+    let mut n_kept = 0;
+
+    for record in BenDecoder::new(reader)? {
+        let (assignment, count) = record?;
+        n_read += count as usize;
+        log!("Filtering sample: {}\r", n_read);
+
+        if keep(&assignment) {
+            for _ in 0..count {
+                ben_encoder.write_assignment_slice(&assignment)?;
+            }
+            n_kept += count as usize;
+        }
+    }
+    ben_encoder.finish()?;
+
+    logln!();
+    logln!("Kept {} of {} samples", n_kept, n_read);
+    Ok(n_kept)
+}
+
+/// Reads the next frame of a Standard or MkvChain BEN file as raw bytes
+/// (header and payload) along with its repetition count. Returns `None` at
+/// the end of the file.
+fn read_raw_frame<R: Read>(reader: &mut R, variant: BenVariant) -> Result<Option<(Vec<u8>, u16)>> {
+    let mut header = [0u8; 6];
+    match reader.read_exact(&mut header[..1]) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    reader.read_exact(&mut header[1..])?;
+
+    let n_bytes = u32::from_be_bytes([header[2], header[3], header[4], header[5]]);
+    // The length comes straight from the file, so the payload is read
+    // through `take` rather than allocated up front.
+    let mut frame = header.to_vec();
+    reader.take(n_bytes as u64).read_to_end(&mut frame)?;
+    if frame.len() != 6 + n_bytes as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!(
+                "Frame payload is {} bytes long but the header says {}",
+                frame.len() - 6,
+                n_bytes
+            ),
+        ));
+    }
+
+    let count = if variant == BenVariant::MkvChain {
+        reader.read_u16::<BigEndian>()?
+    } else {
+        1
+    };
+    Ok(Some((frame, count)))
+}
+
+/// This function concatenates several BEN files (e.g. shards of an ensemble
+/// produced in parallel) into a single BEN file without decoding and
+/// re-encoding the assignment vectors. A single banner is written, and the
+/// frames of each input are then copied into the output after skipping the
+/// 17 byte banner of that input.
+///
+/// This is the same as `merge_ben_files_with_coalesce` with `coalesce` set
+/// to `true`, so the output is identical to encoding all of the samples in
+/// one go.
+///
+/// # Arguments
+///
+/// * `inputs` - Readers for the input BEN files (Standard or MkvChain), in order
+/// * `writer` - A writer for the output BEN file
+/// * `variant` - The variant of the BEN format to write (Standard or MkvChain)
+///
+/// # Returns
+///
+/// A Result type that contains the result of the operation
+///
+/// # Errors
+///
+/// This function will return an error if one of the inputs is not a
+/// Standard or MkvChain BEN file, if the output variant is
+/// `BenVariant::Delta`, or if writing to the writer fails.
+pub fn merge_ben_files<W: Write>(
+    inputs: Vec<Box<dyn BufRead>>,
+    writer: W,
+    variant: BenVariant,
+) -> Result<()> {
+    merge_ben_files_with_coalesce(inputs, writer, variant, true)
+}
+
+/// Same as `merge_ben_files`, but with control over what happens at the
+/// boundary between two inputs when writing the MkvChain variant.
+///
+/// Within each input, consecutive identical samples are always stored as a
+/// single frame with a repetition count. If `coalesce` is `true`, the same
+/// is done across inputs, so when the last sample of one input equals the
+/// first sample of the next their runs are merged into a single frame. If
+/// `coalesce` is `false`, every input starts a new frame, which keeps the
+/// shard boundaries visible in the frame structure of the output. Both
+/// choices decode to the same samples, and the flag has no effect on the
+/// Standard variant.
+pub fn merge_ben_files_with_coalesce<W: Write>(
+    inputs: Vec<Box<dyn BufRead>>,
+    mut writer: W,
+    variant: BenVariant,
+    coalesce: bool,
+) -> Result<()> {
+    let banner: &[u8; 17] = match variant {
+        BenVariant::Standard => b"STANDARD BEN FILE",
+        BenVariant::MkvChain => b"MKVCHAIN BEN FILE",
+        BenVariant::Delta => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Merging is not supported for the delta variant of BEN files",
+            ));
+        }
+    };
+    writer.write_all(banner)?;
+
+    // The pending MkvChain frame and its repetition count
+    let mut pending: Option<(Vec<u8>, u16)> = None;
+    let mut sample_number = 0;
+
+    for (i, mut input) in inputs.into_iter().enumerate() {
+        let input_variant = read_ben_variant(&mut input)
+            .map_err(|e| io::Error::new(e.kind(), format!("Error in input {}: {}", i + 1, e)))?;
+
+        if !coalesce {
+            if let Some((frame, count)) = pending.take() {
+                writer.write_all(&frame)?;
+                writer.write_all(&count.to_be_bytes())?;
+            }
+        }
+
+        while let Some((frame, count)) = read_raw_frame(&mut input, input_variant)? {
+            sample_number += count as usize;
+            log!("Merging sample: {}\r", sample_number);
+
+            if variant == BenVariant::Standard {
+                for _ in 0..count {
+                    writer.write_all(&frame)?;
+                }
+                continue;
+            }
+
+            match pending.as_mut() {
+                Some((prev, prev_count))
+                    if *prev == frame && (*prev_count as u32 + count as u32) <= u16::MAX as u32 =>
+                {
+                    *prev_count += count;
+                }
+                _ => {
+                    if let Some((prev, prev_count)) = pending.replace((frame, count)) {
+                        writer.write_all(&prev)?;
+                        writer.write_all(&prev_count.to_be_bytes())?;
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some((frame, count)) = pending {
+        writer.write_all(&frame)?;
+        writer.write_all(&count.to_be_bytes())?;
+    }
+
+    logln!();
+    logln!("Done!");
+    writer.flush()
+}
+
+/// Checks that a JSONL file survives a round trip through the BEN format
+/// without writing any files. The assignments are encoded into an in-memory
+/// BEN file of the given variant, which is then decoded and compared against
+/// the original assignments sample by sample.
+///
+/// # Arguments
+///
+/// * `reader` - A buffered reader for the input JSONL file
+/// * `variant` - The variant of the BEN format to round trip through
+///
+/// # Errors
+///
+/// Returns an `InvalidData` error naming the first sample (starting from 1)
+/// that does not decode back to its original assignment, or naming the
+/// number of samples if the decoded file has a different number of samples.
+/// Also returns an error if the input cannot be read or parsed.
+pub fn assert_jsonl_roundtrips<R: BufRead>(reader: R, variant: BenVariant) -> Result<()> {
+    let reader = decompress_if_xz(reader)?;
+    let mut assignments = Vec::new();
+    let mut ben_encoder = BenEncoder::new(Vec::new(), variant);
+    for (i, line) in non_blank_lines(reader).enumerate() {
+        let data: Value = serde_json::from_str(&line?)?;
+        let assignment = json_sample_assignment(&data, i + 1)?;
+        ben_encoder.write_assignment_slice(&assignment)?;
+        assignments.push(assignment);
+    }
+    let ben = ben_encoder.finish()?;
+
+    let mut n_decoded = 0;
+    for (i, decoded) in expand_counts(BenDecoder::new(ben.as_slice())?).enumerate() {
+        let decoded = decoded?;
+        if assignments.get(i) != Some(&decoded) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Sample {} does not round trip through the BEN format",
+                    i + 1
+                ),
+            ));
+        }
+        n_decoded += 1;
+    }
+
+    if n_decoded != assignments.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Encoded {} samples but decoded {}",
+                assignments.len(),
+                n_decoded
+            ),
+        ));
+    }
     Ok(())
 }
 
@@ -3,6 +3,7 @@
 //! can be doe either so that the values are in ascending order or according to
 //! a mapping provided by the user in a map file.
 
+use crate::decode::index::read_ben_variant;
 use crate::decode::*;
 use crate::encode::*;
 use byteorder::{BigEndian, ReadBytesExt};
@@ -240,6 +241,353 @@ pub fn relabel_ben_file_with_map<R: Read, W: Write>(
     Ok(())
 }
 
+/// Relabels the districts of every assignment vector in a BEN file using a
+/// permutation of the labels `0..perm.len()`, so that label `l` becomes
+/// `perm[l]`. Unlike `relabel_ben_file_with_map`, which reorders the nodes,
+/// this changes the labels themselves and leaves the node order alone. The
+/// labels are looked up by position, so no hashing is done per run.
+///
+/// Since the labels are permuted, the runs of every frame stay the same and
+/// the MkvChain repetition counts are copied over unchanged.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the BEN file to be relabeled
+/// * `writer` - A writer for the relabeled BEN file
+/// * `perm` - The new label of each old label
+///
+/// # Errors
+///
+/// Returns an `InvalidInput` error if `perm` is not a permutation of
+/// `0..perm.len()` (or has more than `u16::MAX + 1` entries), and an
+/// `InvalidData` error if the file is not a Standard or MkvChain BEN file
+/// or if it contains a label outside of `0..perm.len()`.
+pub fn relabel_ben_file_with_perm<R: BufRead, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    perm: &[usize],
+) -> io::Result<()> {
+    if perm.len() > u16::MAX as usize + 1 {
+        return Err(Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "The permutation has {} labels, which does not fit in 16 bits",
+                perm.len()
+            ),
+        ));
+    }
+    let mut seen = vec![false; perm.len()];
+    for &label in perm {
+        if label >= perm.len() || std::mem::replace(&mut seen[label], true) {
+            return Err(Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} is not a permutation of 0..{}", perm, perm.len()),
+            ));
+        }
+    }
+
+    let variant = read_ben_variant(&mut reader)?;
+    let banner: &[u8; 17] = match variant {
+        BenVariant::MkvChain => b"MKVCHAIN BEN FILE",
+        _ => b"STANDARD BEN FILE",
+    };
+    writer.write_all(banner)?;
+
+    let mut sample_number = 0;
+    loop {
+        let mut tmp_buffer = [0u8];
+        let max_val_bits = match reader.read_exact(&mut tmp_buffer) {
+            Ok(_) => tmp_buffer[0],
+            Err(e) => {
+                if e.kind() == io::ErrorKind::UnexpectedEof {
+                    break;
+                }
+                return Err(e);
+            }
+        };
+
+        let max_len_bits = reader.read_u8()?;
+        let n_bytes = reader.read_u32::<BigEndian>()?;
+
+        let ben_line = decode_ben_line(&mut reader, max_val_bits, max_len_bits, n_bytes)?;
+        let new_rle = ben_line
+            .into_iter()
+            .map(|(val, len)| match perm.get(val as usize) {
+                Some(&new_val) => Ok((new_val as u16, len)),
+                None => Err(Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Sample {} has label {} which is not in the permutation of 0..{}",
+                        sample_number + 1,
+                        val,
+                        perm.len()
+                    ),
+                )),
+            })
+            .collect::<io::Result<Vec<(u16, u16)>>>()?;
+
+        writer.write_all(&encode_ben_vec_from_rle(new_rle))?;
+
+        let count_occurrences = if variant == BenVariant::MkvChain {
+            let count = reader.read_u16::<BigEndian>()?;
+            writer.write_all(&count.to_be_bytes())?;
+            count
+        } else {
+            1
+        };
+
+        sample_number += count_occurrences as usize;
+        log!("Relabeling line: {}\r", sample_number);
+    }
+    logln!();
+    logln!("Done!");
+
+    Ok(())
+}
+
+/// Suggests a relabeling of the nodes of a graph that should make the runs
+/// in the assignment vectors of an ensemble longer, and therefore make the
+/// BEN and XBEN files smaller, for when there is no natural key (like a
+/// GEOID) to sort the nodes by.
+///
+/// The ensemble is first read to count how often the two ends of every edge
+/// are assigned to the same district (weighted by the MkvChain repetition
+/// counts). The nodes are then ordered by a depth-first walk over the graph
+/// that always moves to the unvisited neighbor that most often shares a
+/// district with the current node, starting each connected component from
+/// one of its lowest-degree nodes. Nodes that tend to be in the same district
+/// therefore end up next to each other in the new order.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the BEN file of the ensemble
+/// * `adjacency` - The neighbors of each node in the graph
+///
+/// # Returns
+///
+/// A HashMap where the key is the position in the new assignment vector and
+/// the value is the position in the old assignment vector, which can be passed
+/// directly to `relabel_ben_file_with_map`.
+///
+/// # Errors
+///
+/// Returns an error if the BEN file cannot be decoded, if the adjacency refers
+/// to a node that does not exist, or if one of the samples does not have one
+/// entry per node.
+pub fn suggest_relabeling<R: Read>(
+    reader: R,
+    adjacency: &[Vec<u32>],
+) -> io::Result<HashMap<usize, usize>> {
+    let n_nodes = adjacency.len();
+    if let Some(&bad) = adjacency.iter().flatten().find(|&&v| v as usize >= n_nodes) {
+        return Err(Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Adjacency refers to node {} but the graph only has {} nodes",
+                bad, n_nodes
+            ),
+        ));
+    }
+
+    let mut shared: Vec<Vec<u64>> = adjacency.iter().map(|nbrs| vec![0; nbrs.len()]).collect();
+    let mut sample_number = 0;
+    for record in BenDecoder::new(reader)? {
+        let (assignment, count) = record?;
+        sample_number += count as usize;
+        if assignment.len() != n_nodes {
+            return Err(Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Expected {} nodes in sample {}, but found {}",
+                    n_nodes,
+                    sample_number,
+                    assignment.len()
+                ),
+            ));
+        }
+
+        for (node, nbrs) in adjacency.iter().enumerate() {
+            for (i, &nbr) in nbrs.iter().enumerate() {
+                if assignment[node] == assignment[nbr as usize] {
+                    shared[node][i] += count as u64;
+                }
+            }
+        }
+    }
+
+    let mut starts: Vec<usize> = (0..n_nodes).collect();
+    starts.sort_by_key(|&node| (adjacency[node].len(), node));
+
+    let mut visited = vec![false; n_nodes];
+    let mut order = Vec::with_capacity(n_nodes);
+    let mut stack = Vec::new();
+    for start in starts {
+        if visited[start] {
+            continue;
+        }
+        stack.push(start);
+        while let Some(node) = stack.pop() {
+            if visited[node] {
+                continue;
+            }
+            visited[node] = true;
+            order.push(node);
+
+            // Push the most similar neighbor last so that it is visited next
+            let mut nbrs: Vec<(u64, usize)> = adjacency[node]
+                .iter()
+                .zip(shared[node].iter())
+                .filter(|(&nbr, _)| !visited[nbr as usize])
+                .map(|(&nbr, &weight)| (weight, nbr as usize))
+                .collect();
+            nbrs.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)));
+            stack.extend(nbrs.into_iter().map(|(_, nbr)| nbr));
+        }
+    }
+
+    Ok(order.into_iter().enumerate().collect())
+}
+
+/// Encodes a JSONL file into a BEN file after remapping the assignment labels
+/// to the dense range `0..k`, where `k` is the number of distinct labels in
+/// the file. Labels are numbered in the order in which they first appear.
+/// Sparse labels (e.g. 1000, 2000, 3000) otherwise waste bits in every frame,
+/// so this can shrink both the BEN file and the XBEN file made from it.
+///
+/// Unlike `relabel_ben_file`, which reorders the labels within each sample,
+/// the same mapping is used for the whole file so that it can be undone. The
+/// mapping is written to `map_writer` as a JSON sidecar of the form
+///
+/// ```json
+/// {"dense_to_original_labels": [1000, 2000, 3000]}
+/// ```
+///
+/// which can be read back with `read_dense_label_map` and applied with
+/// `restore_dense_labels`.
+///
+/// # Arguments
+///
+/// * `reader` - A buffered reader for the input JSONL file
+/// * `writer` - A writer for the output BEN file
+/// * `map_writer` - A writer for the JSON sidecar holding the label mapping
+/// * `variant` - The variant of the BEN format to write
+///
+/// # Returns
+///
+/// The original label of each dense label
+///
+/// # Errors
+///
+/// Returns an error if a line of the input is not valid JSON, if an
+/// assignment is invalid, or if writing to either writer fails.
+pub fn jsonl_encode_ben_dense<R: BufRead, W: Write, M: Write>(
+    reader: R,
+    writer: W,
+    mut map_writer: M,
+    variant: BenVariant,
+) -> io::Result<Vec<u16>> {
+    let reader = decompress_if_xz(reader)?;
+    let mut dense_labels: HashMap<u16, u16> = HashMap::new();
+    let mut original_labels: Vec<u16> = Vec::new();
+
+    let mut ben_encoder = BenEncoder::new(writer, variant);
+    for (i, line) in non_blank_lines(reader).enumerate() {
+        log!("Encoding line: {}\r", i + 1);
+        let data: Value = serde_json::from_str(&line?)?;
+        let mut assignment = json_sample_assignment(&data, i + 1)?;
+
+        for label in assignment.iter_mut() {
+            *label = *dense_labels.entry(*label).or_insert_with(|| {
+                original_labels.push(*label);
+                (original_labels.len() - 1) as u16
+            });
+        }
+        ben_encoder.write_assignment(assignment)?;
+    }
+    ben_encoder.finish()?;
+
+    let map = serde_json::json!({ "dense_to_original_labels": original_labels });
+    map_writer.write_all(map.to_string().as_bytes())?;
+    map_writer.flush()?;
+
+    logln!();
+    logln!("Done!");
+    Ok(original_labels)
+}
+
+/// Reads the JSON sidecar written by `jsonl_encode_ben_dense`.
+///
+/// # Returns
+///
+/// The original label of each dense label
+///
+/// # Errors
+///
+/// Returns an `InvalidData` error if the sidecar is not valid JSON or does
+/// not hold a `"dense_to_original_labels"` array of labels.
+pub fn read_dense_label_map<R: Read>(reader: R) -> io::Result<Vec<u16>> {
+    let data: Value = serde_json::from_reader(reader)?;
+    match data.get("dense_to_original_labels") {
+        Some(labels) => json_assignment_to_vec(labels),
+        None => Err(Error::new(
+            io::ErrorKind::InvalidData,
+            "Label map is missing the \"dense_to_original_labels\" key",
+        )),
+    }
+}
+
+/// Undoes the label remapping of `jsonl_encode_ben_dense`, writing a BEN file
+/// of the same variant with the original labels.
+///
+/// # Arguments
+///
+/// * `reader` - A reader containing the BEN file with dense labels
+/// * `writer` - A writer for the BEN file with the original labels
+/// * `original_labels` - The original label of each dense label
+///
+/// # Errors
+///
+/// Returns an error if the BEN file cannot be decoded or if it contains a
+/// label that is not in the mapping.
+pub fn restore_dense_labels<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    original_labels: &[u16],
+) -> io::Result<()> {
+    let decoder = BenRleDecoder::new(reader)?;
+    let mut ben_encoder = BenEncoder::new(writer, decoder.variant());
+
+    let mut sample_number = 0;
+    for record in decoder {
+        let (rle, count) = record?;
+        let rle = rle
+            .into_iter()
+            .map(|(val, len)| match original_labels.get(val as usize) {
+                Some(&original) => Ok((original, len)),
+                None => Err(Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Sample {} has label {} which is not in the label map",
+                        sample_number + 1,
+                        val
+                    ),
+                )),
+            })
+            .collect::<io::Result<Vec<(u16, u16)>>>()?;
+
+        for _ in 0..count {
+            ben_encoder.write_rle(rle.clone())?;
+        }
+        sample_number += count as usize;
+        log!("Restoring labels of sample: {}\r", sample_number);
+    }
+    ben_encoder.finish()?;
+
+    logln!();
+    logln!("Done!");
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -247,6 +595,7 @@ mod tests {
     use rand::SeedableRng;
     use rand_chacha::ChaCha8Rng;
     use rand_distr::{Distribution, Uniform};
+    use serde_json::json;
 
     fn shuffle_with_mapping<T>(vec: &mut Vec<T>) -> HashMap<usize, usize>
     where
@@ -585,4 +934,150 @@ mod tests {
 
         assert_eq!(output_str, out_file);
     }
+
+    #[test]
+    fn test_suggest_relabeling_groups_districts() {
+        let assignments = [vec![1, 2, 1, 2, 1, 2], vec![1, 2, 1, 2, 1, 1]];
+        let adjacency = vec![
+            vec![2],
+            vec![4, 3],
+            vec![0, 4],
+            vec![1, 5],
+            vec![2, 1],
+            vec![3],
+        ];
+
+        let mut input = String::new();
+        for (i, assignment) in assignments.iter().enumerate() {
+            input += &(json!({"assignment": assignment, "sample": i + 1}).to_string() + "\n");
+        }
+        let mut ben = Vec::new();
+        jsonl_encode_ben(input.as_bytes(), &mut ben, BenVariant::MkvChain).unwrap();
+
+        let new_to_old = suggest_relabeling(ben.as_slice(), &adjacency).unwrap();
+        let order: Vec<usize> = (0..6).map(|i| new_to_old[&i]).collect();
+        assert_eq!(order, vec![0, 2, 4, 1, 3, 5]);
+
+        let mut relabeled = Vec::new();
+        relabel_ben_file_with_map(ben.as_slice(), &mut relabeled, new_to_old).unwrap();
+        let decoded = BenDecoder::new(relabeled.as_slice())
+            .unwrap()
+            .map(|record| record.unwrap().0)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            decoded,
+            vec![vec![1, 1, 1, 2, 2, 2], vec![1, 1, 1, 2, 2, 1]]
+        );
+
+        let err = suggest_relabeling(ben.as_slice(), &[vec![7]]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_dense_labels_roundtrip() {
+        let file = format!(
+            "{}\n{}\n{}\n{}\n",
+            "{\"assignment\":[3000,3000,1000,1000,2000,2000],\"sample\":1}",
+            "{\"assignment\":[3000,3000,1000,1000,2000,2000],\"sample\":2}",
+            "{\"assignment\":[1000,2000,3000,1000,2000,3000],\"sample\":3}",
+            "{\"assignment\":[2000,2000,2000,3000,3000,3000],\"sample\":4}"
+        );
+
+        for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+            let mut sparse = Vec::new();
+            jsonl_encode_ben(file.as_bytes(), &mut sparse, variant).unwrap();
+
+            let mut dense = Vec::new();
+            let mut map = Vec::new();
+            let labels =
+                jsonl_encode_ben_dense(file.as_bytes(), &mut dense, &mut map, variant).unwrap();
+            assert_eq!(labels, vec![3000, 1000, 2000]);
+
+            // The labels of the first frame fit in 2 bits instead of 12.
+            assert_eq!(dense[17], 2);
+            assert_eq!(sparse[17], 12);
+            assert!(dense.len() < sparse.len());
+
+            let labels = read_dense_label_map(map.as_slice()).unwrap();
+            let mut restored = Vec::new();
+            restore_dense_labels(dense.as_slice(), &mut restored, &labels).unwrap();
+            assert_eq!(restored, sparse);
+
+            let mut output = Vec::new();
+            jsonl_decode_ben(restored.as_slice(), &mut output).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), file);
+
+            // Blank lines (e.g. a trailing newline) are not samples
+            let mut padded = Vec::new();
+            jsonl_encode_ben_dense(
+                (file.clone() + "\n").as_bytes(),
+                &mut padded,
+                Vec::new(),
+                variant,
+            )
+            .unwrap();
+            assert_eq!(padded, dense);
+        }
+    }
+
+    #[test]
+    fn test_restore_dense_labels_rejects_unknown_label() {
+        let file = "{\"assignment\":[0,1,2],\"sample\":1}\n";
+        let mut ben = Vec::new();
+        jsonl_encode_ben(file.as_bytes(), &mut ben, BenVariant::Standard).unwrap();
+
+        let err = restore_dense_labels(ben.as_slice(), Vec::new(), &[5, 6]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("Sample 1 has label 2"));
+    }
+
+    #[test]
+    fn test_relabel_with_perm_twice_restores_original() {
+        let file = format!(
+            "{}\n{}\n{}\n{}\n",
+            "{\"assignment\":[0,0,1,1,2,3,3],\"sample\":1}",
+            "{\"assignment\":[0,0,1,1,2,3,3],\"sample\":2}",
+            "{\"assignment\":[3,2,1,0,0,1,2],\"sample\":3}",
+            "{\"assignment\":[1,1,1,1,0,0,0],\"sample\":4}"
+        );
+        let reversed = format!(
+            "{}\n{}\n{}\n{}\n",
+            "{\"assignment\":[3,3,2,2,1,0,0],\"sample\":1}",
+            "{\"assignment\":[3,3,2,2,1,0,0],\"sample\":2}",
+            "{\"assignment\":[0,1,2,3,3,2,1],\"sample\":3}",
+            "{\"assignment\":[2,2,2,2,3,3,3],\"sample\":4}"
+        );
+        let perm = [3, 2, 1, 0];
+
+        for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+            let mut ben = Vec::new();
+            jsonl_encode_ben(file.as_bytes(), &mut ben, variant).unwrap();
+
+            let mut once = Vec::new();
+            relabel_ben_file_with_perm(ben.as_slice(), &mut once, &perm).unwrap();
+            let mut output = Vec::new();
+            jsonl_decode_ben(once.as_slice(), &mut output).unwrap();
+            assert_eq!(String::from_utf8(output).unwrap(), reversed);
+
+            let mut twice = Vec::new();
+            relabel_ben_file_with_perm(once.as_slice(), &mut twice, &perm).unwrap();
+            assert_eq!(twice, ben);
+        }
+    }
+
+    #[test]
+    fn test_relabel_with_perm_rejects_bad_input() {
+        let file = "{\"assignment\":[0,1,2],\"sample\":1}\n";
+        let mut ben = Vec::new();
+        jsonl_encode_ben(file.as_bytes(), &mut ben, BenVariant::Standard).unwrap();
+
+        for perm in [vec![0, 0, 1], vec![0, 1, 3]] {
+            let err = relabel_ben_file_with_perm(ben.as_slice(), Vec::new(), &perm).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+
+        let err = relabel_ben_file_with_perm(ben.as_slice(), Vec::new(), &[1, 0]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("Sample 1 has label 2"));
+    }
 }
@@ -1,4 +1,6 @@
 use super::*;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use serde_json::json;
 
 #[test]
@@ -457,11 +459,11 @@ fn jsonl_encode_ben32<R: BufRead, W: Write>(reader: R, mut writer: W) -> std::io
     writer.write_all("STANDARD BEN FILE".as_bytes())?;
     for line_result in reader.lines() {
         eprint!("Encoding line: {}\r", line_num);
-        line_num += 1;
         let line = line_result?; // Handle potential I/O errors for each line
         let data: Value = serde_json::from_str(&line).expect("Error parsing JSON from line");
 
-        writer.write_all(&encode_ben32_line(data))?;
+        writer.write_all(&encode_ben32_line(data, line_num)?)?;
+        line_num += 1;
     }
     eprintln!("Done!"); // Print newline after progress bar
     Ok(())
@@ -659,3 +661,1117 @@ fn jsonl_encode_ben32_multiple_simple_lines() {
     }
     assert_eq!(buffer, expected_output)
 }
+
+#[test]
+fn test_jsonl_encode_delta_ben() {
+    let input = r#"{"assignment":[1,1,2,2],"sample":1}
+{"assignment":[1,2,2,2],"sample":2}
+{"assignment":[1,2,2,2],"sample":3}
+"#;
+
+    let mut output: Vec<u8> = Vec::new();
+    jsonl_encode_delta_ben(input.as_bytes(), &mut output).unwrap();
+
+    let mut expected: Vec<u8> = b"MKVDELTA BEN FILE".to_vec();
+    expected.extend(encode_ben_vec_from_assign(vec![1, 1, 2, 2]));
+    // Node 1 changes to 2
+    expected.extend(vec![1, 2, 0, 0, 0, 1, 0b110_00000]);
+    // No changes
+    expected.extend(vec![1, 1, 0, 0, 0, 0]);
+
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn test_delta_ben_rejects_length_change() {
+    let input = r#"{"assignment":[1,1,2,2],"sample":1}
+{"assignment":[1,2,2],"sample":2}
+"#;
+
+    let mut output: Vec<u8> = Vec::new();
+    let result = jsonl_encode_delta_ben(input.as_bytes(), &mut output);
+
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_jsonl_encode_ben_take_flushes_mkv_run() {
+    let input = r#"{"assignment":[1,1,2,2],"sample":1}
+{"assignment":[1,1,2,2],"sample":2}
+{"assignment":[1,1,2,2],"sample":3}
+{"assignment":[2,1,2,2],"sample":4}
+"#;
+
+    let mut output: Vec<u8> = Vec::new();
+    jsonl_encode_ben_with_take(input.as_bytes(), &mut output, BenVariant::MkvChain, 2).unwrap();
+
+    let mut expected: Vec<u8> = b"MKVCHAIN BEN FILE".to_vec();
+    expected.extend(encode_ben_vec_from_assign(vec![1, 1, 2, 2]));
+    expected.extend(2u16.to_be_bytes());
+
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn test_jsonl_encode_ben_take_skips_blank_lines() {
+    let input = "\n".to_string()
+        + r#"{"assignment":[1,1,2,2],"sample":1}"#
+        + "\n\n"
+        + r#"{"assignment":[2,1,2,2],"sample":2}"#
+        + "\n"
+        + r#"{"assignment":[2,2,2,2],"sample":3}"#
+        + "\n";
+
+    let mut output: Vec<u8> = Vec::new();
+    jsonl_encode_ben_with_take(input.as_bytes(), &mut output, BenVariant::Standard, 2).unwrap();
+
+    let mut expected: Vec<u8> = b"STANDARD BEN FILE".to_vec();
+    expected.extend(encode_ben_vec_from_assign(vec![1, 1, 2, 2]));
+    expected.extend(encode_ben_vec_from_assign(vec![2, 1, 2, 2]));
+
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn test_jsonl_encode_ben_from_xz_input() {
+    let input = r#"{"assignment":[1,1,2,2],"sample":1}
+{"assignment":[1,1,2,2],"sample":2}
+{"assignment":[2,1,2,2],"sample":3}
+"#;
+
+    let mut compressed = Vec::new();
+    let mut xz = XzEncoder::new(&mut compressed, 6);
+    xz.write_all(input.as_bytes()).unwrap();
+    xz.finish().unwrap();
+
+    let mut plain_output = Vec::new();
+    jsonl_encode_ben(input.as_bytes(), &mut plain_output, BenVariant::MkvChain).unwrap();
+    let mut xz_output = Vec::new();
+    jsonl_encode_ben(compressed.as_slice(), &mut xz_output, BenVariant::MkvChain).unwrap();
+
+    assert_eq!(xz_output, plain_output);
+
+    // Only one byte at a time is available, like a slow pipe
+    let mut trickled_output = Vec::new();
+    jsonl_encode_ben(
+        BufReader::with_capacity(1, compressed.as_slice()),
+        &mut trickled_output,
+        BenVariant::MkvChain,
+    )
+    .unwrap();
+
+    assert_eq!(trickled_output, plain_output);
+}
+
+#[test]
+fn test_flatten_mkvchain() {
+    let input = r#"{"assignment":[1,1,2,2],"sample":1}
+{"assignment":[1,1,2,2],"sample":2}
+{"assignment":[1,1,2,2],"sample":3}
+{"assignment":[2,1,2,2],"sample":4}
+"#;
+
+    let mut mkv: Vec<u8> = Vec::new();
+    jsonl_encode_ben(input.as_bytes(), &mut mkv, BenVariant::MkvChain).unwrap();
+    let mut standard: Vec<u8> = Vec::new();
+    jsonl_encode_ben(input.as_bytes(), &mut standard, BenVariant::Standard).unwrap();
+
+    let mut flattened: Vec<u8> = Vec::new();
+    flatten_mkvchain(mkv.as_slice(), &mut flattened).unwrap();
+    assert_eq!(flattened, standard);
+
+    let mut copied: Vec<u8> = Vec::new();
+    flatten_mkvchain(standard.as_slice(), &mut copied).unwrap();
+    assert_eq!(copied, standard);
+}
+
+#[test]
+fn test_interleave_ben() {
+    let input_a = r#"{"assignment":[1,1,2,2],"sample":1}
+{"assignment":[1,1,2,2],"sample":2}
+{"assignment":[1,2,2,2],"sample":3}
+"#;
+    let input_b = r#"{"assignment":[3,3,3,3],"sample":1}
+"#;
+
+    let mut ben_a: Vec<u8> = Vec::new();
+    jsonl_encode_ben(input_a.as_bytes(), &mut ben_a, BenVariant::MkvChain).unwrap();
+    let mut ben_b: Vec<u8> = Vec::new();
+    jsonl_encode_ben(input_b.as_bytes(), &mut ben_b, BenVariant::Standard).unwrap();
+
+    let mut output: Vec<u8> = Vec::new();
+    interleave_ben(
+        ben_a.as_slice(),
+        ben_b.as_slice(),
+        &mut output,
+        BenVariant::Standard,
+    )
+    .unwrap();
+
+    let mut expected: Vec<u8> = b"STANDARD BEN FILE".to_vec();
+    for assignment in [
+        vec![1, 1, 2, 2],
+        vec![3, 3, 3, 3],
+        vec![1, 1, 2, 2],
+        vec![1, 2, 2, 2],
+    ] {
+        expected.extend(encode_ben_vec_from_assign(assignment));
+    }
+
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn test_interleave_ben_mismatched_nodes() {
+    let mut ben_a: Vec<u8> = Vec::new();
+    jsonl_encode_ben(
+        r#"{"assignment":[1,1,2,2],"sample":1}"#.as_bytes(),
+        &mut ben_a,
+        BenVariant::Standard,
+    )
+    .unwrap();
+    let mut ben_b: Vec<u8> = Vec::new();
+    jsonl_encode_ben(
+        r#"{"assignment":[1,1,2],"sample":1}"#.as_bytes(),
+        &mut ben_b,
+        BenVariant::Standard,
+    )
+    .unwrap();
+
+    let mut output: Vec<u8> = Vec::new();
+    let result = interleave_ben(
+        ben_a.as_slice(),
+        ben_b.as_slice(),
+        &mut output,
+        BenVariant::MkvChain,
+    );
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_jsonl_encode_ben_integral_floats() {
+    let floats = r#"{"assignment":[1.0,1.0,2.0,2.0],"sample":1}"#;
+    let ints = r#"{"assignment":[1,1,2,2],"sample":1}"#;
+
+    for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+        let mut float_output: Vec<u8> = Vec::new();
+        jsonl_encode_ben(floats.as_bytes(), &mut float_output, variant).unwrap();
+        let mut int_output: Vec<u8> = Vec::new();
+        jsonl_encode_ben(ints.as_bytes(), &mut int_output, variant).unwrap();
+        assert_eq!(float_output, int_output);
+
+        let mut float_output: Vec<u8> = Vec::new();
+        jsonl_encode_xben(floats.as_bytes(), &mut float_output, variant).unwrap();
+        let mut int_output: Vec<u8> = Vec::new();
+        jsonl_encode_xben(ints.as_bytes(), &mut int_output, variant).unwrap();
+        assert_eq!(float_output, int_output);
+    }
+
+    let fractional = r#"{"assignment":[1.5,1.0],"sample":1}"#;
+    let mut output: Vec<u8> = Vec::new();
+    let result = jsonl_encode_ben(fractional.as_bytes(), &mut output, BenVariant::Standard);
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_encode_ben_from_channel() {
+    let samples = vec![
+        vec![1, 1, 2, 2],
+        vec![1, 1, 2, 2],
+        vec![1, 2, 2, 2],
+        vec![1, 2, 2, 2],
+    ];
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let producer_samples = samples.clone();
+    let producer = std::thread::spawn(move || {
+        for sample in producer_samples {
+            tx.send(sample).unwrap();
+        }
+    });
+
+    let mut output: Vec<u8> = Vec::new();
+    encode_ben_from_channel(rx, &mut output, BenVariant::MkvChain).unwrap();
+    producer.join().unwrap();
+
+    let mut expected: Vec<u8> = Vec::new();
+    let mut encoder = BenEncoder::new(&mut expected, BenVariant::MkvChain);
+    for sample in samples {
+        encoder.write_assignment(sample).unwrap();
+    }
+    drop(encoder);
+
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn test_jsonl_encode_xben_with_level() {
+    let input = (1..=20)
+        .map(|i| json!({"assignment": [1, 1, 2, 2, i % 3 + 1], "sample": i}).to_string() + "\n")
+        .collect::<String>();
+
+    let mut expected = Vec::new();
+    jsonl_encode_ben(input.as_bytes(), &mut expected, BenVariant::MkvChain).unwrap();
+
+    for level in [1, 9] {
+        let mut xben = Vec::new();
+        jsonl_encode_xben_with_level(input.as_bytes(), &mut xben, BenVariant::MkvChain, level)
+            .unwrap();
+
+        let mut ben = Vec::new();
+        crate::decode::decode_xben_to_ben(xben.as_slice(), &mut ben).unwrap();
+        assert_eq!(ben, expected);
+
+        let mut xben = Vec::new();
+        ben_encode_xben_with_level(expected.as_slice(), &mut xben, level).unwrap();
+        let mut ben = Vec::new();
+        crate::decode::decode_xben_to_ben(xben.as_slice(), &mut ben).unwrap();
+        assert_eq!(ben, expected);
+    }
+
+    let err = jsonl_encode_xben_with_level(input.as_bytes(), Vec::new(), BenVariant::MkvChain, 10)
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    let err = ben_encode_xben_with_level(expected.as_slice(), Vec::new(), 10).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_xben_encoder_with_lzma_options() {
+    let input = r#"{"assignment":[1,1,2,2],"sample":1}
+{"assignment":[1,1,2,2],"sample":2}
+{"assignment":[2,1,2,2],"sample":3}
+"#;
+
+    let mut options = LzmaOptions::new_preset(1).unwrap();
+    options.dict_size(1 << 16);
+
+    let mut xben = Vec::new();
+    {
+        let mut encoder =
+            XBenEncoder::with_lzma_options(&mut xben, &options, BenVariant::MkvChain).unwrap();
+        for line in input.lines() {
+            encoder
+                .write_json_value(serde_json::from_str(line).unwrap())
+                .unwrap();
+        }
+    }
+
+    let mut ben = Vec::new();
+    crate::decode::decode_xben_to_ben(xben.as_slice(), &mut ben).unwrap();
+
+    let mut expected = Vec::new();
+    jsonl_encode_ben(input.as_bytes(), &mut expected, BenVariant::MkvChain).unwrap();
+    assert_eq!(ben, expected);
+
+    let result = XBenEncoder::with_lzma_options(Vec::new(), &options, BenVariant::Delta);
+    assert_eq!(result.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_jsonl_encode_xben_rejects_delta() {
+    let input = r#"{"assignment": [1, 1, 2], "sample": 1}"#;
+
+    let result = jsonl_encode_xben(input.as_bytes(), Vec::new(), BenVariant::Delta);
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_ben_encoder_finish_returns_writer() {
+    let mut encoder = BenEncoder::new(Vec::new(), BenVariant::MkvChain);
+    encoder.write_assignment(vec![1, 1, 2, 2]).unwrap();
+    encoder.write_assignment(vec![1, 1, 2, 2]).unwrap();
+    encoder.write_assignment(vec![2, 1, 2, 2]).unwrap();
+    let mut buffer = encoder.finish().unwrap();
+
+    let mut expected: Vec<u8> = b"MKVCHAIN BEN FILE".to_vec();
+    expected.extend(encode_ben_vec_from_assign(vec![1, 1, 2, 2]));
+    expected.extend(2u16.to_be_bytes());
+    expected.extend(encode_ben_vec_from_assign(vec![2, 1, 2, 2]));
+    expected.extend(1u16.to_be_bytes());
+    assert_eq!(buffer, expected);
+
+    // The writer can still be used after the encoder is finished
+    buffer.extend(b"footer");
+    assert!(buffer.ends_with(b"footer"));
+
+    let empty = BenEncoder::new(Vec::new(), BenVariant::Standard)
+        .finish()
+        .unwrap();
+    assert_eq!(empty, b"STANDARD BEN FILE".to_vec());
+}
+
+#[test]
+fn test_jsonl_encode_ben_returns_trailer_write_errors() {
+    // Accepts the banner, then fails like a full disk would
+    struct FullDisk {
+        written: usize,
+    }
+
+    impl Write for FullDisk {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.written >= 17 {
+                return Err(io::Error::new(io::ErrorKind::WriteZero, "disk full"));
+            }
+            let n = buf.len().min(17 - self.written);
+            self.written += n;
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // The only frame is still pending when the input runs out, so it is
+    // written as part of finishing the file.
+    let input = r#"{"assignment": [1, 1, 2], "sample": 1}"#;
+    let result = jsonl_encode_ben(
+        input.as_bytes(),
+        FullDisk { written: 0 },
+        BenVariant::MkvChain,
+    );
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::WriteZero);
+}
+
+#[test]
+fn test_ben_encoder_new_with_frame_crc() {
+    let mut output: Vec<u8> = Vec::new();
+    let mut encoder = BenEncoder::new_with_frame_crc(&mut output, BenVariant::Standard);
+    encoder.write_assignment(vec![1, 1, 2, 2]).unwrap();
+    drop(encoder);
+
+    let frame = encode_ben_vec_from_assign(vec![1, 1, 2, 2]);
+    let mut expected: Vec<u8> = b"STANDARD BEN FCRC".to_vec();
+    expected.extend(&frame[..6]);
+    expected.extend(crc32fast::hash(&frame[6..]).to_be_bytes());
+    expected.extend(&frame[6..]);
+
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn test_assignment_to_ben32_matches_json_encoding() {
+    let assignment: Vec<u16> = vec![3, 3, 3, 1, 2, 2, 500];
+    let data = json!({"assignment": assignment, "sample": 1});
+
+    assert_eq!(
+        assignment_to_ben32(&assignment),
+        encode_ben32_line(data, 1).unwrap()
+    );
+    assert_eq!(assignment_to_ben32(&[]), vec![0, 0, 0, 0]);
+}
+
+#[test]
+fn test_encode_matrix_ben() {
+    let flat: Vec<u16> = vec![1, 1, 2, 1, 1, 2, 2, 2, 1];
+
+    let mut output: Vec<u8> = Vec::new();
+    encode_matrix_ben(&flat, 3, &mut output, BenVariant::MkvChain).unwrap();
+
+    let mut expected: Vec<u8> = b"MKVCHAIN BEN FILE".to_vec();
+    expected.extend(encode_ben_vec_from_assign(vec![1, 1, 2]));
+    expected.extend(2u16.to_be_bytes());
+    expected.extend(encode_ben_vec_from_assign(vec![2, 2, 1]));
+    expected.extend(1u16.to_be_bytes());
+    assert_eq!(output, expected);
+
+    let mut output: Vec<u8> = Vec::new();
+    let result = encode_matrix_ben(&flat, 4, &mut output, BenVariant::Standard);
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert!(output.is_empty());
+}
+
+#[test]
+fn test_ben_encoder_with_simplify_min_run() {
+    let mut output: Vec<u8> = Vec::new();
+    let mut encoder = BenEncoder::new(&mut output, BenVariant::MkvChain).with_simplify_min_run(2);
+    encoder
+        .write_assignment(vec![1, 1, 1, 2, 1, 1, 3, 3])
+        .unwrap();
+    encoder
+        .write_assignment(vec![1, 1, 1, 1, 1, 1, 3, 3])
+        .unwrap();
+    drop(encoder);
+
+    let mut expected: Vec<u8> = Vec::new();
+    let mut encoder = BenEncoder::new(&mut expected, BenVariant::MkvChain);
+    encoder
+        .write_assignment(vec![1, 1, 1, 1, 1, 1, 3, 3])
+        .unwrap();
+    encoder
+        .write_assignment(vec![1, 1, 1, 1, 1, 1, 3, 3])
+        .unwrap();
+    drop(encoder);
+
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn test_jsonl_encode_ben_with_progress() {
+    let input = (1..=4)
+        .map(|i| json!({"assignment": [1, 1, i, 2], "sample": i}).to_string() + "\n")
+        .collect::<String>();
+
+    let mut fractions = Vec::new();
+    let mut output: Vec<u8> = Vec::new();
+    jsonl_encode_ben_with_progress(
+        input.as_bytes(),
+        &mut output,
+        BenVariant::MkvChain,
+        input.len() as u64,
+        |f| fractions.push(f),
+    )
+    .unwrap();
+
+    let mut expected: Vec<u8> = Vec::new();
+    jsonl_encode_ben(input.as_bytes(), &mut expected, BenVariant::MkvChain).unwrap();
+    assert_eq!(output, expected);
+
+    assert_eq!(fractions, vec![0.25, 0.5, 0.75, 1.0, 1.0]);
+}
+
+#[test]
+fn test_jsonl_encode_ben_resume() {
+    let input = [1, 1, 2, 2, 2, 3]
+        .iter()
+        .enumerate()
+        .map(|(i, x)| json!({"assignment": [1, 1, *x, 2], "sample": i + 1}).to_string() + "\n")
+        .collect::<String>();
+
+    let mut full: Vec<u8> = Vec::new();
+    jsonl_encode_ben(input.as_bytes(), &mut full, BenVariant::MkvChain).unwrap();
+
+    let mut resumed = Cursor::new(Vec::new());
+    jsonl_encode_ben_with_take(input.as_bytes(), &mut resumed, BenVariant::MkvChain, 4).unwrap();
+    jsonl_encode_ben_resume(input.as_bytes(), &mut resumed, BenVariant::MkvChain, 4).unwrap();
+
+    let decode = |ben: &[u8]| {
+        BenDecoder::new(ben)
+            .unwrap()
+            .flat_map(|record| {
+                let (assignment, count) = record.unwrap();
+                std::iter::repeat_n(assignment, count as usize)
+            })
+            .collect::<Vec<Vec<u16>>>()
+    };
+    assert_eq!(decode(resumed.get_ref()), decode(&full));
+    assert_eq!(decode(&full).len(), 6);
+}
+
+#[test]
+fn test_jsonl_encode_ben_resume_skips_blank_lines() {
+    let input = [1, 2, 3, 4]
+        .iter()
+        .map(|x| json!({"assignment": [1, *x], "sample": x}).to_string() + "\n\n")
+        .collect::<String>();
+
+    let mut resumed = Cursor::new(Vec::new());
+    jsonl_encode_ben_with_take(input.as_bytes(), &mut resumed, BenVariant::Standard, 2).unwrap();
+    jsonl_encode_ben_resume(input.as_bytes(), &mut resumed, BenVariant::Standard, 2).unwrap();
+
+    let samples = BenDecoder::new(resumed.get_ref().as_slice())
+        .unwrap()
+        .map(|record| record.unwrap().0)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        samples,
+        vec![vec![1, 1], vec![1, 2], vec![1, 3], vec![1, 4]]
+    );
+}
+
+#[test]
+fn test_ben_encoder_append_five_to_five() {
+    let samples = (0..10u16).map(|i| vec![1, 1, 2, i / 2]).collect::<Vec<_>>();
+
+    for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+        let mut file = Cursor::new(Vec::new());
+        let mut encoder = BenEncoder::new(&mut file, variant);
+        for sample in samples[..5].iter() {
+            encoder.write_assignment(sample.clone()).unwrap();
+        }
+        encoder.finish().unwrap();
+
+        // Samples 5 and 6 are equal, so the MkvChain run is split across the
+        // two writes but still decodes to the same samples
+        let mut encoder = BenEncoder::append(&mut file, variant).unwrap();
+        for sample in samples[5..].iter() {
+            encoder.write_assignment(sample.clone()).unwrap();
+        }
+        encoder.finish().unwrap();
+
+        let decoded: Vec<Vec<u16>> =
+            expand_counts(BenDecoder::new(file.get_ref().as_slice()).unwrap())
+                .collect::<Result<_>>()
+                .unwrap();
+        assert_eq!(decoded.len(), 10);
+        assert_eq!(decoded, samples);
+    }
+}
+
+#[test]
+fn test_ben_encoder_slice_writes_match_owned_writes() {
+    let mut rng = ChaCha8Rng::seed_from_u64(42);
+    let samples: Vec<Vec<u16>> = (0..50)
+        .map(|i| {
+            if i % 5 == 0 {
+                vec![1; 40]
+            } else {
+                (0..40).map(|_| rng.gen_range(1..4)).collect()
+            }
+        })
+        .collect();
+
+    for variant in [
+        BenVariant::Standard,
+        BenVariant::MkvChain,
+        BenVariant::Delta,
+    ] {
+        let mut owned = BenEncoder::new(Vec::new(), variant);
+        let mut borrowed = BenEncoder::new(Vec::new(), variant);
+        let mut owned_rle = BenEncoder::new(Vec::new(), variant);
+        let mut borrowed_rle = BenEncoder::new(Vec::new(), variant);
+
+        let mut scratch = Vec::new();
+        for sample in samples.iter() {
+            owned.write_assignment(sample.clone()).unwrap();
+            owned_rle.write_rle(assign_to_rle(sample.clone())).unwrap();
+
+            scratch.clear();
+            scratch.extend_from_slice(sample);
+            borrowed.write_assignment_slice(&scratch).unwrap();
+            borrowed_rle
+                .write_rle_slice(&assign_to_rle(sample.clone()))
+                .unwrap();
+        }
+
+        let expected = owned.finish().unwrap();
+        assert_eq!(borrowed.finish().unwrap(), expected);
+        assert_eq!(owned_rle.finish().unwrap(), expected);
+        assert_eq!(borrowed_rle.finish().unwrap(), expected);
+    }
+}
+
+#[test]
+fn test_ben_encoder_append_rejects_mismatched_variant() {
+    let mut file = Cursor::new(Vec::new());
+    BenEncoder::new(&mut file, BenVariant::Standard)
+        .write_assignment(vec![1, 2])
+        .unwrap();
+
+    let err = BenEncoder::append(&mut file, BenVariant::MkvChain)
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    assert!(BenEncoder::append(&mut file, BenVariant::Delta).is_err());
+
+    let mut garbage = Cursor::new(b"NOT A BEN FILE AT ALL".to_vec());
+    assert!(BenEncoder::append(&mut garbage, BenVariant::Standard).is_err());
+    assert_eq!(garbage.get_ref(), b"NOT A BEN FILE AT ALL");
+}
+
+#[test]
+fn test_ben_encode_xben_with_report() {
+    let input = (1..=50)
+        .map(|i| json!({"assignment": [1, 1, i % 3, 2, 2, 2], "sample": i}).to_string() + "\n")
+        .collect::<String>();
+
+    let mut ben: Vec<u8> = Vec::new();
+    jsonl_encode_ben(input.as_bytes(), &mut ben, BenVariant::Standard).unwrap();
+
+    let mut xben: Vec<u8> = Vec::new();
+    let report = ben_encode_xben_with_report(ben.as_slice(), &mut xben).unwrap();
+
+    assert_eq!(report.ben_bytes, ben.len() as u64);
+    assert_eq!(report.xben_bytes, xben.len() as u64);
+    assert_eq!(report.ratio(), ben.len() as f64 / xben.len() as f64);
+
+    let mut plain: Vec<u8> = Vec::new();
+    ben_encode_xben(ben.as_slice(), &mut plain).unwrap();
+    assert_eq!(plain, xben);
+}
+
+#[test]
+fn test_jsonl_encode_ben_object_assignments() {
+    let input = r#"{"assignment": {"0": 1, "1": 1, "2": 2, "3": 2}, "sample": 1}
+{"assignment": {"3": 1, "2": 2, "1": 1, "0": 1}, "sample": 2}
+"#;
+    let array_input = r#"{"assignment": [1, 1, 2, 2], "sample": 1}
+{"assignment": [1, 1, 2, 1], "sample": 2}
+"#;
+
+    let mut output: Vec<u8> = Vec::new();
+    jsonl_encode_ben(input.as_bytes(), &mut output, BenVariant::Standard).unwrap();
+    let mut expected: Vec<u8> = Vec::new();
+    jsonl_encode_ben(array_input.as_bytes(), &mut expected, BenVariant::Standard).unwrap();
+    assert_eq!(output, expected);
+
+    let mut output: Vec<u8> = Vec::new();
+    jsonl_encode_xben(input.as_bytes(), &mut output, BenVariant::Standard).unwrap();
+    let mut expected: Vec<u8> = Vec::new();
+    jsonl_encode_xben(array_input.as_bytes(), &mut expected, BenVariant::Standard).unwrap();
+    assert_eq!(output, expected);
+
+    let gap = r#"{"assignment": {"0": 1, "2": 2}, "sample": 1}"#;
+    let err = jsonl_encode_ben(gap.as_bytes(), Vec::new(), BenVariant::Standard).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_jsonl_encode_ben_subsample() {
+    let input = (1..=10)
+        .map(|i| json!({"assignment": [1, 1, i, 2], "sample": i}).to_string() + "\n")
+        .collect::<String>();
+    let expected_input = [3, 6, 9]
+        .iter()
+        .map(|i| json!({"assignment": [1, 1, i, 2], "sample": i}).to_string() + "\n")
+        .collect::<String>();
+
+    let mut expected: Vec<u8> = Vec::new();
+    jsonl_encode_ben(
+        expected_input.as_bytes(),
+        &mut expected,
+        BenVariant::MkvChain,
+    )
+    .unwrap();
+
+    let mut output: Vec<u8> = Vec::new();
+    jsonl_encode_ben_subsample(
+        input.as_bytes(),
+        &mut output,
+        BenVariant::MkvChain,
+        Selection::Every(3),
+    )
+    .unwrap();
+    assert_eq!(output, expected);
+
+    let mut output: Vec<u8> = Vec::new();
+    jsonl_encode_ben_subsample(
+        input.as_bytes(),
+        &mut output,
+        BenVariant::MkvChain,
+        Selection::Indices(vec![9, 3, 6]),
+    )
+    .unwrap();
+    assert_eq!(output, expected);
+
+    let mut expected_xben: Vec<u8> = Vec::new();
+    jsonl_encode_xben(
+        expected_input.as_bytes(),
+        &mut expected_xben,
+        BenVariant::Standard,
+    )
+    .unwrap();
+    let mut output: Vec<u8> = Vec::new();
+    jsonl_encode_xben_subsample(
+        input.as_bytes(),
+        &mut output,
+        BenVariant::Standard,
+        Selection::Every(3),
+    )
+    .unwrap();
+    assert_eq!(output, expected_xben);
+
+    assert!(jsonl_encode_ben_subsample(
+        input.as_bytes(),
+        Vec::new(),
+        BenVariant::Standard,
+        Selection::Every(0),
+    )
+    .is_err());
+}
+
+#[test]
+fn test_sort_ben_by() {
+    let input = r#"{"assignment":[2,2,2,1],"sample":1}
+{"assignment":[2,2,2,1],"sample":2}
+{"assignment":[1,1,1,1],"sample":3}
+{"assignment":[1,2,1,2],"sample":4}
+{"assignment":[2,2,1,1],"sample":5}
+"#;
+
+    let mut ben: Vec<u8> = Vec::new();
+    jsonl_encode_ben(input.as_bytes(), &mut ben, BenVariant::MkvChain).unwrap();
+
+    // Sort by the number of nodes assigned to district 2
+    let key = |assignment: &[u16]| assignment.iter().filter(|&&x| x == 2).count() as f64;
+
+    let mut output: Vec<u8> = Vec::new();
+    sort_ben_by(Cursor::new(ben), &mut output, key, BenVariant::MkvChain).unwrap();
+
+    let samples: Vec<Vec<u16>> = expand_counts(BenDecoder::new(output.as_slice()).unwrap())
+        .collect::<Result<_>>()
+        .unwrap();
+    assert_eq!(
+        samples,
+        vec![
+            vec![1, 1, 1, 1],
+            vec![1, 2, 1, 2],
+            vec![2, 2, 1, 1],
+            vec![2, 2, 2, 1],
+            vec![2, 2, 2, 1],
+        ]
+    );
+    assert!(output.starts_with(b"MKVCHAIN BEN FILE"));
+}
+
+#[test]
+fn test_ben_frame_len() {
+    let mut rng = ChaCha8Rng::seed_from_u64(7);
+    let mut assignments = vec![vec![0], vec![1, 1, 1], vec![300; 20], vec![1, 2, 3, 4, 5]];
+    for _ in 0..50 {
+        let n = rng.gen_range(1..200);
+        let max_label = rng.gen_range(1..2000);
+        assignments.push((0..n).map(|_| rng.gen_range(0..max_label)).collect());
+    }
+
+    for assign in assignments {
+        assert_eq!(
+            ben_frame_len(&assign),
+            encode_ben_vec_from_assign(assign.clone()).len()
+        );
+    }
+
+    assert_eq!(ben_frame_trailer_len(BenVariant::Standard), 0);
+    assert_eq!(ben_frame_trailer_len(BenVariant::MkvChain), 2);
+}
+
+#[test]
+fn test_partition_ben_by() {
+    let input = r#"{"assignment":[1,1,2,2],"sample":1}
+{"assignment":[1,1,2,2],"sample":2}
+{"assignment":[1,2,2,2],"sample":3}
+{"assignment":[1,1,2,2],"sample":4}
+{"assignment":[2,2,2,2],"sample":5}
+"#;
+    let mut ben: Vec<u8> = Vec::new();
+    jsonl_encode_ben(input.as_bytes(), &mut ben, BenVariant::MkvChain).unwrap();
+
+    // Partition by the number of nodes assigned to district 2
+    let mut opened = Vec::new();
+    let outputs = partition_ben_by(
+        ben.as_slice(),
+        |assignment| assignment.iter().filter(|&&x| x == 2).count(),
+        |&key| {
+            opened.push(key);
+            Vec::new()
+        },
+        BenVariant::MkvChain,
+    )
+    .unwrap();
+    assert_eq!(opened, vec![2, 3, 4]);
+
+    let decode = |ben: &Vec<u8>| -> Vec<Vec<u16>> {
+        expand_counts(BenDecoder::new(ben.as_slice()).unwrap())
+            .collect::<Result<_>>()
+            .unwrap()
+    };
+    assert_eq!(outputs.len(), 3);
+    assert_eq!(decode(&outputs[&2]), vec![vec![1, 1, 2, 2]; 3]);
+    assert_eq!(decode(&outputs[&3]), vec![vec![1, 2, 2, 2]]);
+    assert_eq!(decode(&outputs[&4]), vec![vec![2, 2, 2, 2]]);
+    assert!(outputs[&2].starts_with(b"MKVCHAIN BEN FILE"));
+}
+
+#[test]
+fn test_merge_ben_files() {
+    let shard = |offset: u16| {
+        let input = (0..10)
+            .map(|i| {
+                json!({"assignment": [1, 2, offset + i / 2], "sample": i + 1}).to_string() + "\n"
+            })
+            .collect::<String>();
+        let mut ben: Vec<u8> = Vec::new();
+        jsonl_encode_ben(input.as_bytes(), &mut ben, BenVariant::MkvChain).unwrap();
+        ben
+    };
+    let decode = |ben: &[u8]| -> Vec<Vec<u16>> {
+        expand_counts(BenDecoder::new(ben).unwrap())
+            .collect::<Result<_>>()
+            .unwrap()
+    };
+
+    let (a, b) = (shard(0), shard(10));
+    for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+        let inputs: Vec<Box<dyn BufRead>> = vec![
+            Box::new(Cursor::new(a.clone())),
+            Box::new(Cursor::new(b.clone())),
+        ];
+        let mut output: Vec<u8> = Vec::new();
+        merge_ben_files(inputs, &mut output, variant).unwrap();
+
+        let samples = decode(&output);
+        assert_eq!(samples.len(), 20);
+        assert_eq!(samples, [decode(&a), decode(&b)].concat());
+    }
+}
+
+#[test]
+fn test_merge_ben_files_boundary() {
+    let encode = |input: &str| {
+        let mut ben: Vec<u8> = Vec::new();
+        jsonl_encode_ben(input.as_bytes(), &mut ben, BenVariant::MkvChain).unwrap();
+        ben
+    };
+    let a = encode("{\"assignment\":[1,2],\"sample\":1}\n{\"assignment\":[2,2],\"sample\":2}\n");
+    let b = encode("{\"assignment\":[2,2],\"sample\":1}\n{\"assignment\":[1,1],\"sample\":2}\n");
+
+    let merge = |coalesce: bool| {
+        let inputs: Vec<Box<dyn BufRead>> = vec![
+            Box::new(Cursor::new(a.clone())),
+            Box::new(Cursor::new(b.clone())),
+        ];
+        let mut output: Vec<u8> = Vec::new();
+        merge_ben_files_with_coalesce(inputs, &mut output, BenVariant::MkvChain, coalesce).unwrap();
+        BenDecoder::new(output.as_slice())
+            .unwrap()
+            .map(|record| record.unwrap().1)
+            .collect::<Vec<u16>>()
+    };
+
+    assert_eq!(merge(true), vec![1, 2, 1]);
+    assert_eq!(merge(false), vec![1, 1, 1, 1]);
+}
+
+#[test]
+fn test_merge_ben_files_truncated_huge_frame() {
+    // A frame header claiming almost 4GB of payload followed by two bytes
+    let mut corrupt = b"STANDARD BEN FILE".to_vec();
+    corrupt.extend([2, 2, 0xFF, 0xFF, 0xFF, 0xF0, 0x5A, 0x5A]);
+
+    let inputs: Vec<Box<dyn BufRead>> = vec![Box::new(Cursor::new(corrupt))];
+    let mut output: Vec<u8> = Vec::new();
+    let err = merge_ben_files(inputs, &mut output, BenVariant::Standard).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn test_jsonl_encode_auto() {
+    let decode = |ben: &[u8]| -> Vec<Vec<u16>> {
+        expand_counts(BenDecoder::new(ben).unwrap())
+            .collect::<Result<_>>()
+            .unwrap()
+    };
+
+    // Every sample is repeated three times, so MkvChain wins
+    let repeated = (0..1500)
+        .map(|i: u16| json!({"assignment": [1, 2, i / 3], "sample": i + 1}).to_string() + "\n")
+        .collect::<String>();
+    let mut output: Vec<u8> = Vec::new();
+    let variant = jsonl_encode_auto(repeated.as_bytes(), &mut output).unwrap();
+    assert_eq!(variant, BenVariant::MkvChain);
+    assert!(output.starts_with(b"MKVCHAIN BEN FILE"));
+    let samples = decode(&output);
+    assert_eq!(samples.len(), 1500);
+    assert_eq!(samples[1499], vec![1, 2, 499]);
+
+    // No sample is repeated, so the repetition counts would be wasted
+    let distinct = (0..20)
+        .map(|i: u16| json!({"assignment": [1, 2, i], "sample": i + 1}).to_string() + "\n")
+        .collect::<String>();
+    let mut output: Vec<u8> = Vec::new();
+    let variant = jsonl_encode_auto(distinct.as_bytes(), &mut output).unwrap();
+    assert_eq!(variant, BenVariant::Standard);
+    assert_eq!(decode(&output).len(), 20);
+
+    // Blank lines (e.g. a trailing newline) are not samples
+    let mut output: Vec<u8> = Vec::new();
+    jsonl_encode_auto((distinct.clone() + "\n").as_bytes(), &mut output).unwrap();
+    assert_eq!(decode(&output).len(), 20);
+}
+
+#[test]
+fn test_assert_jsonl_roundtrips() {
+    let mut rng = ChaCha8Rng::seed_from_u64(42);
+    let mut input = String::new();
+    for i in 0..100 {
+        let assignment: Vec<u16> = (0..200).map(|_| rng.gen_range(1..8)).collect();
+        input.push_str(&format!(
+            "{}\n",
+            json!({"assignment": assignment, "sample": i + 1})
+        ));
+        if i % 10 == 0 {
+            input.push_str(&format!(
+                "{}\n",
+                json!({"assignment": assignment, "sample": i + 1})
+            ));
+        }
+    }
+
+    for variant in [
+        BenVariant::Standard,
+        BenVariant::MkvChain,
+        BenVariant::Delta,
+    ] {
+        assert_jsonl_roundtrips(input.as_bytes(), variant).unwrap();
+    }
+
+    // Blank lines (e.g. a trailing newline) are not samples
+    assert_jsonl_roundtrips((input + "\n").as_bytes(), BenVariant::Standard).unwrap();
+
+    let err = assert_jsonl_roundtrips("not json\n".as_bytes(), BenVariant::Standard).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_missing_assignment_key_is_an_error_not_a_panic() {
+    let input = "{\"assignment\": [1, 2], \"sample\": 1}\n{\"foo\": 1}\n";
+
+    let err = jsonl_encode_ben(input.as_bytes(), Vec::new(), BenVariant::Standard).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("Sample 2"));
+
+    let err = jsonl_encode_xben(input.as_bytes(), Vec::new(), BenVariant::Standard).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("Sample 2"));
+
+    let mut encoder = BenEncoder::new(Vec::new(), BenVariant::MkvChain);
+    let err = encoder
+        .write_json_value(json!({"assignment": [1, -2, 3]}))
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("Sample 1"));
+    assert!(encoder
+        .write_json_value(json!({"assignment": [1, 2.5]}))
+        .is_err());
+    assert!(encoder
+        .write_json_value(json!({"assignment": "1, 2"}))
+        .is_err());
+}
+
+#[test]
+fn test_jsonl_assignments() {
+    let input = "{\"assignment\": [1, 1, 2], \"sample\": 1}\n{\"assignment\": [2, 2, 1], \"sample\": 2}\n\n";
+
+    let samples = JsonlAssignments::new(input.as_bytes())
+        .collect::<Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(samples, vec![(vec![1, 1, 2], 1), (vec![2, 2, 1], 2)]);
+
+    // Lines without a sample number are numbered by position
+    let input = "{\"assignment\": [1, 2]}\n{\"assignment\": [2, 1]}\n";
+    let samples = JsonlAssignments::new(input.as_bytes())
+        .map(|sample| sample.unwrap().1)
+        .collect::<Vec<_>>();
+    assert_eq!(samples, vec![1, 2]);
+
+    let input = "{\"assignment\": [1, 2]}\n{\"assignment\": [2,\n";
+    let mut assignments = JsonlAssignments::new(input.as_bytes());
+    assert!(assignments.next().unwrap().is_ok());
+    let err = assignments.next().unwrap().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(err.to_string().starts_with("Sample 2"));
+}
+
+#[test]
+fn test_write_matrix_dedups_across_batches() {
+    let first_batch = [1, 1, 2, 2, 1, 2, 1, 2, 2, 2, 1, 1];
+    let second_batch = [2, 2, 1, 1, 2, 2, 1, 1, 1, 2, 1, 2];
+
+    let mut encoder = BenEncoder::new(Vec::new(), BenVariant::MkvChain);
+    encoder.write_matrix(&first_batch, 4).unwrap();
+    encoder.write_matrix(&second_batch, 4).unwrap();
+    let ben = encoder.finish().unwrap();
+
+    let flat = [first_batch, second_batch].concat();
+    let mut expected = Vec::new();
+    encode_matrix_ben(&flat, 4, &mut expected, BenVariant::MkvChain).unwrap();
+    assert_eq!(ben, expected);
+
+    let records = BenDecoder::new(ben.as_slice())
+        .unwrap()
+        .map(|record| record.unwrap().1)
+        .collect::<Vec<_>>();
+    assert_eq!(records, vec![1, 1, 3, 1]);
+
+    let mut encoder = BenEncoder::new(Vec::new(), BenVariant::MkvChain);
+    let err = encoder.write_matrix(&first_batch, 5).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn test_ben_xben_roundtrip() {
+    let input = std::fs::read_to_string(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/example/small_example.jsonl"
+    ))
+    .unwrap();
+
+    for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+        let mut ben = Vec::new();
+        jsonl_encode_ben(input.as_bytes(), &mut ben, variant).unwrap();
+
+        let report = ben_xben_roundtrip(ben.as_slice()).unwrap();
+        assert!(report.passed());
+        assert_eq!(report.compression.ben_bytes, ben.len() as u64);
+    }
+
+    // Frames for [1, 1] that use 2 bits for the value where 1 would do
+    // decode to the same samples, but are not re-encoded the same way
+    let mut ben = b"STANDARD BEN FILE".to_vec();
+    for _ in 0..3 {
+        ben.extend([2, 2, 0, 0, 0, 1, 0x60]);
+    }
+    let report = ben_xben_roundtrip(ben.as_slice()).unwrap();
+    assert_eq!(report.mismatch_offset, Some(17));
+    assert!(!report.passed());
+
+    let mut delta = BenEncoder::new(Vec::new(), BenVariant::Delta);
+    delta.write_assignment(vec![1, 1, 2]).unwrap();
+    delta.write_assignment(vec![1, 2, 2]).unwrap();
+    let delta = delta.finish().unwrap();
+    assert!(ben_xben_roundtrip(delta.as_slice()).is_err());
+}
+
+#[test]
+fn test_filter_ben_keeps_whole_frames() {
+    // Samples with a 0 label are unassigned and get dropped
+    let samples: Vec<Vec<u16>> = vec![
+        vec![1, 1, 2],
+        vec![1, 1, 2],
+        vec![0, 1, 2],
+        vec![0, 1, 2],
+        vec![0, 1, 2],
+        vec![1, 2, 2],
+        vec![1, 0, 2],
+        vec![1, 2, 2],
+    ];
+    let mut input = BenEncoder::new(Vec::new(), BenVariant::MkvChain);
+    for sample in samples.iter() {
+        input.write_assignment_slice(sample).unwrap();
+    }
+    let input = input.finish().unwrap();
+
+    for variant in [BenVariant::Standard, BenVariant::MkvChain] {
+        let mut output = Vec::new();
+        let mut n_calls = 0;
+        let n_kept = filter_ben(
+            input.as_slice(),
+            &mut output,
+            |assignment| {
+                n_calls += 1;
+                !assignment.contains(&0)
+            },
+            variant,
+        )
+        .unwrap();
+        assert_eq!(n_kept, 4);
+        assert_eq!(n_calls, 5);
+
+        let records = BenDecoder::new(output.as_slice())
+            .unwrap()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        let expected = match variant {
+            // The two [1, 2, 2] samples are adjacent once [1, 0, 2] is gone
+            BenVariant::MkvChain => vec![(vec![1, 1, 2], 2), (vec![1, 2, 2], 2)],
+            _ => vec![
+                (vec![1, 1, 2], 1),
+                (vec![1, 1, 2], 1),
+                (vec![1, 2, 2], 1),
+                (vec![1, 2, 2], 1),
+            ],
+        };
+        assert_eq!(records, expected);
+    }
+}
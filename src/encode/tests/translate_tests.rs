@@ -8,11 +8,11 @@ use std::io::BufRead;
 
 fn jsonl_encode_ben32<R: BufRead, W: Write>(reader: R, mut writer: W) -> std::io::Result<()> {
     writer.write_all("STANDARD BEN FILE".as_bytes())?;
-    for line_result in reader.lines() {
+    for (i, line_result) in reader.lines().enumerate() {
         let line = line_result?; // Handle potential I/O errors for each line
         let data: Value = serde_json::from_str(&line).expect("Error parsing JSON from line");
 
-        writer.write_all(&encode_ben32_line(data))?;
+        writer.write_all(&encode_ben32_line(data, i + 1)?)?;
     }
     Ok(())
 }
@@ -231,3 +231,44 @@ fn test_random_translation_ben_to_ben32() {
 
     assert_eq!(writer, &buffer);
 }
+
+#[test]
+fn test_ben32_to_lp32_line() {
+    let ben32 = assignment_to_ben32(&[1, 1, 2, 2, 2]);
+    let lp32 = ben32_to_lp32_line(&ben32);
+
+    assert_eq!(lp32, vec![0, 0, 0, 8, 0, 1, 0, 2, 0, 2, 0, 3]);
+    assert_eq!(
+        read_lp32_line(&mut lp32.as_slice()).unwrap(),
+        Some(vec![(1, 2), (2, 3)])
+    );
+    assert_eq!(read_lp32_line(&mut [].as_slice()).unwrap(), None);
+    assert!(read_lp32_line(&mut [0, 0, 0, 3, 1, 2, 3].as_slice()).is_err());
+}
+
+#[test]
+fn test_read_lp32_line_truncated_huge_length() {
+    // A corrupt length of almost 4GB followed by a single run
+    let corrupt = [0xFF, 0xFF, 0xFF, 0xFC, 0, 1, 0, 2];
+
+    let err = read_lp32_line(&mut corrupt.as_slice()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn test_read_ben32_frame() {
+    let mut ben32 = assignment_to_ben32(&[1, 1, 2, 2, 2]);
+    ben32.extend(assignment_to_ben32(&[4]));
+    let mut reader = ben32.as_slice();
+
+    assert_eq!(
+        read_ben32_frame(&mut reader).unwrap(),
+        Some(vec![(1, 2), (2, 3)])
+    );
+    assert_eq!(read_ben32_frame(&mut reader).unwrap(), Some(vec![(4, 1)]));
+    assert_eq!(read_ben32_frame(&mut reader).unwrap(), None);
+
+    // A frame that breaks off before its separator
+    let err = read_ben32_frame(&mut [0, 1, 0, 2, 0, 0].as_slice()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+}
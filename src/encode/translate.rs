@@ -3,6 +3,11 @@
 //! encoding of an assignment vector done at the byte level and for which every
 //! 32 bits of data encodes a one (assignment, count) pair. The BEN format is
 //! a bit-packed version of the ben32 format along with some extra headers.
+//!
+//! There is also a length-prefixed flavor of ben32 (marked with a `LP32`
+//! banner) in which every assignment vector is preceded by its length in
+//! bytes instead of being terminated by a separator. This lets a reader skip
+//! over an assignment vector without having to scan it.
 use byteorder::{BigEndian, ReadBytesExt};
 use std::io::{self, Error, Read, Write};
 
@@ -10,6 +15,10 @@ use super::{log, logln, BenVariant};
 use crate::decode::decode_ben_line;
 use crate::encode::encode_ben_vec_from_rle;
 
+/// The largest number of runs (or delta frame changes) that a reader reserves
+/// room for before it has actually read them.
+pub(crate) const MAX_PREALLOCATED_RUNS: usize = 1 << 16;
+
 /// This function takes a ben32 encoded assignment vector and
 /// transforms into a ben encoded assignment vector.
 ///
@@ -212,6 +221,12 @@ pub fn ben_to_ben32_lines<R: Read, W: Write>(
                 writer.write_all(&ben32_vec)?;
                 writer.write_all(&n_reps.to_be_bytes())?;
             }
+            BenVariant::Delta => {
+                return Err(Error::new(
+                    io::ErrorKind::Unsupported,
+                    "The ben32 format does not support the delta variant of BEN files",
+                ));
+            }
         }
     }
 
@@ -220,6 +235,138 @@ pub fn ben_to_ben32_lines<R: Read, W: Write>(
     Ok(())
 }
 
+/// This function takes a ben32 encoded assignment vector (including the
+/// end of line separator) and transforms it into a length-prefixed ben32
+/// encoded assignment vector. The separator is dropped and a u32 holding
+/// the number of bytes in the run-length pairs is put in front.
+///
+/// # Arguments
+///
+/// * `ben32_vec` - A slice of bytes containing the ben32 encoded assignment vector
+///
+/// # Returns
+///
+/// A vector of bytes containing the length-prefixed ben32 encoded assignment vector
+pub fn ben32_to_lp32_line(ben32_vec: &[u8]) -> Vec<u8> {
+    let pairs = ben32_vec.strip_suffix(&[0u8; 4]).unwrap_or(ben32_vec);
+
+    let mut lp32_vec = Vec::with_capacity(pairs.len() + 4);
+    lp32_vec.extend((pairs.len() as u32).to_be_bytes());
+    lp32_vec.extend(pairs);
+    lp32_vec
+}
+
+/// This function reads a single length-prefixed ben32 encoded assignment
+/// vector from the reader and returns its run-length encoding.
+///
+/// # Arguments
+///
+/// * `reader` - A reader positioned at the start of a length-prefixed frame
+///
+/// # Returns
+///
+/// The run-length encoded assignment vector, or `None` if the reader was
+/// already at the end of the stream.
+///
+/// # Errors
+///
+/// This function will return an error if the length of the frame is not a
+/// multiple of 4 or if the stream ends in the middle of the frame.
+pub fn read_lp32_line<R: Read>(reader: &mut R) -> io::Result<Option<Vec<(u16, u16)>>> {
+    let mut len_buffer = [0u8; 4];
+    match reader.read_exact(&mut len_buffer) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let n_bytes = u32::from_be_bytes(len_buffer) as usize;
+    if !n_bytes.is_multiple_of(4) {
+        return Err(Error::new(
+            io::ErrorKind::InvalidData,
+            "Invalid length-prefixed ben32 data length",
+        ));
+    }
+
+    // The length comes straight from the file, so a corrupt header should not
+    // be able to make us allocate gigabytes up front.
+    let mut rle = Vec::with_capacity((n_bytes / 4).min(MAX_PREALLOCATED_RUNS));
+    let mut buffer = [0u8; 4];
+    for _ in 0..n_bytes / 4 {
+        reader.read_exact(&mut buffer)?;
+        let encoded = u32::from_be_bytes(buffer);
+        rle.push(((encoded >> 16) as u16, (encoded & 0xFFFF) as u16));
+    }
+
+    Ok(Some(rle))
+}
+
+/// This function reads a single separator framed ben32 encoded assignment
+/// vector from the reader, up to and including its terminating `00 00 00 00`
+/// word, and returns its run-length encoding.
+///
+/// # Arguments
+///
+/// * `reader` - A reader positioned at the start of a ben32 frame
+///
+/// # Returns
+///
+/// The run-length encoded assignment vector, or `None` if the reader was
+/// already at the end of the stream.
+///
+/// # Errors
+///
+/// This function will return an `UnexpectedEof` error if the stream ends
+/// before the separator of the frame.
+pub fn read_ben32_frame<R: Read>(reader: &mut R) -> io::Result<Option<Vec<(u16, u16)>>> {
+    let mut rle = Vec::new();
+    let mut buffer = [0u8; 4];
+    loop {
+        match reader.read_exact(&mut buffer) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && rle.is_empty() => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        }
+
+        let encoded = u32::from_be_bytes(buffer);
+        if encoded == 0 {
+            return Ok(Some(rle));
+        }
+        rle.push(((encoded >> 16) as u16, (encoded & 0xFFFF) as u16));
+    }
+}
+
+/// This function takes a reader that contains several length-prefixed ben32
+/// encoded assignment vectors and encodes them into ben encoded assignment
+/// vectors and writes them to the designated writer.
+///
+/// # Arguments
+///
+/// * `reader` - A reader that contains length-prefixed ben32 encoded assignment vectors
+/// * `writer` - A writer that will contain the ben encoded assignment vectors
+/// * `variant` - The variant of the BEN file that the data came from
+///
+/// # Returns
+///
+/// An io::Result containing the result of the operation
+pub fn lp32_to_ben_lines<R: Read, W: Write>(
+    mut reader: R,
+    mut writer: W,
+    variant: BenVariant,
+) -> io::Result<()> {
+    while let Some(rle) = read_lp32_line(&mut reader)? {
+        writer.write_all(&encode_ben_vec_from_rle(rle))?;
+        if variant == BenVariant::MkvChain {
+            let n_reps = reader.read_u16::<BigEndian>()?;
+            writer.write_all(&n_reps.to_be_bytes())?;
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 #[path = "tests/translate_tests.rs"]
 mod tests;
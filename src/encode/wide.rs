@@ -0,0 +1,198 @@
+//! This module contains the functions for writing "wide" BEN files, whose
+//! assignment labels are 32 bits wide rather than 16. These are needed for
+//! ensembles of very large graphs in which the labels (e.g. block IDs) do not
+//! fit in a u16.
+//!
+//! Wide files use the same frame layout as regular BEN files. The only
+//! differences are that the bit width of the values stored in each frame
+//! header may be as large as 32 and that the banner is `STANDARD BEN WIDE`
+//! or `MKVCHAIN BEN WIDE`, so that the decoder knows to hand back u32
+//! labels.
+
+use serde_json::Value;
+use std::io::{self, BufRead, Result, Write};
+
+use super::{log, logln, BenVariant};
+
+/// Returns the banner of a wide BEN file of the given variant.
+///
+/// # Errors
+///
+/// Returns an `Unsupported` error for the delta variant, which has no wide
+/// counterpart.
+pub(crate) fn wide_banner(variant: BenVariant) -> Result<&'static [u8; 17]> {
+    match variant {
+        BenVariant::Standard => Ok(b"STANDARD BEN WIDE"),
+        BenVariant::MkvChain => Ok(b"MKVCHAIN BEN WIDE"),
+        BenVariant::Delta => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Wide BEN files do not support the delta variant",
+        )),
+    }
+}
+
+/// Convert a vector of u32 assignments to a run-length encoded vector.
+/// Runs longer than `u16::MAX` are split.
+pub fn assign_to_rle_u32(assign_vec: &[u32]) -> Vec<(u32, u16)> {
+    let mut rle_vec: Vec<(u32, u16)> = Vec::new();
+    for &val in assign_vec {
+        match rle_vec.last_mut() {
+            Some((prev, len)) if *prev == val && *len < u16::MAX => *len += 1,
+            _ => rle_vec.push((val, 1)),
+        }
+    }
+    rle_vec
+}
+
+/// This function takes a run-length encoded assignment vector with u32
+/// values and encodes it into a bit-packed BEN frame. The layout is the
+/// same as `encode::encode_ben_vec_from_assign`, except that the values
+/// may use up to 32 bits.
+///
+/// # Arguments
+///
+/// * `rle_vec` - A slice of tuples containing the value and length of each run
+///
+/// # Returns
+///
+/// A vector of bytes containing the bit-packed BEN frame
+pub fn encode_ben_vec_from_rle_u32(rle_vec: &[(u32, u16)]) -> Vec<u8> {
+    let max_val = rle_vec.iter().map(|x| x.0).max().unwrap_or(0);
+    let max_len = rle_vec.iter().map(|x| x.1).max().unwrap_or(0);
+    let max_val_bits: u8 = (32 - max_val.leading_zeros() as u8).max(1);
+    let max_len_bits: u8 = 16 - max_len.leading_zeros() as u8;
+    let total_bits = (max_val_bits as u64 + max_len_bits as u64) * rle_vec.len() as u64;
+    let n_bytes = total_bits.div_ceil(8) as u32;
+
+    let mut output_vec: Vec<u8> = Vec::with_capacity(6 + n_bytes as usize);
+    output_vec.push(max_val_bits);
+    output_vec.push(max_len_bits);
+    output_vec.extend(n_bytes.to_be_bytes());
+
+    let mut buffer: u64 = 0;
+    let mut n_bits_in_buff: u8 = 0;
+    for &(val, len) in rle_vec {
+        for (value, bits) in [(val as u64, max_val_bits), (len as u64, max_len_bits)] {
+            buffer = (buffer << bits) | value;
+            n_bits_in_buff += bits;
+            while n_bits_in_buff >= 8 {
+                n_bits_in_buff -= 8;
+                output_vec.push((buffer >> n_bits_in_buff) as u8);
+            }
+            buffer &= (1u64 << n_bits_in_buff) - 1;
+        }
+    }
+
+    if n_bits_in_buff > 0 {
+        output_vec.push((buffer << (8 - n_bits_in_buff)) as u8);
+    }
+
+    output_vec
+}
+
+/// Convert the `"assignment"` array of a JSONL line into a vector of u32
+/// labels.
+fn json_assignment_to_vec_u32(assignment: &Value) -> Result<Vec<u32>> {
+    let invalid = |value: &Value| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid assignment label {}", value),
+        )
+    };
+
+    match assignment.as_array() {
+        Some(array) => array
+            .iter()
+            .map(|value| {
+                value
+                    .as_u64()
+                    .and_then(|n| u32::try_from(n).ok())
+                    .ok_or_else(|| invalid(value))
+            })
+            .collect(),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Assignment must be an array, found {}", assignment),
+        )),
+    }
+}
+
+/// Writes a wide BEN file containing the given assignment vectors. For the
+/// MkvChain variant, consecutive repeated samples are collapsed into a
+/// single frame followed by the number of repetitions.
+///
+/// # Arguments
+///
+/// * `samples` - The assignment vectors to encode
+/// * `writer` - A writer for the output file
+/// * `variant` - The variant of the BEN format to write (Standard or MkvChain)
+///
+/// # Errors
+///
+/// Returns an error if the variant is `BenVariant::Delta`, if one of the
+/// samples produces an error, or if writing to the writer fails.
+pub fn encode_wide_ben<I, W>(samples: I, mut writer: W, variant: BenVariant) -> Result<()>
+where
+    I: IntoIterator<Item = Result<Vec<u32>>>,
+    W: Write,
+{
+    writer.write_all(wide_banner(variant)?)?;
+
+    let mut previous: Option<(Vec<u8>, u16)> = None;
+    for (i, sample) in samples.into_iter().enumerate() {
+        log!("Encoding line: {}\r", i + 1);
+        let frame = encode_ben_vec_from_rle_u32(&assign_to_rle_u32(&sample?));
+
+        if variant == BenVariant::Standard {
+            writer.write_all(&frame)?;
+            continue;
+        }
+
+        match previous.as_mut() {
+            Some((prev, count)) if *prev == frame && *count < u16::MAX => *count += 1,
+            _ => {
+                if let Some((prev, count)) = previous.take() {
+                    writer.write_all(&prev)?;
+                    writer.write_all(&count.to_be_bytes())?;
+                }
+                previous = Some((frame, 1));
+            }
+        }
+    }
+
+    if let Some((prev, count)) = previous {
+        writer.write_all(&prev)?;
+        writer.write_all(&count.to_be_bytes())?;
+    }
+    logln!();
+    logln!("Done!");
+
+    Ok(())
+}
+
+/// Encodes a JSONL file whose assignment labels may be larger than 65535
+/// into a wide BEN file. The JSONL file is expected to be in the standard
+/// `{"assignment": [...], "sample": #}` format.
+///
+/// # Arguments
+///
+/// * `reader` - A buffered reader for the input file
+/// * `writer` - A writer for the output file
+/// * `variant` - The variant of the BEN format to write (Standard or MkvChain)
+///
+/// # Errors
+///
+/// Returns an error if the variant is `BenVariant::Delta`, if a line is not
+/// valid JSON, or if one of the labels is not a non-negative integer that
+/// fits in a u32.
+pub fn jsonl_encode_ben_wide<R: BufRead, W: Write>(
+    reader: R,
+    writer: W,
+    variant: BenVariant,
+) -> Result<()> {
+    let samples = reader.lines().map(|line| {
+        let data: Value = serde_json::from_str(&line?)?;
+        json_assignment_to_vec_u32(&data["assignment"])
+    });
+    encode_wide_ben(samples, writer, variant)
+}
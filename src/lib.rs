@@ -48,4 +48,8 @@ macro_rules! logln {
 pub enum BenVariant {
     Standard,
     MkvChain,
+    /// The first sample is stored as a regular BEN frame and every
+    /// subsequent sample only stores the `(node_index, new_label)` pairs
+    /// that changed relative to the previous sample.
+    Delta,
 }
@@ -5,9 +5,149 @@
 use super::{log, logln};
 use serde_json::{json, Value};
 use std::collections::HashMap;
-use std::io::{Read, Result, Write};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Result, Write};
+use std::path::Path;
 use std::result::Result as StdResult;
 
+/// Convert a JSON value from an assignment vector into a label.
+///
+/// Labels are expected to be non-negative integers, but some JSONL producers
+/// serialize them as floats (e.g. `1.0`), so integral floats are accepted
+/// as well.
+///
+/// # Arguments
+///
+/// * `value` - The JSON value to convert.
+///
+/// # Returns
+///
+/// The label as a u16.
+///
+/// # Errors
+///
+/// Returns an error if the value is not a number, is negative, has a
+/// fractional part, or is too large to fit in a u16.
+pub fn json_value_to_label(value: &Value) -> Result<u16> {
+    let label = match value.as_u64() {
+        Some(n) => Some(n),
+        None => value
+            .as_f64()
+            .filter(|f| f.fract() == 0.0 && *f >= 0.0)
+            .map(|f| f as u64),
+    };
+
+    match label {
+        Some(n) if n <= u16::MAX as u64 => Ok(n as u16),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid assignment label {}", value),
+        )),
+    }
+}
+
+/// Convert the `"assignment"` field of a JSONL line into an assignment
+/// vector. The assignment may either be an array of labels or an object
+/// mapping node indices to labels (e.g. `{"0": 1, "1": 1, "2": 2}`), which
+/// is what some upstream tools write out. The keys of an object are sorted
+/// as integers to build the vector.
+///
+/// # Arguments
+///
+/// * `assignment` - The JSON value of the `"assignment"` field
+///
+/// # Returns
+///
+/// The assignment vector.
+///
+/// # Errors
+///
+/// Returns an error if one of the labels is invalid (see
+/// `json_value_to_label`), if the assignment is neither an array nor an
+/// object, or if the keys of an object are not the contiguous integers
+/// `0..n`.
+pub fn json_assignment_to_vec(assignment: &Value) -> Result<Vec<u16>> {
+    if let Some(array) = assignment.as_array() {
+        return array.iter().map(json_value_to_label).collect();
+    }
+
+    let object = match assignment.as_object() {
+        Some(object) => object,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Assignment must be an array or an object, found {}",
+                    assignment
+                ),
+            ))
+        }
+    };
+
+    let mut assign_vec: Vec<Option<u16>> = vec![None; object.len()];
+    for (key, value) in object {
+        let index = match key.parse::<usize>() {
+            Ok(index) if index < assign_vec.len() => index,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Invalid assignment key {:?}. Keys must be the integers 0 to {}",
+                        key,
+                        assign_vec.len().saturating_sub(1)
+                    ),
+                ))
+            }
+        };
+        if assign_vec[index].is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Duplicate assignment key {:?}", key),
+            ));
+        }
+        assign_vec[index] = Some(json_value_to_label(value)?);
+    }
+
+    // Every slot is filled since there are as many unique keys as slots
+    Ok(assign_vec.into_iter().map(|x| x.unwrap()).collect())
+}
+
+/// Pull the assignment vector out of a parsed JSONL line, naming the sample
+/// in the error so that users who feed the wrong file can tell where the
+/// problem is.
+///
+/// # Arguments
+///
+/// * `data` - The parsed JSONL line
+/// * `sample` - The number of the sample (starting from 1) used in errors
+///
+/// # Returns
+///
+/// The assignment vector.
+///
+/// # Errors
+///
+/// Returns an `InvalidData` error if the line has no `"assignment"` key or
+/// if `json_assignment_to_vec` rejects the assignment.
+pub fn json_sample_assignment(data: &Value, sample: usize) -> Result<Vec<u16>> {
+    let assignment = match data.get("assignment") {
+        Some(assignment) => assignment,
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Sample {} is missing the \"assignment\" key", sample),
+            ))
+        }
+    };
+
+    json_assignment_to_vec(assignment).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Sample {}: {}", sample, e),
+        )
+    })
+}
+
 /// Convert a vector of assignments to a run-length encoded (RLE) vector.
 ///
 /// # Arguments
@@ -68,6 +208,45 @@ pub fn rle_to_vec(rle_vec: Vec<(u16, u16)>) -> Vec<u16> {
     output_vec
 }
 
+/// Simplify a run-length encoded (RLE) vector by absorbing every run that
+/// is shorter than `min_run` into the run that precedes it.
+///
+/// **This is lossy:** the nodes in the absorbed runs are relabeled to the
+/// value of the preceding run, so the decoded assignment will not match the
+/// original one. A short run at the very start of the vector has nothing to
+/// be absorbed into and is kept as is. Adjacent runs that end up with the
+/// same value are merged.
+///
+/// # Arguments
+///
+/// * `rle_vec` - A vector of tuples where the first element is the value and the second element is
+///   the length of the run.
+/// * `min_run` - The shortest run that will be kept. A value of 0 or 1 leaves the
+///   vector unchanged.
+///
+/// # Returns
+///
+/// The simplified RLE vector. The total length of the runs is unchanged.
+pub fn simplify_rle(rle_vec: Vec<(u16, u16)>, min_run: u16) -> Vec<(u16, u16)> {
+    let mut output_vec: Vec<(u16, u16)> = Vec::new();
+
+    for (val, len) in rle_vec {
+        let last = output_vec.last().copied();
+        match last {
+            Some((last_val, last_len)) if len < min_run || last_val == val => {
+                if last_len <= u16::MAX - len {
+                    output_vec.last_mut().unwrap().1 += len;
+                } else {
+                    // Runs longer than u16::MAX need to be split
+                    output_vec.push((last_val, len));
+                }
+            }
+            _ => output_vec.push((val, len)),
+        }
+    }
+    output_vec
+}
+
 /// Sorts a JSON-formatted NetworkX graph file by a key.
 /// This function will sort the nodes in the graph by the key provided and
 /// then relabel the nodes in the graph from 0 to n-1 where n is the number
@@ -147,6 +326,82 @@ pub fn sort_json_file_by_key<R: Read, W: Write>(
     Ok(node_map)
 }
 
+/// Checks whether two paths point to the same file, e.g. so that a tool can
+/// refuse to write its output over its input. A path that does not exist
+/// yet is never the same as another one.
+///
+/// # Arguments
+///
+/// * `first` - The first path
+/// * `second` - The second path
+///
+/// # Returns
+///
+/// True if both paths exist and resolve to the same file
+pub fn is_same_file<P: AsRef<Path>, Q: AsRef<Path>>(first: P, second: Q) -> bool {
+    match (fs::canonicalize(first), fs::canonicalize(second)) {
+        (Ok(first), Ok(second)) => first == second,
+        _ => false,
+    }
+}
+
+/// Rewrites a file in place. The `transcode` function reads from the file
+/// and writes to a temporary file next to it, which is then renamed over the
+/// original once `transcode` has succeeded, so the original is left
+/// untouched if anything goes wrong along the way. The temporary file takes
+/// on the permissions of the original.
+///
+/// # Arguments
+///
+/// * `path` - The file to rewrite
+/// * `transcode` - Copies the file from the reader to the writer, changing
+///   whatever needs to be changed
+///
+/// # Returns
+///
+/// A Result type that contains the result of the operation
+///
+/// # Errors
+///
+/// This function will return an error if the file cannot be opened, if
+/// `transcode` returns an error, or if the temporary file cannot be written
+/// or renamed. The temporary file is removed in all of these cases.
+pub fn transcode_in_place<P, F>(path: P, transcode: F) -> Result<()>
+where
+    P: AsRef<Path>,
+    F: FnOnce(BufReader<File>, &mut BufWriter<File>) -> Result<()>,
+{
+    let path = path.as_ref();
+    let file_name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{:?} is not a file", path),
+        )
+    })?;
+    let tmp_path = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+
+    let reader = BufReader::new(File::open(path)?);
+    let permissions = fs::metadata(path)?.permissions();
+
+    let result = File::create(&tmp_path).and_then(|tmp_file| {
+        let mut writer = BufWriter::new(tmp_file);
+        transcode(reader, &mut writer)?;
+        let tmp_file = writer.into_inner().map_err(|e| e.into_error())?;
+        tmp_file.sync_all()?;
+        fs::set_permissions(&tmp_path, permissions)?;
+        fs::rename(&tmp_path, path)
+    });
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -169,6 +424,54 @@ mod test {
         assert_eq!(rle_to_vec(rle_vec), result);
     }
 
+    #[test]
+    fn test_simplify_rle() {
+        let rle_vec: Vec<(u16, u16)> = vec![(1, 1), (2, 5), (3, 1), (2, 4), (4, 2), (5, 3)];
+
+        assert_eq!(
+            simplify_rle(rle_vec.clone(), 2),
+            vec![(1, 1), (2, 10), (4, 2), (5, 3)]
+        );
+        assert_eq!(
+            simplify_rle(rle_vec.clone(), 3),
+            vec![(1, 1), (2, 12), (5, 3)]
+        );
+        assert_eq!(simplify_rle(rle_vec.clone(), 0), rle_vec);
+        assert_eq!(
+            simplify_rle(vec![(1, u16::MAX), (2, 1)], 2),
+            vec![(1, u16::MAX), (1, 1)]
+        );
+    }
+
+    #[test]
+    fn test_json_assignment_to_vec() {
+        assert_eq!(
+            json_assignment_to_vec(&json!([1, 1, 2])).unwrap(),
+            vec![1, 1, 2]
+        );
+        assert_eq!(
+            json_assignment_to_vec(&json!({"2": 3, "0": 1, "10": 4, "1": 1, "3": 2, "4": 2,
+                "5": 2, "6": 2, "7": 2, "8": 2, "9": 2}))
+            .unwrap(),
+            vec![1, 1, 3, 2, 2, 2, 2, 2, 2, 2, 4]
+        );
+        assert!(json_assignment_to_vec(&json!({"0": 1, "2": 1})).is_err());
+        assert!(json_assignment_to_vec(&json!({"0": 1, "a": 1})).is_err());
+        assert!(json_assignment_to_vec(&json!({"0": 1, "00": 1})).is_err());
+        assert!(json_assignment_to_vec(&json!({"0": 1.5})).is_err());
+        assert!(json_assignment_to_vec(&json!(5)).is_err());
+    }
+
+    #[test]
+    fn test_json_value_to_label() {
+        assert_eq!(json_value_to_label(&json!(3)).unwrap(), 3);
+        assert_eq!(json_value_to_label(&json!(3.0)).unwrap(), 3);
+        assert!(json_value_to_label(&json!(3.5)).is_err());
+        assert!(json_value_to_label(&json!(-1)).is_err());
+        assert!(json_value_to_label(&json!(70000)).is_err());
+        assert!(json_value_to_label(&json!("3")).is_err());
+    }
+
     #[test]
     fn test_relabel_small_file() {
         //
@@ -365,4 +668,34 @@ mod test {
 
         assert_eq!(output_json, expected_output_json);
     }
+
+    #[test]
+    fn test_transcode_in_place() {
+        let dir = std::env::temp_dir().join(format!("ben_in_place_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("labels.txt");
+        fs::write(&path, "abc").unwrap();
+
+        transcode_in_place(&path, |mut reader, writer| {
+            let mut contents = String::new();
+            reader.read_to_string(&mut contents)?;
+            writer.write_all(contents.to_uppercase().as_bytes())
+        })
+        .unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "ABC");
+
+        let err = transcode_in_place(&path, |_, writer| {
+            writer.write_all(b"partial")?;
+            Err(io::Error::new(io::ErrorKind::InvalidData, "bad input"))
+        })
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "ABC");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        assert!(is_same_file(&path, dir.join(".").join("labels.txt")));
+        assert!(!is_same_file(&path, dir.join("other.txt")));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
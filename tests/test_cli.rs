@@ -0,0 +1,295 @@
+use ben::encode::relabel::relabel_ben_file;
+use ben::encode::{jsonl_encode_ben, jsonl_encode_xben};
+use ben::BenVariant;
+use serde_json::{json, Value};
+use std::fs::File;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ben_cli_{}_{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_decode_every() {
+    let dir = scratch_dir("decode_every");
+    let ben_path = dir.join("samples.jsonl.ben");
+
+    // Runs of three repeated samples so that the MkvChain counts matter
+    let input = (1..=100u64)
+        .map(|i| json!({"assignment": [1, 1, 2, i.div_ceil(3)], "sample": i}).to_string() + "\n")
+        .collect::<String>();
+    jsonl_encode_ben(
+        input.as_bytes(),
+        File::create(&ben_path).unwrap(),
+        BenVariant::MkvChain,
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ben"))
+        .args(["-m", "decode", "--every", "10", "-p"])
+        .arg(&ben_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let samples = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            let value: Value = serde_json::from_str(line).unwrap();
+            (
+                value["sample"].as_u64().unwrap(),
+                value["assignment"][3].as_u64().unwrap(),
+            )
+        })
+        .collect::<Vec<_>>();
+    let expected = (1..=10u64)
+        .map(|k| (10 * k, (10 * k).div_ceil(3)))
+        .collect::<Vec<_>>();
+    assert_eq!(samples, expected);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_decode_selections_are_exclusive() {
+    let output = Command::new(env!("CARGO_BIN_EXE_ben"))
+        .args([
+            "-m", "decode", "--every", "10", "--range", "1:5", "-p", "x.ben",
+        ])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cannot be used with"));
+}
+
+#[test]
+fn test_dump_prints_runs() {
+    let dir = scratch_dir("dump");
+    let ben_path = dir.join("samples.jsonl.ben");
+
+    let input = [
+        json!({"assignment": [1, 1, 2, 2], "sample": 1}),
+        json!({"assignment": [1, 1, 1, 2, 2, 5], "sample": 2}),
+    ]
+    .iter()
+    .map(|value| value.to_string() + "\n")
+    .collect::<String>();
+    jsonl_encode_ben(
+        input.as_bytes(),
+        File::create(&ben_path).unwrap(),
+        BenVariant::Standard,
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ben"))
+        .args(["-m", "dump", "-n", "2", "-p"])
+        .arg(&ben_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[0].starts_with("sample 2: "));
+    assert!(lines[0].contains("max_val_bits 3, max_len_bits 2"));
+    assert_eq!(lines[1], "1:3 2:2 5:1");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_roundtrip_small_example() {
+    let dir = scratch_dir("roundtrip");
+    let ben_path = dir.join("small_example.jsonl.ben");
+
+    let input = std::fs::read(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/example/small_example.jsonl"
+    ))
+    .unwrap();
+    jsonl_encode_ben(
+        input.as_slice(),
+        File::create(&ben_path).unwrap(),
+        BenVariant::MkvChain,
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ben"))
+        .args(["-m", "roundtrip"])
+        .arg(&ben_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8(output.stdout)
+        .unwrap()
+        .starts_with("PASS: "));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_x_decode_prints_only_jsonl() {
+    let dir = scratch_dir("x_decode_print");
+    let xben_path = dir.join("samples.jsonl.xben");
+
+    let input = (1..=5u64)
+        .map(|i| json!({"assignment": [1, 1, 2, i], "sample": i}).to_string() + "\n")
+        .collect::<String>();
+    jsonl_encode_xben(
+        input.as_bytes(),
+        File::create(&xben_path).unwrap(),
+        BenVariant::Standard,
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ben"))
+        .args(["-m", "x-decode", "-p"])
+        .arg(&xben_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), input);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_filter_assigned() {
+    let dir = scratch_dir("filter");
+    let ben_path = dir.join("samples.jsonl.ben");
+
+    let input = (1..=10u64)
+        .map(|i| json!({"assignment": [1, i % 3, 2], "sample": i}).to_string() + "\n")
+        .collect::<String>();
+    jsonl_encode_ben(
+        input.as_bytes(),
+        File::create(&ben_path).unwrap(),
+        BenVariant::MkvChain,
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ben"))
+        .args(["-m", "filter", "--keep", "assigned"])
+        .arg(&ben_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let filtered = std::fs::read(dir.join("samples.jsonl.filtered.ben")).unwrap();
+    let samples = ben::decode::adapters::expand_counts(
+        ben::decode::BenDecoder::new(filtered.as_slice()).unwrap(),
+    )
+    .collect::<std::io::Result<Vec<_>>>()
+    .unwrap();
+    assert_eq!(samples.len(), 7);
+    assert!(samples.iter().all(|sample| !sample.contains(&0)));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ben"))
+        .args(["-m", "filter", "--keep", "bogus"])
+        .arg(&ben_path)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_decode_split() {
+    let dir = scratch_dir("decode_split");
+    let ben_path = dir.join("samples.jsonl.ben");
+
+    let input = (1..=10u64)
+        .map(|i| json!({"assignment": [1, 2, i.div_ceil(2)], "sample": i}).to_string() + "\n")
+        .collect::<String>();
+    jsonl_encode_ben(
+        input.as_bytes(),
+        File::create(&ben_path).unwrap(),
+        BenVariant::MkvChain,
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ben"))
+        .args(["-m", "decode", "--split", "4"])
+        .arg(&ben_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let parts = (0..3)
+        .map(|part| {
+            std::fs::read_to_string(dir.join(format!("samples.part{}.jsonl", part))).unwrap()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        parts
+            .iter()
+            .map(|part| part.lines().count())
+            .collect::<Vec<_>>(),
+        vec![4, 4, 2]
+    );
+    assert_eq!(parts.concat(), input);
+    assert!(!dir.join("samples.part3.jsonl").exists());
+    assert!(!dir.join("samples.jsonl").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_reben_in_place() {
+    let dir = scratch_dir("reben_in_place");
+    let ben_path = dir.join("samples.jsonl.ben");
+
+    let input = (1..=5u64)
+        .map(|i| json!({"assignment": [3, 3, 1, 2, i % 2 + 1], "sample": i}).to_string() + "\n")
+        .collect::<String>();
+    jsonl_encode_ben(
+        input.as_bytes(),
+        File::create(&ben_path).unwrap(),
+        BenVariant::Standard,
+    )
+    .unwrap();
+    let original = std::fs::read(&ben_path).unwrap();
+
+    let mut expected = Vec::new();
+    relabel_ben_file(original.as_slice(), &mut expected).unwrap();
+    assert_ne!(expected, original);
+
+    // Writing the output over the input is refused without --in-place
+    let output = Command::new(env!("CARGO_BIN_EXE_reben"))
+        .args(["-m", "ben", "-o"])
+        .arg(&ben_path)
+        .arg(&ben_path)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--in-place"));
+    assert_eq!(std::fs::read(&ben_path).unwrap(), original);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_reben"))
+        .args(["-m", "ben", "--in-place"])
+        .arg(&ben_path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(std::fs::read(&ben_path).unwrap(), expected);
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_reben_in_place_is_refused_in_json_mode() {
+    let output = Command::new(env!("CARGO_BIN_EXE_reben"))
+        .args(["-m", "json", "-k", "GEOID20", "--in-place", "x.json"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("--in-place is only supported in BEN mode"));
+}